@@ -0,0 +1,261 @@
+//! `rec-cli mark`: chapter markers dropped during a recording.
+//!
+//! Each mark is a line `<seconds since start> <label>` in `MARKERS_FILE`.
+//! `stop` turns them into chapters of the recording (an ffmetadata file and
+//! a `-c copy` remux) or, when the container won't take them, a
+//! `<recording>.chapters` sidecar with one `HH:MM:SS.mmm label` per line.
+//! With `--mark-on-touch` the supervisor also marks whenever a file changes.
+
+use crate::ffmpeg::probe_duration;
+use crate::output::remux_in_place;
+use crate::session::Session;
+use crate::{remove_temp, write_log, MARKERS_FILE};
+use chrono::{DateTime, Local};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Marker {
+    pub at: f64,
+    pub label: String,
+}
+
+/// Where the chapters of a stopped recording ended up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chapters {
+    /// Written into the recording itself
+    Embedded(usize),
+    /// In a `.chapters` sidecar next to it
+    Sidecar(PathBuf),
+}
+
+/// Record a marker `at` seconds into the recording.
+pub fn append(at: f64, label: &str) -> std::io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&*MARKERS_FILE)?;
+    // One marker per line, whatever the label contains
+    let label = label.replace(['\n', '\r'], " ");
+    writeln!(file, "{:.3} {}", at, label.trim())?;
+    write_log(&format!("Marker at {:.3}s: {}", at, label.trim()));
+    Ok(())
+}
+
+/// `--mark-on-touch`: a marker each time a file is modified, labeled with
+/// the lines appended since (unlabeled for a bare `touch`). Polled by the
+/// supervisor, so markers carry the file's modification time, not the poll's.
+pub struct TouchTrigger {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    /// Bytes already turned into labels
+    len: u64,
+}
+
+impl TouchTrigger {
+    /// Watch `path`; what it holds already is not a marker.
+    pub fn new(path: &Path) -> Self {
+        let meta = fs::metadata(path).ok();
+        TouchTrigger {
+            path: path.to_path_buf(),
+            modified: meta.as_ref().and_then(|m| m.modified().ok()),
+            len: meta.map_or(0, |m| m.len()),
+        }
+    }
+
+    pub fn poll(&mut self) {
+        let Ok(meta) = fs::metadata(&self.path) else {
+            return;
+        };
+        let modified = meta.modified().ok();
+        if modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+        let Some(session) = Session::current() else {
+            return;
+        };
+        let touched: DateTime<Local> = modified.map_or_else(Local::now, DateTime::from);
+        let at = ((touched - session.started_at).num_milliseconds() as f64 / 1000.0).max(0.0);
+
+        // A file rewritten shorter starts over
+        let bytes = fs::read(&self.path).unwrap_or_default();
+        let start = if (bytes.len() as u64) < self.len {
+            0
+        } else {
+            self.len as usize
+        };
+        self.len = bytes.len() as u64;
+        let appended = String::from_utf8_lossy(&bytes[start..]);
+        let mut labels: Vec<&str> = appended
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .collect();
+        if labels.is_empty() {
+            labels.push("");
+        }
+        write_log(&format!("--mark-on-touch: {} changed", self.path.display()));
+        for label in labels {
+            if let Err(err) = append(at, label) {
+                write_log(&format!("--mark-on-touch: cannot save the marker: {}", err));
+            }
+        }
+    }
+}
+
+/// Forget the markers of a previous recording.
+pub fn clear() {
+    let _ = fs::remove_file(&*MARKERS_FILE);
+}
+
+/// The pending markers, in time order. Consumed.
+fn take() -> Vec<Marker> {
+    let saved = fs::read_to_string(&*MARKERS_FILE).unwrap_or_default();
+    clear();
+    let mut markers: Vec<Marker> = saved
+        .lines()
+        .filter_map(|line| {
+            let (at, label) = line.split_once(' ').unwrap_or((line, ""));
+            Some(Marker {
+                at: at.parse().ok()?,
+                label: label.to_string(),
+            })
+        })
+        .collect();
+    markers.sort_by(|a, b| a.at.total_cmp(&b.at));
+    markers
+}
+
+/// `HH:MM:SS.mmm`
+pub fn format_at(secs: f64) -> String {
+    let ms = (secs * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// ffmetadata escapes `=`, `;`, `#`, `\` and newlines with a backslash.
+fn metadata_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// An ffmetadata document with one chapter per marker, each running to the
+/// next (the last to `duration`). Time before the first marker is left out
+/// of any chapter unless a marker sits at 0.
+fn ffmetadata(markers: &[Marker], duration: f64) -> String {
+    let mut doc = String::from(";FFMETADATA1\n");
+    for (i, marker) in markers.iter().enumerate() {
+        let end = markers.get(i + 1).map_or(duration, |next| next.at);
+        let title = if marker.label.is_empty() {
+            format!("Chapter {}", i + 1)
+        } else {
+            marker.label.clone()
+        };
+        doc.push_str(&format!(
+            "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            (marker.at * 1000.0).round() as u64,
+            (end * 1000.0).round() as u64,
+            metadata_escape(&title)
+        ));
+    }
+    doc
+}
+
+fn sidecar(out: &Path, markers: &[Marker]) -> Option<PathBuf> {
+    let path = out.with_extension("chapters");
+    let body: String = markers
+        .iter()
+        .map(|m| format!("{} {}\n", format_at(m.at), m.label))
+        .collect();
+    match fs::write(&path, body) {
+        Ok(()) => {
+            write_log(&format!("Chapters written: {}", path.display()));
+            Some(path)
+        }
+        Err(err) => {
+            write_log(&format!("Failed to write {}: {}", path.display(), err));
+            None
+        }
+    }
+}
+
+/// Remux `out` in place with `markers` as chapters.
+fn embed(
+    out: &Path,
+    markers: &[Marker],
+    duration: f64,
+    movflags: Option<&str>,
+) -> Result<(), String> {
+    let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+    let meta = out.with_file_name(format!(".{}.ffmetadata", stem));
+    fs::write(&meta, ffmetadata(markers, duration))
+        .map_err(|e| format!("cannot write {}: {}", meta.display(), e))?;
+    // Chapters from the ffmetadata; tags (the crop comment) stay the recording's
+    let args = ["-map", "0", "-map_metadata", "0", "-map_chapters", "1"];
+    let result = remux_in_place(out, &[&meta], &args, movflags, "chapters");
+    remove_temp(&meta);
+    result
+}
+
+/// Write the pending markers to a `<out>.chapters` sidecar only, for
+/// recordings that are not one file (`--segment-time`).
+pub fn finish_sidecar(out: &Path) -> Option<Chapters> {
+    let markers = take();
+    if markers.is_empty() {
+        return None;
+    }
+    sidecar(out, &markers).map(Chapters::Sidecar)
+}
+
+/// Apply the pending markers to `out`, shifted by `offset` seconds (an intro
+/// prepended since they were taken), remuxing with the recording's
+/// `movflags`. `None` when nothing was marked.
+pub fn finish(out: &Path, offset: f64, movflags: Option<&str>) -> Option<Chapters> {
+    let mut markers = take();
+    if markers.is_empty() {
+        return None;
+    }
+    for marker in &mut markers {
+        marker.at += offset;
+    }
+    let Some(duration) = probe_duration(out) else {
+        write_log("Chapters: could not probe the recording's duration; writing a sidecar");
+        return sidecar(out, &markers).map(Chapters::Sidecar);
+    };
+    markers.retain(|m| m.at < duration);
+    if markers.is_empty() {
+        write_log("Chapters: every marker is past the end of the recording");
+        return None;
+    }
+    match embed(out, &markers, duration, movflags) {
+        Ok(()) => {
+            write_log(&format!(
+                "{} chapter(s) added to {}",
+                markers.len(),
+                out.display()
+            ));
+            Some(Chapters::Embedded(markers.len()))
+        }
+        Err(err) => {
+            write_log(&format!(
+                "Chapters not embedded ({}); writing a sidecar",
+                err
+            ));
+            sidecar(out, &markers).map(Chapters::Sidecar)
+        }
+    }
+}
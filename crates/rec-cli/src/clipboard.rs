@@ -0,0 +1,120 @@
+//! `--track-clipboard`: when the pasteboard changed during a recording.
+//!
+//! The supervisor reads the pasteboard's change count once a second and
+//! appends the time of each change to `CLIPBOARD_FILE`. `stop` turns that
+//! into a `<recording>.clipboard.json` timeline, on the same clock as the
+//! chapter markers, to line copies and pastes up with the video.
+//!
+//! Privacy: only the change count is read, never what was copied, and the
+//! timeline holds nothing but times. Still, it shows when something was
+//! copied, so share it with the same care as the recording. Where there is
+//! no `osascript` (not macOS) every poll comes up empty and nothing is
+//! recorded.
+
+use crate::session::Session;
+use crate::{write_log, CLIPBOARD_FILE};
+use chrono::Local;
+use serde_json::json;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// JavaScript for Automation reading `NSPasteboard.changeCount`, which goes
+/// up by one with every copy.
+const CHANGE_COUNT_SCRIPT: &str =
+    "ObjC.import('AppKit'); $.NSPasteboard.generalPasteboard.changeCount";
+
+/// The general pasteboard's change count.
+pub fn change_count() -> Option<i64> {
+    let output = Command::new("osascript")
+        .args(["-l", "JavaScript", "-e", CHANGE_COUNT_SCRIPT])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
+/// Start an empty timeline, replacing any previous one.
+pub fn begin() -> std::io::Result<()> {
+    fs::write(&*CLIPBOARD_FILE, "")
+}
+
+pub fn clear() {
+    let _ = fs::remove_file(&*CLIPBOARD_FILE);
+}
+
+/// Samples the change count for the supervisor.
+pub struct Tracker {
+    /// `None` until the first sample; what was on the pasteboard before the
+    /// recording is not a change
+    last: Option<i64>,
+}
+
+impl Tracker {
+    pub fn new() -> Self {
+        let last = change_count();
+        if last.is_none() {
+            write_log("--track-clipboard: cannot read the pasteboard; nothing will be tracked");
+        }
+        Tracker { last }
+    }
+
+    pub fn poll(&mut self) {
+        let Some(count) = change_count() else {
+            return;
+        };
+        let changed = self.last.is_some_and(|last| last != count);
+        self.last = Some(count);
+        if !changed {
+            return;
+        }
+        let Some(session) = Session::current() else {
+            return;
+        };
+        let at = ((Local::now() - session.started_at).num_milliseconds() as f64 / 1000.0).max(0.0);
+        write_log(&format!(
+            "--track-clipboard: pasteboard changed ({:.1}s)",
+            at
+        ));
+        if let Ok(mut file) = OpenOptions::new().append(true).open(&*CLIPBOARD_FILE) {
+            let _ = writeln!(file, "{:.3}", at);
+        }
+    }
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Write the pending timeline next to `out` as `<stem>.clipboard.json`,
+/// times shifted by `offset` seconds (an intro prepended since). Consumed.
+pub fn finish(out: &Path, offset: f64) -> Option<PathBuf> {
+    let saved = fs::read_to_string(&*CLIPBOARD_FILE).ok()?;
+    clear();
+    let changes: Vec<f64> = saved
+        .lines()
+        .filter_map(|line| Some(line.trim().parse::<f64>().ok()? + offset))
+        .collect();
+
+    let stem = out.file_stem()?.to_string_lossy();
+    let path = out.with_file_name(format!("{}.clipboard.json", stem));
+    let doc = json!({ "recording": out, "changes": changes });
+    match serde_json::to_string_pretty(&doc).map(|s| fs::write(&path, s)) {
+        Ok(Ok(())) => {
+            write_log(&format!(
+                "Clipboard timeline written: {} ({} changes)",
+                path.display(),
+                changes.len()
+            ));
+            Some(path)
+        }
+        _ => {
+            write_log(&format!("Failed to write {}", path.display()));
+            None
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! `start --encode-in-background-after-stop`: record lossless, compress at
+//! `stop`.
+//!
+//! During the recording ffmpeg only runs x264 at `-qp 0` (no rate control,
+//! the cheapest encode there is) into a hidden `.<name>.lossless.mkv` next
+//! to the output, with PCM audio. `stop` then encodes that at a slow preset
+//! into the real output and removes it. The capture is many times the size
+//! of the result until then.
+
+use crate::ffmpeg::probe_duration;
+use crate::output::file_size;
+use crate::process::run_command_lines;
+use crate::recorder::StartOptions;
+use crate::title::ffmpeg_timeout;
+use crate::{append_log, remove_temp, write_log};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// x264 preset of the compression `stop` runs
+pub const PRESET: &str = "medium";
+pub const CRF: u32 = 23;
+
+/// The lossless capture recorded in place of `out`.
+pub fn capture_path(out: &Path) -> PathBuf {
+    let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+    out.with_file_name(format!(".{}.lossless.mkv", stem))
+}
+
+/// Encoder flags of the capture, with PCM audio when it has any. Lossless
+/// x264 needs a 4:4:4 profile, so --h264-profile and --h264-level wait for
+/// the compression.
+pub fn capture_args(audio: bool) -> Vec<String> {
+    [
+        "-pix_fmt",
+        "yuv420p",
+        "-c:v",
+        "libx264",
+        "-preset",
+        "ultrafast",
+        "-qp",
+        "0",
+    ]
+    .into_iter()
+    .chain(audio.then_some(["-c:a", "pcm_s16le"]).into_iter().flatten())
+    .map(String::from)
+    .collect()
+}
+
+/// `--crf` of the compression, when given.
+pub fn crf(args: &StartOptions) -> u32 {
+    args.crf.unwrap_or(CRF)
+}
+
+/// `--preset` of the compression, when given.
+pub fn preset(args: &StartOptions) -> &str {
+    args.preset.as_deref().unwrap_or(PRESET)
+}
+
+/// Output flags of the compression, resolved at `start`.
+pub fn encode_args(args: &StartOptions) -> Vec<String> {
+    let mut out: Vec<String> = [
+        "-pix_fmt",
+        "yuv420p",
+        "-profile:v",
+        &args.h264_profile,
+        "-level",
+        &args.h264_level,
+        "-c:v",
+        "libx264",
+        "-preset",
+        preset(args),
+        "-crf",
+        &crf(args).to_string(),
+    ]
+    .map(String::from)
+    .to_vec();
+    // Keep --vfr's gaps rather than refill them at a constant rate
+    if args.vfr {
+        out.extend(["-fps_mode", "vfr"].map(String::from));
+    }
+    if let Some(flags) = args.movflags() {
+        out.extend(["-movflags", flags].map(String::from));
+    }
+    out
+}
+
+/// Encode `capture` into `out` with `encode_args`, calling `on_progress`
+/// with the fraction done. The capture is removed once `out` is written
+/// (unless `--keep-temp`) and kept when anything fails.
+pub fn compress(
+    capture: &Path,
+    out: &Path,
+    encode_args: &[String],
+    on_progress: Option<fn(f64)>,
+) -> Result<(), String> {
+    let duration = probe_duration(capture).filter(|d| *d > 0.0);
+    write_log(&format!(
+        "Compressing {} ({} MB lossless) into {}",
+        capture.display(),
+        file_size(capture) / (1 << 20),
+        out.display()
+    ));
+    let mut args: Vec<OsString> = ["-hide_banner", "-loglevel", "error", "-nostats", "-y", "-i"]
+        .map(OsString::from)
+        .to_vec();
+    args.push(capture.into());
+    args.extend(encode_args.iter().map(OsString::from));
+    args.extend(["-progress", "pipe:1"].map(OsString::from));
+    args.push(out.into());
+
+    // -progress blocks of key=value lines; out_time_us is how far it got
+    let mut logged = 0;
+    let on_line = |line: &str| {
+        let (Some(us), Some(duration)) = (line.strip_prefix("out_time_us="), duration) else {
+            return;
+        };
+        let Ok(us) = us.parse::<f64>() else { return };
+        let done = (us / 1_000_000.0 / duration).clamp(0.0, 1.0);
+        if let Some(report) = on_progress {
+            report(done);
+        }
+        let percent = (done * 100.0) as u32;
+        if percent >= logged + 10 {
+            logged = percent / 10 * 10;
+            write_log(&format!("Compressing: {}%", logged));
+        }
+    };
+    let ran = run_command_lines("ffmpeg", &args, ffmpeg_timeout(duration), on_line);
+    if let Ok(ran) = &ran {
+        append_log(&ran.stderr);
+    }
+    let status = match ran {
+        Ok(ran) => ran.status,
+        Err(err) => {
+            let _ = fs::remove_file(out);
+            return Err(match err.kind() {
+                io::ErrorKind::TimedOut => err.to_string(),
+                _ => format!("cannot run ffmpeg: {}", err),
+            });
+        }
+    };
+    if !status.success() || file_size(out) == 0 {
+        let _ = fs::remove_file(out);
+        return Err(format!("ffmpeg exited with {}", status));
+    }
+    write_log(&format!("Compressed to {} MB", file_size(out) / (1 << 20)));
+    remove_temp(capture);
+    Ok(())
+}
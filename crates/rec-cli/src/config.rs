@@ -0,0 +1,102 @@
+//! `config.json` in the rec.nvim config directory: `start` flags applied to
+//! every recording, and named presets recalled with `--preset-file NAME`.
+//!
+//! ```json
+//! {
+//!   "defaults": { "output-dir": "/Volumes/Work/rec", "framerate": 30 },
+//!   "presets": {
+//!     "youtube": { "framerate": 60, "scale": "1920x1080", "stats": true },
+//!     "quick": { "format": "gif", "duration": 30 }
+//!   }
+//! }
+//! ```
+//!
+//! Keys are `start` flags without the dashes. `true` passes a switch
+//! (`false` leaves it out), a list repeats the flag once per item and
+//! anything else is the flag's value. The flags go in ahead of the ones
+//! typed, defaults first, so a preset beats the defaults and the command
+//! line beats both.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// A set of `start` flags by name
+pub type Flags = Map<String, Value>;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Config {
+    /// Flags for every `start`
+    #[serde(default)]
+    pub defaults: Flags,
+    #[serde(default)]
+    pub presets: BTreeMap<String, Flags>,
+}
+
+/// `config.json` in the rec.nvim config directory.
+pub fn config_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("rec.nvim").join("config.json"))
+}
+
+impl Config {
+    /// The config file, or an empty config when there is none.
+    pub fn load() -> anyhow::Result<Self> {
+        let Some(path) = config_path().filter(|p| p.exists()) else {
+            return Ok(Config::default());
+        };
+        let text = fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("cannot read {}: {}", path.display(), e))?;
+        serde_json::from_str(&text).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+    }
+
+    /// The flags to put ahead of a `start`'s own: the defaults, then
+    /// `preset`'s.
+    pub fn start_args(&self, preset: Option<&str>) -> anyhow::Result<Vec<String>> {
+        let mut args = flag_args(&self.defaults).map_err(|e| anyhow::anyhow!("defaults: {}", e))?;
+        if let Some(name) = preset {
+            let Some(flags) = self.presets.get(name) else {
+                let defined: Vec<&str> = self.presets.keys().map(String::as_str).collect();
+                anyhow::bail!(
+                    "no preset '{}' in {}; defined: {}",
+                    name,
+                    config_path().unwrap_or_default().display(),
+                    if defined.is_empty() {
+                        "none".to_string()
+                    } else {
+                        defined.join(", ")
+                    }
+                );
+            };
+            args.extend(flag_args(flags).map_err(|e| anyhow::anyhow!("preset '{}': {}", name, e))?);
+        }
+        Ok(args)
+    }
+}
+
+/// `flags` as command-line arguments.
+pub fn flag_args(flags: &Flags) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    for (key, value) in flags {
+        let flag = format!("--{}", key.trim_start_matches('-'));
+        if flag == "--preset-file" {
+            return Err("a preset cannot name another preset".into());
+        }
+        let values = match value {
+            Value::Array(items) => items.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                Value::Bool(true) => args.push(flag.clone()),
+                Value::Bool(false) => {}
+                // One `--flag=value` word, so a value like -16 stays a value
+                Value::String(s) => args.push(format!("{}={}", flag, s)),
+                Value::Number(n) => args.push(format!("{}={}", flag, n)),
+                _ => return Err(format!("{}: expected a string, number or boolean", flag)),
+            }
+        }
+    }
+    Ok(args)
+}
@@ -0,0 +1,263 @@
+//! `stop --encrypt` and `rec-cli decrypt`: passphrase encryption at rest.
+//!
+//! Files are written in the age format (scrypt for the passphrase,
+//! ChaCha20-Poly1305 for the payload), so `age -d` opens them too. Every
+//! chunk is authenticated: a wrong passphrase or a damaged file is an error
+//! from `decrypt`, never a plaintext of noise. The passphrase comes from
+//! `REC_PASSPHRASE` or a no-echo prompt on the terminal, never from a
+//! command line where `ps` could see it.
+
+use crate::write_log;
+use age::secrecy::SecretString;
+use age::{Decryptor, Encryptor};
+use nix::sys::signal::{signal, SigHandler, Signal};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::iter;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+pub const PASSPHRASE_ENV: &str = "REC_PASSPHRASE";
+/// Appended to the name of an encrypted recording
+pub const EXTENSION: &str = "age";
+
+/// `REC_PASSPHRASE`, or ask on the terminal. `confirm` asks twice.
+pub fn passphrase(confirm: bool) -> Result<String, String> {
+    if let Ok(pass) = env::var(PASSPHRASE_ENV) {
+        if pass.is_empty() {
+            return Err(format!("{} is empty", PASSPHRASE_ENV));
+        }
+        return Ok(pass);
+    }
+    let pass = prompt("Passphrase: ")?;
+    if pass.is_empty() {
+        return Err("empty passphrase".into());
+    }
+    if confirm && prompt("Confirm passphrase: ")? != pass {
+        return Err("passphrases do not match".into());
+    }
+    Ok(pass)
+}
+
+/// The terminal a prompt has turned echo off on, for [`on_prompt_interrupt`];
+/// -1 when none has.
+static SILENCED_TTY: AtomicI32 = AtomicI32::new(-1);
+
+/// Ctrl-C at a prompt: echo back on, then die of SIGINT as if the prompt had
+/// never caught it. Only async-signal-safe calls.
+extern "C" fn on_prompt_interrupt(_: libc::c_int) {
+    let fd = SILENCED_TTY.load(Ordering::SeqCst);
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if fd >= 0 && libc::tcgetattr(fd, &mut termios) == 0 {
+            termios.c_lflag |= libc::ECHO;
+            libc::tcsetattr(fd, libc::TCSANOW, &termios);
+            libc::write(fd, b"\n".as_ptr().cast(), 1);
+        }
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+        libc::raise(libc::SIGINT);
+    }
+}
+
+/// Echo off on a terminal until dropped, however the prompt ends; a Ctrl-C
+/// in between is [`on_prompt_interrupt`]'s.
+struct Silenced {
+    fd: RawFd,
+    saved: libc::termios,
+    sigint: Option<SigHandler>,
+}
+
+impl Silenced {
+    fn new(fd: RawFd) -> Option<Silenced> {
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut saved) } != 0 {
+            return None;
+        }
+        SILENCED_TTY.store(fd, Ordering::SeqCst);
+        let handler = SigHandler::Handler(on_prompt_interrupt);
+        let sigint = unsafe { signal(Signal::SIGINT, handler) }.ok();
+        let mut silent = saved;
+        silent.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &silent) };
+        Some(Silenced { fd, saved, sigint })
+    }
+}
+
+impl Drop for Silenced {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.saved) };
+        if let Some(sigint) = self.sigint {
+            let _ = unsafe { signal(Signal::SIGINT, sigint) };
+        }
+        SILENCED_TTY.store(-1, Ordering::SeqCst);
+    }
+}
+
+/// Read a line from the controlling terminal with echo off.
+fn prompt(label: &str) -> Result<String, String> {
+    let tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .map_err(|e| format!("no terminal to prompt on ({}); set {}", e, PASSPHRASE_ENV))?;
+    let Some(silenced) = Silenced::new(tty.as_raw_fd()) else {
+        return Err(format!(
+            "cannot configure the terminal; set {}",
+            PASSPHRASE_ENV
+        ));
+    };
+
+    let _ = (&tty).write_all(label.as_bytes());
+    let mut line = String::new();
+    let read = BufReader::new(&tty).read_line(&mut line);
+
+    drop(silenced);
+    let _ = (&tty).write_all(b"\n");
+    read.map_err(|e| format!("cannot read passphrase: {}", e))?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Encrypt `input` into a new `output` with `pass`.
+fn seal(input: &Path, output: &Path, pass: &str) -> Result<(), String> {
+    let mut plain =
+        File::open(input).map_err(|e| format!("cannot open {}: {}", input.display(), e))?;
+    let sealed =
+        File::create(output).map_err(|e| format!("cannot create {}: {}", output.display(), e))?;
+    let encryptor = Encryptor::with_user_passphrase(SecretString::from(pass.to_owned()));
+    let result = encryptor
+        .wrap_output(BufWriter::new(sealed))
+        .and_then(|mut writer| {
+            io::copy(&mut plain, &mut writer)?;
+            writer.finish()?.flush()
+        });
+    result.map_err(|e| {
+        let _ = fs::remove_file(output);
+        format!("cannot encrypt {}: {}", input.display(), e)
+    })
+}
+
+/// Decrypt `input` into a new `output` with `pass`. A wrong passphrase or
+/// a chunk that fails its tag removes what was written.
+fn unseal(input: &Path, output: &Path, pass: &str) -> Result<(), String> {
+    let sealed =
+        File::open(input).map_err(|e| format!("cannot open {}: {}", input.display(), e))?;
+    let decryptor = Decryptor::new_buffered(BufReader::new(sealed))
+        .map_err(|e| format!("{} is not an age file: {}", input.display(), e))?;
+    let identity = age::scrypt::Identity::new(SecretString::from(pass.to_owned()));
+    let mut reader = decryptor
+        .decrypt(iter::once(&identity as &dyn age::Identity))
+        .map_err(|e| format!("cannot decrypt {}: {}", input.display(), e))?;
+    let plain =
+        File::create(output).map_err(|e| format!("cannot create {}: {}", output.display(), e))?;
+    let mut writer = BufWriter::new(plain);
+    let result = io::copy(&mut reader, &mut writer).and_then(|_| writer.flush());
+    result.map_err(|e| {
+        let _ = fs::remove_file(output);
+        format!("cannot decrypt {}: {}", input.display(), e)
+    })
+}
+
+/// Encrypt `path` to `<path>.age` and remove the plaintext.
+pub fn encrypt(path: &Path, pass: &str) -> Result<PathBuf, String> {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", EXTENSION));
+    let encrypted = PathBuf::from(name);
+    seal(path, &encrypted, pass)?;
+
+    // Only drop the plaintext once the ciphertext is known to be there
+    match File::open(&encrypted).and_then(|f| f.metadata()) {
+        Ok(meta) if meta.len() > 0 => {}
+        _ => return Err(format!("{} was not written", encrypted.display())),
+    }
+    fs::remove_file(path).map_err(|e| {
+        format!(
+            "encrypted, but could not remove plaintext {}: {}",
+            path.display(),
+            e
+        )
+    })?;
+    write_log(&format!(
+        "Encrypted {} -> {}",
+        path.display(),
+        encrypted.display()
+    ));
+    Ok(encrypted)
+}
+
+/// Decrypt `path` (a `.age` file) next to itself, or to `output`. The
+/// encrypted file is kept.
+pub fn decrypt(path: &Path, output: Option<&Path>, pass: &str) -> Result<PathBuf, String> {
+    let plain = match output {
+        Some(out) => out.to_path_buf(),
+        None if path.extension().is_some_and(|e| e == EXTENSION) => path.with_extension(""),
+        None => {
+            return Err(format!(
+                "{} does not end in .{}; pass --output",
+                path.display(),
+                EXTENSION
+            ))
+        }
+    };
+    if plain.exists() {
+        return Err(format!("{} already exists", plain.display()));
+    }
+    unseal(path, &plain, pass)?;
+    write_log(&format!(
+        "Decrypted {} -> {}",
+        path.display(),
+        plain.display()
+    ));
+    Ok(plain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch directory for one test, removed first if a run left it.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rec-cli-crypt-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn encrypt_then_decrypt_round_trips() {
+        let dir = scratch("round-trip");
+        let video = dir.join("rec.mp4");
+        let bytes: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&video, &bytes).unwrap();
+
+        let sealed = encrypt(&video, "hunter2").unwrap();
+        assert_eq!(sealed, dir.join("rec.mp4.age"));
+        assert!(!video.exists());
+        assert_ne!(fs::read(&sealed).unwrap()[..], bytes[..]);
+
+        assert_eq!(decrypt(&sealed, None, "hunter2").unwrap(), video);
+        assert_eq!(fs::read(&video).unwrap(), bytes);
+        assert!(sealed.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn decrypt_rejects_a_wrong_passphrase_and_a_damaged_file() {
+        let dir = scratch("rejects");
+        let video = dir.join("rec.mp4");
+        fs::write(&video, vec![7u8; 100_000]).unwrap();
+        let sealed = encrypt(&video, "right").unwrap();
+
+        assert!(decrypt(&sealed, None, "wrong").is_err());
+        assert!(!video.exists());
+
+        let mut damaged = fs::read(&sealed).unwrap();
+        let last = damaged.len() - 1;
+        damaged[last] ^= 1;
+        fs::write(&sealed, damaged).unwrap();
+        assert!(decrypt(&sealed, None, "right").is_err());
+        assert!(!video.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
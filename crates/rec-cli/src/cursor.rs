@@ -0,0 +1,94 @@
+//! `--big-cursor`: a larger mouse pointer for as long as a recording runs,
+//! for tutorials that are scaled down from a HiDPI screen.
+//!
+//! Finding the pointer in captured frames to redraw it bigger is not
+//! practical in a filtergraph, and a synthetic overlay would need a helper
+//! tracking the mouse. So the real pointer is enlarged instead, with the
+//! Pointer size setting of macOS's Accessibility preferences, and ffmpeg
+//! captures it as drawn. The size it replaced is kept in `CURSOR_SIZE_FILE`
+//! and put back by `stop`.
+//!
+//! Writing the setting can need Full Disk Access for the terminal on recent
+//! macOS; without it the recording goes ahead with the normal pointer.
+
+use crate::{write_log, CURSOR_SIZE_FILE};
+use std::fs;
+use std::process::{Command, Stdio};
+
+const DOMAIN: &str = "com.apple.universalaccess";
+const KEY: &str = "mouseDriverCursorSize";
+/// The range the Accessibility slider offers
+const MAX_FACTOR: f64 = 4.0;
+
+/// Parse a `--big-cursor` factor: above 1, at most 4.
+pub fn parse_factor(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(f) if f > 1.0 && f <= MAX_FACTOR => Ok(f),
+        _ => Err(format!(
+            "expected a factor above 1 and at most {}, got '{}'",
+            MAX_FACTOR, s
+        )),
+    }
+}
+
+/// The pointer size now; 1 when it was never changed.
+fn current() -> f64 {
+    Command::new("defaults")
+        .args(["read", DOMAIN, KEY])
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8_lossy(&output.stdout).trim().parse().ok())
+        .unwrap_or(1.0)
+}
+
+fn set(size: f64) -> Result<(), String> {
+    let output = Command::new("defaults")
+        .args(["write", DOMAIN, KEY, "-float", &size.to_string()])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("cannot run defaults ({})", e))?;
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+    }
+}
+
+/// Make the pointer `factor` times its normal size. Returns why it could
+/// not be on failure; the recording itself goes ahead either way.
+pub fn enlarge(factor: f64) -> Result<(), String> {
+    // A size left over from a recording that never stopped stays the one
+    // to go back to
+    let previous = match fs::read_to_string(&*CURSOR_SIZE_FILE) {
+        Ok(kept) => kept.trim().parse().unwrap_or(1.0),
+        Err(_) => current(),
+    };
+    fs::write(&*CURSOR_SIZE_FILE, previous.to_string())
+        .map_err(|e| format!("cannot write {}: {}", *CURSOR_SIZE_FILE, e))?;
+    if let Err(err) = set(factor) {
+        let _ = fs::remove_file(&*CURSOR_SIZE_FILE);
+        return Err(err);
+    }
+    write_log(&format!(
+        "--big-cursor: pointer size {} (was {}), captured by ffmpeg as drawn",
+        factor, previous
+    ));
+    Ok(())
+}
+
+/// Put back the pointer size `enlarge` replaced, if it replaced one.
+pub fn restore() {
+    let Ok(kept) = fs::read_to_string(&*CURSOR_SIZE_FILE) else {
+        return;
+    };
+    let previous: f64 = kept.trim().parse().unwrap_or(1.0);
+    match set(previous) {
+        Ok(()) => write_log(&format!("--big-cursor: pointer size {} restored", previous)),
+        Err(err) => write_log(&format!(
+            "--big-cursor: cannot restore the pointer size: {}",
+            err
+        )),
+    }
+    let _ = fs::remove_file(&*CURSOR_SIZE_FILE);
+}
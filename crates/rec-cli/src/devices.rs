@@ -0,0 +1,1047 @@
+//! avfoundation device enumeration.
+//!
+//! ffmpeg has no machine-readable device listing, so we parse the log lines it
+//! prints for `-list_devices true`:
+//!
+//! ```text
+//! [AVFoundation indev @ 0x7f8] AVFoundation video devices:
+//! [AVFoundation indev @ 0x7f8] [0] FaceTime HD Camera
+//! [AVFoundation indev @ 0x7f8] [1] Capture screen 0
+//! [AVFoundation indev @ 0x7f8] AVFoundation audio devices:
+//! [AVFoundation indev @ 0x7f8] [0] MacBook Pro Microphone
+//! ```
+//!
+//! Listing takes a few seconds, so `start` reads a short-lived JSON cache of
+//! the parsed list (see [`DeviceLookup`]).
+//!
+//! Screens differ in scale on a mixed Retina/non-Retina setup, so a crop in
+//! window (point) coordinates needs the scale of the screen it is on.
+//! [`describe_screens`] adds that, from AppKit's `NSScreen` list: "Capture
+//! screen N" is taken to be `NSScreen.screens[N]`, which both order the
+//! same way (the main display first).
+//!
+//! ffmpeg names devices and nothing more, so [`describe_connections`] adds
+//! how each is attached: CoreAudio's transport for audio devices (from
+//! `system_profiler`), the name's hints otherwise.
+
+use crate::ffmpeg::{get_screen_size, probe, probe_output_size, Backend};
+use crate::{remove_temp, write_log};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// How long a cached device list is trusted.
+const CACHE_TTL_SECS: i64 = 10 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceKind {
+    Video,
+    Audio,
+}
+
+impl DeviceKind {
+    fn label(self) -> &'static str {
+        match self {
+            DeviceKind::Video => "video",
+            DeviceKind::Audio => "audio",
+        }
+    }
+}
+
+/// How a device is attached, for telling a built-in display or microphone
+/// from a capture card or a virtual device in a picker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Connection {
+    /// A "Capture screen N" display capture
+    Screen,
+    BuiltIn,
+    Usb,
+    Bluetooth,
+    /// Audio over HDMI, DisplayPort or Thunderbolt, usually a monitor's
+    Display,
+    /// Software devices (BlackHole, Teams, OBS, ...)
+    Virtual,
+    /// An iPhone used as a camera or microphone
+    Continuity,
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Device {
+    pub index: u32,
+    pub name: String,
+    pub kind: DeviceKind,
+    /// How it is attached: from CoreAudio's transport for audio devices,
+    /// guessed from the name otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection: Option<Connection>,
+    /// Maker, where CoreAudio reports it or the name gives it away
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manufacturer: Option<String>,
+    /// Geometry of a capture screen, when [`describe_screens`] found it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screen: Option<ScreenInfo>,
+}
+
+impl Device {
+    /// avfoundation exposes each display as a "Capture screen N" video device.
+    pub fn is_screen(&self) -> bool {
+        self.kind == DeviceKind::Video && self.name.starts_with("Capture screen")
+    }
+
+    /// The N of "Capture screen N".
+    fn screen_number(&self) -> Option<usize> {
+        self.name
+            .strip_prefix("Capture screen")?
+            .trim()
+            .parse()
+            .ok()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Size {
+    pub width: i32,
+    pub height: i32,
+}
+
+/// A screen in AppKit's global coordinates: points, origin at the bottom
+/// left of the main display.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bounds {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// What a crop on one capture screen has to account for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScreenInfo {
+    /// Size of the frames avfoundation captures, in pixels
+    pub pixels: Option<Size>,
+    /// Where the screen is, in points (what window bounds are measured in)
+    pub points: Option<Bounds>,
+    /// Pixels per point: 2 on Retina, 1 otherwise
+    pub scale_factor: Option<f64>,
+    /// CoreGraphics display UUID, which stays with the display when the
+    /// avfoundation index moves (for `start --display-uuid`)
+    #[serde(default)]
+    pub uuid: Option<String>,
+}
+
+/// One `NSScreen`, as `SCREENS_SCRIPT` reports it.
+#[derive(Debug, Clone)]
+struct AppKitScreen {
+    bounds: Bounds,
+    scale: f64,
+    uuid: Option<String>,
+}
+
+/// JavaScript for Automation printing `x y width height scale uuid` for
+/// each `NSScreen`, one per line.
+const SCREENS_SCRIPT: &str = "ObjC.import('AppKit'); \
+    ObjC.bindFunction('CGDisplayCreateUUIDFromDisplayID', ['id', ['unsigned int']]); \
+    ObjC.bindFunction('CFUUIDCreateString', ['id', ['id', 'id']]); \
+    var s = $.NSScreen.screens, out = []; \
+    for (var i = 0; i < s.count; i++) { var n = s.objectAtIndex(i), f = n.frame; \
+    var id = n.deviceDescription.objectForKey('NSScreenNumber').unsignedIntValue; \
+    var uuid = ObjC.unwrap($.CFUUIDCreateString(null, $.CGDisplayCreateUUIDFromDisplayID(id))); \
+    out.push([f.origin.x, f.origin.y, f.size.width, f.size.height, n.backingScaleFactor, \
+    uuid || '-'].join(' ')); } out.join('\\n')";
+
+/// Parse `SCREENS_SCRIPT` output.
+fn parse_screens(listing: &str) -> Vec<AppKitScreen> {
+    listing
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let v: Vec<f64> = fields
+                .iter()
+                .take(5)
+                .filter_map(|n| n.parse().ok())
+                .collect();
+            let uuid = fields.get(5).filter(|u| **u != "-").map(|u| u.to_string());
+            match v[..] {
+                [x, y, width, height, scale] if width > 0.0 && scale > 0.0 => Some(AppKitScreen {
+                    bounds: Bounds {
+                        x,
+                        y,
+                        width,
+                        height,
+                    },
+                    scale,
+                    uuid,
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn appkit_screens() -> Vec<AppKitScreen> {
+    let listing = probe("osascript", ["-l", "JavaScript", "-e", SCREENS_SCRIPT])
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned());
+    let screens = listing.as_deref().map(parse_screens).unwrap_or_default();
+    if screens.is_empty() {
+        write_log("Screen scale factors and UUIDs unavailable (no AppKit screen list)");
+    }
+    screens
+}
+
+/// Fill in `screen` for every capture screen in `devices`. The pixel size
+/// is the point size times the scale, or probed from the screen when AppKit
+/// cannot be asked (which takes a moment per screen).
+pub fn describe_screens(devices: &mut [Device]) {
+    let screens = appkit_screens();
+    for device in devices.iter_mut().filter(|d| d.is_screen()) {
+        let found = device.screen_number().and_then(|n| screens.get(n));
+        let pixels = match found {
+            Some(AppKitScreen {
+                bounds: b, scale, ..
+            }) => Some((
+                (b.width * scale).round() as i32,
+                (b.height * scale).round() as i32,
+            )),
+            None => get_screen_size(Backend::AVFoundation, &format!("{}:none", device.index)),
+        };
+        let info = ScreenInfo {
+            pixels: pixels.map(|(width, height)| Size { width, height }),
+            points: found.map(|screen| screen.bounds),
+            scale_factor: found.map(|screen| screen.scale),
+            uuid: found.and_then(|screen| screen.uuid.clone()),
+        };
+        write_log(&format!(
+            "{}: {} pixels, scale {}, UUID {}",
+            device.name,
+            info.pixels
+                .map_or("?".into(), |p| format!("{}x{}", p.width, p.height)),
+            info.scale_factor.map_or("?".into(), |s| s.to_string()),
+            info.uuid.as_deref().unwrap_or("?")
+        ));
+        device.screen = Some(info);
+    }
+}
+
+/// Index of the capture screen showing the display `uuid` (any case).
+pub fn resolve_display(devices: &[Device], uuid: &str) -> Result<u32, String> {
+    let screens = appkit_screens();
+    if screens.iter().all(|s| s.uuid.is_none()) {
+        return Err("display UUIDs are unavailable (no AppKit screen list)".into());
+    }
+    let uuid_of = |d: &Device| d.screen_number().and_then(|n| screens.get(n)?.uuid.clone());
+    let shown: Vec<&Device> = devices.iter().filter(|d| d.is_screen()).collect();
+    let pinned = shown
+        .iter()
+        .find(|d| uuid_of(d).is_some_and(|u| u.eq_ignore_ascii_case(uuid)));
+    if let Some(device) = pinned {
+        return Ok(device.index);
+    }
+    let connected: Vec<String> = shown
+        .iter()
+        .map(|d| {
+            format!(
+                "[{}] {} = {}",
+                d.index,
+                d.name,
+                uuid_of(d).as_deref().unwrap_or("?")
+            )
+        })
+        .collect();
+    Err(format!(
+        "no capture screen shows display {} (connected: {})",
+        uuid,
+        connected.join(", ")
+    ))
+}
+
+/// JavaScript for Automation printing the mouse position as `x y`, in the
+/// same coordinates as `SCREENS_SCRIPT`'s bounds.
+const CURSOR_SCRIPT: &str = "ObjC.import('AppKit'); \
+    var p = $.NSEvent.mouseLocation; [p.x, p.y].join(' ')";
+
+fn cursor_position() -> Option<(f64, f64)> {
+    let output = probe("osascript", ["-l", "JavaScript", "-e", CURSOR_SCRIPT])
+        .filter(|output| output.status.success())?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let mut v = text.split_whitespace().map(|n| n.parse::<f64>().ok());
+    Some((v.next()??, v.next()??))
+}
+
+/// Index of the capture screen the mouse is on.
+pub fn display_under_cursor(devices: &[Device]) -> Result<u32, String> {
+    let (x, y) = cursor_position().ok_or("cannot read the cursor position")?;
+    let screens = appkit_screens();
+    let contains = |b: &Bounds| x >= b.x && x <= b.x + b.width && y >= b.y && y <= b.y + b.height;
+    let number = screens
+        .iter()
+        .position(|screen| contains(&screen.bounds))
+        .ok_or_else(|| format!("no screen contains the cursor at {},{}", x, y))?;
+    devices
+        .iter()
+        .find(|d| d.is_screen() && d.screen_number() == Some(number))
+        .map(|d| d.index)
+        .ok_or_else(|| {
+            format!(
+                "the cursor is on screen {}, which has no capture device",
+                number
+            )
+        })
+}
+
+/// Strip the `[AVFoundation indev @ 0x...]` context prefix ffmpeg adds. Its
+/// wording and case vary between ffmpeg versions (`[avfoundation @ ...]`,
+/// `[AVFoundation input device @ ...]`); the `@ 0x` address does not, and
+/// tells it from a `[0]` device index.
+fn strip_context(line: &str) -> &str {
+    let line = line.trim();
+    match line.split_once("] ") {
+        Some((context, rest)) if context.starts_with('[') && context.contains(" @ ") => rest.trim(),
+        _ => line,
+    }
+}
+
+pub fn parse_devices(listing: &str) -> Vec<Device> {
+    let mut devices = Vec::new();
+    let mut kind = None;
+
+    for line in listing.lines() {
+        let line = strip_context(line);
+        let header = line.to_lowercase();
+        if header.ends_with("video devices:") {
+            kind = Some(DeviceKind::Video);
+            continue;
+        }
+        if header.ends_with("audio devices:") {
+            kind = Some(DeviceKind::Audio);
+            continue;
+        }
+        let Some(kind) = kind else { continue };
+        let Some(rest) = line.strip_prefix('[') else {
+            continue;
+        };
+        let Some((index, name)) = rest.split_once(']') else {
+            continue;
+        };
+        if let Ok(index) = index.trim().parse() {
+            devices.push(Device {
+                index,
+                name: name.trim().to_string(),
+                kind,
+                connection: None,
+                manufacturer: None,
+                screen: None,
+            });
+        }
+    }
+
+    devices
+}
+
+/// Index of the main display's capture device, "Capture screen 0", or of
+/// the first capture screen listed when there is no screen 0.
+pub fn detect_screen_index(devices: &[Device]) -> Option<u32> {
+    let mut screens = devices.iter().filter(|d| d.is_screen());
+    let first = screens.clone().next()?;
+    Some(
+        screens
+            .find(|d| d.screen_number() == Some(0))
+            .unwrap_or(first)
+            .index,
+    )
+}
+
+/// Index of the `kind` device called `name`: an exact (case-insensitive)
+/// match, else the single device whose name contains it.
+pub fn resolve_device(devices: &[Device], kind: DeviceKind, name: &str) -> Result<u32, String> {
+    let candidates: Vec<&Device> = devices.iter().filter(|d| d.kind == kind).collect();
+    let wanted = name.to_lowercase();
+    if let Some(exact) = candidates.iter().find(|d| d.name.to_lowercase() == wanted) {
+        return Ok(exact.index);
+    }
+
+    let describe = |list: &[&Device]| {
+        list.iter()
+            .map(|d| format!("[{}] {}", d.index, d.name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let partial: Vec<&Device> = candidates
+        .iter()
+        .copied()
+        .filter(|d| d.name.to_lowercase().contains(&wanted))
+        .collect();
+    match partial[..] {
+        [device] => Ok(device.index),
+        [] => Err(format!(
+            "no {} device named '{}' (available: {})",
+            kind.label(),
+            name,
+            describe(&candidates)
+        )),
+        _ => Err(format!(
+            "'{}' matches several {} devices: {}",
+            name,
+            kind.label(),
+            describe(&partial)
+        )),
+    }
+}
+
+/// Raw `-list_devices` output (ffmpeg prints it on stderr).
+pub fn device_listing() -> Option<String> {
+    let output = probe(
+        "ffmpeg",
+        [
+            "-hide_banner",
+            "-f",
+            "avfoundation",
+            "-list_devices",
+            "true",
+            "-i",
+            "",
+        ],
+    )?;
+    Some(String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+/// An X output `xrandr --query` reports as connected and showing a picture.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct X11Output {
+    pub name: String,
+    pub primary: bool,
+    pub width: i32,
+    pub height: i32,
+    /// Offset on the X screen, for `-grab_x`/`-grab_y`
+    pub x: i32,
+    pub y: i32,
+}
+
+/// `WxH+X+Y`, as xrandr writes an output's geometry.
+fn parse_geometry(s: &str) -> Option<(i32, i32, i32, i32)> {
+    let (size, offset) = s.split_once('+')?;
+    let (w, h) = size.split_once('x')?;
+    let (x, y) = offset.split_once('+')?;
+    Some((
+        w.parse().ok()?,
+        h.parse().ok()?,
+        x.parse().ok()?,
+        y.parse().ok()?,
+    ))
+}
+
+/// The X screen's size (`Screen 0: ... current 3840 x 1080, ...`) and its
+/// connected outputs, from `xrandr --query`.
+fn parse_xrandr(listing: &str) -> (Option<(i32, i32)>, Vec<X11Output>) {
+    let current = listing.lines().find_map(|line| {
+        let rest = line.split("current ").nth(1)?;
+        let mut dims = rest.split(',').next()?.split(" x ");
+        Some((
+            dims.next()?.trim().parse().ok()?,
+            dims.next()?.trim().parse().ok()?,
+        ))
+    });
+    let outputs = listing
+        .lines()
+        .filter(|line| !line.starts_with(char::is_whitespace))
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            let name = words.next()?;
+            if words.next()? != "connected" {
+                return None;
+            }
+            let words: Vec<&str> = words.collect();
+            let (width, height, x, y) = words.iter().find_map(|w| parse_geometry(w))?;
+            Some(X11Output {
+                name: name.to_string(),
+                primary: words.first() == Some(&"primary"),
+                width,
+                height,
+                x,
+                y,
+            })
+        })
+        .collect();
+    (current, outputs)
+}
+
+fn xrandr(display: &str) -> Option<String> {
+    let output = probe("xrandr", ["-display", display, "--query"])?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Size of the X screen x11grab captures from `display` (all outputs
+/// side by side).
+pub fn x11_screen_size(display: &str) -> Option<(i32, i32)> {
+    parse_xrandr(&xrandr(display)?).0
+}
+
+/// The connected outputs of `display`.
+pub fn x11_outputs(display: &str) -> Vec<X11Output> {
+    xrandr(display)
+        .map(|listing| parse_xrandr(&listing).1)
+        .unwrap_or_default()
+}
+
+/// What else a Linux machine records from, as each tool lists it: V4L2
+/// cameras and PipeWire nodes. `None` for a tool that is missing or failed.
+pub fn linux_listings() -> Vec<(&'static str, Option<String>)> {
+    [
+        ("v4l2-ctl", &["--list-devices"][..]),
+        ("pw-cli", &["ls", "Node"][..]),
+    ]
+    .into_iter()
+    .map(|(bin, args)| {
+        let listing = probe(bin, args)
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned());
+        (bin, listing)
+    })
+    .collect()
+}
+
+pub fn list_devices() -> Vec<Device> {
+    let mut devices = device_listing()
+        .map(|listing| parse_devices(&listing))
+        .unwrap_or_default();
+    describe_connections(&mut devices);
+    devices
+}
+
+/// Makers whose name leads their devices' names, as avfoundation shows them
+const MAKERS: &[&str] = &[
+    "Apple",
+    "AVerMedia",
+    "Blackmagic",
+    "Blue",
+    "Elgato",
+    "Focusrite",
+    "Insta360",
+    "Jabra",
+    "Logitech",
+    "Microsoft",
+    "Opal",
+    "Razer",
+    "RODE",
+    "Shure",
+    "Sony",
+    "Zoom",
+];
+
+/// Connection and maker guessed from a device name alone.
+fn name_hints(device: &Device) -> (Connection, Option<String>) {
+    let name = device.name.to_lowercase();
+    let has = |words: &[&str]| words.iter().any(|w| name.contains(w));
+    let maker = MAKERS
+        .iter()
+        .find(|m| name.starts_with(&m.to_lowercase()))
+        .map(|m| m.to_string());
+    let connection = if device.is_screen() {
+        Connection::Screen
+    } else if has(&["iphone", "ipad", "continuity"]) {
+        Connection::Continuity
+    } else if has(&["facetime", "macbook", "imac", "built-in", "studio display"]) {
+        Connection::BuiltIn
+    } else if has(&[
+        "blackhole",
+        "soundflower",
+        "loopback",
+        "virtual",
+        "teams",
+        "zoomaudio",
+    ]) {
+        Connection::Virtual
+    } else if has(&["usb", "cam link", "capture card", "webcam", "hd pro"]) {
+        Connection::Usb
+    } else if has(&["airpods", "bluetooth", "beats"]) {
+        Connection::Bluetooth
+    } else {
+        Connection::Other
+    };
+    let maker = match connection {
+        Connection::BuiltIn | Connection::Continuity => Some("Apple".to_string()),
+        _ => maker,
+    };
+    (connection, maker)
+}
+
+/// CoreAudio's transport and maker for each audio device, by name, from
+/// `system_profiler SPAudioDataType -json`.
+fn coreaudio_devices() -> Vec<(String, Option<Connection>, Option<String>)> {
+    let Some(output) = probe("system_profiler", ["SPAudioDataType", "-json"]) else {
+        return Vec::new();
+    };
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap_or_default();
+    let items = report["SPAudioDataType"].as_array().into_iter().flatten();
+    items
+        .flat_map(|group| group["_items"].as_array().cloned().unwrap_or_default())
+        .filter_map(|item| {
+            let name = item["_name"].as_str()?.to_string();
+            let transport = item["coreaudio_device_transport"]
+                .as_str()
+                .unwrap_or_default();
+            let connection = match transport.trim_start_matches("coreaudio_device_type_") {
+                "builtin" => Some(Connection::BuiltIn),
+                "usb" => Some(Connection::Usb),
+                "bluetooth" => Some(Connection::Bluetooth),
+                "hdmi" | "displayport" | "thunderbolt" => Some(Connection::Display),
+                "virtual" | "aggregate" => Some(Connection::Virtual),
+                "" => None,
+                _ => Some(Connection::Other),
+            };
+            let maker = item["coreaudio_device_manufacturer"]
+                .as_str()
+                .map(str::to_string);
+            Some((name, connection, maker))
+        })
+        .collect()
+}
+
+/// Fill in `connection` and `manufacturer`: CoreAudio's word for audio
+/// devices it knows, the name's hints for everything else.
+pub fn describe_connections(devices: &mut [Device]) {
+    let coreaudio = match devices.iter().any(|d| d.kind == DeviceKind::Audio) {
+        true => coreaudio_devices(),
+        false => Vec::new(),
+    };
+    for device in devices.iter_mut() {
+        let (guess, maker) = name_hints(device);
+        let reported = coreaudio
+            .iter()
+            .find(|(name, ..)| device.kind == DeviceKind::Audio && *name == device.name);
+        device.connection = reported.and_then(|(_, c, _)| *c).or(Some(guess));
+        device.manufacturer = reported.and_then(|(.., m)| m.clone()).or(maker);
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct DeviceCache {
+    listed_at: DateTime<Local>,
+    devices: Vec<Device>,
+}
+
+/// `devices.json` in the rec.nvim config directory.
+pub fn cache_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("rec.nvim").join("devices.json"))
+}
+
+fn read_cache() -> Option<Vec<Device>> {
+    let cache: DeviceCache = serde_json::from_slice(&fs::read(cache_path()?).ok()?).ok()?;
+    let age = Local::now() - cache.listed_at;
+    (age.num_seconds() < CACHE_TTL_SECS && !cache.devices.is_empty()).then_some(cache.devices)
+}
+
+fn write_cache(devices: &[Device]) {
+    let Some(path) = cache_path() else { return };
+    let cache = DeviceCache {
+        listed_at: Local::now(),
+        devices: devices.to_vec(),
+    };
+    let written = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, serde_json::to_vec_pretty(&cache)?));
+    if let Err(err) = written {
+        write_log(&format!(
+            "Device cache not written to {}: {}",
+            path.display(),
+            err
+        ));
+    }
+}
+
+/// Enumerate devices with ffmpeg and refresh the cache.
+pub fn list_devices_fresh() -> Vec<Device> {
+    let devices = list_devices();
+    if !devices.is_empty() {
+        write_cache(&devices);
+    }
+    devices
+}
+
+/// The device list for one `start`: the cache when it is recent, re-listed
+/// (at most once) when it is stale or a lookup misses.
+pub struct DeviceLookup {
+    devices: Vec<Device>,
+    fresh: bool,
+}
+
+impl DeviceLookup {
+    pub fn new(refresh: bool) -> Self {
+        if !refresh {
+            if let Some(devices) = read_cache() {
+                write_log("Using cached device list");
+                return DeviceLookup {
+                    devices,
+                    fresh: false,
+                };
+            }
+        }
+        DeviceLookup {
+            devices: list_devices_fresh(),
+            fresh: true,
+        }
+    }
+
+    /// No list, for a backend that has none; nothing is ever listed.
+    pub fn unlisted() -> Self {
+        DeviceLookup {
+            devices: Vec::new(),
+            fresh: true,
+        }
+    }
+
+    pub fn devices(&self) -> &[Device] {
+        &self.devices
+    }
+
+    fn refresh(&mut self) {
+        if !self.fresh {
+            write_log("Device not in the cached list; listing devices again");
+            self.devices = list_devices_fresh();
+            self.fresh = true;
+        }
+    }
+
+    /// Index of the capture screen showing display `uuid` right now.
+    pub fn resolve_display(&mut self, uuid: &str) -> Result<u32, String> {
+        resolve_display(&self.devices, uuid).or_else(|_| {
+            self.refresh();
+            resolve_display(&self.devices, uuid)
+        })
+    }
+
+    /// Index of the capture screen the mouse is on right now.
+    pub fn display_under_cursor(&mut self) -> Result<u32, String> {
+        display_under_cursor(&self.devices).or_else(|_| {
+            self.refresh();
+            display_under_cursor(&self.devices)
+        })
+    }
+
+    pub fn resolve(&mut self, kind: DeviceKind, name: &str) -> Result<u32, String> {
+        resolve_device(&self.devices, kind, name).or_else(|_| {
+            self.refresh();
+            resolve_device(&self.devices, kind, name)
+        })
+    }
+
+    /// Index of the main display's capture screen, from the device list.
+    pub fn screen_index(&mut self) -> Option<u32> {
+        detect_screen_index(&self.devices).or_else(|| {
+            self.refresh();
+            detect_screen_index(&self.devices)
+        })
+    }
+
+    /// Make sure a `kind` device `index` is not just missing from the cache.
+    pub fn ensure_listed(&mut self, kind: DeviceKind, index: u32) {
+        if !self
+            .devices
+            .iter()
+            .any(|d| d.kind == kind && d.index == index)
+        {
+            self.refresh();
+        }
+    }
+}
+
+/// One capture mode a camera offers: a frame size and a frame rate range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mode {
+    pub width: i32,
+    pub height: i32,
+    pub min_fps: f64,
+    pub max_fps: f64,
+}
+
+impl Mode {
+    /// avfoundation's own rounding slack when matching a frame rate.
+    fn allows(&self, fps: f64) -> bool {
+        self.min_fps - 0.01 <= fps && fps <= self.max_fps + 0.01
+    }
+}
+
+/// The modes ffmpeg lists when a camera refuses the requested one:
+///
+/// ```text
+/// [avfoundation @ 0x7f8] Supported modes:
+/// [avfoundation @ 0x7f8]   1280x720@[1.000000 30.000000]fps
+/// ```
+pub fn parse_modes(listing: &str) -> Vec<Mode> {
+    let mut modes = Vec::new();
+    for line in listing.lines() {
+        let line = line.trim();
+        let line = match line.starts_with('[') {
+            true => line.split_once("] ").map_or(line, |(_, rest)| rest.trim()),
+            false => line,
+        };
+        let Some((size, rates)) = line.split_once('@') else {
+            continue;
+        };
+        let Some((w, h)) = size.split_once('x') else {
+            continue;
+        };
+        let rates = rates
+            .trim_start_matches('[')
+            .trim_end_matches("fps")
+            .trim_end_matches(']');
+        let rates: Vec<f64> = rates
+            .split_whitespace()
+            .filter_map(|r| r.parse().ok())
+            .collect();
+        if let (Ok(width), Ok(height), &[min_fps, max_fps]) = (w.parse(), h.parse(), &rates[..]) {
+            modes.push(Mode {
+                width,
+                height,
+                min_fps,
+                max_fps,
+            });
+        }
+    }
+    modes
+}
+
+/// Ask ffmpeg for the modes of video device `index` by requesting one no
+/// camera has. Empty when ffmpeg lists none (screens take any mode).
+pub fn supported_modes(index: u32) -> Vec<Mode> {
+    let input = format!("{}:none", index);
+    let output = probe(
+        "ffmpeg",
+        [
+            "-hide_banner",
+            "-f",
+            "avfoundation",
+            "-video_size",
+            "1x1",
+            "-framerate",
+            "1000",
+            "-i",
+            &input,
+            "-frames:v",
+            "1",
+            "-f",
+            "null",
+            "-",
+        ],
+    );
+    match output {
+        Some(output) => parse_modes(&String::from_utf8_lossy(&output.stderr)),
+        None => Vec::new(),
+    }
+}
+
+/// A supported (size, fps) to suggest instead
+pub type Suggestion = ((i32, i32), f64);
+
+/// Check `size` (or any, when `None`) at `fps` against `modes`. On a
+/// mismatch, returns the closest supported (size, fps) pairs, best first.
+pub fn check_mode(
+    modes: &[Mode],
+    size: Option<(i32, i32)>,
+    fps: f64,
+) -> Result<(), Vec<Suggestion>> {
+    let fits = |m: &Mode| size.is_none_or(|s| s == (m.width, m.height)) && m.allows(fps);
+    if modes.is_empty() || modes.iter().any(fits) {
+        return Ok(());
+    }
+    let mut nearest: Vec<Suggestion> = modes
+        .iter()
+        .map(|m| ((m.width, m.height), fps.clamp(m.min_fps, m.max_fps).round()))
+        .collect();
+    let cost = |((w, h), f): &Suggestion| {
+        let (sw, sh) = size.unwrap_or((*w, *h));
+        // A size change costs more than a frame rate change
+        ((w - sw).abs() + (h - sh).abs()) as f64 * 10.0 + (f - fps).abs()
+    };
+    nearest.sort_by(|a, b| cost(a).total_cmp(&cost(b)));
+    nearest.dedup();
+    nearest.truncate(3);
+    Err(nearest)
+}
+
+/// The frame rate of `modes` at `size` (any, when `None`) closest to `fps`;
+/// `None` when no mode has that size.
+pub fn nearest_rate(modes: &[Mode], size: Option<(i32, i32)>, fps: f64) -> Option<f64> {
+    modes
+        .iter()
+        .filter(|m| size.is_none_or(|s| s == (m.width, m.height)))
+        .map(|m| fps.clamp(m.min_fps, m.max_fps))
+        .min_by(|a, b| (a - fps).abs().total_cmp(&(b - fps).abs()))
+}
+
+/// Length of each `benchmark-devices` clip, in seconds.
+const PROBE_CLIP_SECS: &str = "1";
+
+/// Mean luma below which a clip counts as black (video black is 16).
+const BLACK_LUMA: f64 = 20.0;
+
+/// Prints each frame's mean luma as `lavfi.signalstats.YAVG=...`.
+const LUMA_FILTER: &str = "signalstats,metadata=print:key=lavfi.signalstats.YAVG:file=-";
+
+/// What a short clip from one capture screen showed.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScreenProbe {
+    pub index: u32,
+    pub name: String,
+    /// Frames decoded from the clip
+    pub frames: usize,
+    pub size: Option<Size>,
+    /// Mean luma over the clip: 16 is black, 235 white
+    pub brightness: Option<f64>,
+    /// Why nothing was recorded
+    pub error: Option<String>,
+}
+
+impl ScreenProbe {
+    /// Whether the screen gave frames that are not all black.
+    pub fn has_picture(&self) -> bool {
+        self.frames > 0 && self.brightness.is_some_and(|b| b >= BLACK_LUMA)
+    }
+}
+
+/// Record a short clip from each capture screen in `devices`, best first:
+/// a picture before black frames before nothing, then the larger screen.
+/// The clips are deleted.
+pub fn probe_screens(devices: &[Device]) -> Vec<ScreenProbe> {
+    let mut probes: Vec<ScreenProbe> = devices
+        .iter()
+        .filter(|d| d.is_screen())
+        .map(probe_screen)
+        .collect();
+    let area = |p: &ScreenProbe| p.size.map_or(0, |s| s.width as i64 * s.height as i64);
+    probes.sort_by_key(|p| (!p.has_picture(), p.frames == 0, -area(p), p.index));
+    probes
+}
+
+fn probe_screen(device: &Device) -> ScreenProbe {
+    let clip = env::temp_dir().join(format!("rec.nvim.devprobe.{}.mkv", device.index));
+    let input = format!("{}:none", device.index);
+    let mut result = ScreenProbe {
+        index: device.index,
+        name: device.name.clone(),
+        frames: 0,
+        size: None,
+        brightness: None,
+        error: None,
+    };
+    write_log(&format!(
+        "benchmark-devices: recording {}s from [{}]",
+        PROBE_CLIP_SECS, input
+    ));
+    let recorded = probe(
+        "ffmpeg",
+        [
+            "-v",
+            "error",
+            "-y",
+            "-f",
+            "avfoundation",
+            "-framerate",
+            "30",
+            "-i",
+            &input,
+            "-t",
+            PROBE_CLIP_SECS,
+            "-pix_fmt",
+            "yuv420p",
+        ]
+        .iter()
+        .map(|a| a.to_string())
+        .chain([clip.to_string_lossy().into_owned()]),
+    );
+    match recorded {
+        Some(output) if output.status.success() => {}
+        Some(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let last = stderr
+                .lines()
+                .map(strip_context)
+                .rfind(|l| !l.trim().is_empty());
+            result.error = Some(last.unwrap_or("ffmpeg failed").trim().to_string());
+        }
+        None => result.error = Some("ffmpeg did not finish".into()),
+    }
+    if result.error.is_none() {
+        result.size = probe_output_size(&clip).map(|(width, height)| Size { width, height });
+        let clip_arg = clip.to_string_lossy();
+        let stats = [
+            "-v",
+            "error",
+            "-i",
+            &clip_arg,
+            "-vf",
+            LUMA_FILTER,
+            "-f",
+            "null",
+            "-",
+        ];
+        if let Some(output) = probe("ffmpeg", stats) {
+            let luma: Vec<f64> = String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(|l| {
+                    l.strip_prefix("lavfi.signalstats.YAVG=")?
+                        .trim()
+                        .parse()
+                        .ok()
+                })
+                .collect();
+            result.frames = luma.len();
+            result.brightness =
+                (!luma.is_empty()).then(|| luma.iter().sum::<f64>() / luma.len() as f64);
+        }
+    }
+    remove_temp(&clip);
+    write_log(&format!("benchmark-devices: {:?}", result));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn screen_index_skips_cameras_and_audio_devices() {
+        let listing = "\
+[AVFoundation indev @ 0x7f8] AVFoundation video devices:
+[AVFoundation indev @ 0x7f8] [0] FaceTime HD Camera
+[AVFoundation indev @ 0x7f8] [1] OBS Virtual Camera
+[AVFoundation indev @ 0x7f8] [2] Capture screen 1
+[AVFoundation indev @ 0x7f8] [3] Capture screen 0
+[AVFoundation indev @ 0x7f8] AVFoundation audio devices:
+[AVFoundation indev @ 0x7f8] [0] Capture screen 0 Audio
+[AVFoundation indev @ 0x7f8] [1] MacBook Pro Microphone
+[in#0 @ 0x7f9] Error opening input: Input/output error
+";
+        let devices = parse_devices(listing);
+        assert_eq!(devices.len(), 6);
+        assert_eq!(
+            devices
+                .iter()
+                .filter(|d| d.kind == DeviceKind::Audio)
+                .count(),
+            2
+        );
+        assert_eq!(detect_screen_index(&devices), Some(3));
+    }
+
+    #[test]
+    fn no_screen_device_detects_nothing() {
+        let listing = "\
+[AVFoundation indev @ 0x7f8] AVFoundation video devices:
+[AVFoundation indev @ 0x7f8] [0] FaceTime HD Camera
+[AVFoundation indev @ 0x7f8] AVFoundation audio devices:
+[AVFoundation indev @ 0x7f8] [0] Capture screen 0
+";
+        assert_eq!(detect_screen_index(&parse_devices(listing)), None);
+        assert_eq!(detect_screen_index(&[]), None);
+    }
+}
@@ -0,0 +1,135 @@
+//! Newline-delimited JSON events written to a Unix socket.
+//!
+//! The Neovim plugin listens on the socket; `rec-cli` connects as a client for
+//! each event so a listener that comes and goes never wedges a recording.
+
+use crate::chapters::TouchTrigger;
+use crate::clipboard;
+use crate::follow::Tracker;
+use crate::output::file_size;
+use crate::power::{screen_locked, SleepDetector};
+use crate::process::{pid_alive, pid_paused};
+use crate::{write_log, Recorder, StopOptions};
+use chrono::Local;
+use serde_json::{json, Map, Value};
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Event names understood by the plugin.
+pub const STARTED: &str = "started";
+pub const PAUSED: &str = "paused";
+pub const RESUMED: &str = "resumed";
+pub const PROGRESS: &str = "progress";
+pub const STOPPED: &str = "stopped";
+pub const ERROR: &str = "error";
+
+/// Seconds between screen-lock checks under `--stop-on-sleep`.
+const LOCK_POLL_TICKS: u64 = 5;
+
+/// Build the JSON line for `event`, merging `fields` into the top-level object.
+pub fn encode(event: &str, fields: Value) -> String {
+    let mut obj = Map::new();
+    obj.insert("event".into(), json!(event));
+    obj.insert("time".into(), json!(Local::now().to_rfc3339()));
+    if let Value::Object(extra) = fields {
+        obj.extend(extra);
+    }
+    Value::Object(obj).to_string()
+}
+
+/// Send one event. Delivery is best effort: a missing listener is not an error
+/// worth failing a recording over, so callers may ignore the result.
+pub fn emit(socket: &Path, event: &str, fields: Value) -> std::io::Result<()> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_write_timeout(Some(Duration::from_millis(500)))?;
+    writeln!(stream, "{}", encode(event, fields))
+}
+
+/// [`emit`] to an optional socket, logging (not returning) delivery failures.
+pub fn publish(socket: Option<&Path>, event: &str, fields: Value) {
+    if let Some(socket) = socket {
+        if let Err(err) = emit(socket, event, fields) {
+            write_log(&format!(
+                "Event '{}' not delivered to {}: {}",
+                event,
+                socket.display(),
+                err
+            ));
+        }
+    }
+}
+
+/// Report pause/resume transitions and once-a-second progress for `pid`
+/// until it exits. `stop` sends the final `stopped` event itself.
+///
+/// With `stop_on_sleep`, also stop the recording when the screen locks or
+/// the system wakes from sleep, so a closed lid leaves a finalized file.
+pub fn supervise(
+    pid: i32,
+    socket: Option<&Path>,
+    output: &Path,
+    stop_on_sleep: bool,
+    follow_window: Option<&str>,
+    mark_on_touch: Option<&Path>,
+    track_clipboard: bool,
+) {
+    let started = Instant::now();
+    let mut follow = follow_window.map(Tracker::new);
+    let mut trigger = mark_on_touch.map(TouchTrigger::new);
+    let mut clipboard = track_clipboard.then(clipboard::Tracker::new);
+    let mut paused = false;
+    let mut sleep = SleepDetector::new(Duration::from_secs(1));
+    let mut ticks: u64 = 0;
+
+    while pid_alive(pid) {
+        if stop_on_sleep {
+            // ioreg is not free, so the lock check is throttled
+            let trigger = match sleep.poll() {
+                Some(lost) => Some(format!("system slept for {}s", lost.as_secs())),
+                None if ticks.is_multiple_of(LOCK_POLL_TICKS) && screen_locked() => {
+                    Some("screen locked".to_string())
+                }
+                None => None,
+            };
+            if let Some(trigger) = trigger {
+                write_log(&format!("--stop-on-sleep: {}; stopping", trigger));
+                if let Err(err) = Recorder::new().stop(&StopOptions::default()) {
+                    write_log(&format!("--stop-on-sleep: stop failed: {}", err));
+                }
+                return;
+            }
+        }
+
+        let now_paused = pid_paused(pid);
+        if now_paused != paused {
+            paused = now_paused;
+            let event = if paused { PAUSED } else { RESUMED };
+            publish(socket, event, json!({ "pid": pid }));
+        }
+        if let (Some(tracker), false) = (&mut follow, paused) {
+            tracker.poll();
+        }
+        if let Some(trigger) = &mut trigger {
+            trigger.poll();
+        }
+        if let Some(tracker) = &mut clipboard {
+            tracker.poll();
+        }
+        if !paused {
+            publish(
+                socket,
+                PROGRESS,
+                json!({
+                    "pid": pid,
+                    "elapsed": started.elapsed().as_secs(),
+                    "size": file_size(output),
+                }),
+            );
+        }
+        ticks += 1;
+        thread::sleep(Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,2372 @@
+//! ffmpeg/ffprobe invocation: probing, crop math and argument construction.
+
+use crate::compress;
+use crate::devices;
+use crate::process::run_command;
+use crate::recorder::StartOptions;
+use crate::segments;
+use crate::write_log;
+use clap::ValueEnum;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::time::Duration;
+
+/*
+  IMPORTANT (macOS avfoundation):
+  From your device list:
+    [4] Capture screen 0
+  Only a fallback: `start` records the "Capture screen 0" it finds in the
+  device list, unless `--screen-index` picks another.
+*/
+pub const SCREEN_INDEX: u32 = 4;
+
+/// Default capture frame rate requested from avfoundation (`--framerate`).
+pub const FRAMERATE: u32 = 30;
+
+/// libx264 quality of the capture encode (lower is better and bigger).
+pub const CRF: u32 = 23;
+
+/// libvpx-vp9 quality of `--format webm` without `--crf`.
+pub const VP9_CRF: u32 = 33;
+
+/// libx264 preset of the capture encode: the cheapest, so frames keep up.
+pub const PRESET: &str = "ultrafast";
+
+/// Presets accepted by libx264's `-preset`, fastest first.
+pub const X264_PRESETS: &[&str] = &[
+    "ultrafast",
+    "superfast",
+    "veryfast",
+    "faster",
+    "fast",
+    "medium",
+    "slow",
+    "slower",
+    "veryslow",
+    "placebo",
+];
+
+/// The hardware H.264 encoder of Macs; no CRF, only a target bitrate.
+pub const VIDEOTOOLBOX: &str = "h264_videotoolbox";
+
+/// Video encoders `--codec` accepts.
+pub const CODECS: &[&str] = &["libx264", VIDEOTOOLBOX];
+
+/// h264_videotoolbox's target bitrate without `--bitrate`, in kbit/s.
+pub const BITRATE_KBPS: u32 = 5000;
+
+/// H.264 profiles accepted by libx264's `-profile:v`.
+pub const H264_PROFILES: &[&str] = &["baseline", "main", "high", "high10", "high422", "high444"];
+
+/// H.264 levels accepted by libx264's `-level`.
+pub const H264_LEVELS: &[&str] = &[
+    "1", "1b", "1.1", "1.2", "1.3", "2", "2.1", "2.2", "3", "3.1", "3.2", "4", "4.1", "4.2", "5",
+    "5.1", "5.2", "6", "6.1", "6.2",
+];
+
+/// (level, max frame size in macroblocks, max macroblocks per second), from
+/// Table A-1 of the H.264 spec, in `H264_LEVELS` order.
+const H264_LEVEL_LIMITS: &[(&str, u32, u32)] = &[
+    ("1", 99, 1485),
+    ("1b", 99, 1485),
+    ("1.1", 396, 3000),
+    ("1.2", 396, 6000),
+    ("1.3", 396, 11880),
+    ("2", 396, 11880),
+    ("2.1", 792, 19800),
+    ("2.2", 1620, 20250),
+    ("3", 1620, 40500),
+    ("3.1", 3600, 108000),
+    ("3.2", 5120, 216000),
+    ("4", 8192, 245760),
+    ("4.1", 8192, 245760),
+    ("4.2", 8704, 522240),
+    ("5", 22080, 589824),
+    ("5.1", 36864, 983040),
+    ("5.2", 36864, 2073600),
+    ("6", 139264, 4177920),
+    ("6.1", 139264, 8355840),
+    ("6.2", 139264, 16711680),
+];
+
+/// The lowest H.264 level, `level` or above, whose limits allow a `w`x`h`
+/// frame at `fps`; `None` when even the highest does not.
+pub fn h264_level_for(level: &str, (w, h): (i32, i32), fps: u32) -> Option<&'static str> {
+    let mbs = |v: i32| (v.max(1) as u32).div_ceil(16);
+    let (mb_w, mb_h) = (mbs(w), mbs(h));
+    let frame = mb_w * mb_h;
+    let start = H264_LEVEL_LIMITS.iter().position(|(l, _, _)| *l == level)?;
+    H264_LEVEL_LIMITS[start..]
+        .iter()
+        .find(|(_, max_fs, max_mbps)| {
+            // Neither side may exceed sqrt(8 * MaxFS) macroblocks
+            let side = ((8 * max_fs) as f64).sqrt() as u32;
+            frame <= *max_fs && frame * fps <= *max_mbps && mb_w <= side && mb_h <= side
+        })
+        .map(|(l, _, _)| *l)
+}
+
+/// Values accepted by `-color_primaries`.
+pub const COLOR_PRIMARIES: &[&str] = &[
+    "bt709",
+    "bt470m",
+    "bt470bg",
+    "smpte170m",
+    "smpte240m",
+    "film",
+    "bt2020",
+    "smpte428",
+    "smpte431",
+    "smpte432",
+    "jedec-p22",
+];
+
+/// Values accepted by `-color_trc`.
+pub const COLOR_TRCS: &[&str] = &[
+    "bt709",
+    "gamma22",
+    "gamma28",
+    "smpte170m",
+    "smpte240m",
+    "linear",
+    "log100",
+    "log316",
+    "iec61966-2-4",
+    "bt1361e",
+    "iec61966-2-1",
+    "bt2020-10",
+    "bt2020-12",
+    "smpte2084",
+    "smpte428",
+    "arib-std-b67",
+];
+
+/// Values accepted by `-colorspace`.
+pub const COLORSPACES: &[&str] = &[
+    "rgb",
+    "bt709",
+    "fcc",
+    "bt470bg",
+    "smpte170m",
+    "smpte240m",
+    "ycgco",
+    "bt2020nc",
+    "bt2020c",
+    "smpte2085",
+    "chroma-derived-nc",
+    "chroma-derived-c",
+    "ictcp",
+];
+
+/// Shorthand for a full set of color tags; explicit `--color-*` flags win.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorPreset {
+    Rec709,
+}
+
+impl ColorPreset {
+    /// (primaries, transfer, matrix)
+    fn tags(self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            ColorPreset::Rec709 => ("bt709", "bt709", "bt709"),
+        }
+    }
+}
+
+/// Container for `--format`.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Mp4,
+    Mkv,
+    Mpegts,
+    /// Recorded lossless, made a 15 fps, 960 px wide GIF in two passes at
+    /// `stop`
+    Gif,
+    /// VP9 and Opus, for browsers and chat apps
+    Webm,
+    /// AAC audio, for --audio-only
+    M4a,
+    /// Uncompressed audio, for --audio-only
+    Wav,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Mp4 => "mp4",
+            OutputFormat::Mkv => "mkv",
+            OutputFormat::Mpegts => "ts",
+            OutputFormat::Gif => "gif",
+            OutputFormat::Webm => "webm",
+            OutputFormat::M4a => "m4a",
+            OutputFormat::Wav => "wav",
+        }
+    }
+
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        OutputFormat::value_variants()
+            .iter()
+            .copied()
+            .find(|f| f.extension().eq_ignore_ascii_case(ext))
+    }
+
+    /// ffmpeg muxer name, for `-f`.
+    pub fn muxer(self) -> &'static str {
+        match self {
+            OutputFormat::Mp4 => "mp4",
+            OutputFormat::Mkv => "matroska",
+            OutputFormat::Mpegts => "mpegts",
+            OutputFormat::Gif => "gif",
+            OutputFormat::Webm => "webm",
+            OutputFormat::M4a => "ipod",
+            OutputFormat::Wav => "wav",
+        }
+    }
+
+    /// Whether the container holds a video track.
+    pub fn has_video(self) -> bool {
+        !matches!(self, OutputFormat::M4a | OutputFormat::Wav)
+    }
+
+    /// Whether the muxer can write to a pipe. mp4 seeks back to write its
+    /// index (and to move it to the front for +faststart), and so does m4a.
+    pub fn is_streamable(self) -> bool {
+        !matches!(self, OutputFormat::Mp4 | OutputFormat::M4a)
+    }
+}
+
+/// What a container gets unless flags say otherwise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FfmpegDefaults {
+    /// `-movflags +faststart`
+    pub faststart: bool,
+    /// An audio track (the device, or silence)
+    pub audio: bool,
+    /// The QuickTime-safe libx264 encode
+    pub h264: bool,
+    /// palettegen/paletteuse, for formats limited to 256 colors
+    pub palette: bool,
+    /// Constant-quality VP9 with Opus audio
+    pub vp9: bool,
+}
+
+pub fn format_defaults(format: OutputFormat) -> FfmpegDefaults {
+    let h264 = FfmpegDefaults {
+        faststart: false,
+        audio: true,
+        h264: true,
+        palette: false,
+        vp9: false,
+    };
+    match format {
+        OutputFormat::Mp4 => FfmpegDefaults {
+            faststart: true,
+            ..h264
+        },
+        OutputFormat::Mkv | OutputFormat::Mpegts => h264,
+        OutputFormat::Gif => FfmpegDefaults {
+            audio: false,
+            h264: false,
+            palette: true,
+            ..h264
+        },
+        OutputFormat::Webm => FfmpegDefaults {
+            h264: false,
+            vp9: true,
+            ..h264
+        },
+        OutputFormat::M4a => FfmpegDefaults {
+            faststart: true,
+            h264: false,
+            ..h264
+        },
+        OutputFormat::Wav => FfmpegDefaults {
+            h264: false,
+            ..h264
+        },
+    }
+}
+
+/// Whether `--scale` resizes the cropped region or the whole screen.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum FilterOrder {
+    /// Crop in screen pixels, then scale the region
+    #[default]
+    CropFirst,
+    /// Scale the screen, then crop in scaled pixels
+    ScaleFirst,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    #[value(name = "90")]
+    Cw90,
+    #[value(name = "180")]
+    Cw180,
+    #[value(name = "270")]
+    Cw270,
+}
+
+impl Rotation {
+    /// Whether the rotation swaps width and height.
+    pub fn is_quarter_turn(self) -> bool {
+        matches!(self, Rotation::Cw90 | Rotation::Cw270)
+    }
+
+    fn filter(self) -> &'static str {
+        match self {
+            Rotation::Cw90 => "transpose=clock",
+            Rotation::Cw180 => "hflip,vflip",
+            Rotation::Cw270 => "transpose=cclock",
+        }
+    }
+}
+
+/// Image formats `--watermark` takes (by extension).
+pub const WATERMARK_FORMATS: &[&str] = &["png", "jpg", "jpeg", "bmp", "gif", "webp", "tif", "tiff"];
+
+/// Pixels between a `--watermark` and the frame edges it sits against.
+const WATERMARK_MARGIN: i32 = 10;
+
+/// Where `--watermark` goes in the frame.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum WatermarkPos {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+    Center,
+}
+
+impl WatermarkPos {
+    /// overlay's `x:y`, in terms of the frame (`W`/`H`) and image (`w`/`h`)
+    fn overlay_xy(self) -> String {
+        let m = WATERMARK_MARGIN;
+        match self {
+            WatermarkPos::TopLeft => format!("{m}:{m}"),
+            WatermarkPos::TopRight => format!("W-w-{m}:{m}"),
+            WatermarkPos::BottomLeft => format!("{m}:H-h-{m}"),
+            WatermarkPos::BottomRight => format!("W-w-{m}:H-h-{m}"),
+            WatermarkPos::Center => "(W-w)/2:(H-h)/2".into(),
+        }
+    }
+}
+
+/// Accept an existing image in one of `WATERMARK_FORMATS`, made absolute so
+/// ffmpeg finds it whatever its working directory.
+pub fn parse_watermark(s: &str) -> Result<PathBuf, String> {
+    let path = Path::new(s);
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase());
+    if !ext
+        .as_deref()
+        .is_some_and(|e| WATERMARK_FORMATS.contains(&e))
+    {
+        return Err(format!(
+            "'{}' is not a supported image (expected {})",
+            s,
+            WATERMARK_FORMATS.join(", ")
+        ));
+    }
+    if !path.is_file() {
+        return Err(format!("{}: no such image", s));
+    }
+    let cwd = std::env::current_dir().map_err(|e| format!("cannot resolve {}: {}", s, e))?;
+    Ok(cwd.join(path))
+}
+
+/// Parse an opacity between 0 (invisible, not allowed) and 1 (opaque).
+pub fn parse_opacity(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(o) if o > 0.0 && o <= 1.0 => Ok(o),
+        _ => Err(format!(
+            "expected an opacity above 0 and at most 1, got '{}'",
+            s
+        )),
+    }
+}
+
+/// Parse a `WxH` size such as `1280x720`.
+pub fn parse_video_size(s: &str) -> Result<(i32, i32), String> {
+    let (w, h) = s
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("expected WxH (e.g. 1280x720), got '{}'", s))?;
+    let parse = |v: &str| v.trim().parse::<i32>().ok().filter(|v| *v > 0);
+    match (parse(w), parse(h)) {
+        (Some(w), Some(h)) => Ok((w, h)),
+        _ => Err(format!(
+            "invalid size '{}': width and height must be positive",
+            s
+        )),
+    }
+}
+
+/// Parse a `--scale` target `WxH`; either side may be `-1` to keep the
+/// aspect ratio. Sides must be even, as yuv420p requires.
+pub fn parse_scale(s: &str) -> Result<(i32, i32), String> {
+    let (w, h) = s
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("expected WxH (e.g. 1280x720 or 1280x-1), got '{}'", s))?;
+    let parse = |v: &str| v.trim().parse::<i32>().ok().filter(|v| *v > 0 || *v == -1);
+    match (parse(w), parse(h)) {
+        (Some(-1), Some(-1)) => Err("only one side of --scale can be -1".into()),
+        (Some(w), Some(h)) if w % 2 == 1 || h % 2 == 1 => {
+            Err(format!("--scale {}: width and height must be even", s))
+        }
+        (Some(w), Some(h)) => Ok((w, h)),
+        _ => Err(format!(
+            "invalid scale '{}': sides must be positive or -1",
+            s
+        )),
+    }
+}
+
+/// The frame `--scale (sw, sh)` turns a `w`x`h` frame into, resolving `-1`
+/// the way the emitted `-2` does (aspect kept, rounded to even).
+pub fn scaled_size((w, h): (i32, i32), (sw, sh): (i32, i32)) -> (i32, i32) {
+    let even = |v: f64| ((v / 2.0).round() * 2.0) as i32;
+    match (sw, sh) {
+        (-1, sh) => (even(w as f64 * sh as f64 / h as f64), sh),
+        (sw, -1) => (sw, even(h as f64 * sw as f64 / w as f64)),
+        size => size,
+    }
+}
+
+/// Parse a positive number of seconds (`2`, `0.5`).
+pub fn parse_seconds(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(secs) if secs.is_finite() && secs > 0.0 => Ok(secs),
+        _ => Err(format!(
+            "expected a positive number of seconds, got '{}'",
+            s
+        )),
+    }
+}
+
+/// Parse a frame rate: a positive number up to 240, fractions allowed
+/// (`29.97`).
+pub fn parse_fps(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(fps) if fps.is_finite() && fps > 0.0 && fps <= 240.0 => Ok(fps),
+        _ => Err(format!(
+            "expected a frame rate between 0 and 240, got '{}'",
+            s
+        )),
+    }
+}
+
+/// Parse a `W:H` aspect ratio such as `16:9`.
+pub fn parse_aspect(s: &str) -> Result<(u32, u32), String> {
+    let (w, h) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected W:H (e.g. 16:9), got '{}'", s))?;
+    let parse = |v: &str| v.trim().parse::<u32>().ok().filter(|v| *v > 0);
+    match (parse(w), parse(h)) {
+        (Some(w), Some(h)) => Ok((w, h)),
+        _ => Err(format!(
+            "invalid aspect '{}': both terms must be positive",
+            s
+        )),
+    }
+}
+
+/// Accept an ffmpeg color (`black`, `0x202020`, `#202020@0.5`) without
+/// characters that would break out of the filtergraph.
+pub fn parse_pad_color(s: &str) -> Result<String, String> {
+    if !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "#@._".contains(c))
+    {
+        Ok(s.to_string())
+    } else {
+        Err(format!("invalid color '{}'", s))
+    }
+}
+
+/// Smallest even-sized frame of aspect `num:den` that contains `w`x`h`.
+pub fn padded_size(w: i32, h: i32, (num, den): (u32, u32)) -> (i32, i32) {
+    let (num, den) = (num as f64, den as f64);
+    let even = |v: f64| ((v / 2.0).round() * 2.0) as i32;
+    (
+        even((w as f64).max(h as f64 * num / den)),
+        even((h as f64).max(w as f64 * den / num)),
+    )
+}
+
+/// Parse a `width,height` pair. Dimensions may be fractional (`1920.0`);
+/// they are rounded to whole pixels for the crop math.
+pub fn parse_screen_size_f64(line: &str) -> Option<(f64, f64)> {
+    let mut parts = line.trim().split(',');
+    let w = parts.next()?.trim().parse::<f64>().ok()?;
+    let h = parts.next()?.trim().parse::<f64>().ok()?;
+    if w.is_finite() && h.is_finite() && w > 0.0 && h > 0.0 {
+        Some((w, h))
+    } else {
+        None
+    }
+}
+
+pub fn parse_screen_size(line: &str) -> Option<(i32, i32)> {
+    let (w, h) = parse_screen_size_f64(line)?;
+    let (w, h) = (w.round() as i32, h.round() as i32);
+    if w > 0 && h > 0 {
+        Some((w, h))
+    } else {
+        None
+    }
+}
+
+/// How long a probe may run before it is killed (a busy device can make
+/// ffprobe or ffmpeg wait forever).
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Run a probe under `PROBE_TIMEOUT`, logging why when it could not finish.
+/// The output is returned whatever the exit status.
+pub fn probe<I, S>(bin: &str, args: I) -> Option<Output>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    match run_command(bin, args, PROBE_TIMEOUT) {
+        Ok(output) => Some(output),
+        Err(err) => {
+            write_log(&format!("{} probe failed: {}", bin, err));
+            None
+        }
+    }
+}
+
+/// Run ffprobe and return the first `width,height` line it prints.
+fn probe_size(args: &[&str]) -> Option<(i32, i32)> {
+    let output = probe("ffprobe", args)?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    first_screen_size(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The first `width,height` line of a probe's output; one stream per screen
+/// is listed, the one asked for first.
+pub fn first_screen_size(output: &str) -> Option<(i32, i32)> {
+    output.lines().find_map(parse_screen_size)
+}
+
+/// Size of the frames `backend` grabs from `input`. For x11grab that is
+/// the whole X screen, which xrandr knows without grabbing a frame.
+pub fn get_screen_size(backend: Backend, input: &str) -> Option<(i32, i32)> {
+    let capture = CaptureBackend::new(backend, input);
+    match capture {
+        CaptureBackend::X11Grab { .. } => {
+            if let Some(size) = devices::x11_screen_size(input) {
+                return Some(size);
+            }
+        }
+        // Probing the portal's pick would ask the user for a screen a second time
+        CaptureBackend::PipeWire { node_id: None } => return None,
+        _ => {}
+    }
+    let mut args: Vec<String> = vec!["-v".into(), "error".into()];
+    args.extend(capture.input_args(None));
+    args.extend(["-show_entries", "stream=width,height", "-of", "csv=p=0"].map(String::from));
+    probe_size(&args.iter().map(String::as_str).collect::<Vec<_>>())
+}
+
+/// Video dimensions of a finished recording.
+pub fn probe_output_size(path: &Path) -> Option<(i32, i32)> {
+    probe_size(&[
+        "-v",
+        "error",
+        "-select_streams",
+        "v:0",
+        "-show_entries",
+        "stream=width,height",
+        "-of",
+        "csv=p=0",
+        &path.to_string_lossy(),
+    ])
+}
+
+/// Sample rate and channel count of a recording's first audio stream.
+pub fn probe_audio(path: &Path) -> Option<(u32, u32)> {
+    let output = probe(
+        "ffprobe",
+        [
+            "-v",
+            "error",
+            "-select_streams",
+            "a:0",
+            "-show_entries",
+            "stream=sample_rate,channels",
+            "-of",
+            "csv=p=0",
+            &path.to_string_lossy(),
+        ],
+    )?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (rate, channels) = stdout.lines().next()?.split_once(',')?;
+    Some((rate.trim().parse().ok()?, channels.trim().parse().ok()?))
+}
+
+/// Average frame rate of a finished recording's video stream.
+pub fn probe_framerate(path: &Path) -> Option<f64> {
+    let output = probe(
+        "ffprobe",
+        [
+            "-v",
+            "error",
+            "-select_streams",
+            "v:0",
+            "-show_entries",
+            "stream=avg_frame_rate",
+            "-of",
+            "csv=p=0",
+            &path.to_string_lossy(),
+        ],
+    )?;
+    if !output.status.success() {
+        return None;
+    }
+    // A fraction, e.g. 30000/1001; 0/0 when ffprobe could not tell
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (num, den) = stdout.lines().next()?.trim().split_once('/')?;
+    let (num, den): (f64, f64) = (num.parse().ok()?, den.parse().ok()?);
+    (den > 0.0 && num > 0.0).then(|| num / den)
+}
+
+/// Container duration of a finished recording, in seconds.
+pub fn probe_duration(path: &Path) -> Option<f64> {
+    match try_probe_duration(path) {
+        Ok(duration) => duration,
+        Err(err) => {
+            write_log(&format!("ffprobe probe failed: {}", err));
+            None
+        }
+    }
+}
+
+/// `probe_duration`, telling an ffprobe that could not run or finish
+/// (`Err`) from a file it found no duration in (`Ok(None)`).
+pub fn try_probe_duration(path: &Path) -> io::Result<Option<f64>> {
+    let output = run_command(
+        "ffprobe",
+        [
+            "-v",
+            "error",
+            "-show_entries",
+            "format=duration",
+            "-of",
+            "csv=p=0",
+            &path.to_string_lossy(),
+        ],
+        PROBE_TIMEOUT,
+    )?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().parse().ok())
+}
+
+/// Parse a crop rectangle given as `x y w h` (spaces or commas) or as JSON
+/// (`{"x":0,"y":0,"width":800,"height":600}`, `w`/`h` also accepted).
+pub fn parse_crop_spec(line: &str) -> Result<(i32, i32, i32, i32), String> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Err("no crop rectangle given".into());
+    }
+
+    if line.starts_with('{') {
+        let value: serde_json::Value =
+            serde_json::from_str(line).map_err(|e| format!("invalid JSON crop: {}", e))?;
+        let field = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|n| value.get(*n))
+                .and_then(|v| v.as_f64())
+                .map(|v| v.round() as i32)
+                .ok_or_else(|| format!("JSON crop is missing '{}'", names[0]))
+        };
+        return Ok((
+            field(&["x"])?,
+            field(&["y"])?,
+            field(&["width", "w"])?,
+            field(&["height", "h"])?,
+        ));
+    }
+
+    let values: Vec<i32> = line
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse::<f64>().map(|v| v.round() as i32))
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("expected 'x y w h', got '{}'", line))?;
+    match values[..] {
+        [x, y, w, h] => Ok((x, y, w, h)),
+        _ => Err(format!("expected 'x y w h', got '{}'", line)),
+    }
+}
+
+/// `--region-grid`: one cell of the screen cut into `rows` x `cols`, cells
+/// numbered from 1 left to right, then top to bottom.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegionGrid {
+    pub rows: i32,
+    pub cols: i32,
+    pub cell: i32,
+}
+
+/// Names for the usual `--region-grid` cells.
+pub const NAMED_REGIONS: &[(&str, RegionGrid)] = &[
+    (
+        "left-half",
+        RegionGrid {
+            rows: 1,
+            cols: 2,
+            cell: 1,
+        },
+    ),
+    (
+        "right-half",
+        RegionGrid {
+            rows: 1,
+            cols: 2,
+            cell: 2,
+        },
+    ),
+    (
+        "top-half",
+        RegionGrid {
+            rows: 2,
+            cols: 1,
+            cell: 1,
+        },
+    ),
+    (
+        "bottom-half",
+        RegionGrid {
+            rows: 2,
+            cols: 1,
+            cell: 2,
+        },
+    ),
+    (
+        "top-left",
+        RegionGrid {
+            rows: 2,
+            cols: 2,
+            cell: 1,
+        },
+    ),
+    (
+        "top-right",
+        RegionGrid {
+            rows: 2,
+            cols: 2,
+            cell: 2,
+        },
+    ),
+    (
+        "bottom-left",
+        RegionGrid {
+            rows: 2,
+            cols: 2,
+            cell: 3,
+        },
+    ),
+    (
+        "bottom-right",
+        RegionGrid {
+            rows: 2,
+            cols: 2,
+            cell: 4,
+        },
+    ),
+    (
+        "left-third",
+        RegionGrid {
+            rows: 1,
+            cols: 3,
+            cell: 1,
+        },
+    ),
+    (
+        "center-third",
+        RegionGrid {
+            rows: 1,
+            cols: 3,
+            cell: 2,
+        },
+    ),
+    (
+        "right-third",
+        RegionGrid {
+            rows: 1,
+            cols: 3,
+            cell: 3,
+        },
+    ),
+    (
+        "top-third",
+        RegionGrid {
+            rows: 3,
+            cols: 1,
+            cell: 1,
+        },
+    ),
+    (
+        "middle-third",
+        RegionGrid {
+            rows: 3,
+            cols: 1,
+            cell: 2,
+        },
+    ),
+    (
+        "bottom-third",
+        RegionGrid {
+            rows: 3,
+            cols: 1,
+            cell: 3,
+        },
+    ),
+];
+
+impl RegionGrid {
+    /// The cell as a crop rectangle on a `screen_width` x `screen_height`
+    /// frame. Inner edges are rounded down to even pixels for yuv420p.
+    pub fn rect(self, screen_width: i32, screen_height: i32) -> (i32, i32, i32, i32) {
+        let edge = |size: i32, parts: i32, i: i32| match i {
+            i if i >= parts => size,
+            i => (size as i64 * i as i64 / parts as i64) as i32 & !1,
+        };
+        let (row, col) = ((self.cell - 1) / self.cols, (self.cell - 1) % self.cols);
+        let x = edge(screen_width, self.cols, col);
+        let y = edge(screen_height, self.rows, row);
+        let w = edge(screen_width, self.cols, col + 1) - x;
+        let h = edge(screen_height, self.rows, row + 1) - y;
+        (x, y, w, h)
+    }
+}
+
+impl std::fmt::Display for RegionGrid {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}x{}:{}", self.rows, self.cols, self.cell)
+    }
+}
+
+/// Parse a `--region-grid` as `ROWSxCOLS:CELL` (`2x2:1` is the top-left
+/// quarter) or one of `NAMED_REGIONS`.
+pub fn parse_region_grid(s: &str) -> Result<RegionGrid, String> {
+    let s = s.trim();
+    if let Some((_, grid)) = NAMED_REGIONS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(s))
+    {
+        return Ok(*grid);
+    }
+    let usage = || {
+        let names: Vec<&str> = NAMED_REGIONS.iter().map(|(name, _)| *name).collect();
+        format!(
+            "expected ROWSxCOLS:CELL (e.g. 2x2:1) or one of {}, got '{}'",
+            names.join(", "),
+            s
+        )
+    };
+    let (grid, cell) = s.split_once(':').ok_or_else(usage)?;
+    let (rows, cols) = grid.split_once(['x', 'X']).ok_or_else(usage)?;
+    let parse = |v: &str| v.trim().parse::<i32>().ok().filter(|v| *v > 0);
+    let (Some(rows), Some(cols), Some(cell)) = (parse(rows), parse(cols), parse(cell)) else {
+        return Err(usage());
+    };
+    match rows.checked_mul(cols) {
+        Some(cells) if cell <= cells => {}
+        cells => {
+            let cells = cells.map_or("?".into(), |n| n.to_string());
+            return Err(format!(
+                "a {}x{} grid has cells 1 to {}, not {}",
+                rows, cols, cells, cell
+            ));
+        }
+    }
+    Ok(RegionGrid { rows, cols, cell })
+}
+
+pub fn clamp_crop(
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    screen_width: i32,
+    screen_height: i32,
+    min_size: i32,
+) -> Option<(i32, i32, i32, i32)> {
+    if screen_width <= 0 || screen_height <= 0 {
+        return None;
+    }
+
+    let cx = x.max(0);
+    let cy = y.max(0);
+    let mut cw = width.max(1);
+    let mut ch = height.max(1);
+
+    if cx >= screen_width || cy >= screen_height {
+        return None;
+    }
+
+    cw = cw.min(screen_width - cx);
+    ch = ch.min(screen_height - cy);
+
+    if cw <= 0 || ch <= 0 {
+        return None;
+    }
+
+    if cw < min_size || ch < min_size {
+        write_log(&format!(
+            "Crop {}x{} at {},{} is below the {}x{} minimum.",
+            cw, ch, cx, cy, min_size, min_size
+        ));
+        return None;
+    }
+
+    Some((cx, cy, cw, ch))
+}
+
+/// ffmpeg screen-grabbing input devices.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// macOS; has no input-side crop
+    #[value(name = "avfoundation")]
+    AVFoundation,
+    /// X11 (a `:0.0+X,Y` origin)
+    #[value(name = "x11grab")]
+    X11Grab,
+    /// Windows GDI (`-offset_x`/`-offset_y`)
+    #[value(name = "gdigrab")]
+    GdiGrab,
+    /// Wayland, through lavfi's pipewiregrab source; has no input-side crop
+    #[value(name = "pipewire")]
+    PipeWire,
+}
+
+/// The lavfi source a PipeWire capture is read from.
+pub const PIPEWIRE_SOURCE: &str = "pipewiregrab";
+
+impl Backend {
+    /// The backend of the OS `rec-cli` was built for.
+    pub fn native() -> Self {
+        if cfg!(target_os = "macos") {
+            Backend::AVFoundation
+        } else if cfg!(windows) {
+            Backend::GdiGrab
+        } else {
+            Backend::X11Grab
+        }
+    }
+
+    /// ffmpeg input format, for `-f`.
+    pub fn format(self) -> &'static str {
+        match self {
+            Backend::AVFoundation => "avfoundation",
+            Backend::X11Grab => "x11grab",
+            Backend::GdiGrab => "gdigrab",
+            Backend::PipeWire => "lavfi",
+        }
+    }
+
+    /// `-i` of a screen: avfoundation's `<screen>:none`, the X display
+    /// (`$DISPLAY`, else :0.0), the whole Windows desktop, or the PipeWire
+    /// stream the desktop portal asks for. Only avfoundation numbers its
+    /// screens.
+    pub fn input(self, screen: u32) -> String {
+        match self {
+            Backend::AVFoundation => format!("{}:none", screen),
+            Backend::X11Grab => std::env::var("DISPLAY")
+                .ok()
+                .filter(|display| !display.is_empty())
+                .unwrap_or(":0.0".into()),
+            Backend::GdiGrab => "desktop".into(),
+            Backend::PipeWire => PIPEWIRE_SOURCE.into(),
+        }
+    }
+}
+
+/// One screen as ffmpeg grabs it, parsed out of its `-i` spec so each
+/// backend can lay out its own `-f`/`-i` arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureBackend {
+    /// avfoundation's `<screen>:none`
+    AvFoundation { screen_index: String },
+    /// Screen `screen` of X display `display`, grabbed from `origin`
+    X11Grab {
+        display: String,
+        screen: u32,
+        origin: Option<(i32, i32)>,
+    },
+    /// The Windows desktop
+    GdiGrab,
+    /// A PipeWire node, or the screen the desktop portal offers when `None`
+    PipeWire { node_id: Option<u32> },
+}
+
+impl CaptureBackend {
+    /// `backend`'s capture of `input`, a `-i` spec as `Backend::input` or
+    /// `pipewire_input` gives it.
+    pub fn new(backend: Backend, input: &str) -> Self {
+        match backend {
+            Backend::AVFoundation => CaptureBackend::AvFoundation {
+                screen_index: input.into(),
+            },
+            Backend::X11Grab => {
+                // [host]:display[.screen]; only the part after the colon
+                // holds a screen number
+                let colon = input.rfind(':').map_or(0, |i| i + 1);
+                let (display, screen) = match input[colon..].split_once('.') {
+                    Some((number, screen)) => {
+                        (&input[..colon + number.len()], screen.parse().unwrap_or(0))
+                    }
+                    None => (input, 0),
+                };
+                CaptureBackend::X11Grab {
+                    display: display.into(),
+                    screen,
+                    origin: None,
+                }
+            }
+            Backend::GdiGrab => CaptureBackend::GdiGrab,
+            Backend::PipeWire => CaptureBackend::PipeWire {
+                node_id: input
+                    .strip_prefix(PIPEWIRE_SOURCE)
+                    .and_then(|opts| opts.strip_prefix("=node="))
+                    .and_then(|id| id.parse().ok()),
+            },
+        }
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self {
+            CaptureBackend::AvFoundation { .. } => Backend::AVFoundation,
+            CaptureBackend::X11Grab { .. } => Backend::X11Grab,
+            CaptureBackend::GdiGrab => Backend::GdiGrab,
+            CaptureBackend::PipeWire { .. } => Backend::PipeWire,
+        }
+    }
+
+    /// `-f` and `-i` (and the capture rate, `fps`, when given) for ffmpeg,
+    /// or without `fps` for ffprobe. x11grab takes the crop origin in its
+    /// `-i`, pipewiregrab the rate among its options.
+    pub fn input_args(&self, fps: Option<f64>) -> Vec<String> {
+        let mut out = Vec::new();
+        if let Some(fps) = fps.filter(|_| self.backend() != Backend::PipeWire) {
+            out.extend(["-framerate".into(), fps.to_string()]);
+        }
+        let input = match self {
+            CaptureBackend::AvFoundation { screen_index } => screen_index.clone(),
+            CaptureBackend::X11Grab {
+                display,
+                screen,
+                origin,
+            } => match origin {
+                Some((x, y)) => format!("{}.{}+{},{}", display, screen, x, y),
+                None => format!("{}.{}", display, screen),
+            },
+            CaptureBackend::GdiGrab => "desktop".into(),
+            CaptureBackend::PipeWire { node_id } => {
+                let mut opts: Vec<String> = Vec::new();
+                if let Some(id) = node_id {
+                    opts.push(format!("node={}", id));
+                }
+                if let Some(fps) = fps {
+                    opts.push(format!("framerate={}", fps));
+                }
+                match opts.is_empty() {
+                    true => PIPEWIRE_SOURCE.into(),
+                    false => format!("{}={}", PIPEWIRE_SOURCE, opts.join(":")),
+                }
+            }
+        };
+        out.extend([
+            "-f".into(),
+            self.backend().format().into(),
+            "-i".into(),
+            input,
+        ]);
+        out
+    }
+
+    /// Crop `(x, y, w, h)` as this backend does it best: at the input when
+    /// it can grab a region (x11grab from an origin, gdigrab from offsets),
+    /// else as a filter.
+    pub fn crop(&mut self, (x, y, w, h): (i32, i32, i32, i32)) -> CropPlacement {
+        let size = ["-video_size".into(), format!("{}x{}", w, h)];
+        match self {
+            CaptureBackend::X11Grab { origin, .. } => {
+                *origin = Some((x, y));
+                CropPlacement::Input(size.to_vec())
+            }
+            CaptureBackend::GdiGrab => {
+                let offsets = [
+                    "-offset_x".into(),
+                    x.to_string(),
+                    "-offset_y".into(),
+                    y.to_string(),
+                ];
+                CropPlacement::Input(offsets.into_iter().chain(size).collect())
+            }
+            CaptureBackend::AvFoundation { .. } | CaptureBackend::PipeWire { .. } => {
+                crop_filter((x, y, w, h))
+            }
+        }
+    }
+}
+
+/// `-i` of `--backend pipewire`: node `node_id`, else the portal's pick.
+pub fn pipewire_input(node_id: Option<u32>) -> String {
+    match node_id {
+        Some(id) => format!("{}=node={}", PIPEWIRE_SOURCE, id),
+        None => PIPEWIRE_SOURCE.into(),
+    }
+}
+
+/// Whether this ffmpeg has the filter (or lavfi source) `name`.
+pub fn has_filter(name: &str) -> bool {
+    let Some(output) = probe(
+        "ffmpeg",
+        ["-hide_banner", "-h", &format!("filter={}", name)],
+    ) else {
+        return false;
+    };
+    // An unknown filter is not an error, just a line saying so
+    String::from_utf8_lossy(&output.stdout).contains(&format!("Filter {}", name))
+}
+
+/// Where a crop rectangle is applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CropPlacement {
+    /// Options for the video input, so only the region is ever grabbed
+    Input(Vec<String>),
+    /// A `crop` filter on the full captured frame
+    Filter(String),
+}
+
+fn crop_filter((x, y, w, h): (i32, i32, i32, i32)) -> CropPlacement {
+    CropPlacement::Filter(format!("crop={}:{}:{}:{}", w, h, x, y))
+}
+
+/// Find `bin` on `PATH`.
+pub fn which(bin: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(bin))
+        .find(|candidate| candidate.is_file())
+}
+
+/// First line of `<bin> -version`.
+pub fn tool_version(bin: &str) -> Option<String> {
+    let output = probe(bin, ["-version"])?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .map(|line| line.trim().to_string())
+}
+
+/// Capture decisions resolved by `Recorder::prepare` (device probing,
+/// clamping) that the argument builder turns into an ffmpeg command line.
+#[derive(Debug, Clone)]
+pub struct CapturePlan {
+    /// Screen grabber the inputs are for
+    pub backend: Backend,
+    /// `-i` specs, one per captured screen (avfoundation's `video:audio`)
+    pub inputs: Vec<String>,
+    /// avfoundation audio device; silence when `None`
+    pub audio: Option<u32>,
+    /// Also stream to an ffplay preview window
+    pub preview: bool,
+    /// Common height to scale stacked screens to
+    pub stack_height: Option<i32>,
+    /// Clamped crop rectangle (x, y, w, h)
+    pub crop: Option<(i32, i32, i32, i32)>,
+    /// Frame `crop` is measured in: the screen, or the --video-size or
+    /// --scale frame
+    pub screen: Option<(i32, i32)>,
+    /// Rate asked of the device: --input-framerate (else --framerate), or
+    /// the nearest a camera offers
+    pub capture_fps: f64,
+    pub output: PathBuf,
+}
+
+/// Packets per input queue once more than one device is captured; ffmpeg's
+/// default of 8 overflows as soon as one input stalls the others
+const BUSY_THREAD_QUEUE_SIZE: u32 = 512;
+/// Probe bytes once more than one device is captured. ffmpeg's 5 MB is less
+/// than one raw frame of a large display, so probing cannot settle
+const BUSY_PROBESIZE: u64 = 50_000_000;
+
+/// -thread_queue_size and -probesize for each device input, as
+/// `-flag value` pairs: the flags when given, else elevated values when
+/// several devices (screens, audio) feed one ffmpeg.
+fn input_tuning(args: &StartOptions, plan: &CapturePlan) -> Vec<String> {
+    let audio = plan.audio.is_some() && args.ffmpeg_defaults().audio;
+    let busy = plan.inputs.len() + usize::from(audio) > 1;
+    let queue = args
+        .thread_queue_size
+        .or(busy.then_some(BUSY_THREAD_QUEUE_SIZE));
+    let probe = args.probesize.or(busy.then_some(BUSY_PROBESIZE));
+    let mut tuning = Vec::new();
+    if let Some(queue) = queue {
+        tuning.extend(["-thread_queue_size".into(), queue.to_string()]);
+    }
+    if let Some(probe) = probe {
+        tuning.extend(["-probesize".into(), probe.to_string()]);
+    }
+    if !tuning.is_empty() {
+        write_log(&format!("Input tuning: {}", tuning.join(" ")));
+    }
+    tuning
+}
+
+/// The video encoder of a recording and its rate control: `--codec`, with
+/// `--crf` and `--preset` for libx264 or `--bitrate` for h264_videotoolbox.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordingConfig {
+    pub codec: String,
+    pub crf: u32,
+    pub preset: String,
+    pub bitrate_kbps: u32,
+}
+
+impl RecordingConfig {
+    pub fn new(args: &StartOptions) -> Self {
+        RecordingConfig {
+            codec: args.codec.clone(),
+            crf: args.crf.unwrap_or(CRF),
+            preset: args.preset.clone().unwrap_or(PRESET.into()),
+            bitrate_kbps: args.bitrate.unwrap_or(BITRATE_KBPS),
+        }
+    }
+
+    /// Whether the encoder is the hardware one, rated by bitrate.
+    pub fn hardware(&self) -> bool {
+        self.codec == VIDEOTOOLBOX
+    }
+
+    /// `-c:v` and the rate control flags that go with it.
+    pub fn encoder_args(&self) -> Vec<String> {
+        let rate = match self.hardware() {
+            true => ["-b:v".into(), format!("{}k", self.bitrate_kbps)].to_vec(),
+            false => [
+                "-preset".into(),
+                self.preset.clone(),
+                "-crf".into(),
+                self.crf.to_string(),
+            ]
+            .to_vec(),
+        };
+        ["-c:v".into(), self.codec.clone()]
+            .into_iter()
+            .chain(rate)
+            .collect()
+    }
+}
+
+pub fn build_ffmpeg_args(args: &StartOptions, plan: &CapturePlan) -> Vec<String> {
+    // -n: a file that appeared since the name was picked fails the start
+    // instead of being recorded over
+    let mut out: Vec<String> = vec![if args.overwrites() { "-y" } else { "-n" }.into()];
+    let tuning = input_tuning(args, plan);
+    if !args.format().has_video() {
+        out.extend(audio_only_args(args, plan, &tuning));
+        return out;
+    }
+
+    // An input-side crop grabs only the region, but it comes before any
+    // scaling and needs the one input with its native size
+    let input_crop = args.filter_order == FilterOrder::CropFirst
+        && plan.inputs.len() == 1
+        && args.video_size.is_none();
+    let mut captures: Vec<CaptureBackend> = plan
+        .inputs
+        .iter()
+        .map(|input| CaptureBackend::new(plan.backend, input))
+        .collect();
+    let crop = plan.crop.map(|rect| match input_crop {
+        true => captures[0].crop(rect),
+        false => crop_filter(rect),
+    });
+    if let Some(CropPlacement::Input(options)) = &crop {
+        write_log(&format!("Input-side crop: {}", options.join(" ")));
+    }
+    out.extend(video_input_args(
+        args,
+        plan,
+        &captures,
+        &tuning,
+        crop.as_ref(),
+    ));
+
+    let defaults = args.ffmpeg_defaults();
+    out.extend(audio_input_args(args, plan, &tuning));
+    let audio_index = plan.inputs.len();
+    let audio_map = defaults.audio.then(|| format!("{}:a", audio_index));
+
+    // The image comes after every capture input, so their indices hold
+    let watermark_index = plan.inputs.len() + defaults.audio as usize;
+    if let Some(image) = &args.watermark {
+        out.extend(["-i".into(), image.to_string_lossy().into_owned()]);
+    }
+
+    // With --preview or --also-output the muxer sits inside tee
+    let tee = plan.preview || !args.also_output.is_empty();
+    out.extend(encode_args(args, plan));
+    out.extend(limit_args(args, plan, tee));
+    out.extend(tag_args(args, plan));
+
+    let crop = match crop {
+        Some(CropPlacement::Filter(filter)) => Some(filter),
+        _ => None,
+    };
+    let mut chain = FilterChain::default();
+    chain.retime(args, plan, audio_map.is_some());
+    chain.geometry(args, crop);
+    chain.overlay(args, watermark_index);
+    chain.finish(args);
+    out.extend(chain.args);
+    // Several inputs, or a watermark's, need a complex filtergraph
+    let complex = plan.inputs.len() > 1 || args.watermark.is_some();
+    out.extend(graph_args(plan, &chain.filters, audio_map, complex, tee));
+
+    // --ffmpeg-arg escape hatch, as late as possible so it can override us
+    out.extend(args.ffmpeg_args.iter().cloned());
+
+    if tee {
+        out.extend(tee_args(args, plan));
+        return out;
+    }
+    if let Some(secs) = args.segment_time {
+        out.extend(segment_args(args, plan, secs));
+        return out;
+    }
+    if args.stdout {
+        out.extend(["-f", args.format().muxer(), "pipe:1"].map(String::from));
+        return out;
+    }
+
+    let file = match args.lossless_capture() {
+        true => compress::capture_path(&plan.output),
+        false => plan.output.clone(),
+    };
+    out.push(file.to_string_lossy().into_owned());
+
+    // A second output: the audio input alone, uncompressed
+    if let Some(wav) = split_audio_path(args, plan) {
+        write_log(&format!(
+            "Video: {}, audio track: {}",
+            file.display(),
+            wav.display()
+        ));
+        out.extend(["-map".into(), format!("{}:a", audio_index)]);
+        out.extend(["-c:a", "pcm_s16le"].map(String::from));
+        if let Some(duration) = args.duration {
+            out.extend(["-t".into(), format!("{}", duration)]);
+        }
+        out.push(wav.to_string_lossy().into_owned());
+    }
+    out
+}
+
+/// Each capture input, with its tuning, size, pointer and input-side crop.
+fn video_input_args(
+    args: &StartOptions,
+    plan: &CapturePlan,
+    captures: &[CaptureBackend],
+    tuning: &[String],
+    crop: Option<&CropPlacement>,
+) -> Vec<String> {
+    let mut out = Vec::new();
+    for capture in captures {
+        out.extend(tuning.iter().cloned());
+        if let Some((w, h)) = args.video_size {
+            out.extend(["-video_size".into(), format!("{}x{}", w, h)]);
+        }
+        // avfoundation leaves the pointer out unless asked; the others draw it
+        if args.big_cursor.is_some() && plan.backend == Backend::AVFoundation {
+            out.extend(["-capture_cursor", "1"].map(String::from));
+        }
+        if let Some(CropPlacement::Input(options)) = crop {
+            out.extend(options.iter().cloned());
+        }
+        out.extend(capture.input_args(Some(plan.capture_fps)));
+    }
+    out
+}
+
+/// The audio device, or silent audio (QuickTime REQUIRES an audio track).
+fn audio_input_args(args: &StartOptions, plan: &CapturePlan, tuning: &[String]) -> Vec<String> {
+    let mut out = Vec::new();
+    match plan.audio {
+        _ if !args.ffmpeg_defaults().audio => {}
+        Some(device) => {
+            if let Some(ms) = args.audio_delay.filter(|ms| *ms != 0) {
+                write_log(&format!("Audio delay: {}ms", ms));
+                out.extend(["-itsoffset".into(), format!("{}", ms as f64 / 1000.0)]);
+            }
+            out.extend(tuning.iter().cloned());
+            out.extend([
+                "-f".into(),
+                "avfoundation".into(),
+                "-i".into(),
+                format!("none:{}", device),
+            ]);
+        }
+        None => out.extend(["-f", "lavfi", "-i", "anullsrc"].map(String::from)),
+    }
+    out
+}
+
+/// The video encoder: the lossless capture, H.264 or VP9.
+fn encode_args(args: &StartOptions, plan: &CapturePlan) -> Vec<String> {
+    let defaults = args.ffmpeg_defaults();
+    let mut out = Vec::new();
+    if args.lossless_capture() {
+        write_log(&format!(
+            "Lossless capture: {} (encoded at stop)",
+            compress::capture_path(&plan.output).display()
+        ));
+        out.extend(compress::capture_args(defaults.audio));
+    } else if defaults.h264 {
+        // QuickTime-safe encoding
+        let config = RecordingConfig::new(args);
+        write_log(&format!("Encoder: {}", config.encoder_args().join(" ")));
+        out.extend(
+            [
+                "-pix_fmt",
+                "yuv420p",
+                "-profile:v",
+                &args.h264_profile,
+                "-level",
+                &args.h264_level,
+            ]
+            .map(String::from),
+        );
+        out.extend(config.encoder_args());
+    } else if defaults.vp9 {
+        // -b:v 0 makes the CRF the only target; the realtime deadline is
+        // what lets libvpx keep up with a live capture at all
+        let crf = args.crf.unwrap_or(VP9_CRF).to_string();
+        write_log(&format!("Encoder: libvpx-vp9 -crf {} (realtime)", crf));
+        out.extend(
+            [
+                "-pix_fmt",
+                "yuv420p",
+                "-c:v",
+                "libvpx-vp9",
+                "-crf",
+                &crf,
+                "-b:v",
+                "0",
+            ]
+            .into_iter()
+            .chain(["-deadline", "realtime", "-cpu-used", "8", "-row-mt", "1"])
+            .chain(["-c:a", "libopus"])
+            .map(String::from),
+        );
+    }
+    if let Some(threads) = args.encoder_threads.filter(|_| defaults.h264) {
+        write_log(&format!("Encoder threads: {}", threads));
+        out.extend(["-threads".into(), threads.to_string()]);
+    }
+    if !defaults.audio {
+        out.push("-an".into());
+    }
+    out
+}
+
+/// Where the output ends (`-shortest`, `-t`, `-frames:v`) and the mp4's own
+/// `-movflags`, which tee carries instead when `tee`.
+fn limit_args(args: &StartOptions, plan: &CapturePlan, tee: bool) -> Vec<String> {
+    let mut out = Vec::new();
+    // Stop the endless anullsrc track when the video ends. A real audio
+    // device is just as endless, but a finite input would cut the video
+    // short, so it is only the default for silence.
+    let shortest = match (args.shortest, args.no_shortest) {
+        (true, _) => true,
+        (_, true) => false,
+        _ => args.ffmpeg_defaults().audio && plan.audio.is_none(),
+    };
+    if shortest {
+        out.push("-shortest".into());
+    }
+    // With --segment-time the mp4 muxer sits inside segment, which carries
+    // its flags. The capture is mkv; the compression adds faststart to the
+    // real output
+    let own_muxer = !tee && args.segment_time.is_none() && !args.lossless_capture();
+    if let Some(flags) = args.movflags().filter(|_| own_muxer) {
+        out.extend(["-movflags", flags].map(String::from));
+    }
+
+    if let Some(duration) = args.duration {
+        // -t counts output time, which a time-lapse compresses
+        let duration = match args.timelapse {
+            Some(interval) => duration / interval / args.timelapse_fps as f64,
+            None => duration,
+        };
+        out.extend(["-t".into(), format!("{}", duration)]);
+    }
+    if let Some(frames) = args.frames {
+        write_log(&format!(
+            "Frame limit: ffmpeg stops after {} frames",
+            frames
+        ));
+        out.extend(["-frames:v".into(), frames.to_string()]);
+    }
+    out
+}
+
+/// Color tags and the crop comment, kept with the file.
+fn tag_args(args: &StartOptions, plan: &CapturePlan) -> Vec<String> {
+    let mut out = Vec::new();
+    // Color tags (primaries, transfer, matrix); nothing unless requested
+    let preset = args.color_preset.map(ColorPreset::tags);
+    let tags = [
+        (
+            "-color_primaries",
+            args.color_primaries.as_deref().or(preset.map(|p| p.0)),
+        ),
+        (
+            "-color_trc",
+            args.color_trc.as_deref().or(preset.map(|p| p.1)),
+        ),
+        (
+            "-colorspace",
+            args.colorspace.as_deref().or(preset.map(|p| p.2)),
+        ),
+    ];
+    for (flag, value) in tags {
+        if let Some(value) = value {
+            write_log(&format!("Color tag: {} {}", flag, value));
+            out.extend([flag.to_string(), value.to_string()]);
+        }
+    }
+
+    // For whoever later wonders why the file shows only part of the screen
+    if let (Some((x, y, w, h)), Some((sw, sh))) = (plan.crop, plan.screen) {
+        let comment = format!("crop={}x{}+{}+{} screen={}x{}", w, h, x, y, sw, sh);
+        write_log(&format!("Metadata: comment={}", comment));
+        out.extend(["-metadata".into(), format!("comment={}", comment)]);
+    }
+    out
+}
+
+/// The video filters in the order they run, and the output options some
+/// of them need alongside.
+#[derive(Default)]
+struct FilterChain {
+    filters: Vec<String>,
+    args: Vec<String>,
+}
+
+impl FilterChain {
+    /// Frames dropped or restamped, before the rest of the chain runs.
+    fn retime(&mut self, args: &StartOptions, plan: &CapturePlan, audio: bool) {
+        // Before anything else, so every later filter counts from the first
+        // kept frame; the audio loses the same stretch to stay in sync
+        if let Some(frames) = args.warmup_frames {
+            let secs = frames as f64 / plan.capture_fps;
+            write_log(&format!(
+                "Warmup: dropping the first {} frames ({:.3}s)",
+                frames, secs
+            ));
+            self.filters
+                .push(format!("trim=start_frame={},setpts=PTS-STARTPTS", frames));
+            if audio {
+                self.args.extend([
+                    "-af".into(),
+                    format!("atrim=start={:.3},asetpts=PTS-STARTPTS", secs),
+                ]);
+            }
+        }
+        // Thin the frames out first, so the rest of the chain runs on few; the
+        // kept frames are then restamped to play back at --timelapse-fps
+        if let Some(interval) = args.timelapse {
+            let filter = format!("fps=1/{},setpts=N/({}*TB)", interval, args.timelapse_fps);
+            write_log(&format!(
+                "Time-lapse: one frame every {}s, played at {} fps ({}x): {}",
+                interval,
+                args.timelapse_fps,
+                interval * args.timelapse_fps as f64,
+                filter
+            ));
+            self.filters.push(filter);
+            self.args
+                .extend(["-r".into(), args.timelapse_fps.to_string()]);
+        }
+        // Likewise the frames --output-fps drops, or a capture at another rate
+        // than --framerate needs dropped or repeated
+        let retimed = plan.capture_fps != args.framerate as f64 && args.timelapse.is_none();
+        if let Some(fps) = args.output_fps.or(retimed.then_some(args.framerate)) {
+            write_log(&format!(
+                "Frame rate: capture {} fps, output {} fps",
+                plan.capture_fps, fps
+            ));
+            self.filters.push(format!("fps={}", fps));
+        }
+    }
+
+    /// Crop and scale in --filter-order, then the transforms and the padding.
+    fn geometry(&mut self, args: &StartOptions, crop: Option<String>) {
+        // -2 rather than -1 keeps the derived side even
+        let scale = args.scale.map(|(w, h)| {
+            let side = |v: i32| if v == -1 { -2 } else { v };
+            format!("scale={}:{}", side(w), side(h))
+        });
+        let (first, second) = match args.filter_order {
+            FilterOrder::CropFirst => (crop, scale),
+            FilterOrder::ScaleFirst => (scale, crop),
+        };
+        for filter in [first, second].into_iter().flatten() {
+            write_log(&format!("Crop/scale filter: {}", filter));
+            self.filters.push(filter);
+        }
+
+        // Transforms run after the crop so crop coordinates stay in screen space
+        let mut transforms = Vec::new();
+        if let Some(rotation) = args.rotate {
+            transforms.push(rotation.filter());
+        }
+        if args.hflip {
+            transforms.push("hflip");
+        }
+        if args.vflip {
+            transforms.push("vflip");
+        }
+        if !transforms.is_empty() {
+            let transform = transforms.join(",");
+            write_log(&format!("Transform filter: {}", transform));
+            self.filters.push(transform);
+        }
+
+        // Letterbox last, so the aspect applies to the frame as it will be seen
+        if let Some((num, den)) = args.aspect {
+            let filter = format!(
+                "pad=w=trunc(max(iw\\,ih*{num}/{den})/2+0.5)*2\
+                 :h=trunc(max(ih\\,iw*{den}/{num})/2+0.5)*2\
+                 :x=trunc((ow-iw)/2):y=trunc((oh-ih)/2):color={},setsar=1",
+                args.pad_color
+            );
+            write_log(&format!("Pad filter: {}", filter));
+            self.filters.push(filter);
+        }
+    }
+
+    /// What goes on top of the frame as it will be seen: the watermark
+    /// (input `watermark_index`) and the fades.
+    fn overlay(&mut self, args: &StartOptions, watermark_index: usize) {
+        // It needs its own input, so the chain is split around a labeled overlay
+        if let Some(image) = &args.watermark {
+            let filter = format!(
+                "null[wm_base];[{}:v]format=rgba,colorchannelmixer=aa={}[wm];\
+                 [wm_base][wm]overlay={}",
+                watermark_index,
+                args.watermark_opacity,
+                args.watermark_pos.overlay_xy()
+            );
+            write_log(&format!(
+                "Watermark: {} ({:?}, opacity {})",
+                image.display(),
+                args.watermark_pos,
+                args.watermark_opacity
+            ));
+            self.filters.push(filter);
+        }
+
+        // Fades cover the whole visible frame, padding included
+        if let Some(secs) = args.fade_in {
+            let filter = format!("fade=t=in:st=0:d={}", secs);
+            write_log(&format!("Fade filter: {}", filter));
+            self.filters.push(filter);
+        }
+        if let (Some(secs), Some(duration)) = (args.fade_out, args.duration) {
+            let filter = format!("fade=t=out:st={}:d={}", (duration - secs).max(0.0), secs);
+            write_log(&format!("Fade filter: {}", filter));
+            self.filters.push(filter);
+        }
+    }
+
+    /// The finished frame: aspect tags, duplicate dropping and the palette.
+    fn finish(&mut self, args: &StartOptions) {
+        // Aspect tags only relabel the frame, so they go last
+        let tag = match (args.sar, args.dar) {
+            (Some((n, m)), _) => Some(format!("setsar={}/{}", n, m)),
+            (None, Some((n, m))) => Some(format!("setdar={}/{}", n, m)),
+            (None, None) => None,
+        };
+        if let Some(filter) = tag {
+            write_log(&format!("Aspect tag: {}", filter));
+            self.filters.push(filter);
+        }
+
+        // Duplicates are judged on the finished frame, so a fade still counts
+        // as change; -fps_mode vfr then keeps the gaps instead of refilling them
+        if args.vfr {
+            write_log("Variable frame rate: mpdecimate, -fps_mode vfr");
+            self.filters.push("mpdecimate".into());
+            self.args.extend(["-fps_mode", "vfr"].map(String::from));
+        }
+
+        // A GIF that cannot wait for the two passes at `stop` still gets a
+        // palette of the whole recording, built once ffmpeg has seen the last
+        // frame
+        if args.ffmpeg_defaults().palette && !args.two_pass_gif() {
+            let filter = "split[pal_in][pal_src];[pal_in]palettegen[pal];[pal_src][pal]paletteuse";
+            write_log(&format!("Palette filter: {}", filter));
+            self.filters.push(filter.into());
+        }
+    }
+}
+
+/// `filters` as `-filter:v`, or as a filtergraph when `complex`, with the
+/// stream maps that go along.
+fn graph_args(
+    plan: &CapturePlan,
+    filters: &[String],
+    audio_map: Option<String>,
+    complex: bool,
+    tee: bool,
+) -> Vec<String> {
+    let mut out = Vec::new();
+    if complex {
+        let mut graph = String::new();
+        if plan.inputs.len() > 1 {
+            let mut stacked = String::new();
+            for i in 0..plan.inputs.len() {
+                match plan.stack_height {
+                    Some(h) => graph.push_str(&format!("[{i}:v]scale=-2:{h}[s{i}];")),
+                    None => graph.push_str(&format!("[{i}:v]null[s{i}];")),
+                }
+                stacked.push_str(&format!("[s{i}]"));
+            }
+            graph.push_str(&format!("{}hstack=inputs={}", stacked, plan.inputs.len()));
+            for filter in filters {
+                graph.push(',');
+                graph.push_str(filter);
+            }
+        } else {
+            graph.push_str("[0:v]");
+            graph.push_str(&filters.join(","));
+        }
+        graph.push_str("[vout]");
+        out.extend(["-filter_complex".into(), graph]);
+        out.extend(["-map".into(), "[vout]".into()]);
+        if let Some(map) = audio_map {
+            out.extend(["-map".into(), map]);
+        }
+    } else {
+        if !filters.is_empty() {
+            out.extend(["-filter:v".into(), filters.join(",")]);
+        }
+        // tee only takes explicitly mapped streams
+        if tee {
+            out.extend(["-map".into(), "0:v".into()]);
+            if let Some(map) = audio_map {
+                out.extend(["-map".into(), map]);
+            }
+        }
+    }
+    out
+}
+
+/// One encode, several sinks: the file, each --also-output, and nut on
+/// stdout for ffplay. A closed preview window or a dropped stream must not
+/// take the recording down with it.
+fn tee_args(args: &StartOptions, plan: &CapturePlan) -> Vec<String> {
+    let file = tee_escape(&plan.output.to_string_lossy());
+    let mut slaves = vec![format!(
+        "[{}]{}",
+        tee_muxer(args.format(), args.movflags()),
+        file
+    )];
+    for dest in &args.also_output {
+        write_log(&format!("Also writing to {}", dest));
+        slaves.push(also_output_slave(dest, args.movflags()));
+    }
+    if plan.preview {
+        slaves.push("[f=nut:select=v:onfail=ignore]pipe:1".into());
+    }
+    let mut out: Vec<String> = ["-flags", "+global_header", "-f", "tee"]
+        .map(String::from)
+        .into();
+    out.push(slaves.join("|"));
+    out
+}
+
+/// `--segment-time secs`: numbered files and the csv list of them.
+fn segment_args(args: &StartOptions, plan: &CapturePlan, secs: f64) -> Vec<String> {
+    let format = args.format();
+    let list = segments::list_path(&plan.output);
+    write_log(&format!(
+        "Segmenting every {}s, list: {}",
+        secs,
+        list.display()
+    ));
+    let mut out = vec![
+        "-f".into(),
+        "segment".into(),
+        "-segment_time".into(),
+        format!("{}", secs),
+    ];
+    out.extend(["-reset_timestamps", "1", "-segment_format", format.muxer()].map(String::from));
+    if let Some(flags) = args.movflags().filter(|_| format == OutputFormat::Mp4) {
+        out.extend([
+            "-segment_format_options".into(),
+            format!("movflags={}", flags),
+        ]);
+    }
+    out.extend(["-segment_list_type", "csv", "-segment_list"].map(String::from));
+    out.push(list.to_string_lossy().into_owned());
+    out.push(
+        segments::pattern(&plan.output)
+            .to_string_lossy()
+            .into_owned(),
+    );
+    out
+}
+
+/// `-movflags` that moves the mp4 index to the front, so it streams.
+pub const FASTSTART: &str = "+faststart";
+/// `-movflags` of `--fragmented`: a fragment per keyframe, the index up front.
+pub const FRAGMENTED: &str = "+frag_keyframe+empty_moov";
+
+/// `-movflags <movflags>` for a stream copy into `output`, when it is an
+/// mp4 or mov. `movflags` is the recording's own (`StartOptions::movflags`),
+/// so a post-processing remux keeps `--fragmented` or `--no-faststart`.
+pub fn faststart_args<'a>(output: &Path, movflags: Option<&'a str>) -> Vec<&'a str> {
+    let ext = output
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase());
+    match (ext.as_deref(), movflags) {
+        (Some("mp4" | "mov" | "m4v" | "m4a"), Some(flags)) => vec!["-movflags", flags],
+        _ => Vec::new(),
+    }
+}
+
+/// `--audio-only`: the audio device alone into an m4a (AAC) or wav (PCM).
+/// No screen is opened, so none of the video flags apply.
+fn audio_only_args(args: &StartOptions, plan: &CapturePlan, tuning: &[String]) -> Vec<String> {
+    let format = args.format();
+    let mut out: Vec<String> = tuning.to_vec();
+    match plan.audio {
+        Some(device) => out.extend([
+            "-f".into(),
+            "avfoundation".into(),
+            "-i".into(),
+            format!("none:{}", device),
+        ]),
+        None => out.extend(["-f", "lavfi", "-i", "anullsrc"].map(String::from)),
+    }
+    let codec = match format {
+        OutputFormat::Wav => "pcm_s16le",
+        _ => "aac",
+    };
+    write_log(&format!(
+        "Audio only: {} into .{}",
+        codec,
+        format.extension()
+    ));
+    out.extend(["-vn".into(), "-c:a".into(), codec.into()]);
+    if let Some(duration) = args.duration {
+        out.extend(["-t".into(), format!("{}", duration)]);
+    }
+    if let Some(flags) = args.movflags() {
+        out.extend(["-movflags", flags].map(String::from));
+    }
+    out.extend(args.ffmpeg_args.iter().cloned());
+    if args.stdout {
+        out.extend(["-f", format.muxer(), "pipe:1"].map(String::from));
+    } else {
+        out.push(plan.output.to_string_lossy().into_owned());
+    }
+    out
+}
+
+/// Where `--split-audio-track` writes, when there is a real audio device
+/// to split off.
+pub fn split_audio_path(args: &StartOptions, plan: &CapturePlan) -> Option<PathBuf> {
+    let audio = plan.audio.is_some() && args.ffmpeg_defaults().audio;
+    (args.split_audio_track && audio).then(|| plan.output.with_extension("wav"))
+}
+
+/// tee slave options writing `format`.
+fn tee_muxer(format: OutputFormat, movflags: Option<&str>) -> String {
+    match (format, movflags) {
+        (OutputFormat::Mp4, Some(flags)) => format!("f=mp4:movflags={}", flags),
+        (other, _) => format!("f={}", other.muxer()),
+    }
+}
+
+/// An `--also-output` as a tee slave. Options given as `[...]` are kept;
+/// otherwise the muxer comes from the URL scheme or the file extension (or
+/// ffmpeg's guess), and a failing destination is dropped.
+fn also_output_slave(dest: &str, movflags: Option<&str>) -> String {
+    if dest.starts_with('[') {
+        return dest.to_string();
+    }
+    let muxer = match dest.split_once("://") {
+        Some(("rtmp" | "rtmps", _)) => Some("f=flv".to_string()),
+        Some(("udp" | "srt" | "tcp" | "rist", _)) => Some("f=mpegts".to_string()),
+        Some(_) => None,
+        None => Path::new(dest)
+            .extension()
+            .and_then(|ext| OutputFormat::from_extension(&ext.to_string_lossy()))
+            .map(|format| tee_muxer(format, movflags)),
+    };
+    let options = muxer.map_or("onfail=ignore".to_string(), |m| {
+        format!("{}:onfail=ignore", m)
+    });
+    format!("[{}]{}", options, tee_escape(dest))
+}
+
+/// Where an `--also-output` writes, its `[...]` tee options aside.
+fn tee_target(dest: &str) -> &str {
+    match dest.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+        Some((_, target)) => target,
+        None => dest,
+    }
+}
+
+/// The `--also-output` destinations that are local files, not URLs.
+pub fn also_output_files(args: &StartOptions) -> Vec<PathBuf> {
+    args.also_output
+        .iter()
+        .map(|dest| tee_target(dest))
+        .filter(|target| !target.contains("://") && !target.starts_with("pipe:"))
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Escape a tee slave filename.
+fn tee_escape(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        if "\\|[]:'".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// `ffplay` reading the `--preview` stream from its stdin.
+pub fn preview_args() -> Vec<String> {
+    [
+        "-hide_banner",
+        "-loglevel",
+        "error",
+        "-autoexit",
+        "-fflags",
+        "nobuffer",
+        "-flags",
+        "low_delay",
+        "-framedrop",
+        "-window_title",
+        "rec.nvim preview",
+        "-f",
+        "nut",
+        "-i",
+        "pipe:0",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+/// Quote `arg` for a POSIX shell, leaving plainly safe words untouched.
+pub fn shell_quote(arg: &str) -> String {
+    let safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c));
+    if safe {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+pub fn shell_command(bin: &str, args: &[String]) -> String {
+    std::iter::once(bin.to_string())
+        .chain(args.iter().map(|a| shell_quote(a)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Start {
+        #[command(flatten)]
+        options: StartOptions,
+    }
+
+    /// `rec-cli start` with `flags`.
+    fn start(flags: &[&str]) -> StartOptions {
+        let argv = ["start"].iter().chain(flags);
+        Start::try_parse_from(argv).unwrap().options
+    }
+
+    /// One x11grab screen recorded to `out`, nothing probed.
+    fn plan(out: &str) -> CapturePlan {
+        CapturePlan {
+            backend: Backend::X11Grab,
+            inputs: vec![":0.0".into()],
+            audio: None,
+            preview: false,
+            stack_height: None,
+            crop: None,
+            screen: None,
+            capture_fps: 30.0,
+            output: PathBuf::from(out),
+        }
+    }
+
+    #[test]
+    fn only_an_asked_for_overwrite_passes_y() {
+        let first =
+            |flags: &[&str]| build_ffmpeg_args(&start(flags), &plan("/r/rec.mp4"))[0].clone();
+        assert_eq!(first(&[]), "-n");
+        assert_eq!(first(&["--on-collision", "suffix"]), "-n");
+        assert_eq!(first(&["--on-collision", "overwrite"]), "-y");
+        assert_eq!(first(&["--replace-last"]), "-y");
+    }
+
+    /// The value after the first `flag` in `args`.
+    fn value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+        args.iter()
+            .position(|a| a == flag)
+            .map(|i| args[i + 1].as_str())
+    }
+
+    #[test]
+    fn format_defaults_of_webm_and_gif() {
+        let webm = format_defaults(OutputFormat::Webm);
+        assert!(webm.vp9 && webm.audio && !webm.h264 && !webm.palette && !webm.faststart);
+        let gif = format_defaults(OutputFormat::Gif);
+        assert!(gif.palette && !gif.audio && !gif.h264 && !gif.vp9 && !gif.faststart);
+    }
+
+    #[test]
+    fn mp4_records_h264_into_the_output() {
+        let args = build_ffmpeg_args(&start(&[]), &plan("/r/rec.mp4"));
+        assert_eq!(value(&args, "-c:v"), Some("libx264"));
+        assert_eq!(value(&args, "-movflags"), Some(FASTSTART));
+        assert_eq!(args.last().unwrap(), "/r/rec.mp4");
+    }
+
+    #[test]
+    fn mkv_and_mpegts_skip_the_mp4_index() {
+        for (format, out) in [("mkv", "/r/rec.mkv"), ("mpegts", "/r/rec.ts")] {
+            let args = build_ffmpeg_args(&start(&["--format", format]), &plan(out));
+            assert_eq!(value(&args, "-c:v"), Some("libx264"), "{}", format);
+            assert_eq!(value(&args, "-movflags"), None, "{}", format);
+            assert_eq!(args.last().unwrap(), out);
+        }
+    }
+
+    #[test]
+    fn mp4_index_flags_follow_the_switches() {
+        let movflags = |flags: &[&str]| {
+            let args = build_ffmpeg_args(&start(flags), &plan("/r/rec.mp4"));
+            value(&args, "-movflags").map(String::from)
+        };
+        assert_eq!(movflags(&["--no-faststart"]), None);
+        assert_eq!(movflags(&["--fragmented"]).as_deref(), Some(FRAGMENTED));
+    }
+
+    #[test]
+    fn webm_records_constant_quality_vp9() {
+        let args = build_ffmpeg_args(&start(&["--format", "webm"]), &plan("/r/rec.webm"));
+        assert_eq!(value(&args, "-c:v"), Some("libvpx-vp9"));
+        assert_eq!(value(&args, "-crf"), Some("33"));
+        assert_eq!(value(&args, "-b:v"), Some("0"));
+        assert_eq!(value(&args, "-c:a"), Some("libopus"));
+        assert_eq!(args.last().unwrap(), "/r/rec.webm");
+    }
+
+    #[test]
+    fn gif_records_a_lossless_capture_for_stop() {
+        let args = build_ffmpeg_args(&start(&["--format", "gif"]), &plan("/r/rec.gif"));
+        assert_eq!(value(&args, "-qp"), Some("0"));
+        assert!(args.contains(&"-an".to_string()));
+        assert_eq!(value(&args, "-c:a"), None);
+        assert!(!args.iter().any(|a| a.contains("palette")), "{:?}", args);
+        assert_eq!(args.last().unwrap(), "/r/.rec.lossless.mkv");
+    }
+
+    #[test]
+    fn a_lossless_capture_with_audio_keeps_it_as_pcm() {
+        let start = start(&["--encode-in-background-after-stop"]);
+        let args = build_ffmpeg_args(&start, &plan("/r/rec.mp4"));
+        assert_eq!(value(&args, "-qp"), Some("0"));
+        assert_eq!(value(&args, "-c:a"), Some("pcm_s16le"));
+        assert!(!args.contains(&"-an".to_string()), "{:?}", args);
+    }
+
+    #[test]
+    fn piped_gif_builds_its_palette_in_one_pass() {
+        let args = build_ffmpeg_args(&start(&["--format", "gif", "--stdout"]), &plan("pipe:1"));
+        assert!(
+            value(&args, "-filter:v").is_some_and(|vf| vf.contains("palettegen")),
+            "{:?}",
+            args
+        );
+        assert_eq!(args[args.len() - 3..], ["-f", "gif", "pipe:1"]);
+    }
+
+    #[test]
+    fn capture_backend_parses_each_input_spec() {
+        let x11 = |display: &str, screen| CaptureBackend::X11Grab {
+            display: display.into(),
+            screen,
+            origin: None,
+        };
+        assert_eq!(CaptureBackend::new(Backend::X11Grab, ":0"), x11(":0", 0));
+        assert_eq!(CaptureBackend::new(Backend::X11Grab, ":1.2"), x11(":1", 2));
+        assert_eq!(
+            CaptureBackend::new(Backend::X11Grab, "host.lan:10.1"),
+            x11("host.lan:10", 1)
+        );
+        assert_eq!(
+            CaptureBackend::new(Backend::AVFoundation, "3:none"),
+            CaptureBackend::AvFoundation {
+                screen_index: "3:none".into()
+            }
+        );
+        assert_eq!(
+            CaptureBackend::new(Backend::PipeWire, &pipewire_input(Some(42))),
+            CaptureBackend::PipeWire { node_id: Some(42) }
+        );
+        assert_eq!(
+            CaptureBackend::new(Backend::PipeWire, &pipewire_input(None)),
+            CaptureBackend::PipeWire { node_id: None }
+        );
+    }
+
+    #[test]
+    fn probe_inputs_leave_the_rate_out() {
+        let x11 = CaptureBackend::new(Backend::X11Grab, ":0.0");
+        assert_eq!(x11.input_args(None), ["-f", "x11grab", "-i", ":0.0"]);
+        let portal = CaptureBackend::PipeWire { node_id: None };
+        assert_eq!(
+            portal.input_args(None),
+            ["-f", "lavfi", "-i", "pipewiregrab"]
+        );
+    }
+
+    #[test]
+    fn libx264_is_rated_by_preset_and_crf() {
+        let config = RecordingConfig::new(&start(&[]));
+        assert!(!config.hardware());
+        assert_eq!(
+            config.encoder_args(),
+            [
+                "-c:v",
+                "libx264",
+                "-preset",
+                PRESET,
+                "-crf",
+                &CRF.to_string()
+            ]
+        );
+        let tuned = start(&["--crf", "18", "--preset", "veryfast", "--bitrate", "8000"]);
+        assert_eq!(
+            RecordingConfig::new(&tuned).encoder_args(),
+            ["-c:v", "libx264", "-preset", "veryfast", "-crf", "18"]
+        );
+    }
+
+    #[test]
+    fn videotoolbox_is_rated_by_bitrate_alone() {
+        let config = RecordingConfig::new(&start(&["--codec", VIDEOTOOLBOX]));
+        assert!(config.hardware());
+        let default = format!("{}k", BITRATE_KBPS);
+        assert_eq!(
+            config.encoder_args(),
+            ["-c:v", VIDEOTOOLBOX, "-b:v", &default]
+        );
+        let rated = start(&["--codec", VIDEOTOOLBOX, "--bitrate", "8000"]);
+        let args = RecordingConfig::new(&rated).encoder_args();
+        assert_eq!(args, ["-c:v", VIDEOTOOLBOX, "-b:v", "8000k"]);
+        assert!(!args.iter().any(|a| a == "-crf" || a == "-preset"));
+    }
+
+    #[test]
+    fn screen_sizes_parse_with_fractions_and_spaces() {
+        assert_eq!(parse_screen_size("1920,1080"), Some((1920, 1080)));
+        assert_eq!(parse_screen_size("1920.0,1080.0"), Some((1920, 1080)));
+        assert_eq!(parse_screen_size(" 1920, 1080\n"), Some((1920, 1080)));
+        assert_eq!(parse_screen_size("1439.6,899.5"), Some((1440, 900)));
+        assert_eq!(parse_screen_size_f64("1439.6,899.5"), Some((1439.6, 899.5)));
+    }
+
+    #[test]
+    fn malformed_screen_sizes_are_rejected() {
+        for line in [
+            "",
+            "1920",
+            "1920x1080",
+            "wide,1080",
+            "0,1080",
+            "-1920,1080",
+            "NaN,1080",
+            "inf,1080",
+            "0.2,0.2",
+        ] {
+            assert_eq!(parse_screen_size(line), None, "{:?}", line);
+        }
+        assert_eq!(parse_screen_size_f64("0.2,0.2"), Some((0.2, 0.2)));
+    }
+
+    #[test]
+    fn first_listed_screen_wins() {
+        let output = "[stream]\n2560,1440\n1920,1080\n";
+        assert_eq!(first_screen_size(output), Some((2560, 1440)));
+        assert_eq!(first_screen_size("N/A\n1920,1080\n"), Some((1920, 1080)));
+        assert_eq!(first_screen_size("N/A\n"), None);
+    }
+
+    #[test]
+    fn crop_is_clamped_to_the_screen_edge() {
+        assert_eq!(
+            clamp_crop(100, 50, 800, 600, 1920, 1080, 16),
+            Some((100, 50, 800, 600))
+        );
+        // Overflows the right and bottom edges
+        assert_eq!(
+            clamp_crop(1600, 900, 800, 600, 1920, 1080, 16),
+            Some((1600, 900, 320, 180))
+        );
+        // Starts above and left of the screen
+        assert_eq!(
+            clamp_crop(-40, -20, 800, 600, 1920, 1080, 16),
+            Some((0, 0, 800, 600))
+        );
+        assert_eq!(clamp_crop(1920, 0, 100, 100, 1920, 1080, 16), None);
+        assert_eq!(clamp_crop(0, 0, 100, 100, 0, 1080, 16), None);
+    }
+
+    #[test]
+    fn crop_below_the_minimum_is_refused() {
+        assert_eq!(clamp_crop(0, 0, 1, 1, 1920, 1080, 16), None);
+        assert_eq!(
+            clamp_crop(0, 0, 16, 16, 1920, 1080, 16),
+            Some((0, 0, 16, 16))
+        );
+        // Clamped down to a sliver at the edge
+        assert_eq!(clamp_crop(1915, 500, 400, 400, 1920, 1080, 16), None);
+        assert_eq!(
+            clamp_crop(1904, 1064, 400, 400, 1920, 1080, 16),
+            Some((1904, 1064, 16, 16))
+        );
+        assert_eq!(clamp_crop(0, 0, 1, 1, 1920, 1080, 1), Some((0, 0, 1, 1)));
+    }
+
+    /// The video filter graph `flags` record a cropped avfoundation screen
+    /// with, which crops in the graph rather than at the input.
+    fn graph(flags: &[&str]) -> String {
+        let plan = CapturePlan {
+            backend: Backend::AVFoundation,
+            inputs: vec!["4:none".into()],
+            crop: Some((100, 50, 1280, 720)),
+            ..plan("/r/rec.mp4")
+        };
+        let args = build_ffmpeg_args(&start(flags), &plan);
+        let graph = value(&args, "-filter:v").or(value(&args, "-filter_complex"));
+        graph.expect("a filter graph").to_string()
+    }
+
+    #[test]
+    fn filter_order_places_the_scale() {
+        let scaled = ["--scale", "1920x1080", "--output-fps", "24"];
+        assert_eq!(
+            graph(&scaled),
+            "fps=24,crop=1280:720:100:50,scale=1920:1080"
+        );
+        let scale_first = [&scaled[..], &["--filter-order", "scale-first"]].concat();
+        assert_eq!(
+            graph(&scale_first),
+            "fps=24,scale=1920:1080,crop=1280:720:100:50"
+        );
+    }
+
+    #[test]
+    fn watermark_goes_on_the_cropped_and_scaled_frame() {
+        let image =
+            std::env::temp_dir().join(format!("rec-cli-test-{}-mark.png", std::process::id()));
+        std::fs::write(&image, b"").unwrap();
+        let flags = ["--scale", "1920x1080", "--output-fps", "24", "--watermark"];
+        let graph = graph(&[&flags[..], &[image.to_str().unwrap()]].concat());
+        std::fs::remove_file(&image).unwrap();
+        assert_eq!(
+            graph,
+            "[0:v]fps=24,crop=1280:720:100:50,scale=1920:1080,null[wm_base];\
+             [2:v]format=rgba,colorchannelmixer=aa=1[wm];\
+             [wm_base][wm]overlay=W-w-10:H-h-10[vout]"
+        );
+    }
+
+    #[test]
+    fn region_grid_cells_tile_the_screen() {
+        let grid = |rows, cols, cell| RegionGrid { rows, cols, cell };
+        assert_eq!(grid(1, 1, 1).rect(1920, 1080), (0, 0, 1920, 1080));
+        assert_eq!(grid(2, 2, 1).rect(1920, 1080), (0, 0, 960, 540));
+        assert_eq!(grid(2, 2, 4).rect(1920, 1080), (960, 540, 960, 540));
+        assert_eq!(grid(1, 2, 2).rect(1920, 1080), (960, 0, 960, 1080));
+        // Inner edges are even; the last cell takes what is left
+        assert_eq!(grid(1, 3, 1).rect(1366, 768), (0, 0, 454, 768));
+        assert_eq!(grid(1, 3, 2).rect(1366, 768), (454, 0, 456, 768));
+        assert_eq!(grid(1, 3, 3).rect(1366, 768), (910, 0, 456, 768));
+        let cells: Vec<_> = (1..=9)
+            .map(|cell| grid(3, 3, cell).rect(1366, 768))
+            .collect();
+        let area: i32 = cells.iter().map(|(_, _, w, h)| w * h).sum();
+        assert_eq!(area, 1366 * 768);
+    }
+
+    #[test]
+    fn region_grids_parse_by_size_or_name() {
+        let grid = |rows, cols, cell| Ok(RegionGrid { rows, cols, cell });
+        assert_eq!(parse_region_grid("2x2:1"), grid(2, 2, 1));
+        assert_eq!(parse_region_grid(" 3X4 : 12 "), grid(3, 4, 12));
+        assert_eq!(parse_region_grid("Right-Half"), grid(1, 2, 2));
+        assert_eq!(parse_region_grid("bottom-third"), grid(3, 1, 3));
+    }
+
+    #[test]
+    fn malformed_region_grids_are_rejected() {
+        for s in [
+            "", "2x2", "2:1", "2x2:", "0x2:1", "2x0:1", "2x2:0", "-1x2:1", "axb:c", "left",
+        ] {
+            let err = parse_region_grid(s).unwrap_err();
+            assert!(
+                err.starts_with("expected ROWSxCOLS:CELL"),
+                "{:?}: {}",
+                s,
+                err
+            );
+        }
+        assert_eq!(
+            parse_region_grid("2x2:5").unwrap_err(),
+            "a 2x2 grid has cells 1 to 4, not 5"
+        );
+        let overflow = parse_region_grid("65536x65536:1").unwrap_err();
+        assert!(overflow.contains("cells 1 to ?"), "{}", overflow);
+    }
+}
@@ -0,0 +1,162 @@
+//! `--follow-window`: the bounds of a (moving) window over the recording.
+//!
+//! ffmpeg cannot move a crop once a stream is running, and restarting the
+//! capture on every move would cut the recording into pieces. So this mode
+//! records the full screen and the supervisor samples the window's bounds
+//! once a second, appending a line to `FOLLOW_FILE` whenever they change.
+//! `stop` turns that into a `<recording>.follow.json` timeline for a
+//! crop-follow pass afterwards.
+//!
+//! Limitations: bounds come from System Events (Accessibility permission),
+//! in the same points as `--x/--y/--width/--height`; moves between samples
+//! are not seen; the first window whose title contains the text is used.
+
+use crate::session::Session;
+use crate::{write_log, FOLLOW_FILE};
+use chrono::Local;
+use serde_json::{json, Value};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// AppleScript taking the title as its argument, so it needs no quoting.
+const BOUNDS_SCRIPT: &[&str] = &[
+    "on run argv",
+    "tell application \"System Events\"",
+    "repeat with p in (every process whose visible is true)",
+    "repeat with w in (every window of p)",
+    "if name of w contains (item 1 of argv) then",
+    "set {x, y} to position of w",
+    "set {ww, hh} to size of w",
+    "return (x as text) & \" \" & y & \" \" & ww & \" \" & hh",
+    "end if",
+    "end repeat",
+    "end repeat",
+    "end tell",
+    "end run",
+];
+
+type Bounds = (i32, i32, i32, i32);
+
+/// (x, y, w, h) of the first visible window whose title contains `title`.
+pub fn window_bounds(title: &str) -> Option<Bounds> {
+    let mut cmd = Command::new("osascript");
+    for line in BOUNDS_SCRIPT {
+        cmd.args(["-e", line]);
+    }
+    let output = cmd
+        .arg(title)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let reply = String::from_utf8_lossy(&output.stdout);
+    let nums: Vec<i32> = reply
+        .split_whitespace()
+        .filter_map(|n| n.parse().ok())
+        .collect();
+    match nums[..] {
+        [x, y, w, h] => Some((x, y, w, h)),
+        _ => None,
+    }
+}
+
+/// Start a timeline for `title`, replacing any previous one.
+pub fn begin(title: &str) -> std::io::Result<()> {
+    fs::write(&*FOLLOW_FILE, format!("# {}\n", title.replace('\n', " ")))
+}
+
+pub fn clear() {
+    let _ = fs::remove_file(&*FOLLOW_FILE);
+}
+
+/// Seconds since the recording started.
+fn elapsed() -> f64 {
+    Session::current()
+        .and_then(|s| (Local::now() - s.started_at).to_std().ok())
+        .map_or(0.0, |d| d.as_secs_f64())
+}
+
+/// Samples the window for the supervisor, keeping only changes.
+pub struct Tracker {
+    title: String,
+    /// `None` before the first sample; `Some(None)` while the window is gone
+    last: Option<Option<Bounds>>,
+}
+
+impl Tracker {
+    pub fn new(title: &str) -> Self {
+        Tracker {
+            title: title.to_string(),
+            last: None,
+        }
+    }
+
+    pub fn poll(&mut self) {
+        let bounds = window_bounds(&self.title);
+        if self.last == Some(bounds) {
+            return;
+        }
+        self.last = Some(bounds);
+        let at = elapsed();
+        let line = match bounds {
+            Some((x, y, w, h)) => {
+                write_log(&format!(
+                    "--follow-window: {}x{} at {},{} ({:.1}s)",
+                    w, h, x, y, at
+                ));
+                format!("{:.3} {} {} {} {}", at, x, y, w, h)
+            }
+            None => {
+                write_log(&format!(
+                    "--follow-window: '{}' not found ({:.1}s)",
+                    self.title, at
+                ));
+                format!("{:.3} -", at)
+            }
+        };
+        if let Ok(mut file) = OpenOptions::new().append(true).open(&*FOLLOW_FILE) {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+}
+
+/// Write the pending timeline next to `out` as `<stem>.follow.json`, times
+/// shifted by `offset` seconds (an intro prepended since). Consumed.
+pub fn finish(out: &Path, offset: f64) -> Option<PathBuf> {
+    let saved = fs::read_to_string(&*FOLLOW_FILE).ok()?;
+    clear();
+    let mut lines = saved.lines();
+    let title = lines.next()?.strip_prefix("# ")?.to_string();
+    let bounds: Vec<Value> = lines
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let t = parts.next()?.parse::<f64>().ok()? + offset;
+            let nums: Vec<i32> = parts.filter_map(|n| n.parse().ok()).collect();
+            Some(match nums[..] {
+                [x, y, width, height] => {
+                    json!({ "t": t, "x": x, "y": y, "width": width, "height": height })
+                }
+                _ => json!({ "t": t, "found": false }),
+            })
+        })
+        .collect();
+
+    let stem = out.file_stem()?.to_string_lossy();
+    let path = out.with_file_name(format!("{}.follow.json", stem));
+    let doc = json!({ "window": title, "recording": out, "bounds": bounds });
+    match serde_json::to_string_pretty(&doc).map(|s| fs::write(&path, s)) {
+        Ok(Ok(())) => {
+            write_log(&format!(
+                "Window bounds timeline written: {}",
+                path.display()
+            ));
+            Some(path)
+        }
+        _ => {
+            write_log(&format!("Failed to write {}", path.display()));
+            None
+        }
+    }
+}
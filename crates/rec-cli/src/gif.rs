@@ -0,0 +1,126 @@
+//! `--format gif`: record lossless, build the GIF in two passes at `stop`.
+//!
+//! A GIF has 256 colors, and the best 256 come from every frame of the
+//! recording. So ffmpeg records the lossless capture of
+//! `--encode-in-background-after-stop`, and `stop` runs two passes over it:
+//! `palettegen` into a hidden `.<name>.palette.png`, then `paletteuse` with
+//! that palette into the GIF. Both passes run in `stop` one after the other,
+//! so no palette is ever left half-written, and the palette is removed once
+//! the GIF is (or fails to be) written.
+
+use crate::ffmpeg::probe_duration;
+use crate::output::file_size;
+use crate::title::{ffmpeg_timeout, run_ffmpeg};
+use crate::{remove_temp, write_log};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Frame rate of the GIF
+pub const FPS: u32 = 15;
+/// Width of the GIF; the height keeps the aspect ratio
+pub const WIDTH: u32 = 960;
+
+/// The palette pass one writes and pass two reads.
+pub fn palette_path(out: &Path) -> PathBuf {
+    let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+    out.with_file_name(format!(".{}.palette.png", stem))
+}
+
+/// Frame rate and size, the same in both passes so the palette matches.
+fn resize() -> String {
+    format!("fps={},scale={}:-1:flags=lanczos", FPS, WIDTH)
+}
+
+/// Pass one: the palette of the whole `capture`.
+pub fn palettegen_args(capture: &Path, palette: &Path) -> Vec<String> {
+    vec![
+        "-i".into(),
+        capture.to_string_lossy().into_owned(),
+        "-vf".into(),
+        format!("{},palettegen", resize()),
+        palette.to_string_lossy().into_owned(),
+    ]
+}
+
+/// Pass two: `capture` mapped onto `palette`, into the GIF at `out`.
+pub fn paletteuse_args(capture: &Path, palette: &Path, out: &Path) -> Vec<String> {
+    vec![
+        "-i".into(),
+        capture.to_string_lossy().into_owned(),
+        "-i".into(),
+        palette.to_string_lossy().into_owned(),
+        "-lavfi".into(),
+        format!("{}[x];[x][1:v]paletteuse", resize()),
+        out.to_string_lossy().into_owned(),
+    ]
+}
+
+/// Run both passes over `capture` into `out`. The capture is removed once
+/// `out` is written (unless `--keep-temp`) and kept when anything fails.
+pub fn encode(capture: &Path, out: &Path) -> Result<(), String> {
+    let palette = palette_path(out);
+    let timeout = ffmpeg_timeout(probe_duration(capture));
+    write_log(&format!(
+        "GIF: palette of {} into {}, then the GIF into {}",
+        capture.display(),
+        palette.display(),
+        out.display()
+    ));
+    let result = run_ffmpeg(&palettegen_args(capture, &palette), timeout)
+        .map_err(|e| format!("palettegen: {}", e))
+        .and_then(|()| {
+            run_ffmpeg(&paletteuse_args(capture, &palette, out), timeout)
+                .map_err(|e| format!("paletteuse: {}", e))
+        })
+        .and_then(|()| match file_size(out) {
+            0 => Err("ffmpeg wrote an empty GIF".into()),
+            _ => Ok(()),
+        });
+    // The palette is scratch either way; a failed GIF is not worth keeping
+    let _ = fs::remove_file(&palette);
+    match result {
+        Ok(()) => {
+            write_log(&format!("GIF written: {} KB", file_size(out) / 1024));
+            remove_temp(capture);
+            Ok(())
+        }
+        Err(err) => {
+            let _ = fs::remove_file(out);
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_passes_resize_alike() {
+        let capture = Path::new("/r/.rec.lossless.mkv");
+        let palette = palette_path(Path::new("/r/rec.gif"));
+        assert_eq!(palette, Path::new("/r/.rec.palette.png"));
+        assert_eq!(
+            palettegen_args(capture, &palette),
+            [
+                "-i",
+                "/r/.rec.lossless.mkv",
+                "-vf",
+                "fps=15,scale=960:-1:flags=lanczos,palettegen",
+                "/r/.rec.palette.png",
+            ]
+        );
+        assert_eq!(
+            paletteuse_args(capture, &palette, Path::new("/r/rec.gif")),
+            [
+                "-i",
+                "/r/.rec.lossless.mkv",
+                "-i",
+                "/r/.rec.palette.png",
+                "-lavfi",
+                "fps=15,scale=960:-1:flags=lanczos[x];[x][1:v]paletteuse",
+                "/r/rec.gif",
+            ]
+        );
+    }
+}
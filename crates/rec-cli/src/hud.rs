@@ -0,0 +1,115 @@
+//! `--hud`: a small always-on-top timer for the presenter, kept out of the
+//! recording itself.
+//!
+//! The window is drawn by `osascript` running a JavaScript for Automation
+//! snippet, so no extra helper has to be installed. It is marked
+//! `NSWindowSharingNone`, which screen capture leaves out, ignores the mouse,
+//! and closes itself once the ffmpeg it was started for is gone (e.g. at the
+//! end of a `--duration` recording).
+
+use crate::process::pid_alive;
+use crate::{write_log, HUD_PID_FILE};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::fs;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+const SCRIPT: &str = r#"
+ObjC.import('Cocoa');
+ObjC.bindFunction('kill', ['int', ['int', 'int']]);
+
+function elapsed(secs) {
+    var pad = function (n) { return (n < 10 ? '0' : '') + n; };
+    var h = Math.floor(secs / 3600), m = Math.floor(secs / 60) % 60, s = secs % 60;
+    return (h > 0 ? h + ':' + pad(m) : m) + ':' + pad(s);
+}
+
+function run(argv) {
+    var ffmpeg = parseInt(argv[0], 10);
+    var started = Date.now();
+    $.NSApplication.sharedApplication.setActivationPolicy($.NSApplicationActivationPolicyAccessory);
+
+    var w = 120, h = 32;
+    var screen = $.NSScreen.mainScreen.visibleFrame;
+    var frame = $.NSMakeRect(
+        screen.origin.x + screen.size.width - w - 16,
+        screen.origin.y + screen.size.height - h - 16,
+        w, h);
+    var panel = $.NSPanel.alloc.initWithContentRectStyleMaskBackingDefer(
+        frame,
+        $.NSWindowStyleMaskBorderless | $.NSWindowStyleMaskNonactivatingPanel,
+        $.NSBackingStoreBuffered,
+        false);
+    panel.level = 25; // NSStatusWindowLevel
+    panel.sharingType = 0; // NSWindowSharingNone: invisible to screen capture
+    panel.collectionBehavior = 1 | 16; // all Spaces, stationary
+    panel.ignoresMouseEvents = true;
+    panel.opaque = false;
+    panel.backgroundColor = $.NSColor.colorWithCalibratedWhiteAlpha(0, 0.65);
+
+    var label = $.NSTextField.labelWithString($(''));
+    label.frame = $.NSMakeRect(0, 6, w, 20);
+    label.alignment = $.NSTextAlignmentCenter;
+    label.textColor = $.NSColor.whiteColor;
+    label.font = $.NSFont.monospacedDigitSystemFontOfSizeWeight(15, 0);
+    panel.contentView.addSubview(label);
+    panel.orderFrontRegardless;
+
+    while ($.kill(ffmpeg, 0) === 0) {
+        label.stringValue = $('● REC ' + elapsed(Math.floor((Date.now() - started) / 1000)));
+        $.NSRunLoop.currentRunLoop.runUntilDate($.NSDate.dateWithTimeIntervalSinceNow(0.5));
+    }
+}
+"#;
+
+/// Open the timer for the recording ffmpeg `pid`. Returns why the HUD is
+/// unavailable on failure; the recording itself goes ahead either way.
+pub fn open(pid: i32) -> Result<(), String> {
+    close();
+    let mut child = Command::new("osascript")
+        .args(["-l", "JavaScript", "-e", SCRIPT])
+        .arg(pid.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("osascript not found on PATH ({})", e))?;
+
+    // A script error (or no window server) ends osascript right away
+    thread::sleep(Duration::from_millis(200));
+    if let Ok(Some(status)) = child.try_wait() {
+        let stderr = child
+            .wait_with_output()
+            .map(|o| String::from_utf8_lossy(&o.stderr).trim().to_string())
+            .unwrap_or_default();
+        return Err(if stderr.is_empty() {
+            format!("osascript exited with {}", status)
+        } else {
+            stderr
+        });
+    }
+
+    fs::write(&*HUD_PID_FILE, child.id().to_string())
+        .map_err(|e| format!("cannot write {}: {}", *HUD_PID_FILE, e))?;
+    write_log(&format!("HUD opened (osascript pid {})", child.id()));
+    Ok(())
+}
+
+/// PID of the running HUD, if there is one.
+pub fn pid() -> Option<i32> {
+    fs::read_to_string(&*HUD_PID_FILE)
+        .ok()
+        .and_then(|p| p.trim().parse::<i32>().ok())
+        .filter(|pid| pid_alive(*pid))
+}
+
+/// Close the HUD, if one is open.
+pub fn close() {
+    if let Some(pid) = pid() {
+        let _ = kill(Pid::from_raw(pid), Signal::SIGTERM);
+        write_log(&format!("HUD closed (osascript pid {})", pid));
+    }
+    let _ = fs::remove_file(&*HUD_PID_FILE);
+}
@@ -0,0 +1,149 @@
+//! `--capture-keys`: keystrokes logged during a recording, written out as an
+//! `.srt` caption file next to it.
+//!
+//! Listening for keys on macOS needs a CoreGraphics event tap and the
+//! Accessibility permission, so the work is done by a separate `rec-keys`
+//! helper on `PATH`. The helper prints one line per keystroke,
+//! `<unix seconds> <key label>` (e.g. `1718000000.125 ⌘S`), and exits
+//! non-zero with a message on stderr when the permission is denied.
+
+use crate::{write_log, KEYS_FILE, KEYS_PID_FILE};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const KEYS_HELPER: &str = "rec-keys";
+
+/// Keys pressed within this many seconds of each other share a caption.
+const GROUP_GAP_SECS: f64 = 1.0;
+/// How long a caption stays up after its last key.
+const LINGER_SECS: f64 = 1.0;
+
+fn now_secs() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// Start the helper. Returns why key capture is unavailable on failure; the
+/// recording itself goes ahead either way.
+pub fn start_capture() -> Result<(), String> {
+    let _ = fs::remove_file(&*KEYS_PID_FILE);
+    let keys =
+        File::create(&*KEYS_FILE).map_err(|e| format!("cannot create {}: {}", *KEYS_FILE, e))?;
+    let mut child = Command::new(KEYS_HELPER)
+        .stdin(Stdio::null())
+        .stdout(keys)
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("{} not found on PATH ({})", KEYS_HELPER, e))?;
+    let started = now_secs();
+
+    // A denied Accessibility permission makes the helper exit right away
+    thread::sleep(Duration::from_millis(200));
+    if let Ok(Some(status)) = child.try_wait() {
+        let stderr = child
+            .wait_with_output()
+            .map(|o| String::from_utf8_lossy(&o.stderr).trim().to_string())
+            .unwrap_or_default();
+        let reason = if stderr.is_empty() {
+            format!("{} exited with {}", KEYS_HELPER, status)
+        } else {
+            stderr
+        };
+        return Err(format!(
+            "{} (grant Accessibility access in System Settings > Privacy & Security)",
+            reason
+        ));
+    }
+
+    fs::write(&*KEYS_PID_FILE, format!("{} {}", child.id(), started))
+        .map_err(|e| format!("cannot write {}: {}", *KEYS_PID_FILE, e))?;
+    write_log(&format!("Key capture started (pid {})", child.id()));
+    Ok(())
+}
+
+/// Stop the helper, if one is running, and write `<output>.srt` from what it
+/// logged. `None` when key capture was not active or nothing was pressed.
+pub fn finish_capture(output: &Path) -> Option<PathBuf> {
+    let state = fs::read_to_string(&*KEYS_PID_FILE).ok()?;
+    let _ = fs::remove_file(&*KEYS_PID_FILE);
+    let mut parts = state.split_whitespace();
+    let pid: i32 = parts.next()?.parse().ok()?;
+    let started: f64 = parts.next()?.parse().ok()?;
+    let _ = kill(Pid::from_raw(pid), Signal::SIGTERM);
+
+    let log = fs::read_to_string(&*KEYS_FILE).unwrap_or_default();
+    let _ = fs::remove_file(&*KEYS_FILE);
+    let presses: Vec<(f64, &str)> = log
+        .lines()
+        .filter_map(|line| {
+            let (ts, key) = line.trim_end().split_once(' ')?;
+            Some((ts.parse::<f64>().ok()? - started, key))
+        })
+        .filter(|(t, key)| *t >= 0.0 && !key.is_empty())
+        .collect();
+    if presses.is_empty() {
+        write_log("Key capture: no keys recorded");
+        return None;
+    }
+
+    let srt = output.with_extension("srt");
+    match fs::write(&srt, to_srt(&presses)) {
+        Ok(()) => {
+            write_log(&format!("Key captions written: {}", srt.display()));
+            Some(srt)
+        }
+        Err(err) => {
+            write_log(&format!("Failed to write {}: {}", srt.display(), err));
+            None
+        }
+    }
+}
+
+/// SRT cues for `(seconds since start, key)` presses, in order.
+fn to_srt(presses: &[(f64, &str)]) -> String {
+    let mut cues: Vec<(f64, f64, Vec<&str>)> = Vec::new();
+    for &(t, key) in presses {
+        match cues.last_mut() {
+            Some((_, last, keys)) if t - *last <= GROUP_GAP_SECS => {
+                *last = t;
+                keys.push(key);
+            }
+            _ => cues.push((t, t, vec![key])),
+        }
+    }
+
+    let mut out = String::new();
+    for (i, (start, last, keys)) in cues.iter().enumerate() {
+        // Never overlap the next cue
+        let mut end = last + LINGER_SECS;
+        if let Some((next, _, _)) = cues.get(i + 1) {
+            end = end.min(*next);
+        }
+        out.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            srt_time(*start),
+            srt_time(end),
+            keys.join(" ")
+        ));
+    }
+    out
+}
+
+fn srt_time(secs: f64) -> String {
+    let ms = (secs * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
@@ -0,0 +1,467 @@
+//! Recording control for rec.nvim.
+//!
+//! The `rec-cli` binary is a thin wrapper around [`Recorder`]; other tools can
+//! link this crate to start, stop and inspect recordings directly.
+
+pub mod chapters;
+pub mod clipboard;
+pub mod compress;
+pub mod config;
+pub mod crypt;
+pub mod cursor;
+pub mod devices;
+pub mod events;
+pub mod ffmpeg;
+pub mod follow;
+pub mod gif;
+pub mod hud;
+pub mod keys;
+pub mod loudness;
+pub mod nvim;
+pub mod output;
+pub mod poster;
+pub mod power;
+pub mod process;
+mod recorder;
+pub mod resume;
+pub mod schedule;
+pub mod segments;
+pub mod session;
+pub mod silence;
+pub mod stats;
+pub mod summary;
+pub mod title;
+
+pub use devices::{Device, DeviceKind};
+pub use recorder::{
+    Benchmark, PreflightCheck, PreparedRecording, Recorder, RecordingHandle, RecordingState,
+    RegionTest, SizeEstimate, StartOptions, StopOptions, StopSignal, StoppedRecording,
+    TrimmedRecording,
+};
+
+use chrono::{Local, SecondsFormat};
+use clap::ValueEnum;
+use once_cell::sync::Lazy;
+use serde_json::json;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// Where the state files below live instead of /tmp, so that tests and
+/// side-by-side installs do not share them
+pub const STATE_DIR_ENV: &str = "REC_STATE_DIR";
+
+/// `--session`: the named session this process records, stops or inspects
+static SESSION: OnceLock<String> = OnceLock::new();
+
+/// Work on the session `name` rather than the default one. Must come before
+/// anything touches a state file, which are then `rec.nvim.<name>.*`.
+pub fn set_session(name: &str) {
+    if name != process::DEFAULT_SESSION {
+        let _ = SESSION.set(name.to_string());
+    }
+}
+
+/// The session this process works on: [`process::DEFAULT_SESSION`] unless
+/// `--session` named another.
+pub fn current_session() -> &'static str {
+    SESSION
+        .get()
+        .map_or(process::DEFAULT_SESSION, String::as_str)
+}
+
+/// `REC_STATE_DIR`, else /tmp (a directory of their own for unit tests,
+/// which must not touch a real recording's files).
+fn state_dir() -> PathBuf {
+    match std::env::var(STATE_DIR_ENV) {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ if cfg!(test) => {
+            let dir = std::env::temp_dir().join(format!("rec-cli-test-{}", std::process::id()));
+            let _ = fs::create_dir_all(&dir);
+            dir
+        }
+        _ => PathBuf::from("/tmp"),
+    }
+}
+
+/// `rec.nvim.<file>` in the state directory, `rec.nvim.<session>.<file>`
+/// for a named session.
+fn state_file(file: &str) -> String {
+    let name = match SESSION.get() {
+        Some(session) => format!("rec.nvim.{}.{}", session, file),
+        None => format!("rec.nvim.{}", file),
+    };
+    state_dir().join(name).to_string_lossy().into_owned()
+}
+
+pub static PID_FILE: Lazy<String> = Lazy::new(|| state_file("pid"));
+pub static OUT_FILE: Lazy<String> = Lazy::new(|| state_file("outpath"));
+pub static LOG_FILE: Lazy<String> = Lazy::new(|| state_file("ffmpeg.log"));
+pub static EVENTS_FILE: Lazy<String> = Lazy::new(|| state_file("events"));
+/// Pending `--stats` record, completed by `stop`
+pub static STATS_FILE: Lazy<String> = Lazy::new(|| state_file("stats.json"));
+/// FIFO wired to ffmpeg's stdin so `stop` can send `q` (see `StopSignal::Q`)
+pub static STDIN_FIFO: Lazy<String> = Lazy::new(|| state_file("stdin"));
+/// The running recording's [`session::Session`], versioned JSON
+pub static STATE_FILE: Lazy<String> = Lazy::new(|| state_file("state.json"));
+/// Unversioned session file of rec-cli before `STATE_FILE`; still read
+pub static SESSION_FILE: Lazy<String> = Lazy::new(|| state_file("session.json"));
+/// ffmpeg's `-progress` report, watched by `start` for the first frame
+pub static PROGRESS_FILE: Lazy<String> = Lazy::new(|| state_file("progress"));
+/// flock()ed while a `start` of any session checks for and then launches a
+/// recording
+pub static START_LOCK: Lazy<String> = Lazy::new(|| {
+    state_dir()
+        .join("rec.nvim.lock")
+        .to_string_lossy()
+        .into_owned()
+});
+/// PID of the `--preview` ffplay window
+pub static PREVIEW_PID_FILE: Lazy<String> = Lazy::new(|| state_file("preview.pid"));
+/// Keystrokes logged by the `--capture-keys` helper
+pub static KEYS_FILE: Lazy<String> = Lazy::new(|| state_file("keys"));
+/// `<pid> <start time>` of the running key capture helper
+pub static KEYS_PID_FILE: Lazy<String> = Lazy::new(|| state_file("keys.pid"));
+/// PID of the `--hud` timer window
+pub static HUD_PID_FILE: Lazy<String> = Lazy::new(|| state_file("hud.pid"));
+/// Pointer size `--big-cursor` replaced, put back by `stop`
+pub static CURSOR_SIZE_FILE: Lazy<String> = Lazy::new(|| state_file("cursor_size"));
+/// `rec-cli mark` markers of the running recording
+pub static MARKERS_FILE: Lazy<String> = Lazy::new(|| state_file("markers"));
+/// `--follow-window` title and bounds timeline of the running recording
+pub static FOLLOW_FILE: Lazy<String> = Lazy::new(|| state_file("follow"));
+/// Pasteboard change times of a `--track-clipboard` recording
+pub static CLIPBOARD_FILE: Lazy<String> = Lazy::new(|| state_file("clipboard"));
+/// Start time of a running `--segment-time` recording
+pub static SEGMENTS_FILE: Lazy<String> = Lazy::new(|| state_file("segments"));
+/// Pending `--title-card`, applied by `stop`
+pub static TITLE_CARD_FILE: Lazy<String> = Lazy::new(|| state_file("title.json"));
+/// The `start --delay`/`--start-at` waiting to begin
+pub static SCHEDULE_FILE: Lazy<String> = Lazy::new(|| state_file("schedule.json"));
+
+/// The log ffmpeg and rec-cli write to. Everything that opens the log or
+/// tells the user where it is goes through here.
+pub fn log_path() -> &'static Path {
+    Path::new(&*LOG_FILE)
+}
+
+/// How `write_log` writes rec-cli's own lines; ffmpeg's stderr is appended
+/// as it comes either way.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// The message as is
+    #[default]
+    Text,
+    /// One JSON object per line: {"time", "level", "pid", "msg"}, plus
+    /// "marker" on the START/STOP lines and "fields" for a `Key: value` line
+    Json,
+}
+
+/// `--log-format json`
+static LOG_JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn set_log_format(format: LogFormat) {
+    LOG_JSON.store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+pub fn log_format() -> LogFormat {
+    match LOG_JSON.load(Ordering::Relaxed) {
+        true => LogFormat::Json,
+        false => LogFormat::Text,
+    }
+}
+
+/// Append one line to the log. ffmpeg writes to the same file, so the line
+/// goes out in a single append-mode write that its output cannot split.
+pub fn write_log(msg: &str) {
+    let line = match log_format() {
+        LogFormat::Text => msg.to_string(),
+        LogFormat::Json => json_log_line(msg),
+    };
+    // Best effort: a log that cannot be opened (a read-only or full state
+    // directory) is no reason to fail the command it describes
+    if let Ok(mut f) = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path())
+    {
+        let _ = f.write_all(format!("{}\n", line).as_bytes());
+        let _ = f.flush();
+    }
+}
+
+fn json_log_line(msg: &str) -> String {
+    let level = match msg.starts_with("WARNING") {
+        true => "warn",
+        false => "info",
+    };
+    let mut line = json!({
+        "time": Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+        "level": level,
+        "pid": std::process::id(),
+        "msg": msg,
+    });
+    if let Some(marker) = msg
+        .strip_prefix("===== ")
+        .and_then(|m| m.strip_suffix(" ====="))
+    {
+        line["marker"] = json!(marker.to_lowercase());
+    }
+    // `Output: /path`, `Screen size: 1920x1080`: a short label and its value
+    if let Some((key, value)) = msg.split_once(": ") {
+        let label = key.split_whitespace().count() <= 3
+            && key.starts_with(|c: char| c.is_ascii_uppercase())
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || " /-".contains(c));
+        if label && !msg.starts_with("WARNING") {
+            let key = key.to_lowercase().replace([' ', '/', '-'], "_");
+            line["fields"] = json!({ key: value });
+        }
+    }
+    line.to_string()
+}
+
+/// Append what a tool printed to the log as is, e.g. the stderr of an
+/// ffmpeg run through [`process::run_command`].
+pub fn append_log(output: &[u8]) {
+    if output.is_empty() {
+        return;
+    }
+    if let Ok(mut f) = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(log_path())
+    {
+        let _ = f.write_all(output);
+    }
+}
+
+/// Push the log to disk, ffmpeg's last lines included, before rec-cli exits.
+pub fn sync_log() {
+    if let Ok(f) = OpenOptions::new().append(true).open(log_path()) {
+        let _ = f.sync_data();
+    }
+}
+
+/// `--keep-temp`: leave intermediates where they are
+static KEEP_TEMP: AtomicBool = AtomicBool::new(false);
+/// The intermediates `remove_temp` left in place
+static KEPT_TEMP: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Keep the intermediates post-processing would delete, to debug an encode.
+pub fn set_keep_temp(keep: bool) {
+    KEEP_TEMP.store(keep, Ordering::Relaxed);
+}
+
+/// Delete the intermediate `path`, or with `--keep-temp` log it and leave it.
+pub fn remove_temp(path: &Path) {
+    if !KEEP_TEMP.load(Ordering::Relaxed) {
+        let _ = fs::remove_file(path);
+    } else if path.exists() {
+        write_log(&format!("--keep-temp: kept {}", path.display()));
+        KEPT_TEMP
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(path.to_path_buf());
+    }
+}
+
+/// The intermediates kept so far, in the order they were done with.
+pub fn kept_temp() -> Vec<PathBuf> {
+    KEPT_TEMP.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// What went wrong, for the machine-readable channels. Each code is printed
+/// as a stable `REC_*` token the Neovim plugin matches on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    AlreadyRunning,
+    /// `--concurrency-limit` recordings are running already
+    TooManySessions,
+    NotRunning,
+    StartFailed,
+    StopFailed,
+    /// ffmpeg is not installed (or not on PATH)
+    NoFfmpeg,
+    /// ffmpeg died from a signal no `stop` sent (the OOM killer, a crash)
+    KilledExternally,
+    StreamClosed,
+    NoFrames,
+    NoScreenPermission,
+    UnsupportedMode,
+    BadCrop,
+    CropTooSmall,
+    CropProbeFailed,
+    BadDevice,
+    LowDisk,
+    /// `--refuse-if-battery-below` and running on battery
+    LowBattery,
+    OutputDirGone,
+    /// The output path is longer than the OS allows
+    PathTooLong,
+    OutputCorrupt,
+    /// ffprobe or the repair remux did not finish in time
+    VerifyTimedOut,
+    /// The lossless capture could not be encoded into the output at `stop`
+    EncodeFailed,
+    EncryptFailed,
+    DecryptFailed,
+    /// `--fail-fast` refused a fallback without a more specific code
+    FallbackRefused,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn token(self) -> &'static str {
+        match self {
+            ErrorCode::AlreadyRunning => "REC_ALREADY_RUNNING",
+            ErrorCode::TooManySessions => "REC_TOO_MANY_SESSIONS",
+            ErrorCode::NotRunning => "REC_NOT_RUNNING",
+            ErrorCode::StartFailed => "REC_START_ERR",
+            ErrorCode::StopFailed => "REC_STOP_ERR",
+            ErrorCode::NoFfmpeg => "REC_NO_FFMPEG",
+            ErrorCode::KilledExternally => "REC_KILLED_EXTERNALLY",
+            ErrorCode::StreamClosed => "REC_STREAM_CLOSED",
+            ErrorCode::NoFrames => "REC_NO_FRAMES",
+            ErrorCode::NoScreenPermission => "REC_NO_SCREEN_PERMISSION",
+            ErrorCode::UnsupportedMode => "REC_UNSUPPORTED_MODE",
+            ErrorCode::BadCrop => "REC_BAD_CROP",
+            ErrorCode::CropTooSmall => "REC_CROP_TOO_SMALL",
+            ErrorCode::CropProbeFailed => "REC_CROP_PROBE_FAILED",
+            ErrorCode::BadDevice => "REC_BAD_DEVICE",
+            ErrorCode::LowDisk => "REC_LOW_DISK",
+            ErrorCode::LowBattery => "REC_LOW_BATTERY",
+            ErrorCode::OutputDirGone => "REC_OUTPUT_DIR_GONE",
+            ErrorCode::PathTooLong => "REC_PATH_TOO_LONG",
+            ErrorCode::OutputCorrupt => "REC_OUTPUT_CORRUPT",
+            ErrorCode::VerifyTimedOut => "REC_VERIFY_TIMEOUT",
+            ErrorCode::EncodeFailed => "REC_ENCODE_ERR",
+            ErrorCode::EncryptFailed => "REC_ENCRYPT_ERR",
+            ErrorCode::DecryptFailed => "REC_DECRYPT_ERR",
+            ErrorCode::FallbackRefused => "REC_FALLBACK_REFUSED",
+            ErrorCode::Internal => "REC_ERROR",
+        }
+    }
+
+    /// One-line human description, used when a rejection carries no details.
+    pub fn summary(self) -> &'static str {
+        match self {
+            ErrorCode::AlreadyRunning => "a recording is already running",
+            ErrorCode::TooManySessions => "too many recordings are running",
+            ErrorCode::NotRunning => "no recording is running",
+            ErrorCode::StartFailed => "the recording could not be started",
+            ErrorCode::StopFailed => "the recording could not be stopped cleanly",
+            ErrorCode::NoFfmpeg => "ffmpeg is not installed",
+            ErrorCode::KilledExternally => "ffmpeg was killed before the recording was stopped",
+            ErrorCode::StreamClosed => "the program reading the recording closed the pipe",
+            ErrorCode::NoFrames => "ffmpeg is running but captured no frames",
+            ErrorCode::NoScreenPermission => "macOS did not let ffmpeg capture the screen",
+            ErrorCode::UnsupportedMode => "the device does not support this size and frame rate",
+            ErrorCode::BadCrop => "the crop region is invalid",
+            ErrorCode::CropTooSmall => "the crop region is too small to record",
+            ErrorCode::CropProbeFailed => "the screen size could not be probed",
+            ErrorCode::BadDevice => "the capture device does not exist",
+            ErrorCode::LowDisk => "not enough free disk space",
+            ErrorCode::LowBattery => "the battery is too low to record on",
+            ErrorCode::OutputDirGone => "the output directory is gone",
+            ErrorCode::PathTooLong => "the output path is too long",
+            ErrorCode::OutputCorrupt => "the recording is not playable",
+            ErrorCode::VerifyTimedOut => "checking the recording did not finish in time",
+            ErrorCode::EncodeFailed => "the recording could not be encoded",
+            ErrorCode::EncryptFailed => "the recording could not be encrypted",
+            ErrorCode::DecryptFailed => "the recording could not be decrypted",
+            ErrorCode::FallbackRefused => "--fail-fast refused to record with a fallback",
+            ErrorCode::Internal => "unexpected error",
+        }
+    }
+
+    /// Whether the ffmpeg log is likely to explain the failure.
+    pub fn logged(self) -> bool {
+        matches!(
+            self,
+            ErrorCode::StartFailed
+                | ErrorCode::NoFrames
+                | ErrorCode::NoScreenPermission
+                | ErrorCode::StopFailed
+                | ErrorCode::CropProbeFailed
+                | ErrorCode::OutputCorrupt
+                | ErrorCode::VerifyTimedOut
+                | ErrorCode::EncodeFailed
+                | ErrorCode::Internal
+        )
+    }
+}
+
+/// A request the recorder declined, and the error type of [`Recorder`]'s
+/// commands. `code` is printed as the machine-readable marker the Neovim
+/// plugin matches on (`REC_ALREADY_RUNNING`, ...); `details` are the
+/// human-readable lines printed after it. Failures with no code of their own
+/// (I/O errors and the like) arrive as [`ErrorCode::Internal`], exit 1.
+#[derive(Debug)]
+pub struct Rejection {
+    pub code: ErrorCode,
+    pub details: Vec<String>,
+    /// Process exit status `rec-cli` reports the rejection with
+    pub exit_code: i32,
+}
+
+impl Rejection {
+    pub fn new(code: ErrorCode) -> Self {
+        Rejection {
+            code,
+            details: Vec::new(),
+            exit_code: 0,
+        }
+    }
+
+    pub fn exit_code(mut self, code: i32) -> Self {
+        self.exit_code = code;
+        self
+    }
+
+    pub fn detail(mut self, line: impl Into<String>) -> Self {
+        self.details.push(line.into());
+        self
+    }
+
+    /// Point the user at the log, as the last detail.
+    pub fn with_log(self) -> Self {
+        let line = format!("Log: {}", log_path().display());
+        self.detail(line)
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code.token())?;
+        for line in &self.details {
+            write!(f, ": {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+impl From<anyhow::Error> for Rejection {
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast::<Rejection>() {
+            Ok(rejection) => rejection,
+            Err(err) => Rejection {
+                code: ErrorCode::Internal,
+                details: err.chain().map(|e| e.to_string()).collect(),
+                exit_code: 1,
+            },
+        }
+    }
+}
+
+impl From<std::io::Error> for Rejection {
+    fn from(err: std::io::Error) -> Self {
+        anyhow::Error::from(err).into()
+    }
+}
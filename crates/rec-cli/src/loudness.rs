@@ -0,0 +1,144 @@
+//! `stop --normalize-audio`: a two-pass EBU R128 `loudnorm` copy of the
+//! recording.
+//!
+//! The first pass only measures; the second feeds those measurements back
+//! so loudnorm can apply one linear gain instead of riding the level
+//! through the recording. Video is copied, audio re-encoded.
+
+use crate::ffmpeg::probe_audio;
+use crate::output::file_size;
+use crate::{log_path, write_log};
+use serde::Deserialize;
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Integrated loudness `--normalize-audio` aims for, in LUFS
+pub const DEFAULT_TARGET: f64 = -16.0;
+const TRUE_PEAK: f64 = -1.5;
+const LOUDNESS_RANGE: f64 = 11.0;
+
+/// What `--normalize-audio` did.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Normalized {
+    /// A normalized copy was written; `input_lufs` is what was measured
+    Done { output: PathBuf, input_lufs: f64 },
+    /// Nothing to normalize, and why
+    Skipped(String),
+}
+
+/// loudnorm's `print_format=json` report; every value is a string.
+#[derive(Deserialize)]
+struct Measurement {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+    target_offset: String,
+}
+
+fn measure(out: &Path, target: f64) -> Result<Measurement, String> {
+    let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-nostats", "-i"])
+        .arg(out)
+        .args(["-vn", "-af"])
+        .arg(format!(
+            "loudnorm=I={}:TP={}:LRA={}:print_format=json",
+            target, TRUE_PEAK, LOUDNESS_RANGE
+        ))
+        .args(["-f", "null", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .output()
+        .map_err(|e| format!("cannot run ffmpeg: {}", e))?;
+    // The report is the last {...} block on stderr
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let report = stderr
+        .rfind('{')
+        .and_then(|start| Some(&stderr[start..=start + stderr[start..].find('}')?]))
+        .ok_or("ffmpeg printed no loudness report")?;
+    serde_json::from_str(report).map_err(|e| format!("unreadable loudness report: {}", e))
+}
+
+/// Measure `out` and write `<stem>_normalized.<ext>` at `target` LUFS.
+pub fn normalize(out: &Path, target: f64) -> Normalized {
+    let Some((rate, _)) = probe_audio(out) else {
+        write_log("--normalize-audio: no audio track; skipped");
+        return Normalized::Skipped("the recording has no audio track".into());
+    };
+    let measured = match measure(out, target) {
+        Ok(m) => m,
+        Err(err) => {
+            write_log(&format!("--normalize-audio: measuring failed: {}", err));
+            return Normalized::Skipped(format!("could not measure loudness ({})", err));
+        }
+    };
+    // anullsrc, the track of a recording without --audio-name, measures -inf
+    let input_lufs = match measured.input_i.parse::<f64>() {
+        Ok(lufs) if lufs.is_finite() && lufs > -70.0 => lufs,
+        _ => {
+            write_log("--normalize-audio: the audio is silent; skipped");
+            return Normalized::Skipped("the audio track is silent (no --audio-name)".into());
+        }
+    };
+    write_log(&format!(
+        "--normalize-audio: measured {} LUFS (true peak {} dBTP, range {} LU); target {} LUFS",
+        measured.input_i, measured.input_tp, measured.input_lra, target
+    ));
+
+    let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = out
+        .extension()
+        .map(|e| e.to_string_lossy())
+        .unwrap_or("mp4".into());
+    let normalized = out.with_file_name(format!("{}_normalized.{}", stem, ext));
+    let filter = format!(
+        "loudnorm=I={}:TP={}:LRA={}:measured_I={}:measured_TP={}:measured_LRA={}:\
+         measured_thresh={}:offset={}:linear=true",
+        target,
+        TRUE_PEAK,
+        LOUDNESS_RANGE,
+        measured.input_i,
+        measured.input_tp,
+        measured.input_lra,
+        measured.input_thresh,
+        measured.target_offset
+    );
+    let Ok(log) = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path())
+    else {
+        return Normalized::Skipped(format!("cannot open {}", log_path().display()));
+    };
+    // loudnorm resamples to 192 kHz internally; -ar puts the rate back
+    let status = Command::new("ffmpeg")
+        .args(["-hide_banner", "-loglevel", "error", "-y", "-i"])
+        .arg(out)
+        .args(["-af", &filter, "-ar", &rate.to_string()])
+        .args(["-c:v", "copy", "-c:a", "aac", "-movflags", "+faststart"])
+        .arg(&normalized)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(log)
+        .status();
+    match status {
+        Ok(status) if status.success() && file_size(&normalized) > 0 => {
+            write_log(&format!(
+                "Normalized copy written: {}",
+                normalized.display()
+            ));
+            Normalized::Done {
+                output: normalized,
+                input_lufs,
+            }
+        }
+        _ => {
+            write_log(&format!(
+                "--normalize-audio: ffmpeg failed (see {})",
+                log_path().display()
+            ));
+            Normalized::Skipped(format!("ffmpeg failed (see {})", log_path().display()))
+        }
+    }
+}
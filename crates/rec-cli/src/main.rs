@@ -1,346 +1,1744 @@
-use chrono::Local;
-use clap::{Parser, Subcommand};
-use dirs::home_dir;
-use nix::sys::signal::{kill, Signal};
-use nix::unistd::Pid;
-use std::fs::{self, OpenOptions};
-use std::io::Write;
+use chrono::{DateTime, Local, NaiveDateTime};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use nix::sys::signal::{signal, SigHandler, Signal};
+use rec_cli::chapters::{self, Chapters};
+use rec_cli::config::{self, Config};
+use rec_cli::crypt;
+use rec_cli::devices;
+use rec_cli::ffmpeg::{
+    probe_duration, shell_command, tool_version, which, Backend, PROBE_TIMEOUT, SCREEN_INDEX,
+};
+use rec_cli::loudness::Normalized;
+use rec_cli::output::{
+    self, default_output_dir, dir_writable, file_size, parse_filename_format, parse_output_dir,
+    parse_time_bound, recordings, OUTPUT_TS_FORMAT,
+};
+use rec_cli::poster::Poster;
+use rec_cli::process::{parse_session_name, pid_alive, read_pid, run_command};
+use rec_cli::resume::Resumed;
+use rec_cli::silence::SilenceTrimmed;
+use rec_cli::summary::FfmpegSummary;
+use rec_cli::{
+    current_session, events, kept_temp, log_format, log_path, set_keep_temp, set_log_format,
+    set_session, sync_log, write_log, ErrorCode, LogFormat, Recorder, RecordingHandle,
+    RecordingState, Rejection, StartOptions, StopOptions, PID_FILE,
+};
+use serde_json::json;
+use std::io::{IsTerminal, Write};
+use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::time::Duration;
 
-const PID_FILE: &str = "/tmp/rec.nvim.pid";
-const OUT_FILE: &str = "/tmp/rec.nvim.outpath";
-const LOG_FILE: &str = "/tmp/rec.nvim.ffmpeg.log";
-
-/*
-  IMPORTANT (macOS avfoundation):
-  From your device list:
-    [4] Capture screen 0
-*/
-const SCREEN_INDEX: &str = "4";
-
 #[derive(Parser, Debug)]
 #[command(name = "rec-cli", version)]
 struct Cli {
+    /// Print machine-readable JSON instead of human-readable text
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Suppress informational output; machine tokens (REC_*) and --json
+    /// output are still printed, error details go to stderr
+    #[arg(long, global = true)]
+    quiet: bool,
+
+    /// Report REC_* tokens and errors as one JSON object per line on stderr
+    /// ({"code", "message", "details", "exit_code", "log_tail"}) instead of
+    /// plain tokens on stdout
+    #[arg(long, global = true)]
+    json_errors: bool,
+
+    /// Color human-readable output: auto colors a terminal unless NO_COLOR
+    /// is set; JSON output is never colored
+    #[arg(long, global = true, value_enum, default_value_t = ColorMode::Auto)]
+    color: ColorMode,
+
+    /// Keep the intermediates post-processing makes and normally deletes
+    /// (lossless capture, remux and title card scratch files), and print
+    /// where they are
+    #[arg(long, global = true)]
+    keep_temp: bool,
+
+    /// Write rec-cli's own log lines as text or as one JSON object per line
+    /// (time, level, message), for a log aggregator. ffmpeg's output is
+    /// appended to the log as it comes either way.
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Record, stop and inspect the session by this name rather than the
+    /// default one; its state files are rec.nvim.<name>.*, so it runs
+    /// alongside the others (see list-running and --concurrency-limit)
+    #[arg(long, global = true, value_name = "NAME", value_parser = parse_session_name)]
+    session: Option<String>,
+
+    /// Print the path of the log rec-cli and ffmpeg write to (with --json,
+    /// as {"log": path}) and exit
+    #[arg(long)]
+    dump_log_path: bool,
+
     #[command(subcommand)]
-    command: Commands,
+    command: Option<Commands>,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
-    /// List avfoundation devices
-    Devices,
+    /// List avfoundation devices (with --json, the parsed list, which also
+    /// refreshes the device cache used by `start`; capture screens come with
+    /// their pixel size, bounds in points, scale factor and display UUID)
+    Devices {
+        /// List what this screen grabber records from instead of this OS's:
+        /// x11grab shows the X outputs (xrandr), and it and pipewire the
+        /// V4L2 and PipeWire devices
+        #[arg(long, value_enum)]
+        backend: Option<Backend>,
+    },
+
+    /// Record a second from each capture screen and rank them, to find the
+    /// --screen-index that shows the screen (not black frames or nothing)
+    BenchmarkDevices,
+
+    /// Report versions, devices and environment diagnostics
+    Doctor,
 
     /// Start recording (optionally cropped)
-    Start {
-        /// Output directory
-        #[arg(long)]
+    Start(Box<StartOptions>),
+
+    /// List recordings in the output directory (and its date subfolders),
+    /// oldest first
+    List {
+        /// Output directory; a relative path is taken from the current directory
+        #[arg(long, value_parser = parse_output_dir)]
         output_dir: Option<PathBuf>,
+        /// Only recordings made at or after this time (YYYY-MM-DD[THH:MM[:SS]])
+        #[arg(long, value_parser = |s: &str| parse_time_bound(s, false))]
+        since: Option<NaiveDateTime>,
+        /// Only recordings made at or before this time; a bare date includes
+        /// the whole day
+        #[arg(long, value_parser = |s: &str| parse_time_bound(s, true))]
+        until: Option<NaiveDateTime>,
+        /// strftime format the recordings were named with (`start
+        /// --filename-format`); local and --utc names are both read
+        #[arg(long, default_value = OUTPUT_TS_FORMAT, value_parser = parse_filename_format)]
+        filename_format: String,
+    },
 
-        /// Crop X (pixels)
+    /// Print the newest recording in the output directory (by the time in
+    /// its name), to grab the latest take
+    #[command(visible_alias = "latest")]
+    Last {
+        /// Open it with the default app instead of only printing it
         #[arg(long)]
-        x: Option<i32>,
-        /// Crop Y (pixels)
-        #[arg(long)]
-        y: Option<i32>,
-        /// Crop width (pixels)
-        #[arg(long)]
-        width: Option<i32>,
-        /// Crop height (pixels)
+        open: bool,
+        /// Output directory; a relative path is taken from the current directory
+        #[arg(long, value_parser = parse_output_dir)]
+        output_dir: Option<PathBuf>,
+        /// strftime format the recordings were named with (`start
+        /// --filename-format`)
+        #[arg(long, default_value = OUTPUT_TS_FORMAT, value_parser = parse_filename_format)]
+        filename_format: String,
+    },
+
+    /// Rename a recording, and its sidecars (stats, chapters, audio track)
+    /// with it. Keeps the extension; a name without one gets the
+    /// recording's. A name without a timestamp drops out of `list`.
+    Rename {
+        /// The recording: a name in the output directory, or a path
+        old: PathBuf,
+        /// New name, in the same directory unless it is a path
+        new: PathBuf,
+        /// Output directory; a relative path is taken from the current directory
+        #[arg(long, value_parser = parse_output_dir)]
+        output_dir: Option<PathBuf>,
+    },
+
+    /// Set a recording's title and/or comment tags, remuxing it in place
+    Tag {
+        /// The recording: a name in the output directory, or a path
+        file: PathBuf,
+        #[arg(long, required_unless_present = "comment")]
+        title: Option<String>,
+        /// Replaces the comment, including the crop note of a cropped recording
         #[arg(long)]
-        height: Option<i32>,
+        comment: Option<String>,
+        /// Output directory; a relative path is taken from the current directory
+        #[arg(long, value_parser = parse_output_dir)]
+        output_dir: Option<PathBuf>,
     },
 
     /// Stop recording
-Stop {
-    /// Output directory (ignored, for compatibility)
-    #[arg(long)]
-    output_dir: Option<PathBuf>,
-},
+    Stop(StopOptions),
 
+    /// Stop every running recording, each with these `stop` flags, and list
+    /// the files saved. Exits 1 when any of them could not be stopped.
+    StopAll(StopOptions),
 
-}
+    /// Start a recording (with these `start` flags) when none is running,
+    /// else stop the running one. The first line says which:
+    /// REC_TOGGLE_STARTED or REC_TOGGLE_STOPPED, then start's or stop's
+    /// usual output
+    Toggle(Box<StartOptions>),
+
+    /// Mark a chapter boundary in the running recording; `stop` writes the
+    /// marks as chapters (or a .chapters file when they can't be embedded)
+    Mark {
+        /// Chapter title [default: Chapter N]
+        label: Option<String>,
+        /// The chapter title, as a flag
+        #[arg(long = "label", value_name = "LABEL", conflicts_with = "label")]
+        label_flag: Option<String>,
+    },
 
-fn write_log(msg: &str) {
-    let mut f = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(LOG_FILE)
-        .expect("failed to open log file");
-    let _ = writeln!(f, "{}", msg);
+    /// Show whether a recording is running, its elapsed time and size
+    Status {
+        /// Keep printing live status every second until the recording
+        /// stops. Ctrl-C ends the watch, not the recording.
+        #[arg(long)]
+        watch: bool,
+    },
+
+    /// List the presets defined in config.json, for `start --preset-file`
+    Presets,
+
+    /// List the active recordings with their PID, output and elapsed time:
+    /// the default session, and any started with --session
+    ListRunning,
+
+    /// Print the file the current recording is writing to, and nothing
+    /// else; prints nothing and exits 1 when not recording
+    PrintOutputPath,
+
+    /// Decrypt a recording made with `stop --encrypt` (passphrase from
+    /// REC_PASSPHRASE or a prompt)
+    Decrypt {
+        /// Encrypted file (<recording>.age)
+        file: PathBuf,
+        /// Where to write the plaintext [default: the name without .age]
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Publish progress events for a running recording and/or stop it on
+    /// sleep (spawned by `start`)
+    #[command(hide = true)]
+    Supervise {
+        #[arg(long)]
+        pid: i32,
+        #[arg(long)]
+        event_socket: Option<PathBuf>,
+        #[arg(long)]
+        output: PathBuf,
+        #[arg(long)]
+        stop_on_sleep: bool,
+        #[arg(long)]
+        follow_window: Option<String>,
+        #[arg(long)]
+        mark_on_touch: Option<PathBuf>,
+        #[arg(long)]
+        track_clipboard: bool,
+    },
 }
 
-fn ensure_parent_dir(path: &Path) {
-    if let Some(parent) = path.parent() {
-        let _ = fs::create_dir_all(parent);
+/// `devices` for x11grab, gdigrab and pipewire, which have no avfoundation
+/// list.
+fn cmd_other_devices(backend: Backend, json: bool) -> anyhow::Result<()> {
+    let display = backend.input(0);
+    let (screen, outputs) = match backend {
+        Backend::X11Grab => (
+            devices::x11_screen_size(&display),
+            devices::x11_outputs(&display),
+        ),
+        _ => (None, Vec::new()),
+    };
+    let listings = match backend {
+        Backend::X11Grab | Backend::PipeWire => devices::linux_listings(),
+        _ => Vec::new(),
+    };
+    if json {
+        let mut report = json!({
+            "backend": backend.format(),
+            "input": display,
+            "screen": screen.map(|(width, height)| json!({ "width": width, "height": height })),
+            "outputs": outputs,
+        });
+        for (bin, listing) in &listings {
+            report[*bin] = json!(listing);
+        }
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    if backend == Backend::GdiGrab {
+        println!(
+            "gdigrab records the whole desktop ({}); it has no devices to list",
+            display
+        );
+        return Ok(());
     }
+    if backend == Backend::PipeWire {
+        println!("pipewire records the screen the desktop portal offers, or --pipewire-node ID");
+    } else {
+        match screen {
+            Some((w, h)) => println!("X display {}: {}x{}", display, w, h),
+            None => println!("X display {}: size unknown (is xrandr installed?)", display),
+        }
+    }
+    for o in &outputs {
+        let primary = if o.primary { " (primary)" } else { "" };
+        println!(
+            "  {} {}x{} at {},{}{}",
+            o.name, o.width, o.height, o.x, o.y, primary
+        );
+    }
+    for (bin, listing) in listings {
+        match listing {
+            Some(listing) => println!("{}:\n{}", bin, listing.trim_end()),
+            None => println!("{}: not available", bin),
+        }
+    }
+    Ok(())
 }
 
-fn read_pid() -> Option<i32> {
-    fs::read_to_string(PID_FILE).ok()?.trim().parse().ok()
+fn cmd_devices(backend: Backend, json: bool) -> anyhow::Result<()> {
+    if backend != Backend::AVFoundation {
+        return cmd_other_devices(backend, json);
+    }
+    if json {
+        let devices = Recorder::new().devices();
+        println!("{}", serde_json::to_string_pretty(&devices)?);
+        // Listed in a shape the parser does not know: the raw text at least
+        if devices.is_empty() {
+            if let Some(listing) = devices::device_listing().filter(|l| !l.trim().is_empty()) {
+                eprintln!("No devices could be parsed from ffmpeg's listing:");
+                eprint!("{}", listing);
+            }
+        }
+        return Ok(());
+    }
+
+    let args = ["-f", "avfoundation", "-list_devices", "true", "-i", ""];
+    match run_command("ffmpeg", args, PROBE_TIMEOUT) {
+        Ok(output) => {
+            std::io::stdout().write_all(&output.stdout)?;
+            std::io::stderr().write_all(&output.stderr)?;
+            if !output.status.success() {
+                eprintln!("ffmpeg device listing failed");
+            }
+        }
+        Err(err) => eprintln!("ffmpeg device listing failed: {}", err),
+    }
+    Ok(())
 }
 
-fn pid_alive(pid: i32) -> bool {
-    kill(Pid::from_raw(pid), None).is_ok()
+fn cmd_benchmark_devices(json: bool) -> anyhow::Result<()> {
+    let probes = devices::probe_screens(&Recorder::new().devices());
+    let recommended = probes.first().filter(|p| p.has_picture()).map(|p| p.index);
+    if json {
+        let report = json!({ "screens": probes, "recommended_screen_index": recommended });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    if probes.is_empty() {
+        println!("No capture screens detected");
+    }
+    for p in &probes {
+        let result = match (&p.error, p.size, p.brightness) {
+            (Some(err), _, _) => format!("failed: {}", err),
+            _ if p.frames == 0 => "no frames".to_string(),
+            (None, size, brightness) => format!(
+                "{}, {} frames, {} (brightness {:.0})",
+                size.map_or("?".into(), |s| format!("{}x{}", s.width, s.height)),
+                p.frames,
+                if p.has_picture() { "picture" } else { "black" },
+                brightness.unwrap_or_default()
+            ),
+        };
+        println!("[{}] {}: {}", p.index, p.name, result);
+    }
+    match recommended {
+        Some(index) if index == SCREEN_INDEX => {
+            println!("Recommended: --screen-index {} (the default)", index)
+        }
+        Some(index) => println!("Recommended: --screen-index {}", index),
+        None => println!("No screen gave a picture; check the Screen Recording permission"),
+    }
+    Ok(())
 }
 
-fn default_output_dir() -> PathBuf {
-    let home = home_dir().unwrap_or_else(|| PathBuf::from("."));
-    home.join("Videos").join("nvim-recordings")
+fn cmd_list(
+    output_dir: Option<PathBuf>,
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+    filename_format: &str,
+    json: bool,
+) -> anyhow::Result<()> {
+    let dir = output_dir.unwrap_or_else(default_output_dir);
+    let listed: Vec<_> = recordings(&dir, filename_format)
+        .into_iter()
+        .filter(|(_, ts)| since.is_none_or(|since| *ts >= since))
+        .filter(|(_, ts)| until.is_none_or(|until| *ts <= until))
+        .collect();
+
+    if json {
+        let items: Vec<_> = listed
+            .iter()
+            .map(|(path, ts)| {
+                json!({
+                    "path": path,
+                    "recorded_at": ts.format("%Y-%m-%dT%H:%M:%S").to_string(),
+                    "size": file_size(path),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&items)?);
+        return Ok(());
+    }
+
+    for (path, ts) in &listed {
+        println!(
+            "{}  {:>10}  {}",
+            ts.format("%Y-%m-%d %H:%M:%S"),
+            file_size(path),
+            path.display()
+        );
+    }
+    Ok(())
 }
 
-fn next_output_file(dir: &Path) -> PathBuf {
-    let ts = Local::now().format("%Y%m%d_%H%M%S");
-    dir.join(format!("rec_{}.mp4", ts))
+fn cmd_last(
+    open: bool,
+    output_dir: Option<PathBuf>,
+    filename_format: &str,
+    json: bool,
+) -> anyhow::Result<()> {
+    let dir = output_dir.unwrap_or_else(default_output_dir);
+    let Some((path, ts)) = recordings(&dir, filename_format).pop() else {
+        anyhow::bail!("no recordings in {}", dir.display());
+    };
+    if open {
+        let status = Command::new("open").arg(&path).status();
+        if !matches!(status, Ok(status) if status.success()) {
+            anyhow::bail!("could not open {}", path.display());
+        }
+    }
+
+    if json {
+        let report = json!({
+            "path": path,
+            "recorded_at": ts.format("%Y-%m-%dT%H:%M:%S").to_string(),
+            "size": file_size(&path),
+            "duration": probe_duration(&path),
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    println!("{}", path.display());
+    Ok(())
 }
 
-fn parse_screen_size(line: &str) -> Option<(i32, i32)> {
-    let mut parts = line.trim().split(',');
-    let w = parts.next()?.trim().parse::<i32>().ok()?;
-    let h = parts.next()?.trim().parse::<i32>().ok()?;
-    if w > 0 && h > 0 {
-        Some((w, h))
+fn cmd_doctor(json: bool) -> anyhow::Result<()> {
+    let ffmpeg = which("ffmpeg");
+    let ffprobe = which("ffprobe");
+    let devices = if ffmpeg.is_some() {
+        Recorder::new().devices()
     } else {
-        None
+        Vec::new()
+    };
+    let detected_screen = devices::detect_screen_index(&devices);
+    let output_dir = default_output_dir();
+    let runtime_dir = Path::new(&*PID_FILE).parent().unwrap_or(Path::new("/tmp"));
+    let recording_pid = read_pid().filter(|pid| pid_alive(*pid));
+
+    let report = json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "ffmpeg": { "path": ffmpeg, "version": ffmpeg.as_ref().and_then(|_| tool_version("ffmpeg")) },
+        "ffprobe": { "path": ffprobe, "version": ffprobe.as_ref().and_then(|_| tool_version("ffprobe")) },
+        "devices": devices,
+        "screen_index": { "configured": SCREEN_INDEX, "detected": detected_screen },
+        "output_dir": { "path": output_dir, "writable": dir_writable(&output_dir) },
+        "runtime_dir": { "path": runtime_dir, "writable": dir_writable(runtime_dir) },
+        "recording": { "active": recording_pid.is_some(), "pid": recording_pid },
+    });
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    let show = |v: &serde_json::Value| match v {
+        serde_json::Value::Null => "not found".to_string(),
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    println!("rec-cli {}", env!("CARGO_PKG_VERSION"));
+    for tool in ["ffmpeg", "ffprobe"] {
+        println!(
+            "{}: {} ({})",
+            tool,
+            show(&report[tool]["path"]),
+            show(&report[tool]["version"])
+        );
+    }
+    println!("Devices:");
+    if devices.is_empty() {
+        println!("  (none detected)");
+    }
+    for d in &devices {
+        let scale = d
+            .screen
+            .as_ref()
+            .and_then(|s| Some((s.pixels?, s.scale_factor?)));
+        let uuid = d.screen.as_ref().and_then(|s| s.uuid.as_deref());
+        match scale {
+            Some((px, scale)) => println!(
+                "  [{:?}] [{}] {} ({}x{} pixels, scale {}{})",
+                d.kind,
+                d.index,
+                d.name,
+                px.width,
+                px.height,
+                scale,
+                uuid.map_or(String::new(), |u| format!(", UUID {}", u))
+            ),
+            None => println!("  [{:?}] [{}] {}", d.kind, d.index, d.name),
+        }
+    }
+    println!(
+        "Screen index: {} (detected: {})",
+        SCREEN_INDEX,
+        show(&report["screen_index"]["detected"])
+    );
+    for dir in ["output_dir", "runtime_dir"] {
+        println!(
+            "{}: {} (writable: {})",
+            dir.replace('_', " "),
+            show(&report[dir]["path"]),
+            report[dir]["writable"]
+        );
+    }
+    match recording_pid {
+        Some(pid) => println!("Recording: active (pid {})", pid),
+        None => println!("Recording: inactive"),
     }
+    Ok(())
 }
 
-fn get_screen_size(input: &str) -> Option<(i32, i32)> {
-    let output = Command::new("ffprobe")
-        .args([
-            "-v",
-            "error",
-            "-f",
-            "avfoundation",
-            "-i",
-            input,
-            "-show_entries",
-            "stream=width,height",
-            "-of",
-            "csv=p=0",
-        ])
-        .output()
-        .ok()?;
+/// A recording named on the command line: a bare name is in the output
+/// directory, anything with a `/` a path.
+fn recording_path(name: &Path, output_dir: Option<&Path>) -> PathBuf {
+    match name.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => name.to_path_buf(),
+        _ => output_dir
+            .map_or_else(default_output_dir, Path::to_path_buf)
+            .join(name),
+    }
+}
 
-    if !output.status.success() {
-        return None;
+fn cmd_rename(
+    old: &Path,
+    new: &Path,
+    output_dir: Option<&Path>,
+    json: bool,
+    quiet: bool,
+) -> anyhow::Result<()> {
+    let old = recording_path(old, output_dir);
+    let (renamed, sidecars) = output::rename_recording(&old, new).map_err(anyhow::Error::msg)?;
+    if json {
+        println!("{}", json!({ "output": renamed, "sidecars": sidecars }));
+    } else if !quiet {
+        println!("Renamed: {}", renamed.display());
+        for sidecar in &sidecars {
+            println!("Also renamed: {}", sidecar.display());
+        }
     }
+    Ok(())
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    for line in stdout.lines() {
-        if let Some(size) = parse_screen_size(line) {
-            return Some(size);
+fn cmd_tag(
+    file: &Path,
+    title: Option<&str>,
+    comment: Option<&str>,
+    json: bool,
+    quiet: bool,
+) -> anyhow::Result<()> {
+    output::tag_recording(file, title, comment).map_err(anyhow::Error::msg)?;
+    if json {
+        println!(
+            "{}",
+            json!({ "output": file, "title": title, "comment": comment })
+        );
+    } else if !quiet {
+        println!("Tagged: {}", file.display());
+    }
+    Ok(())
+}
+
+fn cmd_start(args: &StartOptions, json: bool, quiet: bool) -> anyhow::Result<()> {
+    let recorder = Recorder::new();
+    if let (true, RecordingState::Recording { pid, output, .. }) = (json, recorder.status()) {
+        let report = json!({ "status": "already_running", "pid": pid, "output": output });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        std::process::exit(1);
+    }
+    if args.loop_region_test {
+        return cmd_region_test(args, json);
+    }
+    if args.benchmark {
+        return cmd_benchmark(args, json);
+    }
+    if args.estimate_size {
+        return cmd_estimate_size(args, json);
+    }
+    if args.dry_probe {
+        return cmd_dry_probe(args, json);
+    }
+    // With --stdout, stdout carries the recording itself
+    let say = |line: String| {
+        if args.stdout {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
         }
+    };
+    if let Some(at) = args.scheduled_start() {
+        let schedule = recorder.schedule(args, at)?;
+        if !quiet && !json {
+            say(format!("Recording scheduled for {}", scheduled_for(at)));
+            say(format!("PID: {}", schedule.pid));
+        }
+        recorder.wait_scheduled(&schedule);
+    }
+    let prepared = recorder.prepare(args)?;
+    if let (Some(recovered), false) = (&prepared.recovered, quiet) {
+        say(format!(
+            "Recovered interrupted recording: {}",
+            recovered.display()
+        ));
+    }
+    if args.dump_args {
+        say(shell_command("ffmpeg", &prepared.ffmpeg_args));
     }
 
-    None
+    let handle = recorder.launch(prepared)?;
+    let supervised = args.event_socket.is_some()
+        || args.stop_on_sleep
+        || args.follow_window.is_some()
+        || args.mark_on_touch.is_some()
+        || args.track_clipboard;
+    if supervised {
+        spawn_supervisor(&handle, args)?;
+    }
+    for warning in &handle.warnings {
+        eprintln!("{}", tint(Tone::Warn, warning, true));
+    }
+
+    if json {
+        let report = json!({
+            "pid": handle.pid,
+            "output": handle.output,
+            "warnings": handle.warnings,
+        });
+        say(serde_json::to_string_pretty(&report)?);
+    } else if !quiet {
+        say(tint(Tone::Good, "Recording started", args.stdout));
+        say(format!("Output: {}", handle.output.display()));
+        say(format!("PID: {}", handle.pid));
+    }
+    Ok(())
 }
 
-fn clamp_crop(
-    x: i32,
-    y: i32,
-    width: i32,
-    height: i32,
-    screen_width: i32,
-    screen_height: i32,
-) -> Option<(i32, i32, i32, i32)> {
-    if screen_width <= 0 || screen_height <= 0 {
-        return None;
+fn cmd_region_test(args: &StartOptions, json: bool) -> anyhow::Result<()> {
+    let test = Recorder::new().region_test(args)?;
+    let size = |s: Option<(i32, i32)>| s.map(|(w, h)| format!("{}x{}", w, h));
+    if json {
+        let report = json!({
+            "expected": size(test.expected),
+            "actual": size(test.actual),
+            "matches": test.matches(),
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    match size(test.actual) {
+        Some(actual) => println!("Captured size: {}", actual),
+        None => println!("Captured size: unknown (ffprobe could not read the probe clip)"),
     }
+    if let Some(expected) = size(test.expected) {
+        println!("Requested size: {}", expected);
+        if test.matches() {
+            println!("Region test: OK");
+        } else {
+            println!(
+                "Region test: MISMATCH (see {} for clamping)",
+                log_path().display()
+            );
+        }
+    }
+    Ok(())
+}
 
-    let cx = x.max(0);
-    let cy = y.max(0);
-    let mut cw = width.max(1);
-    let mut ch = height.max(1);
+fn cmd_benchmark(args: &StartOptions, json: bool) -> anyhow::Result<()> {
+    let bench = Recorder::new().benchmark(args)?;
+    if json {
+        let mut report = serde_json::to_value(&bench)?;
+        report["keeps_up"] = json!(bench.keeps_up());
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    let show = |v: Option<String>| v.unwrap_or_else(|| "-".into());
+    let rows = [
+        ("Target fps", bench.target_fps.to_string()),
+        ("Frames", show(bench.frames.map(|f| f.to_string()))),
+        ("Encode fps", show(bench.fps.map(|f| format!("{:.1}", f)))),
+        ("Speed", show(bench.speed.map(|s| format!("{:.2}x", s)))),
+        ("CPU", show(bench.cpu_percent.map(|c| format!("{:.0}%", c)))),
+        (
+            "Max RSS",
+            show(bench.max_rss_kb.map(|k| format!("{} MB", k / 1024))),
+        ),
+    ];
+    for (label, value) in rows {
+        println!("{:<12}{}", label, value);
+    }
+    match bench.keeps_up() {
+        Some(true) => println!("Result: keeps up"),
+        Some(false) => println!("Result: TOO SLOW (try a faster preset or a smaller capture)"),
+        None => println!("Result: unknown (ffmpeg reported no progress)"),
+    }
+    Ok(())
+}
 
-    if cx >= screen_width || cy >= screen_height {
-        return None;
+fn cmd_dry_probe(args: &StartOptions, json: bool) -> anyhow::Result<()> {
+    let checks = Recorder::new().dry_probe(args);
+    let passed = checks.iter().all(|check| check.passed != Some(false));
+    if json {
+        let report = json!({ "passed": passed, "checks": checks });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for check in &checks {
+            let verdict = match check.passed {
+                Some(true) => tint(Tone::Good, "PASS", false),
+                Some(false) => tint(Tone::Bad, "FAIL", false),
+                None => tint(Tone::Warn, "SKIP", false),
+            };
+            println!("{} {}: {}", verdict, check.name, check.detail);
+        }
+        match passed {
+            true => println!("A recording with these options should work"),
+            false => println!("A recording with these options would fail (see FAIL above)"),
+        }
     }
+    if !passed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
 
-    cw = cw.min(screen_width - cx);
-    ch = ch.min(screen_height - cy);
+fn cmd_estimate_size(args: &StartOptions, json: bool) -> anyhow::Result<()> {
+    let seconds = args.estimate_duration.or(args.duration).unwrap_or(3600.0);
+    let estimate = Recorder::new().estimate_size(args, seconds)?;
+    if json {
+        let mut report = serde_json::to_value(&estimate)?;
+        report["estimate"] = json!(true);
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    println!(
+        "Estimated size: ~{} for {} (likely {} to {})",
+        format_size(estimate.bytes),
+        format_elapsed(Duration::from_secs_f64(seconds)),
+        format_size(estimate.low),
+        format_size(estimate.high)
+    );
+    match (estimate.resolution, estimate.framerate) {
+        (Some(size), Some(fps)) => {
+            let crf = estimate
+                .crf
+                .map_or(String::new(), |crf| format!(", CRF {}", crf));
+            println!(
+                "Video: {}x{} at {} fps{}, ~{:.0} kbit/s",
+                size.width, size.height, fps, crf, estimate.video_kbps
+            );
+        }
+        (None, Some(_)) => println!("Video: frame size unknown (the screen could not be probed)"),
+        _ => {}
+    }
+    if estimate.audio_kbps > 0.0 {
+        println!("Audio: ~{:.0} kbit/s", estimate.audio_kbps);
+    }
+    println!("This is a rough guess: how much moves on screen matters more than the settings");
+    Ok(())
+}
 
-    if cw <= 0 || ch <= 0 {
-        return None;
+/// Detach a `rec-cli supervise` process that outlives this invocation and
+/// reports on the recording until ffmpeg exits.
+fn spawn_supervisor(handle: &RecordingHandle, args: &StartOptions) -> anyhow::Result<()> {
+    let mut cmd = Command::new(std::env::current_exe()?);
+    if log_format() == LogFormat::Json {
+        cmd.args(["--log-format", "json"]);
+    }
+    cmd.arg("supervise")
+        .arg("--pid")
+        .arg(handle.pid.to_string())
+        .arg("--output")
+        .arg(&handle.output);
+    if let Some(socket) = &args.event_socket {
+        cmd.arg("--event-socket").arg(socket);
+        write_log(&format!(
+            "Event supervisor started for {}",
+            socket.display()
+        ));
+    }
+    if args.stop_on_sleep {
+        cmd.arg("--stop-on-sleep");
+        write_log("Supervisor will stop the recording on sleep or screen lock");
+    }
+    if let Some(title) = &args.follow_window {
+        cmd.arg("--follow-window").arg(title);
+        write_log(&format!("Supervisor will follow the window '{}'", title));
     }
+    if let Some(file) = &args.mark_on_touch {
+        cmd.arg("--mark-on-touch").arg(file);
+        write_log(&format!(
+            "Supervisor will mark chapters when {} changes",
+            file.display()
+        ));
+    }
+    if args.track_clipboard {
+        cmd.arg("--track-clipboard");
+        write_log("Supervisor will track clipboard changes");
+    }
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok(())
+}
 
-    Some((cx, cy, cw, ch))
+fn cmd_mark(label: &str, json: bool, quiet: bool) -> anyhow::Result<()> {
+    let at = Recorder::new().mark(label)?;
+    if json {
+        println!("{}", json!({ "at": at, "label": label }));
+    } else if !quiet {
+        println!("Marked {}", chapters::format_at(at));
+    }
+    Ok(())
 }
 
-fn cmd_devices() -> anyhow::Result<()> {
-    let status = Command::new("ffmpeg")
-        .args(["-f", "avfoundation", "-list_devices", "true", "-i", ""])
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .status()?;
+fn cmd_toggle(args: &StartOptions, json: bool, quiet: bool) -> anyhow::Result<()> {
+    if Recorder::new().status() == RecordingState::Idle {
+        // With --stdout, stdout carries the recording itself
+        match args.stdout {
+            true => eprintln!("REC_TOGGLE_STARTED"),
+            false => println!("REC_TOGGLE_STARTED"),
+        }
+        cmd_start(args, json, quiet)
+    } else {
+        println!("REC_TOGGLE_STOPPED");
+        cmd_stop(&StopOptions::default(), quiet)
+    }
+}
 
-    if !status.success() {
-        eprintln!("ffmpeg device listing failed");
+fn cmd_stop(args: &StopOptions, quiet: bool) -> anyhow::Result<()> {
+    if let Some(at) = Recorder::new().cancel_scheduled() {
+        if !quiet {
+            println!(
+                "Scheduled recording canceled (was due {})",
+                at.format("%H:%M:%S")
+            );
+        }
+        return Ok(());
+    }
+    let mut args = args.clone();
+    if !quiet && std::io::stderr().is_terminal() {
+        args.on_progress = Some(show_compress_progress);
+    }
+    let stopped = Recorder::new().stop(&args)?;
+    if args.on_progress.is_some() && COMPRESS_SHOWN.load(Ordering::Relaxed) {
+        eprintln!();
+    }
+    if stopped.repaired {
+        let warning = "WARNING: the recording was not playable; repaired by remuxing";
+        eprintln!("{}", tint(Tone::Warn, warning, true));
+    }
+    if quiet {
+        return Ok(());
+    }
+    println!("{}", tint(Tone::Good, "Recording stopped", false));
+    if stopped.streamed() {
+        println!("Recording streamed to stdout");
+        return Ok(());
+    }
+    println!("Recording saved: {}", stopped.output.display());
+    if let Some(sidecar) = stopped.stats {
+        println!("Stats: {}", sidecar.display());
+    }
+    if let Some(summary) = &stopped.summary {
+        println!("Encoded: {}", encoded_summary(summary));
+    }
+    match &stopped.resumed {
+        Some(Resumed::Joined { seam }) => {
+            println!(
+                "Resumed: the interrupted part runs to {:.1}s, then this one",
+                seam
+            )
+        }
+        Some(Resumed::Skipped { recovered, reason }) => {
+            let warning = format!(
+                "WARNING: --resume: not joined ({}); the interrupted part is {}",
+                reason,
+                recovered.display()
+            );
+            println!("{}", tint(Tone::Warn, &warning, false));
+        }
+        None => {}
+    }
+    if let Some((requested, achieved)) = stopped.framerate {
+        println!(
+            "Frame rate: {} requested, {:.1} achieved",
+            requested, achieved
+        );
+        // Below 90% the recording visibly stutters
+        if achieved < requested as f64 * 0.9 {
+            let hint = "The capture could not keep up; try a lower --framerate, --scale or a \
+                        smaller crop";
+            println!("{}", tint(Tone::Warn, hint, false));
+        }
+    }
+    if let Some(captions) = stopped.keys {
+        println!("Keys: {}", captions.display());
+    }
+    if let Some(audio) = stopped.audio_track {
+        println!("Audio: {}", audio.display());
+    }
+    for also in &stopped.also_outputs {
+        println!("Also saved: {}", also.display());
+    }
+    match stopped.chapters {
+        Some(Chapters::Embedded(n)) => println!("Chapters: {}", n),
+        Some(Chapters::Sidecar(path)) => println!("Chapters: {}", path.display()),
+        None => {}
+    }
+    if let Some(timeline) = stopped.follow {
+        println!("Window bounds: {}", timeline.display());
+    }
+    if let Some(timeline) = stopped.clipboard {
+        println!("Clipboard: {}", timeline.display());
+    }
+    if let Some(manifest) = stopped.segments {
+        println!("Segments: {}", manifest.display());
+    }
+    if let Some(trimmed) = stopped.trimmed {
+        println!(
+            "Trimmed: {} ({:.1}s -> {:.1}s)",
+            trimmed.output.display(),
+            trimmed.before,
+            trimmed.after
+        );
+    }
+    match stopped.normalized {
+        Some(Normalized::Done { output, input_lufs }) => {
+            println!(
+                "Normalized: {} (was {:.1} LUFS)",
+                output.display(),
+                input_lufs
+            )
+        }
+        Some(Normalized::Skipped(reason)) => println!("Normalize skipped: {}", reason),
+        None => {}
+    }
+    match stopped.silence_trimmed {
+        Some(SilenceTrimmed::Done {
+            output,
+            before,
+            after,
+            gaps,
+        }) => println!(
+            "Silence trimmed: {} ({:.1}s -> {:.1}s, gaps cut: {})",
+            output.display(),
+            before,
+            after,
+            gaps
+        ),
+        Some(SilenceTrimmed::Skipped(reason)) => println!("Silence trim skipped: {}", reason),
+        None => {}
+    }
+    match stopped.poster {
+        Some(Poster::Done {
+            image,
+            embedded: true,
+        }) => {
+            println!("Poster: {} (set as the cover)", image.display())
+        }
+        Some(Poster::Done {
+            image,
+            embedded: false,
+        }) => println!("Poster: {}", image.display()),
+        Some(Poster::Skipped(reason)) => println!("Poster skipped: {}", reason),
+        None => {}
+    }
+    for removed in &stopped.pruned {
+        println!("Removed (--max-recordings): {}", removed.display());
     }
     Ok(())
 }
 
-fn cmd_start(
-    output_dir: Option<PathBuf>,
-    x: Option<i32>,
-    y: Option<i32>,
-    width: Option<i32>,
-    height: Option<i32>,
-) -> anyhow::Result<()> {
-    if let Some(pid) = read_pid() {
-        if pid_alive(pid) {
-            println!("REC_ALREADY_RUNNING");
-            return Ok(());
+/// `summary` as `1800 frames, 12.1 MB, 1685.5 kbit/s, 1.00x`, leaving out
+/// what ffmpeg did not report.
+fn encoded_summary(summary: &FfmpegSummary) -> String {
+    let parts = [
+        summary.frames.map(|n| format!("{} frames", n)),
+        summary.size_bytes.map(format_size),
+        summary.bitrate_kbps.map(|k| format!("{:.1} kbit/s", k)),
+        summary.speed.map(|x| format!("{:.2}x", x)),
+    ];
+    parts.into_iter().flatten().collect::<Vec<_>>().join(", ")
+}
+
+/// Whether `stop` has drawn a compression progress line to end
+static COMPRESS_SHOWN: AtomicBool = AtomicBool::new(false);
+
+fn show_compress_progress(done: f64) {
+    COMPRESS_SHOWN.store(true, Ordering::Relaxed);
+    eprint!("\r\x1b[2KCompressing: {:.0}%", done * 100.0);
+}
+
+/// How one session of `stop-all` ended.
+enum SessionStopped {
+    Saved(PathBuf),
+    Streamed,
+    Failed(String),
+}
+
+/// Bound on the `stop` of another session; it bounds each of its own ffmpeg
+/// passes, so this only keeps a wedged one from holding up the rest.
+const SESSION_STOP_TIMEOUT: Duration = Duration::from_secs(6 * 3600);
+
+/// `stop` another session than this process's own (whose state files are
+/// the only ones it has): `rec-cli --session <session> stop` with the flags
+/// given to `stop-all`, judged by what it prints.
+fn stop_session(session: &str) -> SessionStopped {
+    let argv: Vec<std::ffi::OsString> = std::env::args_os().skip(1).collect();
+    let Some(at) = argv.iter().position(|arg| arg == "stop-all") else {
+        return SessionStopped::Failed("cannot find the stop-all flags".into());
+    };
+    // The global flags are this process's business, --session above all
+    let command = Cli::command();
+    let globals: Vec<_> = command
+        .get_arguments()
+        .filter(|arg| arg.is_global_set())
+        .filter_map(|arg| Some((arg.get_long()?, arg.get_action().takes_values())))
+        .collect();
+    let mut args: Vec<std::ffi::OsString> = vec!["--session".into(), session.into(), "stop".into()];
+    let mut rest = argv[at + 1..].iter();
+    while let Some(arg) = rest.next() {
+        let flag = arg.to_str().and_then(|a| a.strip_prefix("--"));
+        let global = flag.and_then(|flag| {
+            globals
+                .iter()
+                .find(|(long, _)| flag == *long || flag.starts_with(&format!("{}=", long)))
+        });
+        match global {
+            Some((long, true)) if flag == Some(*long) => {
+                rest.next();
+            }
+            Some(_) => {}
+            None => args.push(arg.clone()),
         }
-        let _ = fs::remove_file(PID_FILE);
     }
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(err) => return SessionStopped::Failed(format!("cannot find rec-cli: {}", err)),
+    };
+    let output = match run_command(&exe.to_string_lossy(), &args, SESSION_STOP_TIMEOUT) {
+        Ok(output) => output,
+        Err(err) => return SessionStopped::Failed(err.to_string()),
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if let Some(saved) = stdout
+        .lines()
+        .find_map(|line| line.strip_prefix("Recording saved: "))
+    {
+        return SessionStopped::Saved(saved.into());
+    }
+    if stdout
+        .lines()
+        .any(|line| line == "Recording streamed to stdout")
+    {
+        return SessionStopped::Streamed;
+    }
+    let said = match stdout.trim() {
+        "" => String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        said => said.to_string(),
+    };
+    SessionStopped::Failed(said)
+}
 
-    let dir = output_dir.unwrap_or_else(default_output_dir);
-    fs::create_dir_all(&dir)?;
-    let output = next_output_file(&dir);
-    ensure_parent_dir(&output);
-
-    fs::write(OUT_FILE, output.to_string_lossy().to_string())?;
-
-    let input = format!("{}:none", SCREEN_INDEX);
-
-    write_log("===== START =====");
-    write_log(&format!("Input: {}", input));
-    write_log(&format!("Output: {}", output.display()));
-
-    let mut crop = None;
-    if let (Some(x), Some(y), Some(w), Some(h)) = (x, y, width, height) {
-        match get_screen_size(&input) {
-            Some((screen_w, screen_h)) => {
-                write_log(&format!(
-                    "Screen size: {}x{} (requested crop: {}x{} at {},{})",
-                    screen_w, screen_h, w, h, x, y
-                ));
-                if let Some((cx, cy, cw, ch)) = clamp_crop(x, y, w, h, screen_w, screen_h) {
-                    crop = Some((cx, cy, cw, ch));
-                } else {
-                    write_log("Crop invalid after clamping; skipping crop.");
+fn cmd_stop_all(args: &StopOptions, json: bool, quiet: bool) -> anyhow::Result<()> {
+    let own = current_session();
+    let (scheduled, sessions): (Vec<_>, Vec<_>) = Recorder::new()
+        .sessions()
+        .into_iter()
+        .partition(|(_, state)| matches!(state, RecordingState::Scheduled { .. }));
+    // Nothing to save from a recording that has not begun
+    let canceled: Vec<_> = scheduled
+        .iter()
+        .filter(|(session, _)| session == own)
+        .filter_map(|(session, _)| Some((session, Recorder::new().cancel_scheduled()?)))
+        .collect();
+    // Each gets the full treatment of `stop`
+    let results: Vec<_> = sessions
+        .iter()
+        .map(|(session, _)| {
+            let stopped = match session == own {
+                true => match Recorder::new().stop(args) {
+                    Ok(stopped) if stopped.streamed() => SessionStopped::Streamed,
+                    Ok(stopped) => SessionStopped::Saved(stopped.output),
+                    Err(err) => SessionStopped::Failed(err.to_string()),
+                },
+                false => stop_session(session),
+            };
+            (session, stopped)
+        })
+        .collect();
+    let failed = results
+        .iter()
+        .filter(|(_, stopped)| matches!(stopped, SessionStopped::Failed(_)))
+        .count();
+
+    if json {
+        let mut items: Vec<_> = results
+            .iter()
+            .map(|(session, stopped)| match stopped {
+                SessionStopped::Streamed => json!({ "session": session, "streamed": true }),
+                SessionStopped::Saved(output) => json!({ "session": session, "output": output }),
+                SessionStopped::Failed(err) => json!({ "session": session, "error": err }),
+            })
+            .collect();
+        items.extend(canceled.iter().map(|(session, at)| {
+            json!({ "session": session, "canceled": true, "start_at": at.to_rfc3339() })
+        }));
+        println!("{}", serde_json::to_string_pretty(&items)?);
+    } else if !quiet {
+        if results.is_empty() && canceled.is_empty() {
+            println!("No recordings running");
+        }
+        for (session, at) in &canceled {
+            println!(
+                "{}: canceled (was scheduled for {})",
+                session,
+                at.format("%H:%M:%S")
+            );
+        }
+        for (session, stopped) in &results {
+            match stopped {
+                SessionStopped::Streamed => println!("{}: streamed to stdout", session),
+                SessionStopped::Saved(output) => {
+                    let saved = tint(Tone::Good, "saved", false);
+                    println!("{}: {} {}", session, saved, output.display())
+                }
+                SessionStopped::Failed(err) => {
+                    println!("{}: {}", session, tint(Tone::Bad, err, false))
                 }
-            }
-            None => {
-                write_log("Failed to get screen size; skipping crop.");
             }
         }
+        if !results.is_empty() {
+            println!(
+                "Stopped {} of {} recording(s)",
+                results.len() - failed,
+                results.len()
+            );
+        }
     }
+    if failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
 
-    let log = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(LOG_FILE)?;
-
-    let mut cmd = Command::new("ffmpeg");
-    cmd.args([
-        "-y",
+fn cmd_presets(json: bool) -> anyhow::Result<()> {
+    let config = Config::load()?;
+    let path = config::config_path().unwrap_or_default();
+    if json {
+        let report = json!({ "config": path, "presets": config.presets });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    if config.presets.is_empty() {
+        println!("No presets defined in {}", path.display());
+        return Ok(());
+    }
+    let width = config.presets.keys().map(String::len).max().unwrap_or(0);
+    for (name, flags) in &config.presets {
+        let args =
+            config::flag_args(flags).unwrap_or_else(|err| vec![format!("(invalid: {})", err)]);
+        println!("{:<width$}  {}", name, args.join(" "), width = width);
+    }
+    Ok(())
+}
 
-        // video input
-        "-f", "avfoundation",
-        "-framerate", "30",
-        "-i", &input,
+fn cmd_print_output_path() -> anyhow::Result<()> {
+    match Recorder::new().status() {
+        RecordingState::Recording { output, .. } if !output.as_os_str().is_empty() => {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(output.as_os_str().as_bytes())?;
+            stdout.write_all(b"\n")?;
+            Ok(())
+        }
+        _ => std::process::exit(1),
+    }
+}
 
-        // silent audio (QuickTime REQUIRES this)
-        "-f", "lavfi",
-        "-i", "anullsrc",
+fn cmd_decrypt(file: &Path, output: Option<&Path>, quiet: bool) -> anyhow::Result<()> {
+    let decrypted = crypt::passphrase(false)
+        .and_then(|pass| crypt::decrypt(file, output, &pass))
+        .map_err(|err| {
+            Rejection::new(ErrorCode::DecryptFailed)
+                .detail(err)
+                .exit_code(1)
+        })?;
+    if !quiet {
+        println!("Decrypted: {}", decrypted.display());
+    }
+    Ok(())
+}
 
-        // QuickTime-safe encoding
-        "-pix_fmt", "yuv420p",
-        "-profile:v", "high",
-        "-level", "4.2",
-        "-movflags", "+faststart",
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
 
-        "-c:v", "libx264",
-        "-preset", "ultrafast",
-        "-crf", "23",
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1 << 30 => format!("{:.2} GB", b as f64 / (1u64 << 30) as f64),
+        b if b >= 1 << 20 => format!("{:.1} MB", b as f64 / (1u64 << 20) as f64),
+        b => format!("{:.0} KB", b as f64 / 1024.0),
+    }
+}
 
-        // stop audio when video ends
-        "-shortest",
-    ]);
+/// `HH:MM:SS (in HH:MM:SS)` for a scheduled start.
+fn scheduled_for(at: DateTime<Local>) -> String {
+    let left = (at - Local::now()).to_std().unwrap_or_default();
+    format!("{} (in {})", at.format("%H:%M:%S"), format_elapsed(left))
+}
 
-    // Apply crop only if all values exist and are safely clamped (RecWin)
-    if let Some((x, y, w, h)) = crop {
-        let filter = format!("crop={}:{}:{}:{}", w, h, x, y);
-        write_log(&format!("Crop filter: {}", filter));
-        cmd.args(["-filter:v", &filter]);
+fn status_json(state: &RecordingState) -> serde_json::Value {
+    match state {
+        RecordingState::Idle => json!({ "recording": false }),
+        RecordingState::Scheduled { pid, at } => json!({
+            "recording": false,
+            "scheduled": true,
+            "pid": pid,
+            "start_at": at.to_rfc3339(),
+        }),
+        RecordingState::Recording {
+            pid,
+            output,
+            paused,
+            elapsed,
+            size,
+            output_dir_gone,
+            hud_pid,
+            settings,
+        } => json!({
+            "recording": true,
+            "pid": pid,
+            "output": output,
+            "paused": paused,
+            "elapsed": elapsed.as_secs_f64(),
+            "size": size,
+            "bitrate_kbps": state.bitrate_kbps(),
+            "output_dir_gone": output_dir_gone,
+            "hud_pid": hud_pid,
+            "settings": settings,
+        }),
     }
+}
 
-    cmd.arg(&output)
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(log);
-
-    let child = cmd.spawn()?;
-    let pid = child.id() as i32;
-    fs::write(PID_FILE, pid.to_string())?;
-
-    // give ffmpeg time to crash if misconfigured
-    thread::sleep(Duration::from_millis(400));
-    if !pid_alive(pid) {
-        println!("REC_START_ERR");
-        println!("ffmpeg exited immediately");
-        println!("Log: {}", LOG_FILE);
+fn cmd_list_running(json: bool) -> anyhow::Result<()> {
+    let sessions = Recorder::new().sessions();
+    if json {
+        let items: Vec<_> = sessions
+            .iter()
+            .map(|(session, state)| {
+                let mut item = status_json(state);
+                item["session"] = json!(session);
+                item
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&items)?);
         return Ok(());
     }
 
-    println!("Recording started");
-    println!("Output: {}", output.display());
+    if sessions.is_empty() {
+        println!("No recordings running");
+        return Ok(());
+    }
+    println!("{:<10}  {:>7}  {:>8}  OUTPUT", "SESSION", "PID", "ELAPSED");
+    for (session, state) in &sessions {
+        match state {
+            RecordingState::Recording {
+                pid,
+                output,
+                elapsed,
+                ..
+            } => println!(
+                "{:<10}  {:>7}  {:>8}  {}",
+                session,
+                pid,
+                format_elapsed(*elapsed),
+                output.display()
+            ),
+            RecordingState::Scheduled { pid, at } => println!(
+                "{:<10}  {:>7}  {:>8}  scheduled for {}",
+                session,
+                pid,
+                "-",
+                scheduled_for(*at)
+            ),
+            RecordingState::Idle => {}
+        }
+    }
     Ok(())
 }
 
-fn cmd_stop() -> anyhow::Result<()> {
-    let pid = match read_pid() {
-        Some(p) => p,
-        None => {
-            println!("REC_NOT_RUNNING");
+/// One-line summary used by `status --watch`.
+fn status_line(state: &RecordingState) -> String {
+    match state {
+        RecordingState::Idle => "Recording: inactive".to_string(),
+        RecordingState::Scheduled { at, .. } => format!("Scheduled for {}", scheduled_for(*at)),
+        RecordingState::Recording {
+            paused,
+            elapsed,
+            size,
+            ..
+        } => format!(
+            "{} {}  {}  {:.0} kbit/s",
+            if *paused { "Paused" } else { "Recording" },
+            format_elapsed(*elapsed),
+            format_size(*size),
+            state.bitrate_kbps().unwrap_or(0.0)
+        ),
+    }
+}
+
+/// The first line of `status`, for the plugin to match on: a recording
+/// that `stop` still has to finalize (it finished or was killed) counts as
+/// unfinalized, a PID file that named nothing as stale.
+fn status_token(state: &RecordingState, unfinalized: bool, stale: bool) -> &'static str {
+    match state {
+        RecordingState::Recording { .. } => "REC_STATUS_RUNNING",
+        RecordingState::Scheduled { .. } => "REC_STATUS_SCHEDULED",
+        RecordingState::Idle if unfinalized => "REC_STATUS_UNFINALIZED",
+        RecordingState::Idle if stale => "REC_STATUS_STALE",
+        RecordingState::Idle => "REC_STATUS_IDLE",
+    }
+}
+
+static WATCH_INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_watch_interrupt(_: libc::c_int) {
+    WATCH_INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+const DIR_GONE_DETAIL: &str = "The output directory was deleted; this recording will be lost";
+
+fn cmd_status(watch: bool, json: bool, json_errors: bool) -> anyhow::Result<()> {
+    let recorder = Recorder::new();
+    if !watch {
+        let stale = recorder.clear_stale();
+        let state = recorder.status();
+        let unfinalized = recorder.unfinalized();
+        let killed = recorder.killed_externally();
+        let token = status_token(&state, unfinalized.is_some(), stale.is_some());
+        if json {
+            let mut report = status_json(&state);
+            report["status"] = json!(token);
+            if let Some(output) = &unfinalized {
+                report["unfinalized"] = json!(output);
+            }
+            if killed {
+                report["killed_externally"] = json!(true);
+            }
+            if let Some(pid) = stale {
+                report["stale_pid"] = json!(pid);
+            }
+            println!("{}", serde_json::to_string_pretty(&report)?);
             return Ok(());
         }
-    };
-
-    write_log("===== STOP =====");
-    let _ = kill(Pid::from_raw(pid), Signal::SIGINT);
+        println!("{}", token);
+        match &state {
+            RecordingState::Idle => match unfinalized {
+                Some(output) if killed => {
+                    let label = tint(Tone::Warn, "killed", false);
+                    println!("Recording: {} (ffmpeg died without `stop`)", label);
+                    println!(
+                        "Output: {} (`stop` recovers what it wrote)",
+                        output.display()
+                    );
+                    if json_errors {
+                        let detail = "ffmpeg died from a signal no stop sent".to_string();
+                        report_json(ErrorCode::KilledExternally, &[detail], 0);
+                    }
+                }
+                Some(output) => {
+                    println!("Recording: finished by itself; `stop` finalizes it");
+                    println!("Output: {}", output.display());
+                }
+                None => {
+                    if json_errors {
+                        report_json(ErrorCode::NotRunning, &[], 0);
+                    }
+                    println!("Recording: inactive");
+                    if let Some(pid) = stale {
+                        println!("Removed the PID file of pid {}, which is gone", pid);
+                    }
+                }
+            },
+            RecordingState::Scheduled { pid, at } => {
+                let label = tint(Tone::Warn, "scheduled", false);
+                println!(
+                    "Recording: {} for {} (pid {})",
+                    label,
+                    scheduled_for(*at),
+                    pid
+                );
+            }
+            RecordingState::Recording {
+                pid,
+                output,
+                paused,
+                elapsed,
+                output_dir_gone,
+                ..
+            } => {
+                let label = match paused {
+                    true => tint(Tone::Warn, "paused", false),
+                    false => tint(Tone::Good, "active", false),
+                };
+                println!("Recording: {} (pid {})", label, pid);
+                println!("Output: {}", output.display());
+                println!("Elapsed: {}", elapsed.as_secs());
+                println!("{}", status_line(&state));
+                if *output_dir_gone && json_errors {
+                    report_json(ErrorCode::OutputDirGone, &[DIR_GONE_DETAIL.into()], 0);
+                } else if *output_dir_gone {
+                    println!("{}", ErrorCode::OutputDirGone.token());
+                    println!("{}", DIR_GONE_DETAIL);
+                }
+            }
+        }
+        return Ok(());
+    }
 
-    for _ in 0..50 {
-        if !pid_alive(pid) {
+    // Ctrl-C only ends the watch; ffmpeg is in another process group
+    unsafe { signal(Signal::SIGINT, SigHandler::Handler(on_watch_interrupt)) }?;
+    let mut warned = false;
+    loop {
+        let state = recorder.status();
+        let done = state == RecordingState::Idle;
+        if let RecordingState::Recording {
+            output_dir_gone: true,
+            ..
+        } = state
+        {
+            if !warned && json_errors {
+                eprintln!();
+                report_json(ErrorCode::OutputDirGone, &[DIR_GONE_DETAIL.into()], 0);
+            } else if !warned {
+                eprintln!("\n{}", ErrorCode::OutputDirGone.token());
+                eprintln!("{}", DIR_GONE_DETAIL);
+            }
+            warned = true;
+        }
+        if json {
+            let event = if done {
+                events::STOPPED
+            } else {
+                events::PROGRESS
+            };
+            println!("{}", events::encode(event, status_json(&state)));
+        } else {
+            print!("\r\x1b[2K{}", status_line(&state));
+            std::io::stdout().flush()?;
+        }
+        if done {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+        if WATCH_INTERRUPTED.load(Ordering::SeqCst) {
             break;
         }
-        thread::sleep(Duration::from_millis(100));
     }
+    if !json {
+        println!();
+    }
+    Ok(())
+}
 
-    let _ = fs::remove_file(PID_FILE);
-
-    let out_path = fs::read_to_string(OUT_FILE).unwrap_or_default();
-    let out = PathBuf::from(out_path.trim());
-
-    // wait for mp4 to finalize
-    for _ in 0..30 {
-        if out.exists() && out.metadata().map(|m| m.len()).unwrap_or(0) > 0 {
-            println!("Recording stopped");
-            println!("Recording saved: {}", out.display());
-            return Ok(());
+fn run(cli: Cli) -> anyhow::Result<()> {
+    if cli.dump_log_path {
+        match cli.json {
+            true => println!("{}", json!({ "log": log_path() })),
+            false => println!("{}", log_path().display()),
+        }
+        return Ok(());
+    }
+    let Some(command) = cli.command else {
+        Cli::command().print_help()?;
+        std::process::exit(2);
+    };
+    match command {
+        Commands::Devices { backend } => {
+            cmd_devices(backend.unwrap_or_else(Backend::native), cli.json)?
+        }
+        Commands::BenchmarkDevices => cmd_benchmark_devices(cli.json)?,
+        Commands::Doctor => cmd_doctor(cli.json)?,
+        Commands::List {
+            output_dir,
+            since,
+            until,
+            filename_format,
+        } => cmd_list(output_dir, since, until, &filename_format, cli.json)?,
+        Commands::Last {
+            open,
+            output_dir,
+            filename_format,
+        } => cmd_last(open, output_dir, &filename_format, cli.json)?,
+        Commands::Rename {
+            old,
+            new,
+            output_dir,
+        } => cmd_rename(&old, &new, output_dir.as_deref(), cli.json, cli.quiet)?,
+        Commands::Tag {
+            file,
+            title,
+            comment,
+            output_dir,
+        } => {
+            let file = recording_path(&file, output_dir.as_deref());
+            cmd_tag(
+                &file,
+                title.as_deref(),
+                comment.as_deref(),
+                cli.json,
+                cli.quiet,
+            )?
         }
-        thread::sleep(Duration::from_millis(100));
+        Commands::Start(args) => cmd_start(&args, cli.json, cli.quiet)?,
+        Commands::Stop(args) => cmd_stop(&args, cli.quiet)?,
+        Commands::StopAll(args) => cmd_stop_all(&args, cli.json, cli.quiet)?,
+        Commands::Toggle(args) => cmd_toggle(&args, cli.json, cli.quiet)?,
+        Commands::Mark { label, label_flag } => {
+            let label = label.or(label_flag);
+            cmd_mark(label.as_deref().unwrap_or(""), cli.json, cli.quiet)?
+        }
+        Commands::Status { watch } => cmd_status(watch, cli.json, cli.json_errors)?,
+        Commands::Presets => cmd_presets(cli.json)?,
+        Commands::ListRunning => cmd_list_running(cli.json)?,
+        Commands::PrintOutputPath => cmd_print_output_path()?,
+        Commands::Decrypt { file, output } => cmd_decrypt(&file, output.as_deref(), cli.quiet)?,
+        Commands::Supervise {
+            pid,
+            event_socket,
+            output,
+            stop_on_sleep,
+            follow_window,
+            mark_on_touch,
+            track_clipboard,
+        } => events::supervise(
+            pid,
+            event_socket.as_deref(),
+            &output,
+            stop_on_sleep,
+            follow_window.as_deref(),
+            mark_on_touch.as_deref(),
+            track_clipboard,
+        ),
     }
 
-    println!("REC_STOP_ERR");
-    println!("Check log: {}", LOG_FILE);
     Ok(())
 }
 
-fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ColorMode {
+    Never,
+    Auto,
+    Always,
+}
+
+/// Whether stdout and stderr take ANSI colors, decided once in `main`
+static COLOR_STDOUT: AtomicBool = AtomicBool::new(false);
+static COLOR_STDERR: AtomicBool = AtomicBool::new(false);
+
+fn init_color(mode: ColorMode) {
+    // NO_COLOR counts when set to anything but the empty string
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let (stdout, stderr) = match mode {
+        ColorMode::Never => (false, false),
+        ColorMode::Always => (true, true),
+        ColorMode::Auto if no_color => (false, false),
+        ColorMode::Auto => (
+            std::io::stdout().is_terminal(),
+            std::io::stderr().is_terminal(),
+        ),
+    };
+    COLOR_STDOUT.store(stdout, Ordering::Relaxed);
+    COLOR_STDERR.store(stderr, Ordering::Relaxed);
+}
+
+#[derive(Clone, Copy)]
+enum Tone {
+    Good,
+    Warn,
+    Bad,
+}
+
+/// `text` in `tone` when the stream it is printed to (stderr or stdout)
+/// takes color. Not for what the plugin matches verbatim: the REC_* tokens
+/// and the `Recording saved:` line.
+fn tint(tone: Tone, text: &str, stderr: bool) -> String {
+    let colored = match stderr {
+        true => COLOR_STDERR.load(Ordering::Relaxed),
+        false => COLOR_STDOUT.load(Ordering::Relaxed),
+    };
+    if !colored {
+        return text.to_string();
+    }
+    let code = match tone {
+        Tone::Good => "32",
+        Tone::Warn => "33",
+        Tone::Bad => "31",
+    };
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+/// Lines of the ffmpeg log attached to --json-errors reports
+const LOG_TAIL_LINES: usize = 20;
+
+fn log_tail() -> Vec<String> {
+    let log = std::fs::read_to_string(log_path()).unwrap_or_default();
+    let lines: Vec<&str> = log.lines().collect();
+    let start = lines.len().saturating_sub(LOG_TAIL_LINES);
+    lines[start..].iter().map(|l| l.to_string()).collect()
+}
 
-    match cli.command {
-        Commands::Devices => cmd_devices()?,
-        Commands::Start { output_dir, x, y, width, height } => {
-            cmd_start(output_dir, x, y, width, height)?
+/// One --json-errors line on stderr.
+fn report_json(code: ErrorCode, details: &[String], exit_code: i32) {
+    let message = details.first().map_or(code.summary(), String::as_str);
+    let mut report = json!({
+        "code": code.token(),
+        "message": message,
+        "details": details,
+        "exit_code": exit_code,
+    });
+    if code.logged() {
+        report["log_tail"] = json!(log_tail());
+    }
+    eprintln!("{}", report);
+}
+
+/// The command line, with config.json's flags (the defaults, then any
+/// `--preset-file`) put in right after `start`/`toggle` so the ones typed
+/// override them.
+fn parse_cli() -> Cli {
+    let cli = Cli::parse();
+    let (name, args) = match &cli.command {
+        Some(Commands::Start(args)) => ("start", args),
+        Some(Commands::Toggle(args)) => ("toggle", args),
+        _ => return cli,
+    };
+    let injected = match Config::load().and_then(|c| c.start_args(args.preset_file.as_deref())) {
+        Ok(injected) if injected.is_empty() => return cli,
+        Ok(injected) => injected,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            std::process::exit(1);
         }
-Commands::Stop { .. } => cmd_stop()?,
+    };
+    let argv: Vec<std::ffi::OsString> = std::env::args_os().collect();
+    // The first word naming the subcommand; no global option takes it as a value
+    let Some(at) = argv.iter().position(|arg| arg == name) else {
+        return cli;
+    };
+    let mut full = argv[..=at].to_vec();
+    full.extend(injected.into_iter().map(Into::into));
+    full.extend_from_slice(&argv[at + 1..]);
+    // A flag given again replaces its earlier value instead of being an error
+    let matches = Cli::command()
+        .mut_subcommand(name, |cmd| cmd.args_override_self(true))
+        .get_matches_from(full);
+    Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit())
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = parse_cli();
+    // Before anything opens a state file
+    if let Some(session) = &cli.session {
+        set_session(session);
     }
+    init_color(cli.color);
+    set_keep_temp(cli.keep_temp);
+    set_log_format(cli.log_format);
+    let quiet = cli.quiet;
+    let json_errors = cli.json_errors;
 
-    Ok(())
+    // Declined requests are reported on stdout for the plugin, not as failures
+    let result = run(cli);
+    sync_log();
+    // On stderr, clear of REC_* tokens and --json output
+    for kept in kept_temp() {
+        eprintln!("Kept: {}", kept.display());
+    }
+    match result {
+        Err(err) => match err.downcast::<Rejection>() {
+            Ok(rejection) if json_errors => {
+                report_json(rejection.code, &rejection.details, rejection.exit_code);
+                if rejection.exit_code != 0 {
+                    std::process::exit(rejection.exit_code);
+                }
+                Ok(())
+            }
+            // An error with no code of its own, as if it had not been converted
+            Ok(rejection) if rejection.code == ErrorCode::Internal => {
+                eprintln!(
+                    "{}: {}",
+                    tint(Tone::Bad, "Error", true),
+                    rejection.details.join(": ")
+                );
+                std::process::exit(rejection.exit_code);
+            }
+            Ok(rejection) => {
+                // REC_STREAM_CLOSED arrives with stdout already gone
+                let mut stdout = std::io::stdout();
+                let mut say = |line: &str| {
+                    if writeln!(stdout, "{}", line).is_err() {
+                        eprintln!("{}", line);
+                    }
+                };
+                say(rejection.code.token());
+                for line in &rejection.details {
+                    if quiet {
+                        eprintln!("{}", line);
+                    } else {
+                        say(line);
+                    }
+                }
+                if rejection.exit_code != 0 {
+                    std::process::exit(rejection.exit_code);
+                }
+                Ok(())
+            }
+            Err(err) if json_errors => {
+                let details: Vec<String> = err.chain().map(|e| e.to_string()).collect();
+                report_json(ErrorCode::Internal, &details, 1);
+                std::process::exit(1);
+            }
+            Err(err) => {
+                eprintln!("{}: {:?}", tint(Tone::Bad, "Error", true), err);
+                std::process::exit(1);
+            }
+        },
+        ok => ok,
+    }
 }
@@ -0,0 +1,65 @@
+//! `--crop-nvim-window`: crop to the focused window of a running Neovim.
+//!
+//! rec-cli asks Neovim over its RPC socket (`nvim --server ... --remote-expr`)
+//! and lets the plugin's own `rec.geometry` do the cell-to-pixel conversion,
+//! so the crop matches what `:Rec` would pick for the same window.
+
+use crate::process::run_command;
+use crate::write_log;
+use serde::Deserialize;
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long a busy Neovim gets to answer before we record without it
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+const GEOMETRY_EXPR: &str =
+    r#"json_encode(luaeval("require('rec.geometry').get_window_geometry()"))"#;
+
+#[derive(Deserialize)]
+struct Geometry {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+/// `--nvim-socket`, or `$NVIM` when rec-cli runs inside Neovim.
+pub fn socket(explicit: Option<&Path>) -> Option<PathBuf> {
+    explicit.map(Path::to_path_buf).or_else(|| {
+        env::var_os("NVIM")
+            .filter(|s| !s.is_empty())
+            .map(PathBuf::from)
+    })
+}
+
+/// (x, y, w, h) of the focused window, in screen pixels.
+pub fn focused_window(socket: &Path) -> Result<(i32, i32, i32, i32), String> {
+    let args = [
+        OsStr::new("--server"),
+        socket.as_os_str(),
+        OsStr::new("--remote-expr"),
+        OsStr::new(GEOMETRY_EXPR),
+    ];
+    let output = run_command("nvim", args, TIMEOUT).map_err(|e| match e.kind() {
+        io::ErrorKind::TimedOut => format!("{} did not answer", socket.display()),
+        _ => format!("nvim not found on PATH ({})", e),
+    })?;
+    let reply = String::from_utf8_lossy(&output.stdout);
+    let reply = reply.trim();
+    write_log(&format!(
+        "Neovim window geometry from {}: {}",
+        socket.display(),
+        reply
+    ));
+
+    match serde_json::from_str::<Option<Geometry>>(reply) {
+        Ok(Some(g)) if g.width > 0 && g.height > 0 => Ok((g.x, g.y, g.width, g.height)),
+        Ok(_) => Err("the focused window cannot be recorded (floating?)".into()),
+        Err(_) if reply.is_empty() => Err(format!("no reply from {}", socket.display())),
+        Err(_) => Err(format!("unexpected reply from Neovim: {}", reply)),
+    }
+}
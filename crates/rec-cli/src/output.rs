@@ -0,0 +1,833 @@
+//! Where recordings go and how they are named.
+
+use crate::ffmpeg::{faststart_args, OutputFormat, FASTSTART, FRAGMENTED};
+use crate::{log_path, remove_temp, write_log};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use clap::ValueEnum;
+use dirs::home_dir;
+use nix::sys::statvfs::statvfs;
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Timestamp embedded in `rec_<ts>.mp4` names
+pub const OUTPUT_TS_FORMAT: &str = "%Y%m%d_%H%M%S";
+/// Ends the timestamp of a `--utc` name, so it can be told from local time
+const UTC_SUFFIX: char = 'Z';
+
+pub fn ensure_parent_dir(path: &Path) {
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+}
+
+/// Store `path` in `file` byte for byte: no newline is added, and nothing
+/// is trimmed or lossily converted on the way back.
+pub fn write_path_file(file: &str, path: &Path) -> std::io::Result<()> {
+    fs::write(file, path.as_os_str().as_bytes())
+}
+
+pub fn read_path_file(file: impl AsRef<Path>) -> Option<PathBuf> {
+    let bytes = fs::read(file).ok()?;
+    Some(PathBuf::from(OsStr::from_bytes(&bytes)))
+}
+
+/// Remux `out` in place: `ffmpeg -i out [-i input]... args`, stream-copied
+/// with `movflags` into a hidden `.<stem>.<label>.<ext>` that replaces `out`
+/// once written.
+pub fn remux_in_place(
+    out: &Path,
+    inputs: &[&Path],
+    args: &[&str],
+    movflags: Option<&str>,
+    label: &str,
+) -> Result<(), String> {
+    let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = out
+        .extension()
+        .map(|e| e.to_string_lossy())
+        .unwrap_or("mp4".into());
+    let rewritten = out.with_file_name(format!(".{}.{}.{}", stem, label, ext));
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path())
+        .map_err(|e| format!("cannot open {}: {}", log_path().display(), e))?;
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(["-hide_banner", "-loglevel", "error", "-y", "-i"])
+        .arg(out);
+    for input in inputs {
+        cmd.arg("-i").arg(input);
+    }
+    let status = cmd
+        .args(args)
+        .args(["-c", "copy"])
+        .args(faststart_args(out, movflags))
+        .arg(&rewritten)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(log)
+        .status();
+    let result = match status {
+        Ok(status) if status.success() && file_size(&rewritten) > 0 => fs::rename(&rewritten, out)
+            .map_err(|e| format!("cannot replace {}: {}", out.display(), e)),
+        Ok(status) => Err(format!("ffmpeg exited with {}", status)),
+        Err(err) => Err(format!("cannot run ffmpeg: {}", err)),
+    };
+    remove_temp(&rewritten);
+    result
+}
+
+/// `rec-cli rename`: move `old` to `new` (in the same directory when `new`
+/// is a bare name), along with its sidecars, the files named `<stem>.*`
+/// beside it. `new` keeps `old`'s extension, which names its container.
+/// Returns the new path and the sidecars moved.
+pub fn rename_recording(old: &Path, new: &Path) -> Result<(PathBuf, Vec<PathBuf>), String> {
+    if !old.is_file() {
+        return Err(format!("no recording at {}", old.display()));
+    }
+    let ext = old
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let mut new = match new.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => new.to_path_buf(),
+        _ => old.with_file_name(new),
+    };
+    match new.extension().map(|e| e.to_string_lossy().to_lowercase()) {
+        None => {
+            new.set_extension(&ext);
+        }
+        Some(given) if given != ext => {
+            return Err(format!(
+                "{} is a .{} file; renaming it to .{} would not change its format",
+                old.display(),
+                ext,
+                given
+            ))
+        }
+        Some(_) => {}
+    }
+    if new.exists() {
+        return Err(format!("{} already exists", new.display()));
+    }
+    let stem = old.file_stem().unwrap_or_default().to_os_string();
+    let new_stem = new.file_stem().unwrap_or_default().to_os_string();
+    let dir = old.parent().unwrap_or(Path::new("."));
+    let sidecars: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|p| p != old && p.is_file() && p.file_stem() == Some(&stem))
+        .collect();
+
+    fs::rename(old, &new).map_err(|e| format!("cannot rename {}: {}", old.display(), e))?;
+    write_log(&format!("Renamed {} to {}", old.display(), new.display()));
+    let mut moved = Vec::new();
+    for sidecar in sidecars {
+        let mut name = new_stem.clone();
+        if let Some(ext) = sidecar.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        let target = new.with_file_name(name);
+        if target.exists() {
+            write_log(&format!(
+                "Not renaming {}: {} exists",
+                sidecar.display(),
+                target.display()
+            ));
+            continue;
+        }
+        match fs::rename(&sidecar, &target) {
+            Ok(()) => moved.push(target),
+            Err(err) => write_log(&format!("Cannot rename {}: {}", sidecar.display(), err)),
+        }
+    }
+    Ok((new, moved))
+}
+
+/// `rec-cli tag`: set the title and/or comment tags of `file` in place.
+pub fn tag_recording(
+    file: &Path,
+    title: Option<&str>,
+    comment: Option<&str>,
+) -> Result<(), String> {
+    if !file.is_file() {
+        return Err(format!("no recording at {}", file.display()));
+    }
+    let tags: Vec<String> = [("title", title), ("comment", comment)]
+        .into_iter()
+        .filter_map(|(key, value)| Some(format!("{}={}", key, value?)))
+        .collect();
+    let mut args = vec!["-map", "0", "-map_metadata", "0"];
+    for tag in &tags {
+        args.extend(["-metadata", tag]);
+    }
+    remux_in_place(file, &[], &args, file_movflags(file), "tag")?;
+    write_log(&format!("Tagged {}: {}", file.display(), tags.join(", ")));
+    Ok(())
+}
+
+/// The `-movflags` that rebuild `file` as it is laid out, for a remux with
+/// no session to ask: fragments when a `moof` box comes before any `mdat`,
+/// `+faststart` when `moov` does, none when the index is at the end. Only
+/// the top-level box headers are read; `+faststart` when they can't be.
+pub fn file_movflags(file: &Path) -> Option<&'static str> {
+    let Ok(mut f) = fs::File::open(file) else {
+        return Some(FASTSTART);
+    };
+    let mut moov = false;
+    loop {
+        let mut header = [0u8; 8];
+        if f.read_exact(&mut header).is_err() {
+            return Some(FASTSTART);
+        }
+        let mut size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let mut read = 8;
+        if size == 1 {
+            let mut large = [0u8; 8];
+            if f.read_exact(&mut large).is_err() {
+                return Some(FASTSTART);
+            }
+            size = u64::from_be_bytes(large);
+            read = 16;
+        }
+        match &header[4..] {
+            b"moof" => return Some(FRAGMENTED),
+            b"mdat" if moov => return Some(FASTSTART),
+            b"mdat" => return None,
+            b"moov" => moov = true,
+            _ => {}
+        }
+        // 0 runs to the end of the file; anything under a header is corrupt
+        if size < read || f.seek(SeekFrom::Current((size - read) as i64)).is_err() {
+            return Some(FASTSTART);
+        }
+    }
+}
+
+pub fn file_size(path: &Path) -> u64 {
+    path.metadata().map(|m| m.len()).unwrap_or(0)
+}
+
+/// How long ago `path` was created; `None` when it does not exist yet or
+/// the filesystem keeps no creation time.
+pub fn file_age(path: &Path) -> Option<Duration> {
+    path.metadata()
+        .and_then(|m| m.created())
+        .ok()?
+        .elapsed()
+        .ok()
+}
+
+/// Longest file or directory name, in bytes (`NAME_MAX` on macOS and Linux)
+const NAME_MAX: usize = 255;
+
+/// Why the OS would refuse `path`: longer than `PATH_MAX` (the terminating
+/// NUL included) or with a name longer than `NAME_MAX`. `None` when it fits.
+pub fn path_length_error(path: &Path) -> Option<String> {
+    let len = path.as_os_str().len();
+    let max = libc::PATH_MAX as usize;
+    if len >= max {
+        return Some(format!(
+            "the path is {} bytes; the limit is {}",
+            len,
+            max - 1
+        ));
+    }
+    let long = path
+        .components()
+        .map(|c| c.as_os_str())
+        .find(|c| c.len() > NAME_MAX)?;
+    Some(format!(
+        "'{}' is {} bytes; a name can be at most {}",
+        long.to_string_lossy(),
+        long.len(),
+        NAME_MAX
+    ))
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `dir`.
+pub fn free_space(dir: &Path) -> Option<u64> {
+    let stat = statvfs(dir).ok()?;
+    Some(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+}
+
+/// Whether we can create files in `dir` (creating it if needed).
+pub fn dir_writable(dir: &Path) -> bool {
+    if fs::create_dir_all(dir).is_err() {
+        return false;
+    }
+    let probe = dir.join(format!(".rec-cli-doctor-{}", std::process::id()));
+    let ok = fs::write(&probe, b"").is_ok();
+    let _ = fs::remove_file(&probe);
+    ok
+}
+
+/// `dir` made absolute against `base`, with `.` and `..` resolved by name
+/// (the directory may not exist yet, so nothing is looked up on disk).
+pub fn absolute_dir(base: &Path, dir: &Path) -> PathBuf {
+    let mut resolved = PathBuf::new();
+    for part in base.join(dir).components() {
+        match part {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            other => resolved.push(other),
+        }
+    }
+    resolved
+}
+
+/// `--output-dir`, resolved against the directory rec-cli was invoked from
+/// while the arguments are parsed, so the path that is logged, stored and
+/// handed to ffmpeg never depends on a later working directory. A leading
+/// `~` is the home directory, as a shell would have it: config.json values
+/// never pass through one.
+pub fn parse_output_dir(s: &str) -> Result<PathBuf, String> {
+    let home = || home_dir().ok_or_else(|| format!("cannot resolve {}: no home directory", s));
+    let dir = match s.strip_prefix('~') {
+        Some("") => home()?,
+        Some(rest) if rest.starts_with('/') => home()?.join(rest.trim_start_matches('/')),
+        _ => PathBuf::from(s),
+    };
+    let dir = dir.as_path();
+    if dir.is_absolute() {
+        return Ok(absolute_dir(Path::new("/"), dir));
+    }
+    let cwd = env::current_dir().map_err(|e| format!("cannot resolve {}: {}", s, e))?;
+    Ok(absolute_dir(&cwd, dir))
+}
+
+/// `--output-file`, resolved like `--output-dir`. It has to name a file,
+/// not end in a directory.
+pub fn parse_output_file(s: &str) -> Result<PathBuf, String> {
+    if s.ends_with('/') {
+        return Err(format!("{} is a directory; use --output-dir", s));
+    }
+    let file = parse_output_dir(s)?;
+    match file.file_name() {
+        Some(_) => Ok(file),
+        None => Err(format!("'{}' does not name a file", s)),
+    }
+}
+
+/// `--output-dir-per-project`: the name of the project `dir` belongs to,
+/// the nearest directory at or above it holding `.git` (a file in a
+/// worktree), else `dir` itself. `None` for `/`.
+pub fn detect_project(dir: &Path) -> Option<String> {
+    let root = dir
+        .ancestors()
+        .find(|d| d.join(".git").exists())
+        .unwrap_or(dir);
+    root.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+}
+
+/// Validate a `--project` name, which becomes one folder of the path.
+pub fn parse_project(s: &str) -> Result<String, String> {
+    let name = s.trim();
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(format!("'{}' is not a usable folder name", s));
+    }
+    Ok(name.to_string())
+}
+
+pub fn default_output_dir() -> PathBuf {
+    let home = home_dir().unwrap_or_else(|| PathBuf::from("."));
+    home.join("Videos").join("nvim-recordings")
+}
+
+/// Parse an octal file mode for `--output-permissions` (`640`, `0640`,
+/// `0o640`).
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if !digits.is_empty() && mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("'{}' is not an octal file mode (e.g. 640)", s)),
+    }
+}
+
+/// Set `mode` on `path`, for `--output-permissions`.
+pub fn set_mode(path: &Path, mode: u32) {
+    match fs::set_permissions(path, fs::Permissions::from_mode(mode)) {
+        Ok(()) => write_log(&format!("Permissions {:o} set on {}", mode, path.display())),
+        Err(e) => write_log(&format!(
+            "--output-permissions: cannot set {:o} on {}: {}",
+            mode,
+            path.display(),
+            e
+        )),
+    }
+}
+
+/// Validate a `--filename-format` strftime string. It has to stay inside one
+/// path component and read back to a date and time for `list` to use it.
+pub fn parse_filename_format(s: &str) -> Result<String, String> {
+    if StrftimeItems::new(s).any(|item| item == Item::Error) {
+        return Err(format!("'{}' is not a valid strftime format", s));
+    }
+    let sample = Local::now().naive_local().format(s).to_string();
+    if sample.is_empty() || sample.contains('/') {
+        return Err(format!("'{}' must produce a non-empty name without '/'", s));
+    }
+    if NaiveDateTime::parse_from_str(&sample, s).is_err() {
+        return Err(format!("'{}' must include the date and the time of day", s));
+    }
+    Ok(s.to_string())
+}
+
+fn timestamp(now: DateTime<Utc>, ts_format: &str, utc: bool) -> String {
+    if utc {
+        format!("{}{}", now.format(ts_format), UTC_SUFFIX)
+    } else {
+        now.with_timezone(&Local).format(ts_format).to_string()
+    }
+}
+
+/// `dir/rec_<ts>.<ext>` for now, in local time or, with `utc`, in UTC.
+pub fn next_output_file(dir: &Path, ext: &str, ts_format: &str, utc: bool) -> PathBuf {
+    dir.join(format!(
+        "rec_{}.{}",
+        timestamp(Utc::now(), ts_format, utc),
+        ext
+    ))
+}
+
+/// What `start` does when its output name is taken, e.g. by a recording
+/// started within the same second.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum OnCollision {
+    /// Add `_1`, `_2`, ... to the name
+    #[default]
+    Suffix,
+    /// Record over the existing file
+    Overwrite,
+    /// Add the milliseconds to the timestamp, then a suffix if that is
+    /// taken too
+    Timestamp,
+    /// Refuse to start
+    Fail,
+}
+
+/// `out`, or the name `policy` picks for it when `taken`. `None` when the
+/// policy is to fail.
+pub fn resolve_collision(
+    out: PathBuf,
+    policy: OnCollision,
+    taken: impl Fn(&Path) -> bool,
+) -> Option<PathBuf> {
+    if !taken(&out) || policy == OnCollision::Overwrite {
+        return Some(out);
+    }
+    let stem = out
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let ext = out
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let named = |suffix: String| out.with_file_name(format!("{}{}{}", stem, suffix, ext));
+    if policy == OnCollision::Fail {
+        return None;
+    }
+    if policy == OnCollision::Timestamp {
+        let precise = named(format!("_{:03}", Utc::now().timestamp_subsec_millis()));
+        if !taken(&precise) {
+            return Some(precise);
+        }
+    }
+    (1..).map(|n| named(format!("_{}", n))).find(|p| !taken(p))
+}
+
+/// `<ts>` in local time; a UTC timestamp is converted.
+fn parse_timestamp(ts: &str, ts_format: &str) -> Option<NaiveDateTime> {
+    match ts.strip_suffix(UTC_SUFFIX) {
+        Some(utc) => {
+            let naive = NaiveDateTime::parse_from_str(utc, ts_format).ok()?;
+            Some(
+                Utc.from_utc_datetime(&naive)
+                    .with_timezone(&Local)
+                    .naive_local(),
+            )
+        }
+        None => NaiveDateTime::parse_from_str(ts, ts_format).ok(),
+    }
+}
+
+/// Local time parsed back out of a `rec_<ts>.<ext>` (or segment) name,
+/// whether `<ts>` was written in local time or with `--utc`.
+pub fn parse_output_timestamp(name: &str, ts_format: &str) -> Option<NaiveDateTime> {
+    let (ts, ext) = name.strip_prefix("rec_")?.rsplit_once('.')?;
+    OutputFormat::from_extension(ext)?;
+    parse_timestamp(ts, ts_format).or_else(|| {
+        // `--segment-time` parts are rec_<ts>_NNN.mp4
+        let (base, n) = ts.rsplit_once('_')?;
+        if n.is_empty() || !n.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        parse_timestamp(base, ts_format)
+    })
+}
+
+/// `dir/YYYY/MM/DD` for today, where `--date-subdirs` files recordings.
+/// With `utc` it is the UTC date, matching the file names.
+pub fn dated_dir(dir: &Path, utc: bool) -> PathBuf {
+    let day = if utc {
+        Utc::now().format("%Y/%m/%d").to_string()
+    } else {
+        Local::now().format("%Y/%m/%d").to_string()
+    };
+    dir.join(day)
+}
+
+/// Recordings in `dir` and its subdirectories (`--date-subdirs`), oldest
+/// first, by the (local) time in their name.
+pub fn recordings(dir: &Path, ts_format: &str) -> Vec<(PathBuf, NaiveDateTime)> {
+    let mut found = Vec::new();
+    collect_recordings(dir, ts_format, &mut found);
+    found.sort_by_key(|(_, ts)| *ts);
+    found
+}
+
+fn collect_recordings(dir: &Path, ts_format: &str, found: &mut Vec<(PathBuf, NaiveDateTime)>) {
+    for entry in fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        // file_type() does not follow symlinks, so a link loop can't trap us
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => collect_recordings(&path, ts_format, found),
+            _ if path.is_file() => {
+                let ts = path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .and_then(|n| parse_output_timestamp(n, ts_format));
+                if let Some(ts) = ts.filter(|_| !is_split_track(&path)) {
+                    found.push((path, ts));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A `--split-audio-track` .wav, which belongs to the video beside it
+/// rather than being an `--audio-only` recording of its own.
+fn is_split_track(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == OutputFormat::Wav.extension())
+        && OutputFormat::value_variants()
+            .iter()
+            .filter(|f| f.has_video())
+            .any(|f| path.with_extension(f.extension()).exists())
+}
+
+/// Delete the oldest recordings under `dir` beyond the newest `keep`
+/// (`--max-recordings`). `protect` and any other part of the same recording
+/// (segments share its timestamp) are never deleted. Returns what was.
+pub fn prune_recordings(dir: &Path, ts_format: &str, keep: usize, protect: &Path) -> Vec<PathBuf> {
+    let protected = protect
+        .file_name()
+        .and_then(|n| n.to_str())
+        .and_then(|n| parse_output_timestamp(n, ts_format));
+    let found = recordings(dir, ts_format);
+    let excess = found.len().saturating_sub(keep);
+    let mut removed = Vec::new();
+    for (path, ts) in found.into_iter().take(excess) {
+        if path == protect || Some(ts) == protected {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                write_log(&format!(
+                    "--max-recordings {}: deleted {}",
+                    keep,
+                    path.display()
+                ));
+                removed.push(path);
+            }
+            Err(err) => write_log(&format!(
+                "--max-recordings {}: could not delete {}: {}",
+                keep,
+                path.display(),
+                err
+            )),
+        }
+    }
+    removed
+}
+
+/// Newest `rec_<ts>.mp4` in `dir`.
+pub fn last_output_file(dir: &Path, ts_format: &str) -> Option<PathBuf> {
+    recordings(dir, ts_format).pop().map(|(path, _)| path)
+}
+
+/// Parse a `--since`/`--until` bound: a date (`2024-01-01`) or a date and
+/// time (`2024-01-01T09:30`, seconds optional). A bare date used as an upper
+/// bound covers the whole day.
+pub fn parse_time_bound(s: &str, end_of_day: bool) -> Result<NaiveDateTime, String> {
+    for fmt in [
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+    ] {
+        if let Ok(t) = NaiveDateTime::parse_from_str(s, fmt) {
+            return Ok(t);
+        }
+    }
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| format!("expected YYYY-MM-DD[THH:MM[:SS]], got '{}'", s))?;
+    let time = if end_of_day {
+        date.and_hms_opt(23, 59, 59)
+    } else {
+        date.and_hms_opt(0, 0, 0)
+    };
+    Ok(time.expect("valid time of day"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A top-level mp4 box of `kind` with `body` zero bytes after its header.
+    fn mp4_box(kind: &[u8; 4], body: usize) -> Vec<u8> {
+        let mut bytes = ((body + 8) as u32).to_be_bytes().to_vec();
+        bytes.extend(kind);
+        bytes.resize(body + 8, 0);
+        bytes
+    }
+
+    /// A path in the temp dir no other test (or test run) uses.
+    fn scratch(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rec-cli-test-{}-{}", std::process::id(), name))
+    }
+
+    fn layout(name: &str, boxes: &[(&[u8; 4], usize)]) -> Option<&'static str> {
+        let file = scratch(&format!("{}.mp4", name));
+        fs::write(
+            &file,
+            boxes
+                .iter()
+                .flat_map(|(kind, body)| mp4_box(kind, *body))
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+        let flags = file_movflags(&file);
+        fs::remove_file(&file).unwrap();
+        flags
+    }
+
+    #[test]
+    fn file_movflags_follows_the_box_order() {
+        assert_eq!(
+            layout("faststart", &[(b"ftyp", 16), (b"moov", 40), (b"mdat", 64)]),
+            Some(FASTSTART)
+        );
+        assert_eq!(
+            layout("end", &[(b"ftyp", 16), (b"mdat", 64), (b"moov", 40)]),
+            None
+        );
+        assert_eq!(
+            layout(
+                "fragmented",
+                &[(b"ftyp", 16), (b"moov", 40), (b"moof", 24), (b"mdat", 64)]
+            ),
+            Some(FRAGMENTED)
+        );
+    }
+
+    #[test]
+    fn file_movflags_falls_back_to_faststart() {
+        assert_eq!(
+            file_movflags(Path::new("/nonexistent/rec.mp4")),
+            Some(FASTSTART)
+        );
+        assert_eq!(layout("truncated", &[(b"ftyp", 16)]), Some(FASTSTART));
+    }
+
+    /// `resolve_collision` for `out` with `existing` already on disk.
+    fn resolve(out: &str, policy: OnCollision, existing: &[&str]) -> Option<PathBuf> {
+        resolve_collision(PathBuf::from(out), policy, |p| {
+            existing.iter().any(|e| p == Path::new(e))
+        })
+    }
+
+    #[test]
+    fn every_policy_keeps_a_free_name() {
+        for policy in OnCollision::value_variants() {
+            assert_eq!(
+                resolve("/r/rec.mp4", *policy, &[]),
+                Some("/r/rec.mp4".into())
+            );
+        }
+    }
+
+    #[test]
+    fn suffix_counts_past_taken_names() {
+        let taken = ["/r/rec.mp4", "/r/rec_1.mp4"];
+        assert_eq!(
+            resolve("/r/rec.mp4", OnCollision::Suffix, &taken),
+            Some("/r/rec_2.mp4".into())
+        );
+    }
+
+    #[test]
+    fn overwrite_reuses_the_taken_name() {
+        let taken = ["/r/rec.mp4"];
+        assert_eq!(
+            resolve("/r/rec.mp4", OnCollision::Overwrite, &taken),
+            Some("/r/rec.mp4".into())
+        );
+    }
+
+    #[test]
+    fn timestamp_adds_milliseconds() {
+        let free = resolve("/r/rec.mov", OnCollision::Timestamp, &["/r/rec.mov"]).unwrap();
+        let name = free.file_name().unwrap().to_string_lossy().into_owned();
+        let millis = name
+            .strip_prefix("rec_")
+            .and_then(|n| n.strip_suffix(".mov"))
+            .unwrap();
+        assert!(
+            millis.len() == 3 && millis.bytes().all(|b| b.is_ascii_digit()),
+            "{}",
+            name
+        );
+    }
+
+    #[test]
+    fn fail_refuses_a_taken_name() {
+        assert_eq!(
+            resolve("/r/rec.mp4", OnCollision::Fail, &["/r/rec.mp4"]),
+            None
+        );
+    }
+
+    #[test]
+    fn path_length_error_checks_the_path_and_each_name() {
+        assert_eq!(
+            path_length_error(Path::new("/home/me/Videos/rec.mp4")),
+            None
+        );
+        let name = "n".repeat(NAME_MAX);
+        assert_eq!(path_length_error(&Path::new("/tmp").join(&name)), None);
+        let long = format!("{}n", name);
+        let err = path_length_error(&Path::new("/tmp").join(&long)).unwrap();
+        assert!(err.contains("256 bytes"), "{}", err);
+        let deep = format!("/{}", ["dir"; 2048].join("/"));
+        let err = path_length_error(Path::new(&deep)).unwrap();
+        assert!(err.contains("the limit is"), "{}", err);
+    }
+
+    #[test]
+    fn path_files_keep_every_byte() {
+        let file = scratch("outpath");
+        let file = file.to_str().unwrap();
+        let paths = [
+            Path::new("/r/rec.mp4"),
+            Path::new(OsStr::from_bytes(b"/r/caf\xe9/rec.mp4")),
+            Path::new("/r/two\nlines/rec.mp4\n"),
+            Path::new(OsStr::from_bytes(b"/r/\xff\xfe\n rec.mp4 ")),
+        ];
+        for path in paths {
+            write_path_file(file, path).unwrap();
+            assert_eq!(read_path_file(file).as_deref(), Some(path));
+        }
+        fs::remove_file(file).unwrap();
+        assert_eq!(read_path_file(file), None);
+    }
+
+    #[test]
+    fn absolute_dir_resolves_by_name() {
+        let base = Path::new("/home/me/project");
+        assert_eq!(
+            absolute_dir(base, Path::new("rec")),
+            Path::new("/home/me/project/rec")
+        );
+        assert_eq!(
+            absolute_dir(base, Path::new("./a/../b/.")),
+            Path::new("/home/me/project/b")
+        );
+        assert_eq!(absolute_dir(base, Path::new("../../../..")), Path::new("/"));
+        assert_eq!(
+            absolute_dir(base, Path::new("/srv/./rec/")),
+            Path::new("/srv/rec")
+        );
+        // Nothing is looked up, so a parent that does not exist is fine
+        let missing = scratch("missing/parent");
+        assert_eq!(
+            absolute_dir(base, &missing.join("../rec")),
+            missing.parent().unwrap().join("rec")
+        );
+        assert!(!missing.exists());
+    }
+
+    #[test]
+    fn output_dir_expands_a_leading_tilde() {
+        let home = home_dir().unwrap();
+        assert_eq!(parse_output_dir("~"), Ok(home.clone()));
+        assert_eq!(
+            parse_output_dir("~/Videos/rec"),
+            Ok(home.join("Videos/rec"))
+        );
+        assert_eq!(parse_output_dir("~//Videos/../rec"), Ok(home.join("rec")));
+        // Someone else's home, or a folder named ~, is left alone
+        let cwd = env::current_dir().unwrap();
+        assert_eq!(parse_output_dir("~bob/rec"), Ok(cwd.join("~bob/rec")));
+        assert_eq!(parse_output_dir("a/~/rec"), Ok(cwd.join("a/~/rec")));
+    }
+
+    /// `resolve_collision` with `--on-collision suffix` for `name` in a
+    /// fresh directory holding `existing`.
+    fn suffixed_on_disk(name: &str, existing: &[&str]) -> String {
+        let dir = scratch(&format!("collide-{}", name));
+        fs::create_dir_all(&dir).unwrap();
+        for file in existing {
+            fs::write(dir.join(file), b"take").unwrap();
+        }
+        let free = resolve_collision(dir.join(name), OnCollision::Suffix, |p| p.exists()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(free.parent(), Some(dir.as_path()));
+        free.file_name().unwrap().to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn suffix_counts_up_on_disk_and_keeps_the_extension() {
+        assert_eq!(suffixed_on_disk("rec.mp4", &[]), "rec.mp4");
+        assert_eq!(suffixed_on_disk("rec.mp4", &["rec.mp4"]), "rec_1.mp4");
+        assert_eq!(
+            suffixed_on_disk("rec.mp4", &["rec.mp4", "rec_1.mp4"]),
+            "rec_2.mp4"
+        );
+        // Only the same name in the same container is taken
+        assert_eq!(
+            suffixed_on_disk("rec.mkv", &["rec.mp4", "rec_1.mp4"]),
+            "rec.mkv"
+        );
+        assert_eq!(
+            suffixed_on_disk("rec.mkv", &["rec.mkv", "rec_1.mp4"]),
+            "rec_1.mkv"
+        );
+        assert_eq!(
+            suffixed_on_disk("demo.v2.webm", &["demo.v2.webm"]),
+            "demo.v2_1.webm"
+        );
+        assert_eq!(suffixed_on_disk("take", &["take"]), "take_1");
+    }
+}
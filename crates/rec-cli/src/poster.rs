@@ -0,0 +1,130 @@
+//! `stop --poster`: the recording's first frame as its thumbnail.
+//!
+//! The frame is written to `<stem>.poster.jpg` next to the recording and,
+//! for mp4 and mov, also into the recording as an `attached_pic` cover,
+//! which Finder and most players show as the file's thumbnail. Other
+//! containers only get the image.
+
+use crate::ffmpeg::{faststart_args, probe_output_size};
+use crate::output::file_size;
+use crate::{log_path, remove_temp, write_log};
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// What `--poster` did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Poster {
+    /// The first frame was saved to `image`, and set as the cover when
+    /// `embedded`
+    Done { image: PathBuf, embedded: bool },
+    /// No poster, and why
+    Skipped(String),
+}
+
+fn ffmpeg(args: &[&OsStr]) -> Result<(), String> {
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path())
+        .map_err(|e| format!("cannot open {}: {}", log_path().display(), e))?;
+    let status = Command::new("ffmpeg")
+        .args(["-hide_banner", "-loglevel", "error", "-y"])
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(log)
+        .status()
+        .map_err(|e| format!("cannot run ffmpeg: {}", e))?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(format!("ffmpeg failed (see {})", log_path().display())),
+    }
+}
+
+/// Save the first frame of `out` as `<stem>.poster.jpg` and embed it,
+/// remuxing with the recording's `movflags`.
+pub fn make(out: &Path, movflags: Option<&str>) -> Poster {
+    if probe_output_size(out).is_none() {
+        write_log("--poster: no video track; skipped");
+        return Poster::Skipped("the recording has no video".into());
+    }
+    let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+    let image = out.with_file_name(format!("{}.poster.jpg", stem));
+    let extracted = ffmpeg(&[
+        "-i".as_ref(),
+        out.as_os_str(),
+        "-frames:v".as_ref(),
+        "1".as_ref(),
+        "-q:v".as_ref(),
+        "2".as_ref(),
+        image.as_os_str(),
+    ]);
+    // Too short to hold a whole frame: ffmpeg succeeds and writes nothing
+    if let Err(err) = extracted.and_then(|()| match file_size(&image) {
+        0 => Err("the recording is too short to have a frame".into()),
+        _ => Ok(()),
+    }) {
+        write_log(&format!("--poster: {}", err));
+        let _ = fs::remove_file(&image);
+        return Poster::Skipped(err);
+    }
+    write_log(&format!("Poster written: {}", image.display()));
+
+    let ext = out
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    if !matches!(ext.as_str(), "mp4" | "mov" | "m4v") {
+        write_log(&format!(
+            "--poster: .{} takes no cover; kept the image only",
+            ext
+        ));
+        return Poster::Done {
+            image,
+            embedded: false,
+        };
+    }
+    let covered = out.with_file_name(format!(".{}.poster.{}", stem, ext));
+    let mut args: Vec<&OsStr> = vec![
+        "-i".as_ref(),
+        out.as_os_str(),
+        "-i".as_ref(),
+        image.as_os_str(),
+    ];
+    let copy = [
+        "-map",
+        "0",
+        "-map",
+        "1",
+        "-c",
+        "copy",
+        "-disposition:v:1",
+        "attached_pic",
+    ];
+    args.extend(copy.into_iter().map(OsStr::new));
+    args.extend(faststart_args(out, movflags).into_iter().map(OsStr::new));
+    args.push(covered.as_os_str());
+    let embedded = ffmpeg(&args).and_then(|()| {
+        fs::rename(&covered, out).map_err(|e| format!("cannot replace the recording: {}", e))
+    });
+    match embedded {
+        Ok(()) => {
+            write_log(&format!("Poster set as the cover of {}", out.display()));
+            Poster::Done {
+                image,
+                embedded: true,
+            }
+        }
+        Err(err) => {
+            write_log(&format!("--poster: not embedded: {}", err));
+            remove_temp(&covered);
+            Poster::Done {
+                image,
+                embedded: false,
+            }
+        }
+    }
+}
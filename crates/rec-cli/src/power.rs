@@ -0,0 +1,109 @@
+//! System sleep and screen-lock detection for `--stop-on-sleep`, and the
+//! battery level for `--refuse-if-battery-below`.
+//!
+//! There is no notification API we can reach from a plain CLI, so the
+//! supervisor polls: the console session's lock flag from `ioreg`, and the
+//! wall clock, which jumps forward across a sleep that suspended everything
+//! (ffmpeg included) without warning.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+/// Wall-clock time beyond the poll interval that counts as a sleep.
+const SLEEP_GAP: Duration = Duration::from_secs(5);
+
+/// Whether the console session's screen is locked (macOS).
+pub fn screen_locked() -> bool {
+    Command::new("ioreg")
+        .args(["-n", "Root", "-d1"])
+        .output()
+        .map(|o| {
+            String::from_utf8_lossy(&o.stdout)
+                .lines()
+                .any(|line| line.contains("\"CGSSessionScreenIsLocked\"=Yes"))
+        })
+        .unwrap_or(false)
+}
+
+/// Notices wall-clock jumps between polls.
+pub struct SleepDetector {
+    last: SystemTime,
+    interval: Duration,
+}
+
+impl SleepDetector {
+    pub fn new(interval: Duration) -> Self {
+        SleepDetector {
+            last: SystemTime::now(),
+            interval,
+        }
+    }
+
+    /// Time lost to a sleep since the previous call, if there was one.
+    pub fn poll(&mut self) -> Option<Duration> {
+        let now = SystemTime::now();
+        let gap = now.duration_since(self.last).unwrap_or_default();
+        self.last = now;
+        gap.checked_sub(self.interval)
+            .filter(|lost| *lost > SLEEP_GAP)
+    }
+}
+
+/// Charge and power source of the machine's battery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Battery {
+    pub percent: u8,
+    /// Plugged in (charging, charged or just powered)
+    pub on_ac: bool,
+}
+
+/// `pmset -g batt`: `Now drawing from 'AC Power'`, then a line per battery
+/// such as ` -InternalBattery-0 (id=1234)\t85%; discharging; 3:12 remaining`.
+fn parse_pmset(output: &str) -> Option<Battery> {
+    let on_ac = output.lines().next()?.contains("'AC Power'");
+    let percent = output.lines().skip(1).find_map(|line| {
+        let (level, _) = line.split_once("%;")?;
+        level.rsplit(char::is_whitespace).next()?.parse().ok()
+    })?;
+    Some(Battery { percent, on_ac })
+}
+
+/// The first `Battery` in `/sys/class/power_supply`; on AC when a `Mains`
+/// supply is online or the battery is not discharging.
+fn sysfs_battery(root: &Path) -> Option<Battery> {
+    let read = |dir: &Path, name: &str| {
+        fs::read_to_string(dir.join(name))
+            .map(|v| v.trim().to_string())
+            .ok()
+    };
+    let supplies: Vec<_> = fs::read_dir(root)
+        .ok()?
+        .flatten()
+        .map(|e| e.path())
+        .collect();
+    let battery = supplies
+        .iter()
+        .find(|dir| read(dir, "type").as_deref() == Some("Battery"))?;
+    let percent = read(battery, "capacity")?.parse().ok()?;
+    let mains = supplies.iter().any(|dir| {
+        read(dir, "type").as_deref() == Some("Mains") && read(dir, "online").as_deref() == Some("1")
+    });
+    let discharging = read(battery, "status").as_deref() == Some("Discharging");
+    Some(Battery {
+        percent,
+        on_ac: mains || !discharging,
+    })
+}
+
+/// The battery, or `None` on a machine without one (or that cannot say).
+pub fn battery() -> Option<Battery> {
+    match cfg!(target_os = "macos") {
+        true => {
+            let output = Command::new("pmset").args(["-g", "batt"]).output().ok()?;
+            parse_pmset(&String::from_utf8_lossy(&output.stdout))
+        }
+        false => sysfs_battery(Path::new("/sys/class/power_supply")),
+    }
+}
@@ -0,0 +1,469 @@
+//! The ffmpeg process: PID bookkeeping, liveness and graceful shutdown.
+
+use crate::{write_log, PID_FILE, START_LOCK, STDIN_FIFO};
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Once;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub fn read_pid() -> Option<i32> {
+    fs::read_to_string(&*PID_FILE).ok()?.trim().parse().ok()
+}
+
+/// The session recorded without a name, whose PID file is `PID_FILE`
+pub const DEFAULT_SESSION: &str = "default";
+
+/// A recording's PID file whose process is alive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveSession {
+    pub session: String,
+    pub pid: i32,
+    /// `rec.nvim.pid`, or `rec.nvim.<session>.pid`
+    pub pid_file: PathBuf,
+}
+
+/// The PID files besides a session's own (`rec.nvim.preview.pid`, ...): the
+/// preview, the key overlay and the HUD. Not session names.
+const HELPER_PIDS: &[&str] = &["preview", "keys", "hud"];
+
+/// The session a PID file by the name `file_name` belongs to: `rec.nvim.pid`
+/// is the default one, `rec.nvim.<session>.pid` a named one. The helpers'
+/// PID files (`rec.nvim.hud.pid`, `rec.nvim.<session>.hud.pid`) are not
+/// sessions.
+pub fn pid_file_session(file_name: &str) -> Option<String> {
+    if file_name == "rec.nvim.pid" {
+        return Some(DEFAULT_SESSION.into());
+    }
+    let session = file_name.strip_prefix("rec.nvim.")?.strip_suffix(".pid")?;
+    parse_session_name(session).ok()
+}
+
+/// `--session`: letters, digits, `-` and `_`, so that the name cannot run
+/// into the rest of a state file's name, and not a helper's.
+pub fn parse_session_name(name: &str) -> Result<String, String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "invalid session name '{}' (letters, digits, - and _)",
+            name
+        ));
+    }
+    if HELPER_PIDS.contains(&name) {
+        return Err(format!("'{}' is reserved", name));
+    }
+    Ok(name.to_string())
+}
+
+/// The sessions whose PID file, next to `PID_FILE`, names a live process.
+pub fn live_sessions() -> Vec<LiveSession> {
+    let dir = Path::new(&*PID_FILE).parent().unwrap_or(Path::new("/tmp"));
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut live: Vec<LiveSession> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let session = pid_file_session(entry.file_name().to_str()?)?;
+            let pid = fs::read_to_string(entry.path()).ok()?.trim().parse().ok()?;
+            pid_alive(pid).then(|| LiveSession {
+                session,
+                pid,
+                pid_file: entry.path(),
+            })
+        })
+        .collect();
+    // The default first, then by name
+    live.sort_by_key(|s| (s.session != DEFAULT_SESSION, s.session.clone()));
+    live
+}
+
+/// When the system last booted: `btime` from /proc/stat on Linux,
+/// `kern.boottime` (`{ sec = 1700000000, usec = 0 } ...`) on macOS.
+pub fn boot_time() -> Option<SystemTime> {
+    let secs: u64 = match fs::read_to_string("/proc/stat") {
+        Ok(stat) => stat
+            .lines()
+            .find_map(|l| l.strip_prefix("btime "))?
+            .trim()
+            .parse()
+            .ok()?,
+        Err(_) => {
+            let output = Command::new("sysctl")
+                .args(["-n", "kern.boottime"])
+                .stdin(Stdio::null())
+                .stderr(Stdio::null())
+                .output()
+                .ok()?;
+            let text = String::from_utf8_lossy(&output.stdout);
+            let sec = text.split("sec = ").nth(1)?;
+            sec.split(|c: char| !c.is_ascii_digit())
+                .next()?
+                .parse()
+                .ok()?
+        }
+    };
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Whether the PID file was written before the last boot. Its pid then
+/// names no process of ours, whatever has that pid now.
+pub fn pid_file_predates_boot() -> bool {
+    let written = fs::metadata(&*PID_FILE).and_then(|m| m.modified()).ok();
+    matches!((written, boot_time()), (Some(written), Some(boot)) if written < boot)
+}
+
+pub fn pid_alive(pid: i32) -> bool {
+    match kill(Pid::from_raw(pid), None) {
+        // A zombie has exited (an ffmpeg done with --duration or --frames)
+        // and only waits for whoever inherited it to reap it
+        Ok(()) => !pid_zombie(pid),
+        // EPERM says the process exists but may not be signalled, which some
+        // sandboxes answer even for our own child; look it up instead, and
+        // believe EPERM when nothing can tell
+        Err(Errno::EPERM) => {
+            static WARNED: Once = Once::new();
+            WARNED.call_once(|| {
+                write_log(&format!(
+                    "kill({}, 0) is not permitted; checking liveness without it",
+                    pid
+                ))
+            });
+            pid_listed(pid).unwrap_or(true)
+        }
+        Err(_) => false,
+    }
+}
+
+/// Whether `pid` is a zombie, as far as /proc tells.
+fn pid_zombie(pid: i32) -> bool {
+    // The state follows the parenthesized command name, which may hold spaces
+    fs::read_to_string(format!("/proc/{}/stat", pid))
+        .ok()
+        .and_then(|stat| {
+            stat.rsplit_once(')')
+                .map(|(_, rest)| rest.trim_start().starts_with('Z'))
+        })
+        .unwrap_or(false)
+}
+
+/// Whether `pid` exists, asked without a signal: `/proc` on Linux, `ps`
+/// elsewhere. `None` when neither answers.
+fn pid_listed(pid: i32) -> Option<bool> {
+    if cfg!(target_os = "linux") && Path::new("/proc/self").exists() {
+        return Some(Path::new(&format!("/proc/{}", pid)).exists());
+    }
+    let output = Command::new("ps")
+        .args(["-o", "pid=", "-p", &pid.to_string()])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    // ps prints nothing (and exits 1) for a missing pid
+    Some(!String::from_utf8_lossy(&output.stdout).trim().is_empty())
+}
+
+/// Send `signal` to `pid`. When kill(2) is refused with EPERM, `kill(1)`
+/// gets a try before giving up with that EPERM.
+pub fn signal_pid(pid: i32, signal: Signal) -> nix::Result<()> {
+    match kill(Pid::from_raw(pid), signal) {
+        Err(Errno::EPERM) => {
+            let name = signal.as_str().trim_start_matches("SIG");
+            write_log(&format!(
+                "Sending {} to {} is not permitted; trying kill -{}",
+                signal, pid, name
+            ));
+            let sent = Command::new("kill")
+                .args([format!("-{}", name), pid.to_string()])
+                .stdin(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success());
+            if sent {
+                Ok(())
+            } else {
+                Err(Errno::EPERM)
+            }
+        }
+        result => result,
+    }
+}
+
+/// Whether the process is job-control stopped (paused with SIGSTOP).
+pub fn pid_paused(pid: i32) -> bool {
+    Command::new("ps")
+        .args(["-o", "stat=", "-p", &pid.to_string()])
+        .output()
+        .map(|o| {
+            String::from_utf8_lossy(&o.stdout)
+                .trim_start()
+                .starts_with('T')
+        })
+        .unwrap_or(false)
+}
+
+/// How long `pid` has been running: its start time in clock ticks since
+/// boot from /proc/<pid>/stat on Linux, `ps -o etime` (`[[dd-]hh:]mm:ss`)
+/// elsewhere.
+pub fn pid_elapsed(pid: i32) -> Option<Duration> {
+    if let Ok(stat) = fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        // starttime is field 22; the fields after `)` start at field 3
+        let (_, rest) = stat.rsplit_once(')')?;
+        let ticks: u64 = rest.split_whitespace().nth(19)?.parse().ok()?;
+        let per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        if per_sec <= 0 {
+            return None;
+        }
+        let started = boot_time()? + Duration::from_millis(ticks * 1000 / per_sec as u64);
+        return SystemTime::now().duration_since(started).ok();
+    }
+    let output = Command::new("ps")
+        .args(["-o", "etime=", "-p", &pid.to_string()])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let etime = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let (days, clock) = match etime.split_once('-') {
+        Some((days, clock)) => (days.parse::<u64>().ok()?, clock),
+        None => (0, etime.as_str()),
+    };
+    let secs = clock.split(':').try_fold(0u64, |total, part| {
+        part.parse::<u64>().ok().map(|n| total * 60 + n)
+    })?;
+    Some(Duration::from_secs(days * 86400 + secs))
+}
+
+/// Poll for up to `ticks` x 100ms; true once the process is gone.
+pub fn wait_for_exit(pid: i32, ticks: u32) -> bool {
+    for _ in 0..ticks {
+        if !pid_alive(pid) {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    !pid_alive(pid)
+}
+
+/// Run `bin` to completion with stdin closed, capturing stdout and stderr.
+/// Past `timeout` the child is killed and the error is `TimedOut`, so a
+/// probe stuck on a busy device cannot hang the caller.
+pub fn run_command<I, S>(bin: &str, args: I, timeout: Duration) -> io::Result<Output>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    run_command_lines(bin, args, timeout, |_| {})
+}
+
+/// `run_command`, also handing each line of stdout to `on_line` as it
+/// arrives, e.g. ffmpeg's `-progress pipe:1` reports.
+pub fn run_command_lines<I, S>(
+    bin: &str,
+    args: I,
+    timeout: Duration,
+    mut on_line: impl FnMut(&str),
+) -> io::Result<Output>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut child = Command::new(bin)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Drained on their own threads so a child filling a pipe is not stuck
+    // writing while we wait for it to exit
+    let (lines, received) = mpsc::channel();
+    let stdout = child.stdout.take().map(|pipe| {
+        thread::spawn(move || {
+            let mut pipe = BufReader::new(pipe);
+            let mut line = Vec::new();
+            while pipe.read_until(b'\n', &mut line).unwrap_or(0) > 0 {
+                if lines.send(std::mem::take(&mut line)).is_err() {
+                    break;
+                }
+            }
+        })
+    });
+    let stderr = child.stderr.take().map(|mut pipe| {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = pipe.read_to_end(&mut buf);
+            buf
+        })
+    });
+
+    let mut out = Vec::new();
+    let mut take = |line: Vec<u8>| {
+        on_line(String::from_utf8_lossy(&line).trim_end());
+        out.extend(line);
+    };
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("{} did not finish within {}s", bin, timeout.as_secs_f64()),
+            ));
+        }
+        match received.recv_timeout(Duration::from_millis(20)) {
+            Ok(line) => take(line),
+            // stdout is closed; the child may still be running
+            Err(RecvTimeoutError::Disconnected) => thread::sleep(Duration::from_millis(20)),
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+    };
+    // What the child wrote before it exited; the sender goes with the thread
+    if let Some(stdout) = stdout {
+        let _ = stdout.join();
+    }
+    received.try_iter().for_each(&mut take);
+    Ok(Output {
+        status,
+        stdout: out,
+        stderr: stderr.and_then(|t| t.join().ok()).unwrap_or_default(),
+    })
+}
+
+/// Create the stdin FIFO and open it read-write: ffmpeg inherits it as stdin
+/// and, because we also hold a writer, never sees EOF after we exit.
+pub fn open_stdin_fifo() -> std::io::Result<fs::File> {
+    let _ = fs::remove_file(&*STDIN_FIFO);
+    let path = std::ffi::CString::new(STDIN_FIFO.as_str()).expect("static path");
+    if unsafe { libc::mkfifo(path.as_ptr(), 0o600) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    OpenOptions::new().read(true).write(true).open(&*STDIN_FIFO)
+}
+
+/// Send `q` to ffmpeg through the stdin FIFO. Non-blocking, so a FIFO
+/// without a reader fails instead of hanging `stop`.
+pub fn send_quit() -> std::io::Result<()> {
+    let mut fifo = OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&*STDIN_FIFO)?;
+    fifo.write_all(b"q\n")
+}
+
+/// Take the advisory start lock without blocking. `None` means another
+/// `start` holds it; the lock is released when the file is dropped.
+pub fn try_lock_start() -> std::io::Result<Option<fs::File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&*START_LOCK)?;
+    match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+        Ok(()) => Ok(Some(file)),
+        Err(Errno::EWOULDBLOCK) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_recordings_have_session_pid_files() {
+        assert_eq!(
+            pid_file_session("rec.nvim.pid").as_deref(),
+            Some(DEFAULT_SESSION)
+        );
+        assert_eq!(
+            pid_file_session("rec.nvim.demo.pid").as_deref(),
+            Some("demo")
+        );
+        for helper in [
+            "rec.nvim.preview.pid",
+            "rec.nvim.keys.pid",
+            "rec.nvim.hud.pid",
+            "rec.nvim.demo.hud.pid",
+        ] {
+            assert_eq!(pid_file_session(helper), None, "{}", helper);
+        }
+        for other in [
+            "rec.nvim.outpath",
+            "rec.nvim..pid",
+            "other.pid",
+            "rec.nvim.demo.pid.bak",
+        ] {
+            assert_eq!(pid_file_session(other), None, "{}", other);
+        }
+    }
+
+    /// An executable `sh` script in the temp dir.
+    fn mock_bin(name: &str, body: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("rec-cli-{}-{}", name, std::process::id()));
+        fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn run_command_kills_a_hung_probe() {
+        let bin = mock_bin("hung-ffprobe", "exec sleep 30");
+        let started = Instant::now();
+        let err =
+            run_command(bin.to_str().unwrap(), ["-v"], Duration::from_millis(200)).unwrap_err();
+        let _ = fs::remove_file(&bin);
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(
+            err.to_string().ends_with("did not finish within 0.2s"),
+            "{}",
+            err
+        );
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "{:?}",
+            started.elapsed()
+        );
+    }
+
+    #[test]
+    fn run_command_captures_both_streams() {
+        let bin = mock_bin("ffprobe", "echo \"out $1\"; echo err >&2; exit 3");
+        let output = run_command(bin.to_str().unwrap(), ["-v"], Duration::from_secs(5)).unwrap();
+        let _ = fs::remove_file(&bin);
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"out -v\n");
+        assert_eq!(output.stderr, b"err\n");
+    }
+
+    #[test]
+    fn run_command_lines_hands_over_each_line() {
+        let bin = mock_bin("progress", "printf 'a\\nb\\n'; printf c");
+        let mut lines = Vec::new();
+        let output =
+            run_command_lines(bin.to_str().unwrap(), ["-v"], Duration::from_secs(5), |l| {
+                lines.push(l.to_string())
+            })
+            .unwrap();
+        let _ = fs::remove_file(&bin);
+        assert_eq!(lines, ["a", "b", "c"]);
+        assert_eq!(output.stdout, b"a\nb\nc");
+    }
+}
@@ -0,0 +1,4545 @@
+//! Starting, stopping and inspecting a recording.
+
+use crate::chapters::{self, Chapters};
+use crate::clipboard;
+use crate::compress;
+use crate::crypt;
+use crate::cursor;
+use crate::devices::{
+    self, check_mode, nearest_rate, supported_modes, Device, DeviceKind, DeviceLookup, Size,
+};
+use crate::events;
+use crate::ffmpeg::{
+    also_output_files, build_ffmpeg_args, clamp_crop, faststart_args, format_defaults,
+    get_screen_size, h264_level_for, has_filter, padded_size, parse_aspect, parse_crop_spec,
+    parse_fps, parse_opacity, parse_pad_color, parse_region_grid, parse_scale, parse_seconds,
+    parse_video_size, parse_watermark, pipewire_input, preview_args, probe_duration,
+    probe_framerate, probe_output_size, scaled_size, split_audio_path, tool_version,
+    try_probe_duration, which, Backend, CapturePlan, ColorPreset, FfmpegDefaults, FilterOrder,
+    OutputFormat, RecordingConfig, RegionGrid, Rotation, WatermarkPos, CODECS, COLORSPACES,
+    COLOR_PRIMARIES, COLOR_TRCS, CRF, FASTSTART, FRAGMENTED, FRAMERATE, H264_LEVELS, H264_PROFILES,
+    PIPEWIRE_SOURCE, SCREEN_INDEX, VIDEOTOOLBOX, X264_PRESETS,
+};
+use crate::follow;
+use crate::gif;
+use crate::hud;
+use crate::keys;
+use crate::loudness::{self, Normalized};
+use crate::nvim;
+use crate::output::{
+    dated_dir, default_output_dir, detect_project, dir_writable, ensure_parent_dir, file_age,
+    file_size, free_space, last_output_file, next_output_file, parse_filename_format, parse_mode,
+    parse_output_dir, parse_output_file, parse_project, path_length_error, prune_recordings,
+    read_path_file, resolve_collision, set_mode, write_path_file, OnCollision, OUTPUT_TS_FORMAT,
+};
+use crate::poster::{self, Poster};
+use crate::power;
+use crate::process::{
+    live_sessions, open_stdin_fifo, pid_alive, pid_elapsed, pid_file_predates_boot, pid_paused,
+    read_pid, run_command, send_quit, signal_pid, try_lock_start, wait_for_exit, DEFAULT_SESSION,
+};
+use crate::resume::{self, Resumed};
+use crate::schedule::{self, next_at, parse_start_at, Schedule};
+use crate::segments;
+use crate::session::{self, Crop, Session, SessionStatus, Settings};
+use crate::silence::{self, SilenceTrimmed};
+use crate::stats::RecordingStats;
+use crate::summary::{self, FfmpegSummary};
+use crate::title::{self, TitleCard};
+use crate::{
+    append_log, current_session, log_path, remove_temp, sync_log, write_log, ErrorCode, Rejection,
+    EVENTS_FILE, KEYS_PID_FILE, OUT_FILE, PID_FILE, PREVIEW_PID_FILE, PROGRESS_FILE, STATS_FILE,
+    STDIN_FIFO,
+};
+use chrono::{DateTime, Local, NaiveTime, TimeDelta};
+use clap::builder::PossibleValuesParser;
+use clap::{Args, ValueEnum};
+use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use serde::Serialize;
+use serde_json::json;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// First `--retry` delay; each later attempt waits this much longer.
+const RETRY_BACKOFF_MS: u64 = 500;
+
+/// How long `start` waits for ffmpeg to report its first captured frame.
+/// Generous: avfoundation can take a few seconds to open a screen.
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the repair remux of `stop` may copy streams before it is killed.
+const REMUX_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// avfoundation's complaints about a screen it may not open. Without Screen
+/// Recording permission, opening the input fails with the first of these.
+const SCREEN_PERMISSION_ERRORS: &[&str] = &[
+    "Input/output error",
+    "Failed to create AV capture input device",
+    "Cannot use Capture screen",
+];
+const SCREEN_PERMISSION_HINT: &str = "Grant Screen Recording permission to the app running \
+     rec-cli (your terminal or Neovim GUI) in System Settings > Privacy & Security > Screen \
+     Recording, then restart that app";
+
+/// Length of the `--loop-region-test` probe clip, in seconds.
+const REGION_TEST_SECS: f64 = 2.0;
+/// Length of the `--benchmark` clip, in seconds.
+const BENCHMARK_SECS: f64 = 5.0;
+/// Length of the `--dry-probe` clip, in seconds.
+const DRY_PROBE_SECS: f64 = 1.0;
+/// Recordings that may run at once unless `--concurrency-limit` says otherwise
+const DEFAULT_CONCURRENCY_LIMIT: u32 = 3;
+
+/// Options for [`Recorder::start`]. Doubles as the `rec-cli start` flags.
+#[derive(Args, Debug, Clone)]
+pub struct StartOptions {
+    /// Output directory; a relative path is taken from the current directory
+    #[arg(long, value_parser = parse_output_dir)]
+    pub output_dir: Option<PathBuf>,
+    /// Record to this file instead of a new rec_<ts> name. Its extension
+    /// has to be --format's (see --infer-format-from-extension); it goes
+    /// through --on-collision like any other name
+    #[arg(
+        long,
+        value_name = "PATH",
+        value_parser = parse_output_file,
+        conflicts_with_all = [
+            "output_dir", "date_subdirs", "replace_last", "stdout", "output_dir_per_project",
+            "project",
+        ]
+    )]
+    pub output_file: Option<PathBuf>,
+    /// Take the container from the --output-file extension, over --format
+    #[arg(long, requires = "output_file")]
+    pub infer_format_from_extension: bool,
+
+    /// Record every detected capture screen side by side in one file
+    #[arg(
+        long,
+        conflicts_with_all = ["x", "y", "width", "height", "crop_from_stdin", "crop_nvim_window"]
+    )]
+    pub all_screens: bool,
+
+    /// ffmpeg screen grabber: avfoundation (macOS), x11grab (X11),
+    /// gdigrab (Windows) or pipewire (Wayland; needs an ffmpeg with the
+    /// pipewiregrab source). Default: the one of this OS
+    #[arg(long, value_enum)]
+    pub backend: Option<Backend>,
+    /// PipeWire node to record with --backend pipewire (see `rec-cli
+    /// devices --backend pipewire`); by default the desktop portal asks
+    #[arg(long, value_name = "ID")]
+    pub pipewire_node: Option<u32>,
+    /// avfoundation video device to record (see `rec-cli devices`); by
+    /// default the "Capture screen 0" in the device list
+    #[arg(long, value_name = "INDEX", conflicts_with = "all_screens")]
+    pub screen_index: Option<u32>,
+    /// Pick the video device by name (e.g. "Capture screen 0") instead of
+    /// index, so configs survive machines where indices differ
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["all_screens", "screen_index"])]
+    pub screen_name: Option<String>,
+    /// Pick the screen by its CoreGraphics display UUID (see `rec-cli
+    /// devices --json`), looked up at each start, so the recording stays on
+    /// the same display when plugging in a monitor renumbers the screens
+    #[arg(
+        long,
+        value_name = "UUID",
+        conflicts_with_all = ["all_screens", "screen_index", "screen_name"]
+    )]
+    pub display_uuid: Option<String>,
+    /// Record whichever screen the mouse is on at start. Falls back to the
+    /// default screen, with a warning, when the cursor cannot be placed
+    #[arg(
+        long,
+        conflicts_with_all = ["all_screens", "screen_index", "screen_name", "display_uuid"]
+    )]
+    pub capture_display_under_cursor: bool,
+    /// Record this avfoundation audio device (by name) instead of silence
+    #[arg(long, value_name = "NAME")]
+    pub audio_name: Option<String>,
+    /// Shift the --audio-name track by MS milliseconds to fix lip-sync:
+    /// positive plays the audio later, negative earlier
+    #[arg(long, value_name = "MS", allow_negative_numbers = true)]
+    pub audio_delay: Option<i32>,
+    /// Also write the --audio-name track to <recording>.wav, to edit it
+    /// apart from the video. Nothing is written for silence.
+    #[arg(long, conflicts_with_all = ["stdout", "preview", "segment_time"])]
+    pub split_audio_track: bool,
+    /// Record only the --audio-name device, no screen, into an m4a (or a
+    /// wav with --format wav): narration to dub in later
+    #[arg(
+        long,
+        requires = "audio_name",
+        conflicts_with_all = [
+            "all_screens", "screen_index", "screen_name", "display_uuid", "audio_delay",
+            "capture_display_under_cursor", "big_cursor", "split_audio_track",
+            "crop_from_stdin", "crop_nvim_window", "region_grid", "follow_window", "x",
+            "video_size",
+            "input_framerate", "output_fps", "scale", "rotate", "hflip", "vflip", "aspect",
+            "sar", "dar", "watermark", "frames", "warmup_frames", "fade_in", "fade_out", "timelapse", "vfr",
+            "color_preset", "color_primaries", "color_trc", "colorspace", "also_output",
+        ]
+    )]
+    pub audio_only: bool,
+    /// Packets each input may queue before ffmpeg drops them (ffmpeg's
+    /// -thread_queue_size). Default: 512 with several inputs, else ffmpeg's
+    #[arg(long, value_name = "PACKETS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub thread_queue_size: Option<u32>,
+    /// Bytes ffmpeg reads to probe each input (ffmpeg's -probesize).
+    /// Default: 50000000 with several inputs, else ffmpeg's
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u64).range(32..))]
+    pub probesize: Option<u64>,
+    /// Encoder threads (ffmpeg's -threads), to leave cores for the editor
+    /// being recorded. Default: ffmpeg's, one per core
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub encoder_threads: Option<u32>,
+    /// Run ffmpeg at this lower priority (nice 1-19), so the recording
+    /// gives way when the CPU is busy
+    #[arg(long, value_name = "NICE", value_parser = clap::value_parser!(i32).range(1..=19))]
+    pub nice: Option<i32>,
+    /// Run ffmpeg at a raised priority so capture is not descheduled:
+    /// SCHED_RR on Linux (needs CAP_SYS_NICE or an rtprio limit), the
+    /// highest QoS tiers via taskpolicy on macOS. Without the permission
+    /// the recording runs at normal priority; the log says which
+    #[arg(long, conflicts_with = "nice")]
+    pub realtime: bool,
+    /// Ignore the cached device list and ask ffmpeg again
+    #[arg(long)]
+    pub refresh_devices: bool,
+
+    /// Read the crop rectangle from one line of stdin, as `x y w h` or JSON
+    /// (`{"x":..,"y":..,"width":..,"height":..}`), instead of --x/--y/...
+    #[arg(long, conflicts_with_all = ["x", "y", "width", "height"])]
+    pub crop_from_stdin: bool,
+    /// Crop to the focused window of a running Neovim, asked over its RPC
+    /// socket. Records the full screen (with a warning) when Neovim can't
+    /// be reached.
+    #[arg(long, conflicts_with_all = ["x", "y", "width", "height", "crop_from_stdin"])]
+    pub crop_nvim_window: bool,
+    /// Crop to one cell of a grid over the screen, as ROWSxCOLS:CELL with
+    /// cells numbered left to right, top to bottom (`2x2:1` is the top-left
+    /// quarter), or by name: left-half, top-right, center-third, ...
+    #[arg(
+        long,
+        value_name = "GRID",
+        value_parser = parse_region_grid,
+        conflicts_with_all = [
+            "x", "y", "width", "height", "crop_from_stdin", "crop_nvim_window", "all_screens",
+        ]
+    )]
+    pub region_grid: Option<RegionGrid>,
+    /// Neovim RPC socket for --crop-nvim-window [default: $NVIM]
+    #[arg(long, value_name = "PATH", requires = "crop_nvim_window")]
+    pub nvim_socket: Option<PathBuf>,
+    /// Experimental: follow the window whose title contains TITLE. A crop
+    /// can't move mid-recording, so the full screen is recorded and the
+    /// window's bounds, sampled every second, go to <recording>.follow.json
+    /// for a crop-follow pass afterwards. Needs Accessibility permission.
+    #[arg(
+        long,
+        value_name = "TITLE",
+        conflicts_with_all = [
+            "all_screens", "x", "y", "width", "height", "crop_from_stdin", "crop_nvim_window",
+            "region_grid", "stdout",
+        ]
+    )]
+    pub follow_window: Option<String>,
+
+    /// Capture size requested from the device (WxH), instead of the native
+    /// resolution. Crop coordinates are relative to this frame; any later
+    /// filters (rotation, flips) apply to the cropped result.
+    #[arg(long, value_parser = parse_video_size)]
+    pub video_size: Option<(i32, i32)>,
+    /// Capture frame rate. Cameras only offer some size/rate combinations;
+    /// an unsupported one is refused with the nearest that works
+    #[arg(
+        long,
+        value_name = "FPS",
+        default_value_t = FRAMERATE,
+        value_parser = clap::value_parser!(u32).range(1..=240)
+    )]
+    pub framerate: u32,
+    /// Frame rate of the file written, at most --framerate: capture smoothly
+    /// at 60 and keep every other frame for a 30 fps file half the size
+    #[arg(
+        long,
+        value_name = "FPS",
+        value_parser = clap::value_parser!(u32).range(1..=240),
+        conflicts_with = "timelapse"
+    )]
+    pub output_fps: Option<u32>,
+    /// Frame rate to ask the capture device for, when it should differ from
+    /// --framerate (the file's). A camera that does not offer it captures at
+    /// the nearest rate it does, and the file keeps --framerate
+    #[arg(long, value_name = "FPS", value_parser = parse_fps)]
+    pub input_framerate: Option<f64>,
+
+    /// Crop X (pixels)
+    #[arg(long)]
+    pub x: Option<i32>,
+    /// Crop Y (pixels)
+    #[arg(long)]
+    pub y: Option<i32>,
+    /// Crop width (pixels)
+    #[arg(long)]
+    pub width: Option<i32>,
+    /// Crop height (pixels)
+    #[arg(long)]
+    pub height: Option<i32>,
+
+    /// Refuse to record (REC_CROP_PROBE_FAILED) when the screen cannot be
+    /// probed for a requested crop, instead of recording uncropped
+    #[arg(long)]
+    pub crop_strict: bool,
+
+    /// Refuse to start where rec-cli would otherwise warn and record
+    /// anyway. Each refusal prints the REC_* token of the problem:
+    /// REC_CROP_PROBE_FAILED when the screen cannot be sized for the crop
+    /// (as --crop-strict), REC_BAD_CROP when the crop or --crop-nvim-window
+    /// is dropped, REC_UNSUPPORTED_MODE when a camera would capture at
+    /// another rate, and REC_FALLBACK_REFUSED when there is no home
+    /// directory for the default output directory, the start lock cannot
+    /// be taken, --audio-name/--audio-delay/--split-audio-track would be
+    /// ignored, ffplay is missing for --preview, stacked screens cannot all
+    /// be sized, or the H.264 level would be raised
+    #[arg(long, alias = "strict")]
+    pub fail_fast: bool,
+
+    /// Smallest crop width/height (pixels) worth recording
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(i32).range(1..))]
+    pub min_crop_size: i32,
+
+    /// Resize the output to WxH (even; either side -1 keeps the aspect ratio)
+    #[arg(long, value_parser = parse_scale)]
+    pub scale: Option<(i32, i32)>,
+    /// Apply the crop before --scale (crop in screen pixels) or after it
+    /// (crop in scaled pixels)
+    #[arg(long, value_enum, default_value_t = FilterOrder::CropFirst)]
+    pub filter_order: FilterOrder,
+
+    /// Rotate the recording clockwise (degrees)
+    #[arg(long, value_enum)]
+    pub rotate: Option<Rotation>,
+    /// Mirror the recording horizontally
+    #[arg(long)]
+    pub hflip: bool,
+    /// Mirror the recording vertically
+    #[arg(long)]
+    pub vflip: bool,
+
+    /// Letterbox the output to this aspect ratio (W:H, e.g. 16:9) instead of
+    /// stretching it. Applied after crop and rotation.
+    #[arg(long, value_parser = parse_aspect)]
+    pub aspect: Option<(u32, u32)>,
+    /// Background color for --aspect padding (ffmpeg color syntax)
+    #[arg(long, default_value = "black", requires = "aspect", value_parser = parse_pad_color)]
+    pub pad_color: String,
+
+    /// Tag the sample (pixel) aspect ratio, N:M, without resampling. Only
+    /// needed when a source has non-square pixels (some external capture
+    /// devices); screens never do. Default: leave the source's aspect alone.
+    #[arg(
+        long,
+        value_name = "N:M",
+        value_parser = parse_aspect,
+        conflicts_with_all = ["aspect", "dar"]
+    )]
+    pub sar: Option<(u32, u32)>,
+    /// Tag the display aspect ratio, N:M, instead (see --sar)
+    #[arg(long, value_name = "N:M", value_parser = parse_aspect, conflicts_with = "aspect")]
+    pub dar: Option<(u32, u32)>,
+
+    /// Overlay this image (png, jpg, gif, ...) on the recording, after crop,
+    /// scale and --aspect padding
+    #[arg(long, value_name = "IMAGE_PATH", value_parser = parse_watermark)]
+    pub watermark: Option<PathBuf>,
+    /// Corner (or center) of the frame for --watermark
+    #[arg(long, value_enum, default_value_t = WatermarkPos::BottomRight, requires = "watermark")]
+    pub watermark_pos: WatermarkPos,
+    /// Opacity of --watermark, from just above 0 to 1 (opaque)
+    #[arg(long, default_value_t = 1.0, requires = "watermark", value_parser = parse_opacity)]
+    pub watermark_opacity: f64,
+
+    /// Wait this many seconds before recording. `status` reports the
+    /// recording as scheduled meanwhile and `stop` cancels it.
+    #[arg(
+        long,
+        value_name = "SECS",
+        value_parser = parse_seconds,
+        conflicts_with_all = ["start_at", "loop_region_test", "benchmark"]
+    )]
+    pub delay: Option<f64>,
+    /// Start recording at this time of day (HH:MM or HH:MM:SS), tomorrow if
+    /// it has passed today; see --delay
+    #[arg(
+        long,
+        value_name = "HH:MM",
+        value_parser = parse_start_at,
+        conflicts_with_all = ["loop_region_test", "benchmark"]
+    )]
+    pub start_at: Option<NaiveTime>,
+
+    /// Stop recording by itself after this many seconds
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    pub duration: Option<f64>,
+    /// Stop recording by itself after exactly N frames of output, however
+    /// long they take, for short predictable clips
+    #[arg(
+        long,
+        value_name = "N",
+        alias = "framecount-limit",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["loop_region_test", "benchmark"]
+    )]
+    pub frames: Option<u64>,
+    /// Drop the first N captured frames (and the audio alongside), where
+    /// avfoundation can deliver black or repeated frames right after it
+    /// opens the screen. Default: none dropped
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub warmup_frames: Option<u32>,
+    /// Fade in from black over the first SECS seconds
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    pub fade_in: Option<f64>,
+    /// Fade out to black over the last SECS seconds. The end has to be known
+    /// when recording starts, so this needs --duration.
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds, requires = "duration")]
+    pub fade_out: Option<f64>,
+
+    /// Time-lapse: keep one frame every SECS seconds and play them back at
+    /// --timelapse-fps, so an hour at 10s intervals lasts 12s. Unlike a
+    /// normal recording there is no audio, and --duration still counts
+    /// wall-clock time.
+    #[arg(
+        long,
+        value_name = "SECS",
+        value_parser = parse_seconds,
+        conflicts_with_all = ["fade_out", "title_card"]
+    )]
+    pub timelapse: Option<f64>,
+    /// Playback frame rate of a --timelapse
+    #[arg(
+        long,
+        value_name = "FPS",
+        default_value_t = FRAMERATE,
+        value_parser = clap::value_parser!(u32).range(1..=120),
+        requires = "timelapse"
+    )]
+    pub timelapse_fps: u32,
+
+    /// Variable frame rate: drop frames that look the same as the one before
+    /// and keep the timestamps of the rest, so a mostly static screen makes a
+    /// far smaller file. Some players and editors handle VFR poorly (seeking,
+    /// audio drift in older QuickTime and some NLEs), and the frame rate a
+    /// player or --stats reports is an average of what changed, not
+    /// --framerate.
+    #[arg(long, conflicts_with = "timelapse")]
+    pub vfr: bool,
+
+    /// Open the recording with a generated intro showing this text, joined
+    /// on when it stops
+    #[arg(long, value_name = "TEXT")]
+    pub title_card: Option<String>,
+    /// How long the --title-card intro runs
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 3.0,
+        value_parser = parse_seconds,
+        requires = "title_card"
+    )]
+    pub title_card_duration: f64,
+    /// Background color of the --title-card intro (ffmpeg color syntax)
+    #[arg(long, default_value = "black", requires = "title_card", value_parser = parse_pad_color)]
+    pub title_card_color: String,
+
+    /// Split the recording into rec_<ts>_000.mp4, rec_<ts>_001.mp4, ... of
+    /// about SECS seconds each (cut at keyframes). A rec_<ts>.segments.json
+    /// manifest maps each segment to its wall-clock start time.
+    #[arg(
+        long,
+        value_name = "SECS",
+        value_parser = parse_seconds,
+        conflicts_with_all = ["preview", "stats", "title_card"]
+    )]
+    pub segment_time: Option<f64>,
+
+    /// End the output with its shortest stream (ffmpeg `-shortest`). On by
+    /// default only for the silent track; with --audio-name it is off, so
+    /// audio and video each run until `stop`.
+    #[arg(long, overrides_with = "no_shortest")]
+    pub shortest: bool,
+    /// Never pass `-shortest`, even for the silent track
+    #[arg(long, overrides_with = "shortest")]
+    pub no_shortest: bool,
+
+    /// Leave the mp4 index at the end instead of moving it to the front
+    /// when ffmpeg finishes: a quicker, lower-risk finalize for very large
+    /// recordings, but a browser has to fetch the whole file to play it
+    #[arg(long, conflicts_with = "fragmented")]
+    pub no_faststart: bool,
+    /// Write a fragmented mp4 (`+frag_keyframe+empty_moov`), playable up
+    /// to the last keyframe even if ffmpeg is killed before it finishes
+    #[arg(long)]
+    pub fragmented: bool,
+
+    /// Container to record into [default: mp4, or mkv with --stdout]
+    #[arg(long, value_enum)]
+    pub format: Option<OutputFormat>,
+    /// Write the recording to stdout instead of a file, for piping into
+    /// other tools (`rec-cli start --stdout | ...`). Needs a streamable
+    /// --format; messages go to stderr.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "output_dir", "date_subdirs", "replace_last", "preview", "segment_time", "stats",
+            "capture_keys", "title_card", "output_dir_per_project", "project",
+        ]
+    )]
+    pub stdout: bool,
+
+    /// H.264 profile (lower it for older playback hardware)
+    #[arg(long, default_value = "high", value_parser = PossibleValuesParser::new(H264_PROFILES))]
+    pub h264_profile: String,
+    /// H.264 level
+    #[arg(long, default_value = "4.2", value_parser = PossibleValuesParser::new(H264_LEVELS))]
+    pub h264_level: String,
+    /// Video encoder: libx264, or h264_videotoolbox for the hardware
+    /// encoder of Macs, which is rated by --bitrate rather than --crf
+    #[arg(long, default_value = "libx264", value_parser = PossibleValuesParser::new(CODECS))]
+    pub codec: String,
+    /// libx264 quality, 0 (lossless) to 51: higher makes smaller files that
+    /// look worse. Default: 23 (33 for VP9 with --format webm)
+    #[arg(long, value_name = "CRF", value_parser = clap::value_parser!(u32).range(0..=51))]
+    pub crf: Option<u32>,
+    /// libx264 preset: slower ones make smaller files at more CPU, which
+    /// can cost frames while recording. Default: ultrafast
+    #[arg(long, value_name = "PRESET", value_parser = PossibleValuesParser::new(X264_PRESETS))]
+    pub preset: Option<String>,
+    /// h264_videotoolbox target bitrate, in kbit/s. Default: 5000
+    #[arg(long, value_name = "KBPS", value_parser = clap::value_parser!(u32).range(100..))]
+    pub bitrate: Option<u32>,
+    /// Record losslessly with the cheapest encode there is, for the
+    /// smoothest capture, and compress to the final file (at a better ratio
+    /// than a live encode) when the recording stops. Until then the capture
+    /// takes many times the disk space, and `stop` takes about as long as
+    /// the compression.
+    #[arg(
+        long,
+        conflicts_with_all = ["stdout", "preview", "segment_time", "loop_region_test", "benchmark"]
+    )]
+    pub encode_in_background_after_stop: bool,
+
+    /// Refuse to start unless the output directory's filesystem has at least
+    /// this much free space (MB)
+    #[arg(long, value_name = "MB")]
+    pub min_free_space: Option<u64>,
+    /// Refuse to start on battery power with less than this much charge
+    /// left (percent), so the battery does not die mid-recording. Desktops
+    /// and laptops on AC always start
+    #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub refuse_if_battery_below: Option<u8>,
+
+    /// Tag the output with a standard set of color metadata
+    #[arg(long, value_enum)]
+    pub color_preset: Option<ColorPreset>,
+    /// Color primaries tag (e.g. bt709); overrides --color-preset
+    #[arg(long, value_parser = PossibleValuesParser::new(COLOR_PRIMARIES))]
+    pub color_primaries: Option<String>,
+    /// Transfer characteristics tag (e.g. bt709); overrides --color-preset
+    #[arg(long, value_parser = PossibleValuesParser::new(COLOR_TRCS))]
+    pub color_trc: Option<String>,
+    /// YUV matrix tag (e.g. bt709); overrides --color-preset
+    #[arg(long, value_parser = PossibleValuesParser::new(COLORSPACES))]
+    pub colorspace: Option<String>,
+
+    /// File recordings in a folder of the output directory named after the
+    /// project: the git repository holding the current directory, else the
+    /// directory itself. Any --date-subdirs go inside it.
+    #[arg(long)]
+    pub output_dir_per_project: bool,
+    /// Project folder to use instead of detecting one (implies
+    /// --output-dir-per-project)
+    #[arg(long, value_name = "NAME", value_parser = parse_project)]
+    pub project: Option<String>,
+
+    /// File recordings under YYYY/MM/DD/ subfolders of the output directory
+    #[arg(long)]
+    pub date_subdirs: bool,
+
+    /// Time in file names (and --date-subdirs folders) is UTC rather than
+    /// local time; the timestamp then ends in Z
+    #[arg(long)]
+    pub utc: bool,
+    /// strftime format for the timestamp in rec_<ts>.mp4 names. It must
+    /// include the date and time of day; pass the same one to `list`.
+    #[arg(long, default_value = OUTPUT_TS_FORMAT, value_parser = parse_filename_format)]
+    pub filename_format: String,
+
+    /// Reuse the newest rec_*.mp4 filename in the output directory,
+    /// overwriting that take instead of creating a new one
+    #[arg(long)]
+    pub replace_last: bool,
+
+    /// What to do when the new recording's name is already taken
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t)]
+    pub on_collision: OnCollision,
+
+    /// Relaunch ffmpeg up to N more times when it exits right away (e.g. the
+    /// capture device is still busy after the display wakes)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub retry: u32,
+
+    /// How `stop` asks ffmpeg to finish. `q` is written to ffmpeg's stdin
+    /// through a FIFO, which finalizes most cleanly; `sigint` sends SIGINT.
+    /// `stop` falls back to SIGINT when the FIFO is unavailable.
+    #[arg(long, value_enum, default_value_t = StopSignal::Q)]
+    pub stop_signal: StopSignal,
+
+    /// Extra argument passed to ffmpeg just before the output path
+    /// (repeatable, e.g. `--ffmpeg-arg=-tune --ffmpeg-arg=zerolatency`).
+    /// Unchecked: a bad argument can break recording.
+    #[arg(long = "ffmpeg-arg", value_name = "ARG", allow_hyphen_values = true)]
+    pub ffmpeg_args: Vec<String>,
+
+    /// Print the exact ffmpeg command (shell-quoted, one line) before recording
+    #[arg(long)]
+    pub dump_args: bool,
+
+    /// Write a rec_<ts>.json sidecar describing the recording on stop
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Log keystrokes during the recording and write them to an .srt
+    /// caption file next to it. Needs the `rec-keys` helper and macOS
+    /// Accessibility permission; recording continues without it.
+    #[arg(long)]
+    pub capture_keys: bool,
+
+    /// Show a small always-on-top timer while recording. It is hidden from
+    /// screen capture, so it never shows up in the video.
+    #[arg(long)]
+    pub hud: bool,
+    /// Capture the mouse pointer, enlarged FACTOR times (up to 4) with the
+    /// Accessibility pointer size for the length of the recording, so it
+    /// stays visible when a HiDPI screen is scaled down
+    #[arg(long, value_name = "FACTOR", value_parser = cursor::parse_factor)]
+    pub big_cursor: Option<f64>,
+
+    /// Record a 2-second probe clip with these options, report the frame
+    /// size it actually came out at (and whether that is what the crop asked
+    /// for), delete it and exit without starting a recording
+    #[arg(long, conflicts_with_all = ["stdout", "preview", "segment_time", "duration"])]
+    pub loop_region_test: bool,
+
+    /// Record a 5-second clip with these options and report the encoding
+    /// fps, speed and CPU use, to check the settings keep up on this
+    /// machine, then delete it and exit without starting a recording
+    #[arg(
+        long,
+        conflicts_with_all = ["stdout", "preview", "segment_time", "duration", "loop_region_test"]
+    )]
+    pub benchmark: bool,
+    /// Print a rough prediction of the file size these options make and
+    /// exit without recording. For --estimate-duration, else --duration,
+    /// else an hour
+    #[arg(long, conflicts_with_all = ["loop_region_test", "benchmark"])]
+    pub estimate_size: bool,
+    /// Check everything a recording with these options needs (ffmpeg and
+    /// ffprobe, the options, the output directory, the screen and crop,
+    /// the Screen Recording permission), print a pass/fail line for each
+    /// and exit without recording. Exits 1 when a check fails
+    #[arg(long, conflicts_with_all = ["loop_region_test", "benchmark", "estimate_size"])]
+    pub dry_probe: bool,
+    /// Recording length --estimate-size predicts for
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds, requires = "estimate_size")]
+    pub estimate_duration: Option<f64>,
+
+    /// Start even though the PID file names a live process. For when that
+    /// PID was reused by something else and `start` keeps answering
+    /// REC_ALREADY_RUNNING with nothing recording.
+    #[arg(long, alias = "ignore-existing-pid")]
+    pub force_start: bool,
+    /// Refuse to start (REC_TOO_MANY_SESSIONS) while this many recordings
+    /// are running, the default session and any started with --session;
+    /// set it in config.json to change it everywhere
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_CONCURRENCY_LIMIT,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub concurrency_limit: u32,
+    /// Pick up a recording that was interrupted (ffmpeg died without
+    /// `stop`): `stop` joins what could be recovered of it and this new
+    /// recording into one file. The seam is a hard cut, and the parts must
+    /// be recorded alike (format, size, audio) or they are kept apart.
+    #[arg(long, conflicts_with_all = ["stdout", "segment_time", "force_start"])]
+    pub resume: bool,
+
+    /// Apply the flags of the preset NAME from config.json (see `rec-cli
+    /// presets`); flags given here win over the preset's
+    #[arg(long, value_name = "NAME")]
+    pub preset_file: Option<String>,
+
+    /// Show a live ffplay window of what is being recorded (crop and all)
+    #[arg(long)]
+    pub preview: bool,
+
+    /// Also send the recording to PATH or URL (repeatable), muxed from the
+    /// same encode by ffmpeg's tee muxer. Every destination gets the same
+    /// codecs, so each container must take H.264 (or GIF) as recorded.
+    /// The muxer comes from the extension or scheme (rtmp:// is flv,
+    /// udp:// and srt:// are mpegts), or give tee options first:
+    /// `[f=mpegts]out.ts`. A destination that fails is dropped, the rest
+    /// keep recording. Files are checked and encrypted with the recording
+    #[arg(
+        long,
+        value_name = "PATH/URL",
+        conflicts_with_all = [
+            "stdout", "segment_time", "split_audio_track", "encode_in_background_after_stop",
+        ]
+    )]
+    pub also_output: Vec<String>,
+
+    /// Stop (and finalize) the recording when the screen locks or the Mac
+    /// sleeps, instead of leaving a corrupt file after a lid close
+    #[arg(long)]
+    pub stop_on_sleep: bool,
+
+    /// Drop a chapter marker whenever FILE is modified, e.g. by an editor
+    /// autocommand. Lines appended since the last marker become labels
+    /// (`echo 'opened file' >> FILE`); a bare `touch FILE` marks unlabeled.
+    #[arg(long, value_name = "FILE", conflicts_with = "stdout")]
+    pub mark_on_touch: Option<PathBuf>,
+
+    /// Write when the clipboard changed (a copy) during the recording to a
+    /// .clipboard.json timeline next to it. Only the times are kept, never
+    /// what was copied; macOS only
+    #[arg(long, conflicts_with = "stdout")]
+    pub track_clipboard: bool,
+
+    /// Unix socket to send newline-delimited JSON events to (started,
+    /// paused, resumed, progress, stopped, error)
+    #[arg(long)]
+    pub event_socket: Option<PathBuf>,
+}
+
+impl StartOptions {
+    /// The screen grabber actually used, `--backend` or this OS's.
+    pub fn backend(&self) -> Backend {
+        self.backend.unwrap_or_else(Backend::native)
+    }
+
+    /// The container actually used: `--format` or its default, or the
+    /// `--output-file` extension's with --infer-format-from-extension.
+    pub fn format(&self) -> OutputFormat {
+        let inferred = self
+            .extension_format()
+            .filter(|_| self.infer_format_from_extension);
+        if let Some(format) = inferred {
+            return format;
+        }
+        match self.format {
+            Some(format) => format,
+            None if self.audio_only && self.stdout => OutputFormat::Wav,
+            None if self.audio_only => OutputFormat::M4a,
+            None if self.stdout => OutputFormat::Mkv,
+            None => OutputFormat::Mp4,
+        }
+    }
+
+    /// The container the `--output-file` extension names, if it names one.
+    fn extension_format(&self) -> Option<OutputFormat> {
+        let ext = self.output_file.as_deref()?.extension()?.to_str()?;
+        OutputFormat::from_extension(ext)
+    }
+
+    /// What ffmpeg gets unless flags override it: the container's defaults,
+    /// without audio for a time-lapse.
+    pub fn ffmpeg_defaults(&self) -> FfmpegDefaults {
+        let defaults = format_defaults(self.format());
+        let defaults = FfmpegDefaults {
+            faststart: defaults.faststart && !self.no_faststart && !self.fragmented,
+            ..defaults
+        };
+        match self.timelapse {
+            Some(_) => FfmpegDefaults {
+                audio: false,
+                ..defaults
+            },
+            None => defaults,
+        }
+    }
+
+    /// `-movflags` for the mp4 muxer: `+faststart`, fragments, or neither.
+    pub fn movflags(&self) -> Option<&'static str> {
+        match (self.fragmented, self.ffmpeg_defaults().faststart) {
+            (true, _) => Some(FRAGMENTED),
+            (_, true) => Some(FASTSTART),
+            _ => None,
+        }
+    }
+
+    /// Whether a GIF gets its palette from a pass over the whole recording
+    /// at `stop`. One piped to stdout or written by tee has to build it in
+    /// the recording's own filtergraph.
+    pub fn two_pass_gif(&self) -> bool {
+        self.ffmpeg_defaults().palette && !self.stdout && self.also_output.is_empty()
+    }
+
+    /// Whether ffmpeg records a lossless capture that `stop` encodes into
+    /// the output: `--encode-in-background-after-stop`, or a two-pass GIF.
+    pub fn lossless_capture(&self) -> bool {
+        self.encode_in_background_after_stop || self.two_pass_gif()
+    }
+
+    /// Whether ffmpeg may write over an existing file: `--on-collision
+    /// overwrite`, or `--replace-last`, which is for exactly that. Any other
+    /// name was picked free.
+    pub fn overwrites(&self) -> bool {
+        self.on_collision == OnCollision::Overwrite || self.replace_last
+    }
+
+    /// `--project`, or the detected one with `--output-dir-per-project`.
+    pub fn project_folder(&self) -> Option<String> {
+        if self.project.is_some() {
+            return self.project.clone();
+        }
+        let cwd = env::current_dir().ok()?;
+        self.output_dir_per_project
+            .then(|| detect_project(&cwd))
+            .flatten()
+    }
+
+    /// `--output-dir` (or the default), with the project folder if any:
+    /// where recordings go, above any date subfolder. The folder of
+    /// `--output-file` when there is one.
+    pub fn output_root(&self) -> PathBuf {
+        if let Some(parent) = self.output_file.as_deref().and_then(Path::parent) {
+            return parent.to_path_buf();
+        }
+        let dir = self.output_dir.clone().unwrap_or_else(default_output_dir);
+        match self.project_folder() {
+            Some(project) => dir.join(project),
+            None => dir,
+        }
+    }
+
+    /// When `--delay` or `--start-at` has the recording begin, if later.
+    pub fn scheduled_start(&self) -> Option<DateTime<Local>> {
+        if let Some(delay) = self.delay {
+            return Some(Local::now() + TimeDelta::milliseconds((delay * 1000.0) as i64));
+        }
+        self.start_at.map(next_at)
+    }
+
+    /// Frames per second of the file written.
+    /// Frame rate asked of the capture device, before any substitution.
+    pub fn capture_framerate(&self) -> f64 {
+        self.input_framerate.unwrap_or(self.framerate as f64)
+    }
+
+    pub fn output_framerate(&self) -> u32 {
+        match self.timelapse {
+            Some(_) => self.timelapse_fps,
+            None => self.output_fps.unwrap_or(self.framerate),
+        }
+    }
+}
+
+/// Options for [`Recorder::stop`]. Doubles as the `rec-cli stop` flags.
+#[derive(Args, Debug, Clone, Default)]
+pub struct StopOptions {
+    /// Output directory (ignored, for compatibility)
+    #[arg(long)]
+    pub output_dir: Option<PathBuf>,
+
+    /// If the recording does not finalize, remove the empty file it left behind
+    #[arg(long)]
+    pub delete_on_fail: bool,
+
+    /// Report a recording that does not play as corrupt and keep it as
+    /// recorded, instead of trying to remux it into a playable file first
+    #[arg(long)]
+    pub no_repair: bool,
+
+    /// Cut this many seconds from the start into a rec_<ts>_trimmed.mp4 copy.
+    /// Without --trim-accurate the cut snaps to the nearest keyframe, so it
+    /// can be off by up to a couple of seconds.
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    pub trim_start: Option<f64>,
+    /// Cut this many seconds from the end (see --trim-start)
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    pub trim_end: Option<f64>,
+    /// Re-encode the trimmed copy so the cuts land exactly (slower)
+    #[arg(long)]
+    pub trim_accurate: bool,
+
+    /// Write a loudness-normalized rec_<ts>_normalized.mp4 copy (two-pass
+    /// loudnorm). Skipped when there is no real audio (--audio-name).
+    #[arg(long)]
+    pub normalize_audio: bool,
+    /// Integrated loudness for --normalize-audio, in LUFS
+    #[arg(
+        long,
+        value_name = "LUFS",
+        default_value_t = loudness::DEFAULT_TARGET,
+        allow_negative_numbers = true,
+        requires = "normalize_audio"
+    )]
+    pub loudness_target: f64,
+
+    /// Write a rec_<ts>_nosilence copy with the long silent gaps cut out
+    /// (video cut along with them), for voiceovers. Skipped when there is
+    /// no real audio (--audio-name).
+    #[arg(long)]
+    pub trim_silence: bool,
+    /// Level below which --trim-silence hears silence, in dB
+    #[arg(
+        long,
+        value_name = "DB",
+        default_value_t = silence::DEFAULT_THRESHOLD,
+        allow_negative_numbers = true,
+        requires = "trim_silence"
+    )]
+    pub silence_threshold: f64,
+    /// Shortest silent gap --trim-silence cuts, in seconds
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = silence::DEFAULT_DURATION,
+        value_parser = parse_seconds,
+        requires = "trim_silence"
+    )]
+    pub silence_duration: f64,
+
+    /// After a successful stop, delete the oldest recordings in the output
+    /// directory so that only the newest N remain. Off unless given
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_recordings: Option<u32>,
+
+    /// Encrypt the recording (and its trimmed copy and key captions) with a
+    /// passphrase into <file>.age, removing the plaintext. The passphrase is
+    /// read from REC_PASSPHRASE or prompted for; see `rec-cli decrypt`.
+    #[arg(long)]
+    pub encrypt: bool,
+
+    /// Save the first frame as <recording>.poster.jpg and, in an mp4 or
+    /// mov, set it as the cover file browsers show as the thumbnail
+    #[arg(long)]
+    pub poster: bool,
+
+    /// Set this octal mode (e.g. 640) on the recording and every file
+    /// `stop` writes for it, once they are finished, whatever the umask
+    #[arg(long, value_name = "MODE", value_parser = parse_mode)]
+    pub output_permissions: Option<u32>,
+
+    /// Called with the fraction done while `stop` compresses a
+    /// `--encode-in-background-after-stop` capture
+    #[arg(skip)]
+    pub on_progress: Option<fn(f64)>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopSignal {
+    Q,
+    Sigint,
+}
+
+/// A fully resolved recording, ready to launch. Inspect `ffmpeg_args` to see
+/// exactly what will run.
+#[derive(Debug)]
+pub struct PreparedRecording {
+    pub options: StartOptions,
+    pub plan: CapturePlan,
+    pub ffmpeg_args: Vec<String>,
+    /// Crop rectangle as asked for (x, y, w, h), before clamping
+    pub requested_crop: Option<(i32, i32, i32, i32)>,
+    /// Probed screen size the crop was clamped against; `None` with
+    /// --video-size, whose frame cannot change under us
+    pub crop_screen: Option<(i32, i32)>,
+    /// Remuxed copy of a previous recording whose ffmpeg died mid-capture
+    pub recovered: Option<PathBuf>,
+    /// `--replace-last` take in another format, which `stop` deletes once
+    /// this one is saved
+    pub replaces: Option<PathBuf>,
+    /// Requested behavior that had to be dropped; carried into the handle
+    pub warnings: Vec<String>,
+    /// Start lock, held until `launch` has written the PID file
+    lock: Option<fs::File>,
+}
+
+/// A recording that `start` launched.
+#[derive(Debug, Clone)]
+pub struct RecordingHandle {
+    pub pid: i32,
+    pub output: PathBuf,
+    /// Optional extras that could not be enabled (e.g. key capture)
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordingState {
+    Idle,
+    Recording {
+        pid: i32,
+        output: PathBuf,
+        paused: bool,
+        /// Time since ffmpeg was launched, pauses included
+        elapsed: Duration,
+        /// Bytes written to the output so far
+        size: u64,
+        /// The output's directory was deleted; ffmpeg is writing to a file
+        /// nothing can reach any more
+        output_dir_gone: bool,
+        /// The `--hud` timer window, if one is open
+        hud_pid: Option<i32>,
+        /// What `start` set the recording up as
+        settings: Option<Settings>,
+    },
+    /// A `start --delay`/`--start-at` waiting to begin
+    Scheduled {
+        /// The waiting `start`
+        pid: i32,
+        at: DateTime<Local>,
+    },
+}
+
+impl RecordingState {
+    /// Average bitrate so far, in kbit/s.
+    pub fn bitrate_kbps(&self) -> Option<f64> {
+        match self {
+            RecordingState::Recording { elapsed, size, .. } if !elapsed.is_zero() => {
+                Some(*size as f64 * 8.0 / 1000.0 / elapsed.as_secs_f64())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A recording that `stop` finalized.
+#[derive(Debug, Clone)]
+pub struct StoppedRecording {
+    pub output: PathBuf,
+    /// `--stats` sidecar, when one was requested
+    pub stats: Option<PathBuf>,
+    /// `--stats` frame rates: (requested, achieved); not with `--vfr`
+    pub framerate: Option<(u32, f64)>,
+    /// `--capture-keys` captions, when keys were recorded
+    pub keys: Option<PathBuf>,
+    /// `--segment-time` manifest; `output` is then the first segment
+    pub segments: Option<PathBuf>,
+    /// `--trim-start`/`--trim-end` copy
+    pub trimmed: Option<TrimmedRecording>,
+    /// The file failed verification and was fixed by remuxing it
+    pub repaired: bool,
+    /// Where `rec-cli mark` markers went, when there were any
+    pub chapters: Option<Chapters>,
+    /// `--follow-window` bounds timeline
+    pub follow: Option<PathBuf>,
+    /// `--normalize-audio` result
+    pub normalized: Option<Normalized>,
+    /// Older recordings `--max-recordings` deleted
+    pub pruned: Vec<PathBuf>,
+    /// `--split-audio-track` WAV
+    pub audio_track: Option<PathBuf>,
+    /// `--track-clipboard` timeline
+    pub clipboard: Option<PathBuf>,
+    /// `--also-output` files that were written
+    pub also_outputs: Vec<PathBuf>,
+    /// ffmpeg's closing stats line, when it got to print one
+    pub summary: Option<FfmpegSummary>,
+    /// `--resume` result
+    pub resumed: Option<Resumed>,
+    /// `--poster` result
+    pub poster: Option<Poster>,
+    /// `--trim-silence` result
+    pub silence_trimmed: Option<SilenceTrimmed>,
+}
+
+impl StoppedRecording {
+    /// Whether the recording went to stdout (`--stdout`) rather than a file.
+    pub fn streamed(&self) -> bool {
+        self.output == Path::new("pipe:1")
+    }
+}
+
+/// A `--estimate-size` prediction. Screen content compresses very unevenly
+/// (a still editor next to scrolling video), so only the order of magnitude
+/// is worth trusting.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SizeEstimate {
+    /// Recording length predicted for
+    pub seconds: f64,
+    /// Frame size and rate written; `None` for audio only
+    pub resolution: Option<Size>,
+    pub framerate: Option<u32>,
+    pub crf: Option<u32>,
+    /// Guessed average bitrates, in kbit/s
+    pub video_kbps: f64,
+    pub audio_kbps: f64,
+    /// Expected size, and the range it will likely land in, in bytes
+    pub bytes: u64,
+    pub low: u64,
+    pub high: u64,
+}
+
+impl SizeEstimate {
+    fn new(settings: &Settings, options: &StartOptions, seconds: f64) -> Self {
+        let format = options.format();
+        let defaults = options.ffmpeg_defaults();
+        // Bits per pixel of a libx264 screen capture at CRF 23 (ultrafast);
+        // each 6 CRF halves or doubles it
+        let bpp = match settings.crf {
+            Some(crf) => 0.05 * 2f64.powf((CRF as f64 - crf as f64) / 6.0),
+            None => 0.2,
+        };
+        let config = RecordingConfig::new(options);
+        let video_kbps = match (settings.resolution, settings.framerate) {
+            // The hardware encoder holds its bitrate whatever the content
+            (Some(_), Some(_)) if config.hardware() => config.bitrate_kbps as f64,
+            (Some(size), Some(fps)) => {
+                (size.width as f64 * size.height as f64) * fps as f64 * bpp / 1000.0
+            }
+            _ => 0.0,
+        };
+        // Silence from anullsrc costs next to nothing
+        let audio_kbps = match (format, defaults.audio, &settings.audio) {
+            (_, false, _) => 0.0,
+            (OutputFormat::Wav, true, _) => 1536.0,
+            (_, true, Some(_)) => 128.0,
+            (_, true, None) => 2.0,
+        };
+        // A time-lapse plays back far shorter than it records
+        let played = match options.timelapse {
+            Some(interval) => seconds / interval / options.timelapse_fps as f64,
+            None => seconds,
+        };
+        let bytes = ((video_kbps * played + audio_kbps * seconds) * 1000.0 / 8.0) as u64;
+        SizeEstimate {
+            seconds,
+            resolution: settings.resolution,
+            framerate: settings.framerate,
+            crf: settings.crf,
+            video_kbps,
+            audio_kbps,
+            bytes,
+            low: bytes / 2,
+            high: bytes * 2,
+        }
+    }
+}
+
+/// One line of the `--dry-probe` report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PreflightCheck {
+    pub name: &'static str,
+    /// `None` when an earlier failure left nothing to check
+    pub passed: Option<bool>,
+    pub detail: String,
+}
+
+impl PreflightCheck {
+    fn new(name: &'static str, result: Result<String, String>) -> Self {
+        let (passed, detail) = match result {
+            Ok(detail) => (Some(true), detail),
+            Err(detail) => (Some(false), detail),
+        };
+        PreflightCheck {
+            name,
+            passed,
+            detail,
+        }
+    }
+
+    fn skipped(name: &'static str, why: &str) -> Self {
+        PreflightCheck {
+            name,
+            passed: None,
+            detail: why.to_string(),
+        }
+    }
+}
+
+/// What `region_test` recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionTest {
+    /// Frame size the requested crop should produce, when one was given
+    pub expected: Option<(i32, i32)>,
+    /// Frame size ffprobe found in the probe clip
+    pub actual: Option<(i32, i32)>,
+}
+
+impl RegionTest {
+    pub fn matches(&self) -> bool {
+        self.expected.is_none() || self.expected == self.actual
+    }
+}
+
+/// How a `--benchmark` clip encoded.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Benchmark {
+    /// Capture frame rate the encode has to keep up with
+    pub target_fps: u32,
+    pub frames: Option<u64>,
+    /// Average encoding rate
+    pub fps: Option<f64>,
+    /// Encoding speed relative to real time (1.0 keeps up)
+    pub speed: Option<f64>,
+    /// User + system CPU time over wall time; 100 is one full core
+    pub cpu_percent: Option<f64>,
+    pub max_rss_kb: Option<u64>,
+}
+
+impl Benchmark {
+    /// Read ffmpeg's `-progress` key=value blocks and `-benchmark` summary
+    /// (`bench: utime=1.2s stime=0.1s rtime=5.0s`, `bench: maxrss=123kB`).
+    fn parse(progress: &str, stderr: &str, target_fps: u32) -> Self {
+        let last = |key: &str| {
+            progress
+                .lines()
+                .rev()
+                .find_map(|line| line.trim().strip_prefix(key)?.strip_prefix('='))
+                .map(|v| v.trim().trim_end_matches('x').to_string())
+        };
+        let bench = |key: &str, unit: &str| {
+            stderr
+                .lines()
+                .filter(|l| l.starts_with("bench:"))
+                .find_map(|line| {
+                    line.split_whitespace()
+                        .find_map(|field| field.strip_prefix(key)?.strip_prefix('='))
+                        .and_then(|v| v.strip_suffix(unit))
+                        .map(str::to_string)
+                })
+        };
+        let secs = |key: &str| bench(key, "s").and_then(|v| v.parse::<f64>().ok());
+        let cpu_percent = match (secs("utime"), secs("stime"), secs("rtime")) {
+            (Some(user), Some(system), Some(real)) if real > 0.0 => {
+                Some((user + system) / real * 100.0)
+            }
+            _ => None,
+        };
+        Benchmark {
+            target_fps,
+            frames: last("frame").and_then(|v| v.parse().ok()),
+            fps: last("fps").and_then(|v| v.parse().ok()),
+            speed: last("speed").and_then(|v| v.parse().ok()),
+            cpu_percent,
+            max_rss_kb: bench("maxrss", "kB").and_then(|v| v.parse().ok()),
+        }
+    }
+
+    /// Whether the encode ran at least as fast as it was captured.
+    pub fn keeps_up(&self) -> Option<bool> {
+        match (self.speed, self.fps) {
+            (Some(speed), _) => Some(speed >= 0.98),
+            (None, Some(fps)) => Some(fps >= self.target_fps as f64 * 0.98),
+            _ => None,
+        }
+    }
+}
+
+/// A throwaway clip from `Recorder::record_clip`.
+struct ProbeClip {
+    options: StartOptions,
+    prepared: PreparedRecording,
+    path: PathBuf,
+    output: std::process::Output,
+}
+
+impl Drop for ProbeClip {
+    fn drop(&mut self) {
+        remove_temp(&self.path);
+    }
+}
+
+/// The trimmed copy `stop` made of a recording.
+#[derive(Debug, Clone)]
+pub struct TrimmedRecording {
+    pub output: PathBuf,
+    /// Durations in seconds, before and after trimming
+    pub before: f64,
+    pub after: f64,
+}
+
+/// Controls the single rec.nvim recording tracked in the runtime files.
+#[derive(Debug, Default, Clone)]
+pub struct Recorder;
+
+impl Recorder {
+    pub fn new() -> Self {
+        Recorder
+    }
+
+    /// Parsed avfoundation devices, freshly listed (this refreshes the cache).
+    /// The devices ffmpeg lists, capture screens with their geometry.
+    pub fn devices(&self) -> Vec<Device> {
+        let mut devices = devices::list_devices_fresh();
+        devices::describe_screens(&mut devices);
+        devices
+    }
+
+    pub fn status(&self) -> RecordingState {
+        match read_pid().filter(|pid| pid_alive(*pid)) {
+            Some(pid) => {
+                let session = Session::current();
+                let output = session
+                    .as_ref()
+                    .and_then(|s| s.output.clone())
+                    .unwrap_or_default();
+                // The session starts with ffmpeg's first frame; without one,
+                // the output's creation or else ffmpeg's own start is the
+                // closest there is, and 0 before either can tell
+                let elapsed = session
+                    .as_ref()
+                    .and_then(|s| (Local::now() - s.started_at).to_std().ok())
+                    .or_else(|| file_age(&output))
+                    .or_else(|| pid_elapsed(pid))
+                    .unwrap_or_default();
+                // Until `stop`, a lossless capture is what grows
+                let written = session.as_ref().and_then(|s| s.capture.clone());
+                RecordingState::Recording {
+                    pid,
+                    paused: pid_paused(pid),
+                    elapsed,
+                    size: file_size(written.as_ref().unwrap_or(&output)),
+                    output_dir_gone: session.as_ref().is_some_and(Session::output_dir_gone),
+                    hud_pid: hud::pid(),
+                    settings: session.and_then(|s| s.settings),
+                    output,
+                }
+            }
+            None => match Schedule::current() {
+                Some(s) => RecordingState::Scheduled {
+                    pid: s.pid,
+                    at: s.at,
+                },
+                None => RecordingState::Idle,
+            },
+        }
+    }
+
+    /// Every recording by session name, the default one first: this
+    /// process's own (see [`crate::set_session`]) as [`Recorder::status`]
+    /// sees it, then any other with a live `rec.nvim.<session>.pid`. Another
+    /// session is only known by its PID file and the output path next to it
+    /// (`rec.nvim.<session>.outpath`), and has been running since the PID
+    /// file was written.
+    pub fn sessions(&self) -> Vec<(String, RecordingState)> {
+        let own = current_session();
+        let mut sessions = match self.status() {
+            RecordingState::Idle => Vec::new(),
+            state => vec![(own.to_string(), state)],
+        };
+        for live in live_sessions().into_iter().filter(|s| s.session != own) {
+            let output = read_path_file(live.pid_file.with_extension("outpath"));
+            let output = output.unwrap_or_default();
+            let elapsed = file_age(&live.pid_file).or_else(|| pid_elapsed(live.pid));
+            let state = RecordingState::Recording {
+                pid: live.pid,
+                paused: pid_paused(live.pid),
+                elapsed: elapsed.unwrap_or_default(),
+                size: file_size(&output),
+                output_dir_gone: false,
+                hud_pid: None,
+                settings: None,
+                output,
+            };
+            sessions.push((live.session, state));
+        }
+        sessions.sort_by_key(|(session, _)| (session != DEFAULT_SESSION, session.clone()));
+        sessions
+    }
+
+    /// Claim the schedule for a recording this process starts at `at`, once
+    /// it has waited via [`Recorder::wait_scheduled`]. The options are
+    /// checked now rather than when the wait is over.
+    pub fn schedule(
+        &self,
+        options: &StartOptions,
+        at: DateTime<Local>,
+    ) -> Result<Schedule, Rejection> {
+        validate_options(options)?;
+        match self.status() {
+            RecordingState::Recording { pid, output, .. } => {
+                return Err(Rejection::new(ErrorCode::AlreadyRunning)
+                    .detail(format!("Output: {}", output.display()))
+                    .detail(format!("PID: {}", pid))
+                    .exit_code(1))
+            }
+            RecordingState::Scheduled { pid, at } => {
+                return Err(Rejection::new(ErrorCode::AlreadyRunning)
+                    .detail(format!(
+                        "A recording is scheduled for {}",
+                        at.format("%H:%M:%S")
+                    ))
+                    .detail(format!("PID: {}", pid))
+                    .exit_code(1))
+            }
+            RecordingState::Idle => {}
+        }
+        let schedule = Schedule {
+            pid: std::process::id() as i32,
+            at,
+        };
+        schedule.save()?;
+        Ok(schedule)
+    }
+
+    /// Sleep until a [`Recorder::schedule`]d start is due, then release it.
+    pub fn wait_scheduled(&self, schedule: &Schedule) {
+        schedule::wait_until(schedule.at);
+        schedule::clear();
+    }
+
+    /// Cancel a scheduled recording that has not started yet. Returns when
+    /// it was due, `None` when nothing was scheduled.
+    pub fn cancel_scheduled(&self) -> Option<DateTime<Local>> {
+        schedule::cancel().map(|s| s.at)
+    }
+
+    /// The output of a recording whose ffmpeg exited by itself (--duration,
+    /// --frames) and that `stop` has yet to finalize.
+    pub fn unfinalized(&self) -> Option<PathBuf> {
+        let pid = read_pid().filter(|pid| !pid_alive(*pid))?;
+        let session = Session::load().filter(|s| s.pid == pid)?;
+        session.output
+    }
+
+    /// Remove a PID file whose ffmpeg is gone and left nothing for `stop` to
+    /// finalize, as `stop` would. Returns the pid it named.
+    pub fn clear_stale(&self) -> Option<i32> {
+        let pid = read_pid().filter(|pid| !pid_alive(*pid))?;
+        if self.unfinalized().is_some() {
+            return None;
+        }
+        write_log(&format!(
+            "PID file names pid {}, which is gone; removing it",
+            pid
+        ));
+        let _ = fs::remove_file(&*PID_FILE);
+        let _ = fs::remove_file(&*OUT_FILE);
+        session::clear();
+        Some(pid)
+    }
+
+    /// Whether ffmpeg died with no `stop` under way and without the closing
+    /// stats of a clean exit: killed from outside (the OOM killer, `kill
+    /// -9`, a crash) rather than finished by --duration or stopped by us.
+    pub fn killed_externally(&self) -> bool {
+        let Some(pid) = read_pid().filter(|pid| !pid_alive(*pid)) else {
+            return false;
+        };
+        let session = Session::load().filter(|s| s.pid == pid && s.output.is_some());
+        session.is_some_and(|s| s.status == SessionStatus::Recording) && summary::read().is_none()
+    }
+
+    /// Mark a chapter boundary at the current point of the recording.
+    /// Returns the time marked, pauses included.
+    pub fn mark(&self, label: &str) -> Result<f64, Rejection> {
+        let RecordingState::Recording { elapsed, .. } = self.status() else {
+            return Err(Rejection::new(ErrorCode::NotRunning));
+        };
+        let at = elapsed.as_secs_f64();
+        chapters::append(at, label)?;
+        Ok(at)
+    }
+
+    pub fn start(&self, options: &StartOptions) -> Result<RecordingHandle, Rejection> {
+        let prepared = self.prepare(options)?;
+        self.launch(prepared)
+    }
+
+    /// Record a short probe clip with `options` and measure its frame size.
+    /// Nothing is left behind.
+    pub fn region_test(&self, options: &StartOptions) -> Result<RegionTest, Rejection> {
+        let clip = self.record_clip(options, REGION_TEST_SECS, "region_test", &[])?;
+        let actual = probe_output_size(&clip.path);
+        let (options, prepared) = (&clip.options, &clip.prepared);
+
+        let expected = frame_size(options, None, prepared.requested_crop).map(|(w, h)| {
+            let (w, h) = match options.rotate {
+                Some(r) if r.is_quarter_turn() => (h, w),
+                _ => (w, h),
+            };
+            match options.aspect {
+                Some(aspect) => padded_size(w, h, aspect),
+                None => (w, h),
+            }
+        });
+        let result = RegionTest { expected, actual };
+        write_log(&format!("Region test: {:?}", result));
+        Ok(result)
+    }
+
+    /// Record a `BENCHMARK_SECS` clip with `options` and report how the
+    /// encode kept up. Nothing is left behind.
+    pub fn benchmark(&self, options: &StartOptions) -> Result<Benchmark, Rejection> {
+        let clip = self.record_clip(
+            options,
+            BENCHMARK_SECS,
+            "benchmark",
+            &["-benchmark", "-nostats", "-progress", "pipe:1"],
+        )?;
+        let progress = String::from_utf8_lossy(&clip.output.stdout);
+        let stderr = String::from_utf8_lossy(&clip.output.stderr);
+        let target = options.output_fps.unwrap_or(options.framerate);
+        let result = Benchmark::parse(&progress, &stderr, target);
+        write_log(&format!("Benchmark: {:?}", result));
+        Ok(result)
+    }
+
+    /// Run each check a recording with `options` has to pass, in order.
+    /// Nothing is left behind but the 1-second probe clip's log lines.
+    pub fn dry_probe(&self, options: &StartOptions) -> Vec<PreflightCheck> {
+        let found = |tool: &str| match which(tool) {
+            Some(path) => Ok(path.display().to_string()),
+            None => Err("not on PATH".to_string()),
+        };
+        let rejected = |r: Rejection| match r.details.is_empty() {
+            true => format!("{}: {}", r.code.token(), r.code.summary()),
+            false => format!("{}: {}", r.code.token(), r.details.join("; ")),
+        };
+        let ffmpeg = PreflightCheck::new("ffmpeg", found("ffmpeg"));
+        let has_ffmpeg = ffmpeg.passed == Some(true);
+        let mut checks = vec![ffmpeg, PreflightCheck::new("ffprobe", found("ffprobe"))];
+
+        checks.push(PreflightCheck::new(
+            "options",
+            validate_options(options)
+                .map(|()| "valid".to_string())
+                .map_err(|e| rejected(e.into())),
+        ));
+
+        let dir = options.output_root();
+        let output = match (dir_writable(&dir), free_space(&dir)) {
+            (false, _) => Err(format!("{} is not writable", dir.display())),
+            (true, free) => {
+                let free_mb = free.map(|b| b / (1024 * 1024));
+                match (free_mb, options.min_free_space) {
+                    (Some(mb), Some(min)) if mb < min => Err(format!(
+                        "{}: {} MB free, need {} MB",
+                        dir.display(),
+                        mb,
+                        min
+                    )),
+                    (Some(mb), _) => Ok(format!("{} ({} MB free)", dir.display(), mb)),
+                    (None, _) => Ok(dir.display().to_string()),
+                }
+            }
+        };
+        checks.push(PreflightCheck::new("output directory", output));
+
+        if !has_ffmpeg {
+            checks.push(PreflightCheck::skipped("screen and crop", "needs ffmpeg"));
+            checks.push(PreflightCheck::skipped("screen permission", "needs ffmpeg"));
+            return checks;
+        }
+        // The start lock is released again when `prepared` is dropped
+        let screen = self.prepare(options).map(|prepared| {
+            let (inputs, crop) = (prepared.plan.inputs.join(" + "), prepared.plan.crop);
+            match crop {
+                Some((x, y, w, h)) => format!("{}, crop {}x{} at {},{}", inputs, w, h, x, y),
+                None => format!("{}, full screen", inputs),
+            }
+        });
+        let prepared = screen.is_ok();
+        checks.push(PreflightCheck::new(
+            "screen and crop",
+            screen.map_err(rejected),
+        ));
+        if !prepared {
+            checks.push(PreflightCheck::skipped(
+                "screen permission",
+                "needs the screen",
+            ));
+            return checks;
+        }
+
+        let log_start = file_size(log_path());
+        let clip = self.record_clip(options, DRY_PROBE_SECS, "dry_probe", &[]);
+        let permission = match (screen_permission_error(log_start), clip) {
+            (Some(error), _) => Err(format!("avfoundation refused the screen ({})", error)),
+            (None, Ok(clip)) if options.audio_only || probe_output_size(&clip.path).is_some() => {
+                Ok("a probe clip recorded".to_string())
+            }
+            (None, Ok(_)) => Err("the probe clip has no frames".to_string()),
+            (None, Err(err)) => Err(rejected(err.into())),
+        };
+        checks.push(PreflightCheck::new("screen permission", permission));
+        write_log(&format!("Dry probe: {:?}", checks));
+        checks
+    }
+
+    /// Predict the size of a recording with `options`, `seconds` long,
+    /// from its frame size, rate and CRF. Nothing is recorded.
+    pub fn estimate_size(
+        &self,
+        options: &StartOptions,
+        seconds: f64,
+    ) -> Result<SizeEstimate, Rejection> {
+        let prepared = self.prepare(options)?;
+        let mut settings = settings(&prepared);
+        // Without a crop the screen was never probed
+        let input = prepared
+            .plan
+            .inputs
+            .first()
+            .filter(|_| options.format().has_video());
+        if let (None, Some(input)) = (settings.resolution, input) {
+            let screen = get_screen_size(prepared.plan.backend, input);
+            settings.resolution = frame_size(options, screen, prepared.plan.crop)
+                .map(|(width, height)| Size { width, height });
+        }
+        let estimate = SizeEstimate::new(&settings, options, seconds);
+        write_log(&format!("Size estimate: {:?}", estimate));
+        Ok(estimate)
+    }
+
+    /// Record a throwaway clip (`--loop-region-test`, `--benchmark`) to a
+    /// temp file, deleted when the returned clip is dropped.
+    fn record_clip(
+        &self,
+        options: &StartOptions,
+        secs: f64,
+        label: &str,
+        global_args: &[&str],
+    ) -> anyhow::Result<ProbeClip> {
+        let mut options = options.clone();
+        options.duration = Some(secs);
+        options.stats = false;
+        options.capture_keys = false;
+        let prepared = self.prepare(&options)?;
+
+        let mut plan = prepared.plan.clone();
+        let ext = options.format().extension();
+        plan.output = env::temp_dir().join(format!("rec.nvim.{}.{}", label, ext));
+        // A clip left by an interrupted probe is ours to record over
+        options.on_collision = OnCollision::Overwrite;
+        write_log(&format!(
+            "{}: recording {}s to {}",
+            label,
+            secs,
+            plan.output.display()
+        ));
+        let output = Command::new("ffmpeg")
+            .args(global_args)
+            .args(build_ffmpeg_args(&options, &plan))
+            .stdin(Stdio::null())
+            .output()
+            .map_err(spawn_error)?;
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_path())?;
+        let _ = log.write_all(&output.stderr);
+
+        let clip = ProbeClip {
+            options,
+            prepared,
+            path: plan.output,
+            output,
+        };
+        if !clip.output.status.success() {
+            return Err(Rejection::new(ErrorCode::StartFailed)
+                .detail(format!(
+                    "{} recording failed ({})",
+                    label, clip.output.status
+                ))
+                .with_log()
+                .into());
+        }
+        Ok(clip)
+    }
+
+    /// Resolve the output file, probe the screen and build the ffmpeg command
+    /// without starting anything.
+    pub fn prepare(&self, options: &StartOptions) -> Result<PreparedRecording, Rejection> {
+        let args = options;
+        validate_options(args)?;
+        let mut warnings = Vec::new();
+        let (lock, recovered) = claim_start(args, &mut warnings)?;
+        let mut requested_crop = requested_crop(args, &mut warnings)?;
+
+        check_battery(args.refuse_if_battery_below)?;
+
+        let (output, replaces) = match args.stdout {
+            true => (PathBuf::from("pipe:1"), None),
+            false => plan_output(args)?,
+        };
+
+        // Only avfoundation lists (and numbers) its devices
+        let backend = args.backend();
+        let listed = backend == Backend::AVFoundation;
+        let mut lookup = match listed {
+            true => DeviceLookup::new(args.refresh_devices),
+            false => DeviceLookup::unlisted(),
+        };
+        let screen = resolve_screen(args, &mut lookup, &mut warnings)?;
+        let audio = resolve_audio(args, &mut lookup, &mut warnings)?;
+        let capture_fps = match args.all_screens || args.audio_only || !listed {
+            true => args.capture_framerate(),
+            false => {
+                lookup.ensure_listed(DeviceKind::Video, screen);
+                check_video_device(lookup.devices(), screen)?;
+                check_camera_mode(lookup.devices(), screen, args)?
+            }
+        };
+        let inputs = capture_inputs(args, &lookup, screen)?;
+        let input = inputs.first().map_or("", String::as_str);
+        log_start(args, &inputs, audio, &output);
+
+        let crop = resolve_crop(args, &mut requested_crop, input, &mut warnings)?;
+        let stack_height = stack_height(args, &inputs)?;
+        let frame = encoded_frame(args, &inputs, crop.rect);
+
+        let mut options = options.clone();
+        if let (true, Some(frame)) = (args.ffmpeg_defaults().h264, frame) {
+            fit_h264_level(&mut options, frame, &mut warnings)?;
+        }
+        anchor_also_output(&mut options);
+        let args = &options;
+
+        let preview = args.preview && which("ffplay").is_some();
+        if args.preview && !preview {
+            write_log("--preview: ffplay not found; recording without a preview.");
+            let warning = "ffplay is not installed for --preview";
+            refuse_fallback(args, ErrorCode::FallbackRefused, warning)?;
+            warnings.push("WARNING: ffplay not found; recording without --preview".into());
+        }
+
+        let plan = CapturePlan {
+            backend,
+            inputs,
+            audio,
+            preview,
+            stack_height,
+            crop: crop.rect,
+            screen: crop.frame,
+            capture_fps,
+            output,
+        };
+        if !args.ffmpeg_args.is_empty() {
+            write_log(&format!(
+                "Extra ffmpeg args: {}",
+                args.ffmpeg_args.join(" ")
+            ));
+        }
+        let ffmpeg_args = build_ffmpeg_args(args, &plan);
+        if let Some(i) = ffmpeg_args.iter().position(|a| a == "-filter_complex") {
+            write_log(&format!("Filtergraph: {}", ffmpeg_args[i + 1]));
+        }
+
+        Ok(PreparedRecording {
+            options,
+            plan,
+            ffmpeg_args,
+            requested_crop,
+            crop_screen: crop.probed,
+            recovered,
+            replaces,
+            warnings,
+            lock,
+        })
+    }
+
+    /// Spawn ffmpeg for a prepared recording.
+    pub fn launch(&self, mut prepared: PreparedRecording) -> Result<RecordingHandle, Rejection> {
+        recheck_screen(&mut prepared);
+        let args = &prepared.options;
+        let output = &prepared.plan.output;
+
+        // No file to finalize when streaming; `stop` sees OUT_FILE missing
+        if args.stdout {
+            let _ = fs::remove_file(&*OUT_FILE);
+        } else {
+            write_path_file(&OUT_FILE, output)?;
+        }
+
+        let pid = spawn_capturing(&prepared)?;
+        save_session(&prepared, pid)?;
+        // Any later `start` now sees the PID file
+        drop(prepared.lock.take());
+
+        save_pending_stats(&prepared)?;
+        begin_sidecars(args, output)?;
+        let mut warnings = prepared.warnings.clone();
+        open_helpers(args, pid, &mut warnings);
+
+        if let Some(socket) = &args.event_socket {
+            write_path_file(&EVENTS_FILE, socket)?;
+            events::publish(
+                Some(socket),
+                events::STARTED,
+                json!({ "pid": pid, "output": output }),
+            );
+        } else {
+            let _ = fs::remove_file(&*EVENTS_FILE);
+        }
+
+        Ok(RecordingHandle {
+            pid,
+            output: output.clone(),
+            warnings,
+        })
+    }
+
+    pub fn stop(&self, options: &StopOptions) -> Result<StoppedRecording, Rejection> {
+        let pid = match read_pid() {
+            Some(p) => p,
+            None => return Err(Rejection::new(ErrorCode::NotRunning)),
+        };
+
+        write_log("===== STOP =====");
+        let session = Session::current();
+        // `--stdout` recordings have no output file and nothing to finalize
+        let recorded = session.as_ref().and_then(|s| s.output.clone());
+        let streamed = recorded.is_none();
+        let out = recorded.unwrap_or_else(|| PathBuf::from("pipe:1"));
+        if !streamed && self.killed_externally() {
+            return Err(killed_rejection(pid, &out));
+        }
+        // Ask before stopping, so a bad passphrase leaves the recording running
+        let passphrase = match (options.encrypt, streamed) {
+            (true, false) => match crypt::passphrase(true) {
+                Ok(pass) => Some(pass),
+                Err(err) => return Err(Rejection::new(ErrorCode::EncryptFailed).detail(err)),
+            },
+            (true, true) => {
+                write_log("--encrypt: the recording went to stdout; nothing to encrypt");
+                None
+            }
+            _ => None,
+        };
+        // Recreate a deleted output directory before ffmpeg finalizes, so at
+        // least the +faststart rewrite has somewhere to go
+        let dir_gone = session.as_ref().is_some_and(Session::output_dir_gone);
+        if let Some(session) = &session {
+            mark_stopping(session);
+        }
+        if dir_gone {
+            write_log(&format!(
+                "Output directory is gone; recreating it for {}",
+                out.display()
+            ));
+            ensure_parent_dir(&out);
+        }
+
+        let closed = end_ffmpeg(pid, &out, streamed)?;
+        // ffmpeg's final stats are in the log now
+        sync_log();
+        let summary = summary::read();
+        let socket = release_recording();
+        let finishing = Finishing {
+            options,
+            keys: keys::finish_capture(&out),
+            // Every remux below keeps the layout the recording was muxed with
+            movflags: session
+                .as_ref()
+                .map_or(Some(FASTSTART.to_string()), |s| s.movflags.clone()),
+            session,
+            out,
+            passphrase,
+            socket,
+            summary,
+        };
+
+        if streamed {
+            return finishing.streamed(closed);
+        }
+        if segments::is_pending() {
+            if let Some((parts, manifest)) = segments::finish(&finishing.out) {
+                return finishing.segmented(parts, manifest);
+            }
+        }
+        finishing.encode_capture()?;
+        match finishing.await_finalized()? {
+            Some(repaired) => finishing.finish(repaired),
+            None => Err(finishing.unfinalized(dir_gone)),
+        }
+    }
+}
+
+/// Take the start lock and clear the way for a new recording: refuse while
+/// one is running (or scheduled), salvage what a dead ffmpeg left behind.
+/// Returns the lock, held until `launch`, and the recovered partial file.
+fn claim_start(
+    args: &StartOptions,
+    warnings: &mut Vec<String>,
+) -> anyhow::Result<(Option<fs::File>, Option<PathBuf>)> {
+    let mut recovered = None;
+    // Serialize the check below against a racing `start` (double keybind)
+    let lock = match try_lock_start() {
+        Ok(Some(lock)) => Some(lock),
+        Ok(None) => {
+            return Err(Rejection::new(ErrorCode::AlreadyRunning)
+                .detail("Another start is in progress")
+                .exit_code(1)
+                .into())
+        }
+        Err(err) => {
+            write_log(&format!(
+                "Start lock unavailable ({}); continuing unlocked",
+                err
+            ));
+            let warning = format!("the start lock is unavailable ({})", err);
+            refuse_fallback(args, ErrorCode::FallbackRefused, &warning)?;
+            None
+        }
+    };
+    if let Some(pid) = read_pid().filter(|_| args.force_start) {
+        let warning = format!(
+            "WARNING: --force-start: ignoring PID file for pid {} (alive: {}); \
+             if that is a recording, it is now untracked",
+            pid,
+            pid_alive(pid)
+        );
+        write_log(&warning);
+        warnings.push(warning);
+        let _ = fs::remove_file(&*PID_FILE);
+        let _ = fs::remove_file(&*OUT_FILE);
+        session::clear();
+    }
+    if let Some(pid) = read_pid() {
+        let before_boot = pid_file_predates_boot();
+        if before_boot {
+            write_log(&format!(
+                "PID file (pid {}) predates the last boot; it is stale",
+                pid
+            ));
+        }
+        if !before_boot && pid_alive(pid) {
+            // Tell the caller what is already recording so it can attach
+            return Err(Rejection::new(ErrorCode::AlreadyRunning)
+                .detail(format!("Output: {}", read_output_path().display()))
+                .detail(format!("PID: {}", pid))
+                .exit_code(1)
+                .into());
+        }
+        // ffmpeg died without `stop`: salvage what it left behind
+        let stale = Session::load();
+        if stale
+            .as_ref()
+            .is_some_and(|s| s.status == SessionStatus::Stopping)
+        {
+            write_log(&format!(
+                "The last stop (pid {}) did not finish; recovering",
+                pid
+            ));
+        }
+        // Never compressed; too big to keep quietly, too precious to delete
+        if let Some(capture) = stale.and_then(|s| s.capture).filter(|c| c.exists()) {
+            let warning = format!(
+                "WARNING: an interrupted recording left its lossless capture at {}",
+                capture.display()
+            );
+            write_log(&warning);
+            warnings.push(warning);
+        }
+        // Before the PID file goes: the session is only current with it
+        recovered = recover_partial(&read_output_path());
+        let _ = fs::remove_file(&*PID_FILE);
+        let _ = fs::remove_file(&*OUT_FILE);
+        session::clear();
+    }
+    // Past REC_ALREADY_RUNNING, so only other sessions are left to count
+    check_concurrency(args.concurrency_limit)?;
+    match &recovered {
+        Some(part) if args.resume => write_log(&format!(
+            "--resume: {} goes in front of this recording",
+            part.display()
+        )),
+        None if args.resume => {
+            write_log("--resume: no interrupted recording to resume; recording a new one");
+            let warning = "there is no interrupted recording to --resume";
+            refuse_fallback(args, ErrorCode::FallbackRefused, warning)?;
+            warnings.push("WARNING: nothing to --resume; this is a new recording".into());
+        }
+        _ => {}
+    }
+    // Another `start` is waiting to begin (ours has released it by now)
+    let own = std::process::id() as i32;
+    if let Some(scheduled) = Schedule::current().filter(|s| s.pid != own) {
+        return Err(Rejection::new(ErrorCode::AlreadyRunning)
+            .detail(format!(
+                "A recording is scheduled for {}",
+                scheduled.at.format("%H:%M:%S")
+            ))
+            .detail(format!("PID: {}", scheduled.pid))
+            .exit_code(1)
+            .into());
+    }
+    Ok((lock, recovered))
+}
+
+/// The crop as asked for (x, y, w, h): from stdin, the focused Neovim
+/// window, or `--x/--y/--width/--height`.
+fn requested_crop(
+    args: &StartOptions,
+    warnings: &mut Vec<String>,
+) -> anyhow::Result<Option<(i32, i32, i32, i32)>> {
+    if args.crop_from_stdin {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        return match parse_crop_spec(&line) {
+            Ok(rect) => Ok(Some(rect)),
+            Err(err) => Err(Rejection::new(ErrorCode::BadCrop).detail(err).into()),
+        };
+    }
+    if args.crop_nvim_window {
+        let window = match nvim::socket(args.nvim_socket.as_deref()) {
+            Some(socket) => nvim::focused_window(&socket),
+            None => Err("no --nvim-socket given and $NVIM is not set".into()),
+        };
+        return match window {
+            Ok(rect) => Ok(Some(rect)),
+            Err(err) => {
+                write_log(&format!("--crop-nvim-window: {}", err));
+                let warning = format!("cannot get the Neovim window ({})", err);
+                refuse_fallback(args, ErrorCode::BadCrop, &warning)?;
+                warnings.push(format!(
+                    "WARNING: cannot get the Neovim window ({}); recording full screen",
+                    err
+                ));
+                Ok(None)
+            }
+        };
+    }
+    Ok(match (args.x, args.y, args.width, args.height) {
+        (Some(x), Some(y), Some(w), Some(h)) => Some((x, y, w, h)),
+        _ => None,
+    })
+}
+
+/// The file to record into, checked for room and length and with its
+/// folder created, and with `--replace-last` the take it replaces.
+fn plan_output(args: &StartOptions) -> anyhow::Result<(PathBuf, Option<PathBuf>)> {
+    if args.output_dir.is_none() && dirs::home_dir().is_none() {
+        write_log("No home directory; the default output directory is under '.'");
+        let warning = "there is no home directory for the default output directory";
+        refuse_fallback(args, ErrorCode::FallbackRefused, warning)?;
+    }
+    let dir = args.output_root();
+    if let Some(project) = args.project_folder() {
+        write_log(&format!("Project folder: {}", project));
+    } else if args.output_dir_per_project {
+        write_log("--output-dir-per-project: no project found; using the output directory");
+    }
+    check_path_length(&dir)?;
+    fs::create_dir_all(&dir)?;
+    check_free_space(&dir, args.min_free_space)?;
+    // ensure_parent_dir below creates today's folder on demand
+    let new_dir = match args.date_subdirs {
+        true => dated_dir(&dir, args.utc),
+        false => dir.clone(),
+    };
+    let format = args.format();
+    let ts_format = &args.filename_format;
+    let next = || -> anyhow::Result<PathBuf> {
+        let out = match &args.output_file {
+            Some(file) => file.clone(),
+            None => next_output_file(&new_dir, format.extension(), ts_format, args.utc),
+        };
+        free_output_name(args, out)
+    };
+    let mut replaces = None;
+    let output = match args.replace_last.then(|| last_output_file(&dir, ts_format)) {
+        Some(Some(last)) => {
+            write_log(&format!("--replace-last: overwriting {}", last.display()));
+            // The reused name takes --format's extension, never the
+            // reverse, so it cannot contradict the muxer
+            let output = last.with_extension(format.extension());
+            if output != last {
+                write_log(&format!(
+                    "--replace-last: {} is removed once {} is saved",
+                    last.display(),
+                    output.display()
+                ));
+                replaces = Some(last);
+            }
+            output
+        }
+        Some(None) => {
+            write_log("--replace-last: no previous recording; using a new file");
+            next()?
+        }
+        None => next()?,
+    };
+    check_path_length(&output)?;
+    ensure_parent_dir(&output);
+    Ok((output, replaces))
+}
+
+/// `out`, or the name `--on-collision` picks when it is taken.
+fn free_output_name(args: &StartOptions, out: PathBuf) -> anyhow::Result<PathBuf> {
+    // A name is taken by any file this recording would write under it
+    let taken = |p: &Path| {
+        p.exists() || compress::capture_path(p).exists() || segments::list_path(p).exists()
+    };
+    let Some(free) = resolve_collision(out.clone(), args.on_collision, taken) else {
+        anyhow::bail!("{} already exists (--on-collision fail)", out.display());
+    };
+    if free != out {
+        write_log(&format!(
+            "{} exists; --on-collision {} picked {}",
+            out.display(),
+            format!("{:?}", args.on_collision).to_lowercase(),
+            free.display()
+        ));
+    } else if taken(&out) {
+        write_log(&format!(
+            "--on-collision overwrite: replacing {}",
+            out.display()
+        ));
+    }
+    Ok(free)
+}
+
+/// The screen device to record: pinned by `--display-uuid`, the display
+/// under the cursor, `--screen-name` or `--screen-index`, else the main one.
+fn resolve_screen(
+    args: &StartOptions,
+    lookup: &mut DeviceLookup,
+    warnings: &mut Vec<String>,
+) -> anyhow::Result<u32> {
+    let listed = args.backend() == Backend::AVFoundation;
+    let pinned = match &args.display_uuid {
+        Some(uuid) => {
+            let index = lookup
+                .resolve_display(uuid)
+                .map_err(|err| Rejection::new(ErrorCode::BadDevice).detail(err))?;
+            write_log(&format!("--display-uuid {} is device {}", uuid, index));
+            Some(index)
+        }
+        None if args.capture_display_under_cursor => match lookup.display_under_cursor() {
+            Ok(index) => {
+                write_log(&format!("Display under the cursor is device {}", index));
+                Some(index)
+            }
+            Err(err) => {
+                write_log(&format!("--capture-display-under-cursor: {}", err));
+                let warning = format!("WARNING: {}; recording the main screen", err);
+                refuse_fallback(args, ErrorCode::BadDevice, &warning)?;
+                warnings.push(warning);
+                None
+            }
+        },
+        None => None,
+    };
+    // Nothing picked the screen: the main display's, as the list has it
+    let chosen = match (&args.screen_name, pinned.or(args.screen_index)) {
+        (None, None) if !listed => None,
+        (None, None) => match lookup.screen_index() {
+            Some(index) => {
+                write_log(&format!("Detected screen capture device {}", index));
+                Some(index)
+            }
+            None => {
+                write_log(&format!(
+                    "No capture screen in the device list; using device {}",
+                    SCREEN_INDEX
+                ));
+                None
+            }
+        },
+        (_, index) => index,
+    };
+    Ok(match &args.screen_name {
+        Some(name) => {
+            let index = device_by_name(lookup, DeviceKind::Video, name)?;
+            write_log(&format!("--screen-name '{}' is device {}", name, index));
+            index
+        }
+        None => chosen.unwrap_or(SCREEN_INDEX),
+    })
+}
+
+/// The `--audio-name` device, when this recording has audio at all; drops
+/// the audio-only flags there is no track for.
+fn resolve_audio(
+    args: &StartOptions,
+    lookup: &mut DeviceLookup,
+    warnings: &mut Vec<String>,
+) -> anyhow::Result<Option<u32>> {
+    let audio = match &args.audio_name {
+        Some(_) if !args.ffmpeg_defaults().audio => {
+            let what = match args.timelapse {
+                Some(_) => "--timelapse".to_string(),
+                None => format!("--format {}", args.format().extension()),
+            };
+            let warning = format!("WARNING: {} has no audio; ignoring --audio-name", what);
+            refuse_fallback(args, ErrorCode::FallbackRefused, &warning)?;
+            warnings.push(warning);
+            None
+        }
+        Some(name) => {
+            let index = device_by_name(lookup, DeviceKind::Audio, name)?;
+            write_log(&format!("--audio-name '{}' is device {}", name, index));
+            Some(index)
+        }
+        None => None,
+    };
+    if let (Some(ms), None) = (args.audio_delay, audio) {
+        let warning = format!(
+            "WARNING: no --audio-name track; ignoring --audio-delay {}",
+            ms
+        );
+        refuse_fallback(args, ErrorCode::FallbackRefused, &warning)?;
+        warnings.push(warning);
+    }
+    if let (true, None) = (args.split_audio_track, audio) {
+        let warning = "WARNING: no --audio-name track; ignoring --split-audio-track";
+        refuse_fallback(args, ErrorCode::FallbackRefused, warning)?;
+        warnings.push(warning.into());
+    }
+    Ok(audio)
+}
+
+fn device_by_name(lookup: &mut DeviceLookup, kind: DeviceKind, name: &str) -> anyhow::Result<u32> {
+    lookup
+        .resolve(kind, name)
+        .map_err(|err| Rejection::new(ErrorCode::BadDevice).detail(err).into())
+}
+
+/// The ffmpeg inputs: none for `--audio-only`, every screen for
+/// `--all-screens`, else the one `screen`.
+fn capture_inputs(
+    args: &StartOptions,
+    lookup: &DeviceLookup,
+    screen: u32,
+) -> anyhow::Result<Vec<String>> {
+    let backend = args.backend();
+    if args.audio_only {
+        return Ok(Vec::new());
+    }
+    if args.all_screens {
+        let screens: Vec<String> = lookup
+            .devices()
+            .iter()
+            .filter(|d| d.is_screen())
+            .map(|d| format!("{}:none", d.index))
+            .collect();
+        if screens.is_empty() {
+            write_log("--all-screens: no capture screens detected; using the default screen.");
+            return Ok(vec![format!("{}:none", screen)]);
+        }
+        return Ok(screens);
+    }
+    if backend == Backend::PipeWire {
+        if !has_filter(PIPEWIRE_SOURCE) {
+            return Err(Rejection::new(ErrorCode::BadDevice)
+                .detail(format!("this ffmpeg has no {} source", PIPEWIRE_SOURCE))
+                .detail("--backend pipewire needs an ffmpeg built with libpipewire")
+                .into());
+        }
+        return Ok(vec![pipewire_input(args.pipewire_node)]);
+    }
+    Ok(vec![backend.input(screen)])
+}
+
+/// The `===== START =====` block of the log: what is recorded, and how.
+fn log_start(args: &StartOptions, inputs: &[String], audio: Option<u32>, output: &Path) {
+    let format = args.format();
+    write_log("===== START =====");
+    for input in inputs {
+        write_log(&format!("Input: {}", input));
+    }
+    if let (true, Some(device)) = (args.audio_only, audio) {
+        write_log(&format!(
+            "Audio only: device {} ({}), no screen, into .{}",
+            device,
+            args.audio_name.as_deref().unwrap_or_default(),
+            format.extension()
+        ));
+    }
+    write_log(&format!("Output: {}", output.display()));
+    if matches!(format, OutputFormat::Mp4 | OutputFormat::M4a) {
+        write_log(match args.movflags() {
+            Some("+faststart") => "Muxing: +faststart (index moved to the front at stop)",
+            Some(_) => "Muxing: fragmented (playable even if interrupted)",
+            None => "Muxing: no faststart (index left at the end)",
+        });
+    }
+    if format.has_video() {
+        write_log(&format!(
+            "H.264 profile/level: {}/{}",
+            args.h264_profile, args.h264_level
+        ));
+    }
+    if let Some((w, h)) = args.video_size {
+        write_log(&format!("Requested capture size: {}x{}", w, h));
+    }
+}
+
+/// Where the crop landed against the screen.
+#[derive(Default)]
+struct ResolvedCrop {
+    /// The clamped crop, if any survived
+    rect: Option<(i32, i32, i32, i32)>,
+    /// The probed screen size, for `recheck_screen`
+    probed: Option<(i32, i32)>,
+    /// The frame `rect` is relative to
+    frame: Option<(i32, i32)>,
+}
+
+/// Clamp the requested crop (or `--region-grid` cell, written back into
+/// `requested`) to the screen, or drop it when the screen cannot be sized.
+fn resolve_crop(
+    args: &StartOptions,
+    requested: &mut Option<(i32, i32, i32, i32)>,
+    input: &str,
+    warnings: &mut Vec<String>,
+) -> anyhow::Result<ResolvedCrop> {
+    let mut crop = ResolvedCrop::default();
+    if requested.is_none() && args.region_grid.is_none() {
+        return Ok(crop);
+    }
+    if let Some((x, y, w, h)) = requested.filter(|_| args.crop_from_stdin) {
+        write_log(&format!("Crop from stdin: {}x{} at {},{}", w, h, x, y));
+    }
+    // With --video-size the captured frame is exactly that size
+    let screen = match args.video_size {
+        Some(size) => Some(size),
+        None => {
+            crop.probed = get_screen_size(args.backend(), input);
+            crop.probed
+        }
+    };
+    match screen.map(|s| crop_frame(args, s)) {
+        Some((screen_w, screen_h)) => {
+            if let Some(grid) = args.region_grid {
+                *requested = Some(grid.rect(screen_w, screen_h));
+            }
+            let (x, y, w, h) = requested.unwrap_or_default();
+            write_log(&format!(
+                "Screen size: {}x{} (requested crop: {}x{} at {},{})",
+                screen_w, screen_h, w, h, x, y
+            ));
+            let min = args.min_crop_size;
+            if let Some(rect) = clamp_crop(x, y, w, h, screen_w, screen_h, min) {
+                crop.rect = Some(rect);
+                crop.frame = Some((screen_w, screen_h));
+            } else if clamp_crop(x, y, w, h, screen_w, screen_h, 1).is_some() {
+                let detail = format!("Crop must be at least {}x{} pixels", min, min);
+                return Err(Rejection::new(ErrorCode::CropTooSmall)
+                    .detail(format!("{} on screen", detail))
+                    .into());
+            } else {
+                write_log("Crop invalid after clamping; skipping crop.");
+                let warning = "the crop lies outside the screen";
+                refuse_fallback(args, ErrorCode::BadCrop, warning)?;
+            }
+        }
+        None if args.crop_strict || args.fail_fast => {
+            write_log("Failed to get screen size; --crop-strict refuses to go uncropped.");
+            return Err(Rejection::new(ErrorCode::CropProbeFailed)
+                .detail(format!("ffprobe could not open {} to size the crop", input))
+                .with_log()
+                .into());
+        }
+        None => {
+            write_log("Failed to get screen size; skipping crop.");
+            warnings.push(format!(
+                "WARNING: could not probe {} for the crop; recording the full screen \
+                 (use --crop-strict to fail instead)",
+                input
+            ));
+        }
+    }
+    Ok(crop)
+}
+
+/// Stacked screens are scaled to the smallest height so hstack accepts them.
+fn stack_height(args: &StartOptions, inputs: &[String]) -> anyhow::Result<Option<i32>> {
+    if inputs.len() < 2 {
+        return Ok(None);
+    }
+    let heights: Vec<i32> = inputs
+        .iter()
+        .filter_map(|input| {
+            args.video_size
+                .or_else(|| get_screen_size(args.backend(), input))
+        })
+        .map(|(_, h)| h)
+        .collect();
+    if heights.len() < inputs.len() {
+        write_log("Failed to get every screen size; stacking without scaling.");
+        let warning = "not every screen could be sized for stacking";
+        refuse_fallback(args, ErrorCode::FallbackRefused, warning)?;
+    }
+    Ok(heights.into_iter().min())
+}
+
+/// The size of the encoded frame, when it can be known up front: one
+/// screen, cropped, scaled, turned and padded as the filters will.
+fn encoded_frame(
+    args: &StartOptions,
+    inputs: &[String],
+    crop: Option<(i32, i32, i32, i32)>,
+) -> Option<(i32, i32)> {
+    let h264 = args.ffmpeg_defaults().h264;
+    let screen = match (crop, inputs) {
+        (None, [input]) if h264 || args.aspect.is_some() => args
+            .video_size
+            .or_else(|| get_screen_size(args.backend(), input)),
+        _ => None,
+    };
+    let frame = match inputs.len() {
+        1 => frame_size(args, screen, crop),
+        _ => None,
+    };
+    let frame = frame.map(|(w, h)| match args.rotate {
+        Some(r) if r.is_quarter_turn() => (h, w),
+        _ => (w, h),
+    });
+    let Some(aspect) = args.aspect else {
+        return frame;
+    };
+    match frame {
+        Some((w, h)) => {
+            let (pw, ph) = padded_size(w, h, aspect);
+            write_log(&format!(
+                "Aspect {}:{}: padding {}x{} to {}x{}",
+                aspect.0, aspect.1, w, h, pw, ph
+            ));
+            Some((pw, ph))
+        }
+        None => {
+            write_log(&format!(
+                "Aspect {}:{}: frame size unknown; ffmpeg will size the padding",
+                aspect.0, aspect.1
+            ));
+            None
+        }
+    }
+}
+
+/// Raise `--h264-level` to one that fits `frame`. A frame beyond the
+/// declared level plays badly, if at all, on hardware decoders that trust
+/// the level.
+fn fit_h264_level(
+    options: &mut StartOptions,
+    frame: (i32, i32),
+    warnings: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    let fps = options.output_framerate();
+    match h264_level_for(&options.h264_level, frame, fps) {
+        Some(level) if level == options.h264_level => write_log(&format!(
+            "H.264 level {} fits {}x{} at {} fps",
+            level, frame.0, frame.1, fps
+        )),
+        Some(level) => {
+            let warning = format!(
+                "WARNING: {}x{} at {} fps exceeds H.264 level {}; using level {}",
+                frame.0, frame.1, fps, options.h264_level, level
+            );
+            write_log(&warning);
+            refuse_fallback(options, ErrorCode::FallbackRefused, &warning)?;
+            warnings.push(warning);
+            options.h264_level = level.to_string();
+        }
+        None => {
+            let warning = format!(
+                "WARNING: {}x{} at {} fps exceeds every H.264 level; the file may not \
+                 play on hardware decoders (try --scale)",
+                frame.0, frame.1, fps
+            );
+            write_log(&warning);
+            warnings.push(warning);
+        }
+    }
+    Ok(())
+}
+
+/// Relative `--also-output` files are taken from where `start` ran; their
+/// folders are created here.
+fn anchor_also_output(options: &mut StartOptions) {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    for dest in &mut options.also_output {
+        if !dest.starts_with('[') && !dest.contains("://") && Path::new(dest).is_relative() {
+            *dest = cwd.join(&dest).to_string_lossy().into_owned();
+        }
+    }
+    for file in also_output_files(options) {
+        ensure_parent_dir(&file);
+    }
+}
+
+/// Fail with `REC_BAD_DEVICE` unless `index` is a listed video device. An
+/// empty listing means ffmpeg could not enumerate at all, so let it try.
+fn check_video_device(devices: &[Device], index: u32) -> anyhow::Result<()> {
+    if devices.is_empty() {
+        write_log("No devices listed; not validating the screen index.");
+        return Ok(());
+    }
+    let video: Vec<&Device> = devices
+        .iter()
+        .filter(|d| d.kind == DeviceKind::Video)
+        .collect();
+    if video.iter().any(|d| d.index == index) {
+        return Ok(());
+    }
+
+    let valid: Vec<String> = video
+        .iter()
+        .map(|d| format!("{} ({})", d.index, d.name))
+        .collect();
+    Err(Rejection::new(ErrorCode::BadDevice)
+        .detail(format!("No avfoundation video device with index {}", index))
+        .detail(format!("Valid video devices: {}", valid.join(", ")))
+        .into())
+}
+
+/// The frame a crop is relative to: the screen, or with --filter-order
+/// scale-first the scaled screen.
+fn crop_frame(args: &StartOptions, screen: (i32, i32)) -> (i32, i32) {
+    match (args.filter_order, args.scale) {
+        (FilterOrder::ScaleFirst, Some(scale)) => {
+            let scaled = scaled_size(screen, scale);
+            write_log(&format!(
+                "--filter-order scale-first: crop is relative to the scaled {}x{} frame",
+                scaled.0, scaled.1
+            ));
+            scaled
+        }
+        _ => screen,
+    }
+}
+
+/// Probe the screen again right before ffmpeg starts: a resolution change
+/// or an unplugged display since `prepare` would leave the crop out of
+/// bounds, which ffmpeg does not survive. Re-clamps, or drops the crop.
+fn recheck_screen(prepared: &mut PreparedRecording) {
+    let (Some(probed), Some(_), Some((x, y, w, h))) = (
+        prepared.crop_screen,
+        prepared.plan.crop,
+        prepared.requested_crop,
+    ) else {
+        return;
+    };
+    let args = &prepared.options;
+    let Some(now) = get_screen_size(prepared.plan.backend, &prepared.plan.inputs[0]) else {
+        return;
+    };
+    if now == probed {
+        return;
+    }
+    write_log(&format!(
+        "Screen changed from {}x{} to {}x{} since the crop was clamped",
+        probed.0, probed.1, now.0, now.1
+    ));
+    let (screen_w, screen_h) = crop_frame(args, now);
+    // A grid cell is a share of the screen, so it moves with it
+    let (x, y, w, h) = match args.region_grid {
+        Some(grid) => grid.rect(screen_w, screen_h),
+        None => (x, y, w, h),
+    };
+    let crop = clamp_crop(x, y, w, h, screen_w, screen_h, args.min_crop_size);
+    let warning = match crop {
+        Some((cx, cy, cw, ch)) => {
+            write_log(&format!(
+                "Crop re-clamped to {}x{} at {},{}",
+                cw, ch, cx, cy
+            ));
+            format!(
+                "WARNING: the screen is now {}x{}; crop adjusted to {}x{} at {},{}",
+                now.0, now.1, cw, ch, cx, cy
+            )
+        }
+        None => {
+            write_log("Crop no longer fits the screen; recording the full screen.");
+            format!(
+                "WARNING: the screen is now {}x{} and the crop no longer fits; \
+                 recording the full screen",
+                now.0, now.1
+            )
+        }
+    };
+    prepared.plan.crop = crop;
+    prepared.plan.screen = crop.map(|_| (screen_w, screen_h));
+    prepared.crop_screen = Some(now);
+    prepared.ffmpeg_args = build_ffmpeg_args(&prepared.options, &prepared.plan);
+    prepared.warnings.push(warning);
+}
+
+/// Under `--fail-fast`, refuse with `code` the fallback `warning` describes
+/// rather than take it.
+fn refuse_fallback(args: &StartOptions, code: ErrorCode, warning: &str) -> anyhow::Result<()> {
+    if !args.fail_fast {
+        return Ok(());
+    }
+    let problem = warning.trim_start_matches("WARNING: ");
+    write_log(&format!("--fail-fast: refusing to start: {}", problem));
+    Err(Rejection::new(code)
+        .detail(problem)
+        .detail("--fail-fast refuses to record with a fallback")
+        .exit_code(1)
+        .into())
+}
+
+/// Refuse another recording once `limit` sessions are live.
+fn check_concurrency(limit: u32) -> Result<(), Rejection> {
+    let live = live_sessions();
+    write_log(&format!(
+        "Sessions running: {} (--concurrency-limit {})",
+        live.len(),
+        limit
+    ));
+    if live.len() < limit as usize {
+        return Ok(());
+    }
+    let mut rejection = Rejection::new(ErrorCode::TooManySessions)
+        .detail(format!(
+            "Recordings running: {} (the limit is {})",
+            live.len(),
+            limit
+        ))
+        .exit_code(1);
+    for session in &live {
+        rejection = rejection.detail(format!("{}: PID {}", session.session, session.pid));
+    }
+    Err(rejection)
+}
+
+/// The muxer follows `--format` and players follow the extension, so an
+/// `--output-file` has to agree with `--format`, unless
+/// --infer-format-from-extension has the extension decide.
+fn check_output_extension(args: &StartOptions) -> anyhow::Result<()> {
+    let Some(file) = &args.output_file else {
+        return Ok(());
+    };
+    let names: Vec<&str> = OutputFormat::value_variants()
+        .iter()
+        .map(|f| f.extension())
+        .collect();
+    match args.extension_format() {
+        None if args.infer_format_from_extension => anyhow::bail!(
+            "--infer-format-from-extension: {} has no container extension ({})",
+            file.display(),
+            names.join(", ")
+        ),
+        Some(inferred) if args.infer_format_from_extension => {
+            if let Some(format) = args.format.filter(|f| *f != inferred) {
+                write_log(&format!(
+                    "--infer-format-from-extension: recording {} as {}, not --format {}",
+                    file.display(),
+                    inferred.extension(),
+                    format.extension()
+                ));
+            }
+        }
+        found if found != Some(args.format()) => anyhow::bail!(
+            "--output-file {} is not a .{} file, as --format asks; rename it or pass \
+             --infer-format-from-extension",
+            file.display(),
+            args.format().extension()
+        ),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Refuse flag combinations that depend on values, before anything is
+/// probed or locked. Combinations that never work are clap's
+/// `conflicts_with`/`requires`.
+fn validate_options(args: &StartOptions) -> anyhow::Result<()> {
+    let crop = [
+        ("--x", args.x.is_some()),
+        ("--y", args.y.is_some()),
+        ("--width", args.width.is_some()),
+        ("--height", args.height.is_some()),
+    ];
+    if crop.iter().any(|(_, set)| *set) && !crop.iter().all(|(_, set)| *set) {
+        let missing: Vec<&str> = crop
+            .iter()
+            .filter(|(_, set)| !set)
+            .map(|(f, _)| *f)
+            .collect();
+        anyhow::bail!(
+            "--x, --y, --width and --height go together; missing {}",
+            missing.join(", ")
+        );
+    }
+    if let (Some(x), Some(y), Some(w), Some(h)) = (args.x, args.y, args.width, args.height) {
+        check_geometry(args, (x, y, w, h))?;
+    }
+
+    if let Some(duration) = args.duration {
+        for (flag, fade) in [("--fade-in", args.fade_in), ("--fade-out", args.fade_out)] {
+            if let Some(fade) = fade.filter(|fade| *fade > duration) {
+                anyhow::bail!(
+                    "{} ({}s) is longer than --duration ({}s)",
+                    flag,
+                    fade,
+                    duration
+                );
+            }
+        }
+    }
+
+    check_output_extension(args)?;
+
+    // fps would only repeat frames to get there
+    if let Some(fps) = args.output_fps.filter(|fps| *fps > args.framerate) {
+        anyhow::bail!(
+            "--output-fps ({}) is above --framerate ({})",
+            fps,
+            args.framerate
+        );
+    }
+
+    // Each encoder has its own rate control; the other's flags do nothing
+    if RecordingConfig::new(args).hardware() {
+        if args.crf.is_some() || args.preset.is_some() {
+            anyhow::bail!(
+                "--crf and --preset are libx264's; {} takes --bitrate",
+                VIDEOTOOLBOX
+            );
+        }
+        if args.encode_in_background_after_stop {
+            anyhow::bail!(
+                "--encode-in-background-after-stop compresses with libx264, not {}",
+                VIDEOTOOLBOX
+            );
+        }
+        if !["baseline", "main", "high"].contains(&args.h264_profile.as_str()) {
+            anyhow::bail!(
+                "{} encodes --h264-profile baseline, main or high, not {}",
+                VIDEOTOOLBOX,
+                args.h264_profile
+            );
+        }
+    } else if args.bitrate.is_some() {
+        anyhow::bail!(
+            "--bitrate is for --codec {}; libx264 takes --crf",
+            VIDEOTOOLBOX
+        );
+    }
+    let h264_only = [
+        (args.codec != CODECS[0], "--codec"),
+        (args.preset.is_some(), "--preset"),
+        (args.bitrate.is_some(), "--bitrate"),
+    ];
+    match h264_only.iter().find(|(set, _)| *set) {
+        Some((_, flag)) if !args.ffmpeg_defaults().h264 => {
+            let format = args.format().extension();
+            anyhow::bail!(
+                "{} picks the H.264 encoder; --format {} has none",
+                flag,
+                format
+            );
+        }
+        _ => {}
+    }
+
+    if args.pipewire_node.is_some() && args.backend() != Backend::PipeWire {
+        anyhow::bail!("--pipewire-node is for --backend pipewire");
+    }
+    // Everything picked by name or by display comes from avfoundation's list
+    if args.backend() != Backend::AVFoundation {
+        let listed_only = [
+            (args.screen_index.is_some(), "--screen-index"),
+            (args.screen_name.is_some(), "--screen-name"),
+            (args.display_uuid.is_some(), "--display-uuid"),
+            (
+                args.capture_display_under_cursor,
+                "--capture-display-under-cursor",
+            ),
+            (args.all_screens, "--all-screens"),
+            (args.audio_name.is_some(), "--audio-name"),
+        ];
+        if let Some((_, flag)) = listed_only.iter().find(|(set, _)| *set) {
+            anyhow::bail!(
+                "{} needs avfoundation's device list; --backend {} has none",
+                flag,
+                args.backend().format()
+            );
+        }
+    }
+
+    let format = args.format();
+    if args.audio_only && format.has_video() {
+        anyhow::bail!(
+            "--audio-only records m4a or wav, not --format {}",
+            format.extension()
+        );
+    }
+    if !args.audio_only && !format.has_video() {
+        anyhow::bail!(
+            "--format {} has no video; add --audio-only",
+            format.extension()
+        );
+    }
+    if !args.ffmpeg_defaults().h264 {
+        // These all join or tee H.264 streams
+        let conflict = [
+            (args.preview, "--preview"),
+            (args.segment_time.is_some(), "--segment-time"),
+            (args.title_card.is_some(), "--title-card"),
+            (
+                args.encode_in_background_after_stop,
+                "--encode-in-background-after-stop",
+            ),
+        ];
+        if let Some((_, flag)) = conflict.iter().find(|(set, _)| *set) {
+            anyhow::bail!(
+                "{} does not work with --format {}",
+                flag,
+                format.extension()
+            );
+        }
+    }
+    // tee muxes the one encode everywhere: H.264 cannot go into a .gif
+    for file in also_output_files(args) {
+        let ext = file
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_lowercase();
+        let Some(other) = OutputFormat::from_extension(&ext) else {
+            continue;
+        };
+        if format_defaults(other).h264 != args.ffmpeg_defaults().h264 {
+            anyhow::bail!(
+                "--also-output {}: a .{} cannot hold the --format {} encode",
+                file.display(),
+                ext,
+                format.extension()
+            );
+        }
+    }
+    if args.fragmented && !matches!(format, OutputFormat::Mp4 | OutputFormat::M4a) {
+        anyhow::bail!(
+            "--fragmented is for mp4 and m4a, not --format {}",
+            format.extension()
+        );
+    }
+    if args.stdout && !format.is_streamable() {
+        anyhow::bail!(
+            "--format {} cannot be written to stdout; use mkv or mpegts",
+            format.extension()
+        );
+    }
+    Ok(())
+}
+
+/// Refuse a crop the other geometry flags would quietly change: one reaching
+/// past the frame it is measured in (the `--video-size` capture, or the
+/// `--scale`d one with `--filter-order scale-first`), a `--scale` that
+/// stretches it out of shape, or one with no area at all.
+fn check_geometry(args: &StartOptions, (x, y, w, h): (i32, i32, i32, i32)) -> anyhow::Result<()> {
+    if w <= 0 || h <= 0 {
+        let detail = format!("the {}x{} crop at {},{} has no area", w, h, x, y);
+        return Err(Rejection::new(ErrorCode::CropTooSmall)
+            .detail(detail)
+            .into());
+    }
+    let frame = match (args.filter_order, args.scale, args.video_size) {
+        (FilterOrder::ScaleFirst, Some(scale), Some(size)) => {
+            Some((scaled_size(size, scale), "--video-size scaled by --scale"))
+        }
+        (FilterOrder::ScaleFirst, Some((sw, sh)), None) if sw > 0 && sh > 0 => {
+            Some(((sw, sh), "--scale"))
+        }
+        (FilterOrder::ScaleFirst, Some(_), None) => None,
+        (_, _, size) => size.map(|size| (size, "--video-size")),
+    };
+    if let Some(((fw, fh), source)) = frame.filter(|((fw, fh), _)| x + w > *fw || y + h > *fh) {
+        anyhow::bail!(
+            "the {}x{} crop at {},{} reaches past the {}x{} frame of {}; it would be cut to {}x{}",
+            w,
+            h,
+            x,
+            y,
+            fw,
+            fh,
+            source,
+            (fw - x).clamp(0, w),
+            (fh - y).clamp(0, h)
+        );
+    }
+
+    // --aspect pads and --sar/--dar retag instead, both on purpose
+    let shaped = args.aspect.is_some() || args.sar.is_some() || args.dar.is_some();
+    let scale = args
+        .scale
+        .filter(|_| args.filter_order == FilterOrder::CropFirst && !shaped);
+    if let Some((sw, sh)) = scale {
+        let stretch = (sw as f64 / sh as f64) / (w as f64 / h as f64);
+        if sw > 0 && sh > 0 && (stretch - 1.0).abs() > 0.01 {
+            let (kept_w, kept_h) = scaled_size((w, h), (sw, -1));
+            anyhow::bail!(
+                "--scale {}x{} stretches the {}x{} crop out of shape; use --scale {}x-1 \
+                 (giving {}x{}) or --aspect to letterbox",
+                sw,
+                sh,
+                w,
+                h,
+                sw,
+                kept_w,
+                kept_h
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The rate to capture device `index` at: the one asked for, or the nearest
+/// a camera offers at `--video-size`. Refuse a size the camera cannot
+/// deliver, which would otherwise make ffmpeg print its modes and exit.
+/// Screens accept any.
+fn check_camera_mode(devices: &[Device], index: u32, args: &StartOptions) -> anyhow::Result<f64> {
+    let fps = args.capture_framerate();
+    let Some(camera) = devices
+        .iter()
+        .find(|d| d.kind == DeviceKind::Video && d.index == index && !d.is_screen())
+    else {
+        return Ok(fps);
+    };
+    let modes = supported_modes(index);
+    write_log(&format!("{} modes: {:?}", camera.name, modes));
+    let Err(nearest) = check_mode(&modes, args.video_size, fps) else {
+        return Ok(fps);
+    };
+    if let Some(rate) = nearest_rate(&modes, args.video_size, fps) {
+        write_log(&format!(
+            "Capture rate: {} cannot capture at {} fps; using its nearest, {} fps",
+            camera.name, fps, rate
+        ));
+        let warning = format!(
+            "{} cannot capture at {} fps, only {}",
+            camera.name, fps, rate
+        );
+        refuse_fallback(args, ErrorCode::UnsupportedMode, &warning)?;
+        return Ok(rate);
+    }
+    let size = args
+        .video_size
+        .map_or("its default size".into(), |(w, h)| format!("{}x{}", w, h));
+    let mut rejection = Rejection::new(ErrorCode::UnsupportedMode).detail(format!(
+        "[{}] {} cannot capture {} at {} fps",
+        index, camera.name, size, fps
+    ));
+    for ((w, h), fps) in nearest {
+        rejection = rejection.detail(format!("Try: --video-size {}x{} --framerate {}", w, h, fps));
+    }
+    let supported: Vec<String> = modes
+        .iter()
+        .map(|m| format!("{}x{}@{}-{}", m.width, m.height, m.min_fps, m.max_fps))
+        .collect();
+    Err(rejection
+        .detail(format!("Supported: {}", supported.join(", ")))
+        .into())
+}
+
+/// What `status --json` reports the recording as.
+fn settings(prepared: &PreparedRecording) -> Settings {
+    let args = &prepared.options;
+    let plan = &prepared.plan;
+    let format = args.format();
+    let defaults = args.ffmpeg_defaults();
+    let video = format.has_video();
+    let config = RecordingConfig::new(args);
+    let crf = match (defaults.h264, args.encode_in_background_after_stop) {
+        (false, _) => None,
+        (true, true) => Some(compress::crf(args)),
+        (true, false) => Some(config.crf).filter(|_| !config.hardware()),
+    };
+    let audio = args.audio_name.clone().filter(|_| defaults.audio);
+    Settings {
+        resolution: frame_size(args, plan.screen.or(args.video_size), plan.crop)
+            .filter(|_| video)
+            .map(|(width, height)| Size { width, height }),
+        framerate: video.then(|| args.output_framerate()),
+        crf,
+        crop: plan.crop.map(|(x, y, width, height)| Crop {
+            x,
+            y,
+            width,
+            height,
+        }),
+        format: format.extension().to_string(),
+        audio,
+    }
+}
+
+/// Frame size after crop and `--scale`, in `--filter-order`, before any
+/// rotation or padding. `screen` is the captured frame, when known.
+fn frame_size(
+    args: &StartOptions,
+    screen: Option<(i32, i32)>,
+    crop: Option<(i32, i32, i32, i32)>,
+) -> Option<(i32, i32)> {
+    let crop = crop.map(|(_, _, w, h)| (w, h));
+    let scale = |frame: Option<(i32, i32)>| match (args.scale, frame) {
+        (Some(scale), Some(frame)) => Some(scaled_size(frame, scale)),
+        (Some((w, h)), None) if w > 0 && h > 0 => Some((w, h)),
+        (Some(_), None) => None,
+        (None, frame) => frame,
+    };
+    match args.filter_order {
+        FilterOrder::CropFirst => scale(crop.or(screen)),
+        FilterOrder::ScaleFirst => crop.or_else(|| scale(screen)),
+    }
+}
+
+/// Spawn ffmpeg (and its `--preview` window) for one launch attempt.
+fn spawn_ffmpeg(prepared: &PreparedRecording) -> anyhow::Result<Child> {
+    let args = &prepared.options;
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path())?;
+
+    let stdin = match args.stop_signal {
+        StopSignal::Q => match open_stdin_fifo() {
+            Ok(fifo) => Stdio::from(fifo),
+            Err(err) => {
+                write_log(&format!(
+                    "Stdin FIFO unavailable ({}); stop will use SIGINT.",
+                    err
+                ));
+                Stdio::null()
+            }
+        },
+        StopSignal::Sigint => {
+            let _ = fs::remove_file(&*STDIN_FIFO);
+            Stdio::null()
+        }
+    };
+
+    // ffplay reads the preview stream ffmpeg writes to stdout
+    let _ = fs::remove_file(&*PREVIEW_PID_FILE);
+    let mut stdout = if args.stdout {
+        Stdio::inherit()
+    } else {
+        Stdio::null()
+    };
+    if prepared.plan.preview {
+        let mut ffplay = Command::new("ffplay")
+            .args(preview_args())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(log.try_clone()?)
+            .spawn()?;
+        if let Some(pipe) = ffplay.stdin.take() {
+            stdout = Stdio::from(pipe);
+        }
+        fs::write(&*PREVIEW_PID_FILE, ffplay.id().to_string())?;
+        write_log(&format!("Preview started (ffplay pid {})", ffplay.id()));
+    }
+
+    // Read back by `first_frame`
+    let _ = fs::remove_file(&*PROGRESS_FILE);
+    // taskpolicy execs ffmpeg in its place, so the PID is still ffmpeg's
+    let taskpolicy = cfg!(target_os = "macos") && Path::new(TASKPOLICY).exists();
+    let mut cmd = match args.realtime && taskpolicy {
+        true => {
+            let mut cmd = Command::new(TASKPOLICY);
+            cmd.args(["-t", "0", "-l", "0", "ffmpeg"]);
+            cmd
+        }
+        false => Command::new("ffmpeg"),
+    };
+    cmd.args(["-progress", &*PROGRESS_FILE])
+        .args(&prepared.ffmpeg_args)
+        .stdin(stdin)
+        .stdout(stdout)
+        .stderr(log);
+    if let Some(nice) = args.nice {
+        write_log(&format!("Priority: nice {}", nice));
+        // Between fork and exec, so every thread ffmpeg starts inherits it.
+        // setpriority is async-signal-safe, and lowering needs no privilege.
+        unsafe {
+            cmd.pre_exec(move || {
+                libc::setpriority(libc::PRIO_PROCESS, 0, nice);
+                Ok(())
+            });
+        }
+    }
+    #[cfg(target_os = "linux")]
+    if args.realtime {
+        // Failure (no privilege) is left to the check after the spawn
+        unsafe {
+            cmd.pre_exec(|| {
+                let param = libc::sched_param {
+                    sched_priority: REALTIME_PRIORITY,
+                };
+                libc::sched_setscheduler(0, libc::SCHED_RR, &param);
+                Ok(())
+            });
+        }
+    }
+    let child = cmd.spawn().map_err(spawn_error)?;
+    if args.realtime {
+        match realtime_missing(child.id() as i32, taskpolicy) {
+            None => write_log(&format!("Priority: real-time (ffmpeg pid {})", child.id())),
+            Some(why) => write_log(&format!(
+                "Priority: --realtime not applied ({}); running at normal priority",
+                why
+            )),
+        }
+    }
+    Ok(child)
+}
+
+/// `REC_NO_FFMPEG` when ffmpeg could not be spawned for not being installed.
+fn spawn_error(err: std::io::Error) -> Rejection {
+    match err.kind() {
+        std::io::ErrorKind::NotFound => Rejection::new(ErrorCode::NoFfmpeg)
+            .detail("ffmpeg is not on PATH; install it (e.g. `brew install ffmpeg`)"),
+        _ => err.into(),
+    }
+}
+
+const TASKPOLICY: &str = "/usr/sbin/taskpolicy";
+/// SCHED_RR priority for `--realtime`: above normal threads, well below
+/// the kernel's own (1-99)
+#[cfg(target_os = "linux")]
+const REALTIME_PRIORITY: i32 = 10;
+
+/// Why `--realtime` did not take effect on ffmpeg (`pid`), if it did not.
+#[cfg(target_os = "linux")]
+fn realtime_missing(pid: i32, _taskpolicy: bool) -> Option<String> {
+    match unsafe { libc::sched_getscheduler(pid) } {
+        libc::SCHED_RR => None,
+        _ => Some("SCHED_RR needs CAP_SYS_NICE or an rtprio limit".into()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn realtime_missing(_pid: i32, taskpolicy: bool) -> Option<String> {
+    match taskpolicy {
+        true => None,
+        false => Some(format!("{} not found", TASKPOLICY)),
+    }
+}
+
+enum FirstFrame {
+    Captured,
+    Exited(ExitStatus),
+    TimedOut,
+}
+
+/// Wait for ffmpeg's `-progress` report to count a frame. The report goes to
+/// `PROGRESS_FILE` rather than a pipe: ffmpeg keeps writing it after `start`
+/// exits, and stdout may carry the recording or the preview. Without
+/// `video` there are no frames to count, only the audio's `out_time_us`.
+fn first_frame(child: &mut Child, video: bool) -> FirstFrame {
+    let started = Instant::now();
+    let key = if video { "frame=" } else { "out_time_us=" };
+    loop {
+        thread::sleep(Duration::from_millis(100));
+        if let Ok(Some(status)) = child.try_wait() {
+            return FirstFrame::Exited(status);
+        }
+        let progress = fs::read_to_string(&*PROGRESS_FILE).unwrap_or_default();
+        let frames = progress
+            .lines()
+            .filter_map(|l| l.strip_prefix(key)?.trim().parse::<u64>().ok())
+            .max();
+        if let Some(frames) = frames.filter(|n| *n > 0) {
+            let elapsed = started.elapsed().as_millis();
+            match video {
+                true => write_log(&format!(
+                    "First frame after {}ms ({} so far)",
+                    elapsed, frames
+                )),
+                false => write_log(&format!("First audio after {}ms", elapsed)),
+            }
+            return FirstFrame::Captured;
+        }
+        if started.elapsed() >= FIRST_FRAME_TIMEOUT {
+            return FirstFrame::TimedOut;
+        }
+    }
+}
+
+/// Spawn ffmpeg until it captures a first frame, `--retry` times over.
+/// Returns its pid, written to the PID file.
+fn spawn_capturing(prepared: &PreparedRecording) -> Result<i32, Rejection> {
+    let args = &prepared.options;
+    let attempts = args.retry + 1;
+    let mut attempt = 1;
+    loop {
+        let log_start = file_size(log_path());
+        let mut child = spawn_ffmpeg(prepared)?;
+        let pid = child.id() as i32;
+
+        // Alive is not enough: a stuck capture device leaves ffmpeg
+        // running with an empty recording
+        match first_frame(&mut child, args.format().has_video()) {
+            FirstFrame::Captured => {
+                fs::write(&*PID_FILE, pid.to_string())?;
+                return Ok(pid);
+            }
+            FirstFrame::TimedOut => return Err(abandon_frameless(child, prepared)),
+            // Whatever was reading stdout is gone; a retry would not help
+            FirstFrame::Exited(status) if args.stdout && stream_closed(status) => {
+                write_log(&format!("Stream consumer closed stdout; ffmpeg {}", status));
+                return Err(stream_closed_rejection());
+            }
+            FirstFrame::Exited(_) => {}
+        }
+        close_preview();
+        if let Some(rejection) = exited_for_good(args, log_start, attempt) {
+            return Err(rejection);
+        }
+        // avfoundation is often still busy right after the display wakes
+        let backoff = Duration::from_millis(RETRY_BACKOFF_MS * attempt as u64);
+        write_log(&format!(
+            "ffmpeg exited immediately (attempt {}/{}); retrying in {}ms",
+            attempt,
+            attempts,
+            backoff.as_millis()
+        ));
+        thread::sleep(backoff);
+        attempt += 1;
+    }
+}
+
+/// Stop an ffmpeg that is running but captured no frame in time, and remove
+/// what it wrote.
+fn abandon_frameless(mut child: Child, prepared: &PreparedRecording) -> Rejection {
+    let args = &prepared.options;
+    write_log(&format!(
+        "No frame within {}s; stopping ffmpeg (pid {})",
+        FIRST_FRAME_TIMEOUT.as_secs(),
+        child.id()
+    ));
+    let _ = child.kill();
+    let _ = child.wait();
+    close_preview();
+    let _ = fs::remove_file(&*PROGRESS_FILE);
+    // Nothing but a header, if anything
+    if !args.stdout {
+        let _ = fs::remove_file(&prepared.plan.output);
+    }
+    events::publish(
+        args.event_socket.as_deref(),
+        events::ERROR,
+        json!({ "message": "ffmpeg captured no frames" }),
+    );
+    Rejection::new(ErrorCode::NoFrames)
+        .detail(format!(
+            "ffmpeg captured no frames within {}s",
+            FIRST_FRAME_TIMEOUT.as_secs()
+        ))
+        .detail(match args.format().has_video() {
+            true => "Check the device and the Screen Recording permission",
+            false => "Check the device and the Microphone permission",
+        })
+        .with_log()
+}
+
+/// Why an ffmpeg that exited right away is not worth another `attempt`:
+/// refused the screen (with what it logged past `log_start`), or out of
+/// `--retry`. `None` to try again.
+fn exited_for_good(args: &StartOptions, log_start: u64, attempt: u32) -> Option<Rejection> {
+    let socket = args.event_socket.as_deref();
+    let attempts = args.retry + 1;
+    // Retrying cannot grant a permission
+    if let Some(error) = screen_permission_error(log_start) {
+        write_log(&format!(
+            "avfoundation refused the screen ({}); not retrying",
+            error
+        ));
+        events::publish(
+            socket,
+            events::ERROR,
+            json!({ "message": "no Screen Recording permission" }),
+        );
+        return Some(
+            Rejection::new(ErrorCode::NoScreenPermission)
+                .detail(format!(
+                    "avfoundation could not open the screen ({})",
+                    error
+                ))
+                .detail(SCREEN_PERMISSION_HINT)
+                .with_log(),
+        );
+    }
+    if attempt < attempts {
+        return None;
+    }
+    events::publish(
+        socket,
+        events::ERROR,
+        json!({ "message": "ffmpeg exited immediately" }),
+    );
+    let mut rejection = Rejection::new(ErrorCode::StartFailed).detail("ffmpeg exited immediately");
+    if attempts > 1 {
+        rejection = rejection.detail(format!("Gave up after {} attempts", attempts));
+    }
+    Some(rejection.with_log())
+}
+
+/// Write the state file `stop` finishes the recording from.
+fn save_session(prepared: &PreparedRecording, pid: i32) -> Result<(), Rejection> {
+    let args = &prepared.options;
+    let output = &prepared.plan.output;
+    let mut session = match args.stdout {
+        true => Session::new(pid, None),
+        false => Session {
+            output_root: Some(args.output_root()),
+            filename_format: args.filename_format.clone(),
+            ..Session::new(pid, Some(output.clone()))
+        },
+    };
+    session.ffmpeg_args = prepared.ffmpeg_args.clone();
+    session.audio_track = split_audio_path(args, &prepared.plan);
+    session.also_outputs = also_output_files(args);
+    session.resume_from = prepared.recovered.clone().filter(|_| args.resume);
+    session.movflags = args.movflags().map(String::from);
+    session.replaces = prepared.replaces.clone();
+    session.settings = Some(settings(prepared));
+    if args.lossless_capture() {
+        session.capture = Some(compress::capture_path(output));
+    }
+    if args.encode_in_background_after_stop {
+        session.encode_args = compress::encode_args(args);
+    }
+    session.save()?;
+    Ok(())
+}
+
+/// `--stats`: what is known of the recording at its start, for `stop` to
+/// complete.
+fn save_pending_stats(prepared: &PreparedRecording) -> Result<(), Rejection> {
+    let args = &prepared.options;
+    if !args.stats {
+        let _ = fs::remove_file(&*STATS_FILE);
+        return Ok(());
+    }
+    let config = RecordingConfig::new(args);
+    let pending = RecordingStats {
+        output: prepared.plan.output.clone(),
+        started_at: Local::now(),
+        ended_at: None,
+        duration_secs: None,
+        resolution: None,
+        framerate: args.output_framerate(),
+        achieved_framerate: None,
+        vfr: args.vfr,
+        crf: match args.encode_in_background_after_stop {
+            true => compress::crf(args),
+            false => config.crf,
+        },
+        preset: match args.encode_in_background_after_stop {
+            true => compress::preset(args).into(),
+            false => config.preset.clone(),
+        },
+        bitrate_kbps: config.hardware().then_some(config.bitrate_kbps),
+        codec: config.codec,
+        crop: prepared.plan.crop,
+        screen: prepared.plan.screen,
+        size_bytes: None,
+        ffmpeg_version: None,
+        ffmpeg_summary: None,
+    };
+    pending.save(Path::new(&*STATS_FILE))?;
+    Ok(())
+}
+
+/// Start the title card, segment, marker, window and clipboard records of
+/// a new recording, clearing those of the last one.
+fn begin_sidecars(args: &StartOptions, output: &Path) -> Result<(), Rejection> {
+    match &args.title_card {
+        Some(text) => TitleCard {
+            output: output.to_path_buf(),
+            text: text.clone(),
+            duration: args.title_card_duration,
+            color: args.title_card_color.clone(),
+            h264_profile: args.h264_profile.clone(),
+            h264_level: args.h264_level.clone(),
+        }
+        .save()?,
+        None => title::clear(),
+    }
+
+    if args.segment_time.is_some() {
+        segments::begin(Local::now())?;
+    } else {
+        segments::clear();
+    }
+    chapters::clear();
+    match &args.follow_window {
+        Some(title) => follow::begin(title)?,
+        None => follow::clear(),
+    }
+    match args.track_clipboard {
+        true => clipboard::begin()?,
+        false => clipboard::clear(),
+    }
+    Ok(())
+}
+
+/// Start key capture, the HUD and the big pointer beside ffmpeg (`pid`).
+/// One that cannot start is a warning, not a failed `start`.
+fn open_helpers(args: &StartOptions, pid: i32, warnings: &mut Vec<String>) {
+    if args.capture_keys {
+        if let Err(reason) = keys::start_capture() {
+            write_log(&format!("Key capture unavailable: {}", reason));
+            warnings.push(format!("Key capture unavailable: {}", reason));
+        }
+    } else {
+        let _ = fs::remove_file(&*KEYS_PID_FILE);
+    }
+
+    if args.hud {
+        if let Err(reason) = hud::open(pid) {
+            write_log(&format!("HUD unavailable: {}", reason));
+            warnings.push(format!("HUD unavailable: {}", reason));
+        }
+    } else {
+        hud::close();
+    }
+    match args.big_cursor {
+        Some(factor) => {
+            if let Err(reason) = cursor::enlarge(factor) {
+                write_log(&format!("--big-cursor unavailable: {}", reason));
+                warnings.push(format!("Pointer not enlarged: {}", reason));
+            }
+        }
+        None => cursor::restore(),
+    }
+}
+
+/// Record in the state file that a `stop` is under way.
+fn mark_stopping(session: &Session) {
+    let stopping = Session {
+        status: SessionStatus::Stopping,
+        ..session.clone()
+    };
+    if let Err(err) = stopping.save() {
+        write_log(&format!(
+            "Could not record the stop in the state file: {}",
+            err
+        ));
+    }
+}
+
+/// End ffmpeg, `q` on its stdin first, then SIGINT, so it finalizes what it
+/// wrote. Returns whether a `--stdout` ffmpeg had already exited, its reader
+/// gone.
+fn end_ffmpeg(pid: i32, out: &Path, streamed: bool) -> Result<bool, Rejection> {
+    // A streaming ffmpeg that died on its own lost its reader
+    let closed = streamed && !pid_alive(pid);
+    if closed {
+        write_log("ffmpeg already exited; the stream consumer closed stdout");
+    }
+
+    // A SIGSTOPped ffmpeg can neither read `q` nor act on SIGINT
+    if pid_paused(pid) {
+        write_log("Recording is paused; sending SIGCONT before stopping");
+        let _ = signal_pid(pid, Signal::SIGCONT);
+    }
+
+    let mut exited = false;
+    match send_quit() {
+        Ok(()) => {
+            write_log("Sent 'q' to ffmpeg stdin");
+            exited = wait_for_exit(pid, 50);
+            if !exited {
+                write_log("ffmpeg ignored 'q'; falling back to SIGINT");
+            }
+        }
+        Err(err) if Path::new(&*STDIN_FIFO).exists() => {
+            write_log(&format!(
+                "Could not write to stdin FIFO ({}); using SIGINT",
+                err
+            ));
+        }
+        Err(_) => {}
+    }
+    if !exited {
+        let sent = signal_pid(pid, Signal::SIGINT);
+        let gone = wait_for_exit(pid, 50);
+        // Neither `q` nor a signal reached it: say so and leave it running
+        // rather than forget a live recording
+        if !gone && sent == Err(Errno::EPERM) {
+            write_log(&format!(
+                "Cannot signal ffmpeg (pid {}): permission denied",
+                pid
+            ));
+            return Err(Rejection::new(ErrorCode::StopFailed)
+                .detail(format!(
+                    "ffmpeg (pid {}) is still recording, but rec-cli may not signal it \
+                     and it did not take 'q' on stdin",
+                    pid
+                ))
+                .detail(format!("Stop it from a terminal with: kill -INT {}", pid))
+                .with_log());
+        }
+    }
+    // An ffmpeg that took neither still holds what it wrote: end it, and
+    // the repair after the wait salvages that if it can
+    if !streamed && pid_alive(pid) {
+        write_log(&format!(
+            "Recovery: ffmpeg (pid {}) ignored the stop; killing it to salvage {}",
+            pid,
+            out.display()
+        ));
+        let _ = signal_pid(pid, Signal::SIGKILL);
+        wait_for_exit(pid, 20);
+    }
+    Ok(closed)
+}
+
+/// With ffmpeg gone, close what ran beside it and remove the state files of
+/// the recording. Returns the `--events-socket` it had, for the events still
+/// to come.
+fn release_recording() -> Option<PathBuf> {
+    let _ = fs::remove_file(&*STDIN_FIFO);
+    close_preview();
+    hud::close();
+    cursor::restore();
+
+    let _ = fs::remove_file(&*PID_FILE);
+    let _ = fs::remove_file(&*PROGRESS_FILE);
+    session::clear();
+
+    let socket = read_path_file(&*EVENTS_FILE);
+    let _ = fs::remove_file(&*EVENTS_FILE);
+    socket
+}
+
+/// What `stop` knows of a recording once ffmpeg has exited, for finishing
+/// it into a [`StoppedRecording`].
+struct Finishing<'a> {
+    options: &'a StopOptions,
+    session: Option<Session>,
+    out: PathBuf,
+    movflags: Option<String>,
+    passphrase: Option<String>,
+    socket: Option<PathBuf>,
+    keys: Option<PathBuf>,
+    summary: Option<FfmpegSummary>,
+}
+
+impl Finishing<'_> {
+    /// The recording as ffmpeg left it, before any post-processing.
+    fn stopped(&self, output: PathBuf) -> StoppedRecording {
+        StoppedRecording {
+            output,
+            stats: None,
+            framerate: None,
+            keys: self.keys.clone(),
+            segments: None,
+            trimmed: None,
+            repaired: false,
+            chapters: None,
+            follow: None,
+            normalized: None,
+            pruned: Vec::new(),
+            audio_track: None,
+            clipboard: None,
+            also_outputs: Vec::new(),
+            summary: self.summary.clone(),
+            resumed: None,
+            poster: None,
+            silence_trimmed: None,
+        }
+    }
+
+    /// `--stdout`: nothing to finalize; `closed` when the reader went first.
+    fn streamed(self, closed: bool) -> Result<StoppedRecording, Rejection> {
+        chapters::clear();
+        follow::clear();
+        clipboard::clear();
+        let socket = self.socket.as_deref();
+        events::publish(socket, events::STOPPED, json!({ "output": self.out }));
+        if closed {
+            return Err(stream_closed_rejection());
+        }
+        Ok(self.stopped(self.out.clone()))
+    }
+
+    /// `--segment-time`: the finished parts, the first one standing for the
+    /// recording.
+    fn segmented(
+        self,
+        parts: Vec<segments::Segment>,
+        manifest: PathBuf,
+    ) -> Result<StoppedRecording, Rejection> {
+        let first = parts[0].file.clone();
+        let poster = self
+            .options
+            .poster
+            .then(|| poster::make(&first, self.movflags.as_deref()));
+        let pruned = prune(&first, self.options, self.session.as_ref());
+        events::publish(
+            self.socket.as_deref(),
+            events::STOPPED,
+            json!({ "output": first, "segments": manifest }),
+        );
+        let stopped = StoppedRecording {
+            segments: Some(manifest),
+            chapters: chapters::finish_sidecar(&self.out),
+            follow: follow::finish(&self.out, 0.0),
+            pruned,
+            clipboard: clipboard::finish(&self.out, 0.0),
+            poster,
+            ..self.stopped(first)
+        };
+        let rest: Vec<PathBuf> = parts.into_iter().skip(1).map(|p| p.file).collect();
+        self.seal(stopped, &rest)
+    }
+
+    /// `--encode-in-background-after-stop` and gif: encode the lossless
+    /// capture into the recording.
+    fn encode_capture(&self) -> Result<(), Rejection> {
+        let Some(session) = &self.session else {
+            return Ok(());
+        };
+        let Some(capture) = &session.capture else {
+            return Ok(());
+        };
+        let out = &self.out;
+        let ext = out.extension().unwrap_or_default().to_string_lossy();
+        let encoded = match OutputFormat::from_extension(&ext) {
+            Some(OutputFormat::Gif) => gif::encode(capture, out),
+            _ => compress::compress(capture, out, &session.encode_args, self.options.on_progress),
+        };
+        if let Err(err) = encoded {
+            write_log(&format!("Encoding the capture failed: {}", err));
+            events::publish(
+                self.socket.as_deref(),
+                events::ERROR,
+                json!({ "message": "compression failed", "output": capture }),
+            );
+            return Err(Rejection::new(ErrorCode::EncodeFailed)
+                .detail(format!("Encoding the recording failed: {}", err))
+                .detail(format!(
+                    "The lossless capture is kept: {}",
+                    capture.display()
+                ))
+                .with_log());
+        }
+        Ok(())
+    }
+
+    /// Wait for the output to be written, then verify it plays. `Some`
+    /// whether it had to be repaired, `None` when it never appeared.
+    fn await_finalized(&self) -> Result<Option<bool>, Rejection> {
+        let out = &self.out;
+        // wait for mp4 to finalize
+        for _ in 0..30 {
+            if out.exists() && out.metadata().map(|m| m.len()).unwrap_or(0) > 0 {
+                let repair = !self.options.no_repair;
+                return match verify_playable(out, repair, self.movflags.as_deref()) {
+                    Ok(repaired) => Ok(Some(repaired)),
+                    Err(rejection) => {
+                        let message = match rejection.code {
+                            ErrorCode::VerifyTimedOut => "verifying the recording timed out",
+                            _ => "recording is corrupt",
+                        };
+                        events::publish(
+                            self.socket.as_deref(),
+                            events::ERROR,
+                            json!({ "message": message, "output": out }),
+                        );
+                        Err(rejection
+                            .detail(format!("Kept as recorded: {}", out.display()))
+                            .with_log())
+                    }
+                };
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        Ok(None)
+    }
+
+    /// Everything `stop` does to a verified recording: --replace-last, the
+    /// resumed part, the title card, the sidecars, then the trims, the
+    /// loudness pass, the poster and --max-recordings.
+    fn finish(self, repaired: bool) -> Result<StoppedRecording, Rejection> {
+        let options = self.options;
+        let session = self.session.as_ref();
+        let out = &self.out;
+        let movflags = self.movflags.as_deref();
+        if let Some(previous) = session.and_then(|s| s.replaces.as_deref()) {
+            replace_last(previous);
+        }
+        let resume_from = session.and_then(|s| s.resume_from.as_deref());
+        let resumed = resume_from.map(|part| resume::join(part, out, movflags));
+        let seam = match &resumed {
+            Some(Resumed::Joined { seam }) => *seam,
+            _ => 0.0,
+        };
+        // Markers were taken before the intro and the resumed part existed
+        let offset = seam
+            + match TitleCard::take(out) {
+                Some(card) if title::apply(&card, out, movflags) => card.duration,
+                _ => 0.0,
+            };
+        let chapters = chapters::finish(out, offset, movflags);
+        let follow = follow::finish(out, offset);
+        let clipboard = clipboard::finish(out, offset);
+        let audio_track = session.and_then(|s| s.audio_track.clone());
+        let audio_track = audio_track.filter(|wav| written(wav, "--split-audio-track"));
+        let also = session.map_or(&[][..], |s| &s.also_outputs[..]);
+        let also_outputs: Vec<PathBuf> = also
+            .iter()
+            .filter(|file| written(file, "--also-output"))
+            .cloned()
+            .collect();
+        events::publish(
+            self.socket.as_deref(),
+            events::STOPPED,
+            json!({
+                "output": out,
+                "size": file_size(out),
+                "audio_track": audio_track,
+                "also_outputs": also_outputs,
+            }),
+        );
+        let stats = finish_stats(out, self.summary.clone());
+        // A --vfr average is not a shortfall
+        let framerate = stats
+            .as_ref()
+            .filter(|r| !r.vfr)
+            .and_then(|r| Some((r.framerate, r.achieved_framerate?)));
+        let stats = stats.map(|r| r.sidecar_path());
+        let trimmed = trim(out, options);
+        let normalized = options
+            .normalize_audio
+            .then(|| loudness::normalize(out, options.loudness_target));
+        let silence_trimmed = options
+            .trim_silence
+            .then(|| silence::trim(out, options.silence_threshold, options.silence_duration));
+        let poster = options.poster.then(|| poster::make(out, movflags));
+        let pruned = prune(out, options, session);
+        let stopped = StoppedRecording {
+            stats,
+            framerate,
+            trimmed,
+            repaired,
+            chapters,
+            follow,
+            normalized,
+            pruned,
+            audio_track,
+            clipboard,
+            also_outputs,
+            resumed,
+            poster,
+            silence_trimmed,
+            ..self.stopped(out.clone())
+        };
+        self.seal(stopped, &[])
+    }
+
+    /// `--encrypt` and `--output-permissions`, last, over every file `stop`
+    /// left behind; `segments` past the first.
+    fn seal(
+        &self,
+        stopped: StoppedRecording,
+        segments: &[PathBuf],
+    ) -> Result<StoppedRecording, Rejection> {
+        let stopped = encrypt_outputs(stopped, self.passphrase.as_deref(), segments)?;
+        if let Some(mode) = self.options.output_permissions {
+            set_output_permissions(&stopped, segments, mode);
+        }
+        Ok(stopped)
+    }
+
+    /// The recording never appeared: clean up after it as asked, and say
+    /// why when its directory was deleted under it.
+    fn unfinalized(self, dir_gone: bool) -> Rejection {
+        let out = &self.out;
+        events::publish(
+            self.socket.as_deref(),
+            events::ERROR,
+            json!({ "message": "recording did not finalize", "output": out }),
+        );
+        if self.options.delete_on_fail {
+            discard_unfinalized(out);
+            for file in self.session.iter().flat_map(|s| &s.also_outputs) {
+                discard_unfinalized(file);
+            }
+        }
+        if dir_gone {
+            let dir = out.parent().unwrap_or(Path::new("/"));
+            return Rejection::new(ErrorCode::OutputDirGone)
+                .detail(format!(
+                    "Output directory was deleted while recording: {}",
+                    dir.display()
+                ))
+                .detail("The recording was written to the deleted directory and is lost")
+                .with_log();
+        }
+        Rejection::new(ErrorCode::StopFailed).with_log()
+    }
+}
+
+/// `--replace-last`: remove the recording this one replaces, now that it
+/// is safely written.
+fn replace_last(previous: &Path) {
+    match fs::remove_file(previous) {
+        Ok(()) => write_log(&format!("--replace-last: removed {}", previous.display())),
+        Err(err) => write_log(&format!(
+            "--replace-last: could not remove {} ({})",
+            previous.display(),
+            err
+        )),
+    }
+}
+
+/// Whether an extra output `flag` asked for was written, saying so in the
+/// log when not.
+fn written(file: &Path, flag: &str) -> bool {
+    let written = file_size(file) > 0;
+    if !written {
+        write_log(&format!("{}: {} is missing", flag, file.display()));
+    }
+    written
+}
+
+/// Whether a `--stdout` ffmpeg that exited did so because its reader went
+/// away: killed by SIGPIPE, or our own stdout now has no reader.
+fn stream_closed(status: ExitStatus) -> bool {
+    if status.signal() == Some(libc::SIGPIPE) {
+        return true;
+    }
+    let mut fd = libc::pollfd {
+        fd: libc::STDOUT_FILENO,
+        events: 0,
+        revents: 0,
+    };
+    let ready = unsafe { libc::poll(&mut fd, 1, 0) };
+    ready == 1 && fd.revents & (libc::POLLERR | libc::POLLHUP) != 0
+}
+
+/// A closed stream is how a piped recording normally ends, not a failure.
+fn stream_closed_rejection() -> Rejection {
+    Rejection::new(ErrorCode::StreamClosed)
+        .detail("The program reading the recording closed the pipe")
+}
+
+/// Close the `--preview` window, if one is open. ffplay usually exits on
+/// its own once ffmpeg closes the stream.
+fn close_preview() {
+    let Some(pid) = fs::read_to_string(&*PREVIEW_PID_FILE)
+        .ok()
+        .and_then(|p| p.trim().parse::<i32>().ok())
+    else {
+        return;
+    };
+    let _ = fs::remove_file(&*PREVIEW_PID_FILE);
+    if pid_alive(pid) {
+        let _ = kill(Pid::from_raw(pid), Signal::SIGTERM);
+        write_log(&format!("Preview closed (ffplay pid {})", pid));
+    }
+}
+
+/// Rewrite `input` into `output` with `-c copy`, which rebuilds the
+/// container (index, moov atom) around the same streams, muxed with
+/// `movflags` when it is an mp4/mov. `Err` when ffmpeg could not run or did
+/// not finish within `REMUX_TIMEOUT`.
+fn remux(input: &Path, output: &Path, movflags: Option<&str>) -> io::Result<bool> {
+    let mut args: Vec<OsString> = ["-hide_banner", "-loglevel", "error", "-y", "-i"]
+        .map(OsString::from)
+        .to_vec();
+    args.push(input.into());
+    args.extend(["-c", "copy"].map(OsString::from));
+    args.extend(
+        faststart_args(output, movflags)
+            .into_iter()
+            .map(OsString::from),
+    );
+    args.push(output.into());
+    let ran = run_command("ffmpeg", &args, REMUX_TIMEOUT);
+    if let Ok(ran) = &ran {
+        append_log(&ran.stderr);
+    }
+    let ok = matches!(&ran, Ok(ran) if ran.status.success()) && file_size(output) > 0;
+    if !ok {
+        remove_temp(output);
+    }
+    ran.map(|_| ok)
+}
+
+/// Which of `SCREEN_PERMISSION_ERRORS` ffmpeg logged past `log_start`, the
+/// log's length when it was launched.
+fn screen_permission_error(log_start: u64) -> Option<&'static str> {
+    let log = fs::read(log_path()).ok()?;
+    let since = String::from_utf8_lossy(log.get(log_start as usize..)?);
+    SCREEN_PERMISSION_ERRORS
+        .iter()
+        .copied()
+        .find(|error| since.contains(error))
+}
+
+/// Clean up after an ffmpeg that was killed from outside, salvaging what it
+/// wrote to `out`, and say what happened.
+fn killed_rejection(pid: i32, out: &Path) -> Rejection {
+    write_log(&format!(
+        "ffmpeg (pid {}) was killed without a stop (no closing stats); recovering {}",
+        pid,
+        out.display()
+    ));
+    let recovered = recover_partial(out);
+    let socket = read_path_file(&*EVENTS_FILE);
+    events::publish(
+        socket.as_deref(),
+        events::ERROR,
+        json!({ "message": "ffmpeg was killed", "output": out, "recovered": recovered }),
+    );
+    close_preview();
+    hud::close();
+    cursor::restore();
+    for file in [
+        &*STDIN_FIFO,
+        &*PID_FILE,
+        &*OUT_FILE,
+        &*PROGRESS_FILE,
+        &*EVENTS_FILE,
+    ] {
+        let _ = fs::remove_file(file);
+    }
+    session::clear();
+    chapters::clear();
+    follow::clear();
+    clipboard::clear();
+
+    let rejection = Rejection::new(ErrorCode::KilledExternally).detail(format!(
+        "ffmpeg (pid {}) died before `stop`, killed by a signal (e.g. when memory ran out)",
+        pid
+    ));
+    match recovered {
+        Some(fixed) => rejection.detail(format!("Recovered: {}", fixed.display())),
+        None => rejection.detail("Nothing playable could be recovered"),
+    }
+    .detail(format!("Partial file kept: {}", out.display()))
+}
+
+/// Try to remux the partial file an interrupted recording left at `partial`
+/// into `<name>_recovered.<ext>` next to it. The partial is kept either way.
+fn recover_partial(partial: &Path) -> Option<PathBuf> {
+    if partial.as_os_str().is_empty() || file_size(partial) == 0 {
+        return None;
+    }
+    let stem = partial.file_stem()?.to_string_lossy();
+    let ext = partial
+        .extension()
+        .map(|e| e.to_string_lossy())
+        .unwrap_or("mp4".into());
+    let fixed = partial.with_file_name(format!("{}_recovered.{}", stem, ext));
+
+    write_log(&format!(
+        "Interrupted recording found: {}",
+        partial.display()
+    ));
+    if remux(partial, &fixed, Some(FASTSTART)).unwrap_or_else(|err| {
+        write_log(&format!("Remuxing {} failed: {}", partial.display(), err));
+        false
+    }) {
+        write_log(&format!(
+            "Recovered interrupted recording: {}",
+            fixed.display()
+        ));
+        Some(fixed)
+    } else {
+        write_log(&format!("Could not recover {}", partial.display()));
+        None
+    }
+}
+
+/// Check that ffprobe can read a duration from `out`, i.e. the container
+/// was finalized (an mp4 without its moov atom has size but won't play). A
+/// failing file is remuxed in place (with `movflags`) unless `repair` is
+/// off; `Err` when that does not fix it, or when ffprobe or the remux does
+/// not finish in time.
+fn verify_playable(out: &Path, repair: bool, movflags: Option<&str>) -> Result<bool, Rejection> {
+    let timed_out = |err: io::Error| {
+        write_log(&format!("Verifying {} failed: {}", out.display(), err));
+        Rejection::new(ErrorCode::VerifyTimedOut).detail(err.to_string())
+    };
+    let playable = |path: &Path| {
+        try_probe_duration(path)
+            .map(|d| d.is_some_and(|d| d > 0.0))
+            .map_err(timed_out)
+    };
+    if which("ffprobe").is_none() {
+        write_log("ffprobe not found; not verifying the output");
+        return Ok(false);
+    }
+    if playable(out)? {
+        return Ok(false);
+    }
+
+    let corrupt = |detail: String| Rejection::new(ErrorCode::OutputCorrupt).detail(detail);
+    if !repair {
+        write_log(&format!(
+            "{} is not playable; not repairing it (--no-repair)",
+            out.display()
+        ));
+        return Err(corrupt(format!(
+            "ffprobe cannot read {} (not repaired: --no-repair)",
+            out.display()
+        )));
+    }
+    write_log(&format!(
+        "Recovery: {} is not playable; attempting a remux repair",
+        out.display()
+    ));
+    let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = out
+        .extension()
+        .map(|e| e.to_string_lossy())
+        .unwrap_or("mp4".into());
+    let repaired = out.with_file_name(format!(".{}.repair.{}", stem, ext));
+    if remux(out, &repaired, movflags).map_err(timed_out)?
+        && playable(&repaired)?
+        && fs::rename(&repaired, out).is_ok()
+    {
+        write_log(&format!("Recovery: repaired {}", out.display()));
+        return Ok(true);
+    }
+    remove_temp(&repaired);
+    write_log(&format!("Recovery: could not repair {}", out.display()));
+    Err(corrupt(format!(
+        "ffprobe cannot read {} and remuxing did not fix it",
+        out.display()
+    )))
+}
+
+/// `--encrypt` everything `stop` produced that shows the screen or the
+/// keyboard. `segments` are the segment files besides `stopped.output`.
+fn encrypt_outputs(
+    mut stopped: StoppedRecording,
+    pass: Option<&str>,
+    segments: &[PathBuf],
+) -> anyhow::Result<StoppedRecording> {
+    let Some(pass) = pass else {
+        return Ok(stopped);
+    };
+    let failed = |path: &Path, err: String| {
+        write_log(&format!(
+            "Encryption failed for {}: {}",
+            path.display(),
+            err
+        ));
+        Rejection::new(ErrorCode::EncryptFailed)
+            .detail(err)
+            .detail(format!("Left unencrypted: {}", path.display()))
+    };
+
+    stopped.output =
+        crypt::encrypt(&stopped.output, pass).map_err(|e| failed(&stopped.output, e))?;
+    for segment in segments {
+        crypt::encrypt(segment, pass).map_err(|e| failed(segment, e))?;
+    }
+    if let Some(trimmed) = &mut stopped.trimmed {
+        trimmed.output =
+            crypt::encrypt(&trimmed.output, pass).map_err(|e| failed(&trimmed.output, e))?;
+    }
+    if let Some(Normalized::Done { output, .. }) = &mut stopped.normalized {
+        *output = crypt::encrypt(output, pass).map_err(|e| failed(output, e))?;
+    }
+    if let Some(keys) = &stopped.keys {
+        stopped.keys = Some(crypt::encrypt(keys, pass).map_err(|e| failed(keys, e))?);
+    }
+    if let Some(audio) = &stopped.audio_track {
+        stopped.audio_track = Some(crypt::encrypt(audio, pass).map_err(|e| failed(audio, e))?);
+    }
+    for also in &mut stopped.also_outputs {
+        *also = crypt::encrypt(also, pass).map_err(|e| failed(also, e))?;
+    }
+    if let Some(SilenceTrimmed::Done { output, .. }) = &mut stopped.silence_trimmed {
+        *output = crypt::encrypt(output, pass).map_err(|e| failed(output, e))?;
+    }
+    if let Some(Poster::Done { image, .. }) = &mut stopped.poster {
+        *image = crypt::encrypt(image, pass).map_err(|e| failed(image, e))?;
+    }
+    Ok(stopped)
+}
+
+/// `--output-permissions`: set `mode` on everything `stop` left behind.
+fn set_output_permissions(stopped: &StoppedRecording, segments: &[PathBuf], mode: u32) {
+    let mut files = vec![&stopped.output];
+    files.extend(segments);
+    files.extend(&stopped.segments);
+    files.extend(stopped.trimmed.as_ref().map(|trimmed| &trimmed.output));
+    if let Some(Normalized::Done { output, .. }) = &stopped.normalized {
+        files.push(output);
+    }
+    if let Some(SilenceTrimmed::Done { output, .. }) = &stopped.silence_trimmed {
+        files.push(output);
+    }
+    if let Some(Poster::Done { image, .. }) = &stopped.poster {
+        files.push(image);
+    }
+    let sidecars = [
+        &stopped.stats,
+        &stopped.keys,
+        &stopped.audio_track,
+        &stopped.follow,
+    ];
+    files.extend(sidecars.into_iter().flatten());
+    files.extend(&stopped.also_outputs);
+    for file in files {
+        set_mode(file, mode);
+    }
+}
+
+/// `--max-recordings`: remove the oldest recordings where `start` put `out`.
+fn prune(out: &Path, options: &StopOptions, session: Option<&Session>) -> Vec<PathBuf> {
+    let Some(keep) = options.max_recordings else {
+        return Vec::new();
+    };
+    match session.and_then(|s| Some((s.output_root.as_deref()?, &s.filename_format))) {
+        Some((root, ts_format)) => prune_recordings(root, ts_format, keep as usize, out),
+        None => {
+            write_log("--max-recordings: output directory unknown; nothing deleted");
+            Vec::new()
+        }
+    }
+}
+
+/// Write the `--trim-start`/`--trim-end` copy of `out`, leaving `out` alone.
+fn trim(out: &Path, options: &StopOptions) -> Option<TrimmedRecording> {
+    if options.trim_start.is_none() && options.trim_end.is_none() {
+        return None;
+    }
+    let Some(before) = probe_duration(out) else {
+        write_log(&format!(
+            "Not trimming: could not probe the duration of {}",
+            out.display()
+        ));
+        return None;
+    };
+    let start = options.trim_start.unwrap_or(0.0);
+    let length = before - start - options.trim_end.unwrap_or(0.0);
+    if length <= 0.0 {
+        write_log(&format!("Not trimming: nothing left of {:.1}s", before));
+        return None;
+    }
+
+    let stem = out.file_stem()?.to_string_lossy();
+    let ext = out
+        .extension()
+        .map(|e| e.to_string_lossy())
+        .unwrap_or("mp4".into());
+    let trimmed = out.with_file_name(format!("{}_trimmed.{}", stem, ext));
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(["-hide_banner", "-loglevel", "error", "-y"])
+        .args(["-ss".into(), format!("{}", start), "-i".into()])
+        .arg(out)
+        .args(["-t".into(), format!("{}", length)]);
+    if options.trim_accurate {
+        cmd.args([
+            "-c:v",
+            "libx264",
+            "-preset",
+            "ultrafast",
+            "-crf",
+            "23",
+            "-pix_fmt",
+            "yuv420p",
+        ])
+        .args(["-c:a", "aac"]);
+    } else {
+        cmd.args(["-c", "copy"]);
+    }
+    let status = cmd
+        .args(["-movflags", "+faststart"])
+        .arg(&trimmed)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(log_path())
+                .ok()?,
+        )
+        .status();
+    match status {
+        Ok(status) if status.success() && file_size(&trimmed) > 0 => {
+            let after = probe_duration(&trimmed).unwrap_or(length);
+            write_log(&format!(
+                "Trimmed {} ({:.1}s -> {:.1}s)",
+                trimmed.display(),
+                before,
+                after
+            ));
+            Some(TrimmedRecording {
+                output: trimmed,
+                before,
+                after,
+            })
+        }
+        _ => {
+            write_log(&format!("Could not trim {}", out.display()));
+            remove_temp(&trimmed);
+            None
+        }
+    }
+}
+
+/// Refuse a path the OS would reject before anything is created for it or
+/// ffmpeg is spawned to fail on it obscurely.
+fn check_path_length(path: &Path) -> anyhow::Result<()> {
+    let Some(err) = path_length_error(path) else {
+        return Ok(());
+    };
+    write_log(&format!("Path too long ({}): {}", err, path.display()));
+    Err(Rejection::new(ErrorCode::PathTooLong)
+        .detail(format!("Path too long: {}", err))
+        .detail(format!("Path: {}", path.display()))
+        .detail("Use a shorter --output-dir or --filename-format")
+        .into())
+}
+
+/// Log the space left for `dir` and enforce `--min-free-space`.
+fn check_free_space(dir: &Path, min_mb: Option<u64>) -> anyhow::Result<()> {
+    let Some(free) = free_space(dir) else {
+        write_log(&format!(
+            "Could not determine free space for {}",
+            dir.display()
+        ));
+        return Ok(());
+    };
+    let free_mb = free / (1024 * 1024);
+    write_log(&format!("Free space on {}: {} MB", dir.display(), free_mb));
+    match min_mb {
+        Some(min) if free_mb < min => Err(Rejection::new(ErrorCode::LowDisk)
+            .detail(format!(
+                "Only {} MB free in {} (need {} MB)",
+                free_mb,
+                dir.display(),
+                min
+            ))
+            .into()),
+        _ => Ok(()),
+    }
+}
+
+fn check_battery(min_percent: Option<u8>) -> anyhow::Result<()> {
+    let Some(battery) = power::battery() else {
+        return Ok(());
+    };
+    let source = if battery.on_ac {
+        "AC power"
+    } else {
+        "battery power"
+    };
+    write_log(&format!("Battery: {}% on {}", battery.percent, source));
+    match min_percent {
+        Some(min) if !battery.on_ac && battery.percent < min => {
+            Err(Rejection::new(ErrorCode::LowBattery)
+                .detail(format!(
+                    "Battery at {}% and not charging (need {}%)",
+                    battery.percent, min
+                ))
+                .detail("Plug in the charger, or lower --refuse-if-battery-below")
+                .into())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn read_output_path() -> PathBuf {
+    Session::current()
+        .and_then(|s| s.output)
+        .unwrap_or_default()
+}
+
+/// Remove the missing or zero-length output `stop` gave up on, along with
+/// `OUT_FILE`. `out` is the stored path, so only this recording's file is
+/// ever touched.
+fn discard_unfinalized(out: &Path) {
+    if out.as_os_str().is_empty() {
+        return;
+    }
+    match out.symlink_metadata() {
+        Ok(meta) if meta.is_file() && meta.len() == 0 => match fs::remove_file(out) {
+            Ok(()) => write_log(&format!(
+                "--delete-on-fail: removed empty {}",
+                out.display()
+            )),
+            Err(err) => write_log(&format!(
+                "--delete-on-fail: could not remove {}: {}",
+                out.display(),
+                err
+            )),
+        },
+        Ok(_) => {
+            write_log(&format!(
+                "--delete-on-fail: kept non-empty {}",
+                out.display()
+            ));
+            return;
+        }
+        Err(_) => write_log(&format!(
+            "--delete-on-fail: {} was never written",
+            out.display()
+        )),
+    }
+    let _ = fs::remove_file(&*OUT_FILE);
+}
+
+/// Complete the pending `--stats` record for `out` and write its sidecar.
+fn finish_stats(out: &Path, summary: Option<FfmpegSummary>) -> Option<RecordingStats> {
+    let mut record = RecordingStats::load(Path::new(&*STATS_FILE))?;
+    let _ = fs::remove_file(&*STATS_FILE);
+    if record.output != out {
+        return None;
+    }
+
+    let ended_at = Local::now();
+    record.duration_secs = probe_duration(out)
+        .or_else(|| Some((ended_at - record.started_at).num_milliseconds() as f64 / 1000.0));
+    record.ended_at = Some(ended_at);
+    record.resolution = probe_output_size(out);
+    record.achieved_framerate = probe_framerate(out);
+    match record.achieved_framerate {
+        Some(fps) if record.vfr => write_log(&format!("Frame rate: variable, {:.2} average", fps)),
+        Some(fps) => write_log(&format!(
+            "Frame rate: {} requested, {:.2} achieved",
+            record.framerate, fps
+        )),
+        None => {}
+    }
+    record.size_bytes = Some(file_size(out));
+    record.ffmpeg_version = tool_version("ffmpeg");
+    record.ffmpeg_summary = summary;
+
+    let sidecar = record.sidecar_path();
+    match record.save(&sidecar) {
+        Ok(()) => {
+            write_log(&format!("Stats written: {}", sidecar.display()));
+            Some(record)
+        }
+        Err(err) => {
+            write_log(&format!(
+                "Failed to write stats {}: {}",
+                sidecar.display(),
+                err
+            ));
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Start {
+        #[command(flatten)]
+        options: StartOptions,
+    }
+
+    /// `rec-cli start` with `flags`.
+    fn start(flags: &[&str]) -> StartOptions {
+        let argv = ["start"].iter().chain(flags);
+        Start::try_parse_from(argv).unwrap().options
+    }
+
+    /// A microphone picked by name, for --audio-only
+    const MIC: &[&str] = &["--backend", "avfoundation", "--audio-name", "Mic"];
+
+    #[test]
+    fn validate_options_rejects_each_conflict() {
+        let rejected: &[(&[&str], &str)] = &[
+            (&["--x", "0", "--y", "0"], "missing --width, --height"),
+            (
+                &["--duration", "5", "--fade-in", "6"],
+                "--fade-in (6s) is longer",
+            ),
+            (
+                &["--duration", "5", "--fade-out", "6"],
+                "--fade-out (6s) is longer",
+            ),
+            (
+                &["--framerate", "30", "--output-fps", "60"],
+                "above --framerate",
+            ),
+            (&["--codec", VIDEOTOOLBOX, "--crf", "20"], "takes --bitrate"),
+            (
+                &["--codec", VIDEOTOOLBOX, "--preset", "fast"],
+                "takes --bitrate",
+            ),
+            (
+                &["--codec", VIDEOTOOLBOX, "--encode-in-background-after-stop"],
+                "compresses with libx264",
+            ),
+            (
+                &["--codec", VIDEOTOOLBOX, "--h264-profile", "high10"],
+                "not high10",
+            ),
+            (&["--bitrate", "4000"], "libx264 takes --crf"),
+            (
+                &["--format", "webm", "--preset", "fast"],
+                "--preset picks the H.264 encoder",
+            ),
+            (&["--pipewire-node", "42"], "is for --backend pipewire"),
+            (
+                &["--backend", "x11grab", "--screen-index", "1"],
+                "--screen-index needs",
+            ),
+            (
+                &["--backend", "x11grab", "--audio-name", "Mic"],
+                "--audio-name needs",
+            ),
+            (
+                &["--backend", "gdigrab", "--all-screens"],
+                "--all-screens needs",
+            ),
+            (
+                &[MIC, &["--audio-only", "--format", "mp4"]].concat(),
+                "records m4a or wav",
+            ),
+            (&["--format", "wav"], "has no video; add --audio-only"),
+            (&["--format", "gif", "--preview"], "--preview does not work"),
+            (
+                &["--format", "gif", "--segment-time", "60"],
+                "--segment-time does not work",
+            ),
+            (
+                &["--format", "gif", "--title-card", "Hi"],
+                "--title-card does not work",
+            ),
+            (
+                &["--also-output", "/r/copy.gif"],
+                "cannot hold the --format mp4 encode",
+            ),
+            (
+                &["--format", "mkv", "--fragmented"],
+                "--fragmented is for mp4 and m4a",
+            ),
+            (
+                &["--stdout", "--format", "mp4"],
+                "cannot be written to stdout",
+            ),
+            (&["--output-file", "/r/take.mkv"], "is not a .mp4 file"),
+        ];
+        for (flags, reason) in rejected {
+            let err = validate_options(&start(flags)).expect_err(&flags.join(" "));
+            assert!(err.to_string().contains(reason), "{:?}: {}", flags, err);
+        }
+    }
+
+    /// `check_geometry` of a crop at (`x`, `y`) sized `w` x `h`, with `flags`.
+    fn geometry(flags: &[&str], (x, y, w, h): (i32, i32, i32, i32)) -> Result<(), String> {
+        check_geometry(&start(flags), (x, y, w, h)).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn crops_inside_the_frame_pass() {
+        let size = ["--video-size", "1920x1080"];
+        assert_eq!(geometry(&size, (0, 0, 1920, 1080)), Ok(()));
+        assert_eq!(geometry(&size, (1280, 720, 640, 360)), Ok(()));
+        // Without --video-size the screen clamps it later
+        assert_eq!(geometry(&[], (5000, 5000, 640, 360)), Ok(()));
+        assert_eq!(
+            geometry(&["--scale", "1280x-1"], (0, 0, 1920, 1080)),
+            Ok(())
+        );
+        assert_eq!(geometry(&["--scale", "640x360"], (0, 0, 1280, 720)), Ok(()));
+    }
+
+    #[test]
+    fn crops_past_the_frame_are_refused() {
+        let size = ["--video-size", "1920x1080"];
+        let err = geometry(&size, (1600, 0, 640, 360)).unwrap_err();
+        assert!(err.contains("reaches past the 1920x1080 frame"), "{}", err);
+        assert!(err.contains("cut to 320x360"), "{}", err);
+        let scaled = ["--scale", "1280x720", "--filter-order", "scale-first"];
+        let err = geometry(&scaled, (0, 600, 1280, 360)).unwrap_err();
+        assert!(err.contains("frame of --scale"), "{}", err);
+        let err = geometry(&["--scale", "1920x1080"], (0, 0, 1000, 1000)).unwrap_err();
+        assert!(err.contains("out of shape"), "{}", err);
+        assert_eq!(
+            geometry(
+                &["--scale", "1920x1080", "--aspect", "16:9"],
+                (0, 0, 1000, 1000)
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn crops_without_area_are_refused() {
+        for rect in [(0, 0, 0, 1080), (0, 0, 1920, 0), (0, 0, -640, 360)] {
+            let err = check_geometry(&start(&["--scale", "1280x720"]), rect).unwrap_err();
+            let rejection = Rejection::from(err);
+            assert_eq!(rejection.code, ErrorCode::CropTooSmall, "{:?}", rect);
+            assert!(
+                rejection.details[0].contains("has no area"),
+                "{:?}",
+                rejection.details
+            );
+        }
+    }
+
+    #[test]
+    fn validate_options_accepts_compatible_flags() {
+        let accepted: &[&[&str]] = &[
+            &[],
+            &["--x", "0", "--y", "0", "--width", "640", "--height", "480"],
+            &["--duration", "5", "--fade-in", "1", "--fade-out", "1"],
+            &["--codec", VIDEOTOOLBOX, "--bitrate", "8000"],
+            &["--backend", "pipewire", "--pipewire-node", "42"],
+            &[MIC, &["--audio-only"]].concat(),
+            &[MIC, &["--audio-only", "--format", "wav"]].concat(),
+            &["--also-output", "/r/copy.mkv"],
+            &["--fragmented"],
+            &["--stdout"],
+        ];
+        for flags in accepted {
+            if let Err(err) = validate_options(&start(flags)) {
+                panic!("{:?}: {}", flags, err);
+            }
+        }
+    }
+
+    #[test]
+    fn output_file_extension_has_to_match_format() {
+        let valid = |flags: &[&str]| validate_options(&start(flags)).is_ok();
+        assert!(valid(&["--output-file", "/r/take.mp4"]));
+        assert!(valid(&["--output-file", "/r/take.MKV", "--format", "mkv"]));
+        assert!(!valid(&["--output-file", "/r/take.mp4", "--format", "mkv"]));
+        assert!(!valid(&["--output-file", "/r/take", "--format", "mkv"]));
+
+        let inferred = ["--output-file", "/r/take.mp4", "--format", "mkv"];
+        let inferred = start(&[&inferred[..], &["--infer-format-from-extension"]].concat());
+        assert!(validate_options(&inferred).is_ok());
+        assert_eq!(inferred.format(), OutputFormat::Mp4);
+        let unknown = start(&[
+            "--output-file",
+            "/r/take.avi",
+            "--infer-format-from-extension",
+        ]);
+        assert!(validate_options(&unknown).is_err());
+    }
+
+    #[test]
+    fn output_file_is_the_output_root_parent() {
+        let options = start(&["--output-file", "/r/talks/take.mp4"]);
+        assert_eq!(options.output_root(), Path::new("/r/talks"));
+        assert!(Start::try_parse_from(["start", "--output-file", "/r/"]).is_err());
+        assert!(Start::try_parse_from(["start", "--infer-format-from-extension"]).is_err());
+    }
+
+    #[test]
+    fn concurrency_limit_is_at_least_one() {
+        assert_eq!(start(&[]).concurrency_limit, DEFAULT_CONCURRENCY_LIMIT);
+        assert_eq!(start(&["--concurrency-limit", "1"]).concurrency_limit, 1);
+        assert!(Start::try_parse_from(["start", "--concurrency-limit", "0"]).is_err());
+    }
+}
@@ -0,0 +1,109 @@
+//! `start --resume`: carry on with a recording that was interrupted.
+//!
+//! `start` remuxes the partial file a dead ffmpeg left behind (as it always
+//! does) and records a new part as usual. `stop` then joins the recovered
+//! part and the new one with the concat demuxer, into the new recording.
+//!
+//! Seams: the join is a stream copy, so both parts must be encoded alike,
+//! in the same container with the same frame size and audio layout; when
+//! they are not, the parts are kept apart. Even then the seam is a hard cut.
+//! Whatever happened between the crash and the `--resume` is missing, the
+//! recovered part ends at its last complete packet (which can be a second
+//! or so before the crash), and some players hitch for a frame at the join.
+
+use crate::ffmpeg::{faststart_args, probe_audio, probe_duration, probe_output_size};
+use crate::title::{concat_quote, ffmpeg_timeout, run_ffmpeg};
+use crate::{remove_temp, write_log};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What `--resume` did at `stop`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resumed {
+    /// The recovered part now opens the recording; the new part starts at
+    /// `seam` seconds
+    Joined { seam: f64 },
+    /// The parts were kept apart, and why; `recovered` is still on disk
+    Skipped { recovered: PathBuf, reason: String },
+}
+
+/// Join `recovered` onto the front of `out`, in place, muxed with the
+/// recording's `movflags`. On failure `out` is left as recorded.
+pub fn join(recovered: &Path, out: &Path, movflags: Option<&str>) -> Resumed {
+    match concat(recovered, out, movflags) {
+        Ok(seam) => {
+            write_log(&format!(
+                "--resume: joined {} ({:.2}s) and {} at the seam",
+                recovered.display(),
+                seam,
+                out.display()
+            ));
+            remove_temp(recovered);
+            Resumed::Joined { seam }
+        }
+        Err(reason) => {
+            write_log(&format!(
+                "--resume: not joined ({}); {} kept",
+                reason,
+                recovered.display()
+            ));
+            Resumed::Skipped {
+                recovered: recovered.to_path_buf(),
+                reason,
+            }
+        }
+    }
+}
+
+fn concat(recovered: &Path, out: &Path, movflags: Option<&str>) -> Result<f64, String> {
+    if !recovered.exists() {
+        return Err(format!("{} is gone", recovered.display()));
+    }
+    let ext = |p: &Path| p.extension().map(|e| e.to_string_lossy().to_lowercase());
+    if ext(recovered) != ext(out) {
+        return Err("the parts are in different containers".into());
+    }
+    if probe_output_size(recovered) != probe_output_size(out) {
+        return Err("the parts have different frame sizes".into());
+    }
+    if probe_audio(recovered) != probe_audio(out) {
+        return Err("the parts have different audio layouts".into());
+    }
+    let seam = probe_duration(recovered).ok_or("cannot read the recovered part's duration")?;
+
+    let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+    let list = out.with_file_name(format!(".{}.resume.txt", stem));
+    let joined = out.with_file_name(format!(
+        ".{}.resumed.{}",
+        stem,
+        ext(out).unwrap_or_else(|| "mp4".into())
+    ));
+    write_log(&format!(
+        "--resume: concat list {}, joined {}",
+        list.display(),
+        joined.display()
+    ));
+    let entries = format!(
+        "file {}\nfile {}\n",
+        concat_quote(recovered),
+        concat_quote(out)
+    );
+    let result = fs::write(&list, entries)
+        .map_err(|e| format!("cannot write concat list: {}", e))
+        .and_then(|()| {
+            let mut args: Vec<String> = ["-f", "concat", "-safe", "0", "-i"]
+                .map(String::from)
+                .into();
+            args.push(list.to_string_lossy().into_owned());
+            args.extend(["-c", "copy"].map(String::from));
+            args.extend(faststart_args(out, movflags).into_iter().map(String::from));
+            args.push(joined.to_string_lossy().into_owned());
+            run_ffmpeg(&args, ffmpeg_timeout(probe_duration(out).map(|d| d + seam)))
+        })
+        .and_then(|()| {
+            fs::rename(&joined, out).map_err(|e| format!("cannot replace the recording: {}", e))
+        });
+    remove_temp(&list);
+    remove_temp(&joined);
+    result.map(|()| seam)
+}
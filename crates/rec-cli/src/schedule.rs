@@ -0,0 +1,108 @@
+//! `start --delay`/`--start-at`: a recording that begins later.
+//!
+//! The `start` process itself does the waiting. `SCHEDULE_FILE` names it and
+//! the time it will start at, so `status` can report the recording as
+//! scheduled and `stop` can cancel it by ending the wait. `PID_FILE` only
+//! appears once ffmpeg runs.
+
+use crate::process::{pid_alive, signal_pid};
+use crate::{write_log, SCHEDULE_FILE};
+use chrono::{DateTime, Local, NaiveTime, TimeDelta};
+use nix::sys::signal::Signal;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+/// Seconds between countdown lines in the log (and every one of the last 5)
+const COUNTDOWN_EVERY: i64 = 10;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Schedule {
+    /// The waiting `start`
+    pub pid: i32,
+    pub at: DateTime<Local>,
+}
+
+impl Schedule {
+    pub fn save(&self) -> anyhow::Result<()> {
+        fs::write(&*SCHEDULE_FILE, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// The pending schedule, while its `start` is still waiting.
+    pub fn current() -> Option<Self> {
+        let schedule: Self =
+            serde_json::from_str(&fs::read_to_string(&*SCHEDULE_FILE).ok()?).ok()?;
+        pid_alive(schedule.pid).then_some(schedule)
+    }
+}
+
+pub fn clear() {
+    let _ = fs::remove_file(&*SCHEDULE_FILE);
+}
+
+/// Parse a `--start-at` time of day, `HH:MM` or `HH:MM:SS`.
+pub fn parse_start_at(s: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(s.trim(), "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(s.trim(), "%H:%M"))
+        .map_err(|_| format!("expected a time of day as HH:MM or HH:MM:SS, got '{}'", s))
+}
+
+/// The next `time`: today, or tomorrow once today's has passed.
+pub fn next_at(time: NaiveTime) -> DateTime<Local> {
+    let now = Local::now();
+    let mut day = now.date_naive();
+    loop {
+        // A time skipped by a DST change falls to the next day that has it
+        if let Some(at) = day.and_time(time).and_local_timezone(Local).earliest() {
+            if at > now {
+                return at;
+            }
+        }
+        day = day.succ_opt().unwrap_or(day);
+    }
+}
+
+/// Block until `at`, logging a countdown, then log when the wait ended.
+pub fn wait_until(at: DateTime<Local>) {
+    write_log(&format!(
+        "Scheduled start at {}",
+        at.format("%Y-%m-%d %H:%M:%S")
+    ));
+    let mut logged = None;
+    loop {
+        let left = at - Local::now();
+        if left <= TimeDelta::zero() {
+            break;
+        }
+        let secs = left.num_seconds();
+        if secs > 0 && logged != Some(secs) && (secs % COUNTDOWN_EVERY == 0 || secs <= 5) {
+            write_log(&format!("Scheduled start in {}s", secs));
+            logged = Some(secs);
+        }
+        thread::sleep(
+            left.to_std()
+                .unwrap_or_default()
+                .min(Duration::from_secs(1)),
+        );
+    }
+    write_log(&format!(
+        "Scheduled for {}; starting at {}",
+        at.format("%H:%M:%S%.3f"),
+        Local::now().format("%H:%M:%S%.3f")
+    ));
+}
+
+/// End a waiting `start`. The schedule that was canceled, if any.
+pub fn cancel() -> Option<Schedule> {
+    let schedule = Schedule::current()?;
+    let _ = signal_pid(schedule.pid, Signal::SIGTERM);
+    clear();
+    write_log(&format!(
+        "Canceled the recording scheduled for {} (pid {})",
+        schedule.at.format("%Y-%m-%d %H:%M:%S"),
+        schedule.pid
+    ));
+    Some(schedule)
+}
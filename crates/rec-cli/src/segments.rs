@@ -0,0 +1,132 @@
+//! `--segment-time`: one recording split across numbered files by ffmpeg's
+//! segment muxer, plus a manifest giving each segment's wall-clock start.
+//!
+//! For a recording named `rec_<ts>.mp4` ffmpeg writes `rec_<ts>_000.mp4`,
+//! `rec_<ts>_001.mp4`, ... and a CSV segment list (`file,start,end` with
+//! offsets in seconds). On stop the list becomes `rec_<ts>.segments.json`.
+
+use crate::{write_log, SEGMENTS_FILE};
+use chrono::{DateTime, Duration as ChronoDuration, Local};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+fn with_suffix(out: &Path, suffix: &str) -> PathBuf {
+    let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+    out.with_file_name(format!("{}{}", stem, suffix))
+}
+
+/// The `%03d` filename pattern handed to the segment muxer.
+pub fn pattern(out: &Path) -> PathBuf {
+    let ext = out.extension().unwrap_or_default().to_string_lossy();
+    with_suffix(out, &format!("_%03d.{}", ext))
+}
+
+/// ffmpeg's running CSV segment list.
+pub fn list_path(out: &Path) -> PathBuf {
+    with_suffix(out, ".segments.csv")
+}
+
+pub fn manifest_path(out: &Path) -> PathBuf {
+    with_suffix(out, ".segments.json")
+}
+
+#[derive(Debug, Serialize)]
+pub struct Segment {
+    pub index: usize,
+    pub file: PathBuf,
+    /// Offsets into the recording, in seconds
+    pub start: f64,
+    pub end: f64,
+    /// Wall-clock time the segment's first frame was captured
+    pub started_at: DateTime<Local>,
+}
+
+#[derive(Debug, Serialize)]
+struct Manifest<'a> {
+    recording: &'a Path,
+    started_at: DateTime<Local>,
+    segments: &'a [Segment],
+}
+
+/// Remember when a segmented recording started, for `finish`.
+pub fn begin(started_at: DateTime<Local>) -> std::io::Result<()> {
+    fs::write(&*SEGMENTS_FILE, started_at.to_rfc3339())
+}
+
+/// Whether the running recording is segmented.
+pub fn is_pending() -> bool {
+    Path::new(&*SEGMENTS_FILE).exists()
+}
+
+/// Forget any previous segmented recording.
+pub fn clear() {
+    let _ = fs::remove_file(&*SEGMENTS_FILE);
+}
+
+fn parse_list(out: &Path, list: &str, started_at: DateTime<Local>) -> Vec<Segment> {
+    let dir = out.parent().unwrap_or(Path::new("."));
+    list.lines()
+        .filter_map(|line| {
+            // File names may contain commas; the offsets are the last fields
+            let mut fields = line.trim().rsplitn(3, ',');
+            let end: f64 = fields.next()?.parse().ok()?;
+            let start: f64 = fields.next()?.parse().ok()?;
+            let file = fields.next()?.trim_matches('"');
+            Some((dir.join(file), start, end))
+        })
+        .enumerate()
+        .map(|(index, (file, start, end))| Segment {
+            index,
+            file,
+            start,
+            end,
+            started_at: started_at + ChronoDuration::milliseconds((start * 1000.0) as i64),
+        })
+        .collect()
+}
+
+/// Wait for ffmpeg's final segment list and write the manifest. Returns the
+/// segments and the manifest path; `None` if no segment was finalized.
+pub fn finish(out: &Path) -> Option<(Vec<Segment>, PathBuf)> {
+    let started_at = fs::read_to_string(&*SEGMENTS_FILE)
+        .ok()
+        .and_then(|t| DateTime::parse_from_rfc3339(t.trim()).ok())
+        .map(|t| t.with_timezone(&Local))
+        .unwrap_or_else(Local::now);
+    clear();
+
+    let list = list_path(out);
+    let mut segments = Vec::new();
+    for _ in 0..30 {
+        segments = fs::read_to_string(&list)
+            .map(|csv| parse_list(out, &csv, started_at))
+            .unwrap_or_default();
+        if !segments.is_empty() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    if segments.is_empty() {
+        write_log(&format!("No finalized segments in {}", list.display()));
+        return None;
+    }
+
+    let manifest = manifest_path(out);
+    let body = Manifest {
+        recording: out,
+        started_at,
+        segments: &segments,
+    };
+    match serde_json::to_vec_pretty(&body).map(|json| fs::write(&manifest, json)) {
+        Ok(Ok(())) => {
+            write_log(&format!("Segment manifest written: {}", manifest.display()));
+            let _ = fs::remove_file(&list);
+        }
+        Ok(Err(err)) => write_log(&format!("Failed to write {}: {}", manifest.display(), err)),
+        Err(err) => write_log(&format!("Failed to encode segment manifest: {}", err)),
+    }
+    Some((segments, manifest))
+}
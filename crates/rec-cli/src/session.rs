@@ -0,0 +1,219 @@
+//! What `start` resolved for the running recording, so `stop` and `status`
+//! report exactly that instead of working it out again.
+//!
+//! Kept in `STATE_FILE` as versioned JSON. A file from a newer rec-cli, or
+//! one that does not parse, is logged and ignored; `PID_FILE` and `OUT_FILE`
+//! are still written alongside for the Neovim plugin, which reads them, and
+//! rebuild a session when there is nothing better.
+
+use crate::devices::Size;
+use crate::ffmpeg::FASTSTART;
+use crate::output::{read_path_file, OUTPUT_TS_FORMAT};
+use crate::process::read_pid;
+use crate::{write_log, OUT_FILE, PID_FILE, SESSION_FILE, STATE_FILE};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Once;
+
+/// `version` of the `STATE_FILE` this rec-cli writes. Bump it when a field
+/// changes meaning; new fields with a `serde(default)` need no bump.
+pub const STATE_VERSION: u32 = 1;
+
+/// Where the recording is in its life.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionStatus {
+    #[default]
+    Recording,
+    /// `stop` has begun finalizing
+    Stopping,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    /// `STATE_VERSION` when written; 0 for a pre-versioning `SESSION_FILE`
+    #[serde(default)]
+    pub version: u32,
+    pub pid: i32,
+    /// The recording, `None` when it goes to stdout
+    pub output: Option<PathBuf>,
+    /// Directory holding `output`, date subfolder included
+    pub output_dir: Option<PathBuf>,
+    /// `--output-dir` as given (or the default), above any date subfolder
+    #[serde(default)]
+    pub output_root: Option<PathBuf>,
+    /// `--filename-format` the output was named with
+    #[serde(default = "default_filename_format")]
+    pub filename_format: String,
+    pub started_at: DateTime<Local>,
+    #[serde(default)]
+    pub status: SessionStatus,
+    /// The ffmpeg command line `start` resolved its options to
+    #[serde(default)]
+    pub ffmpeg_args: Vec<String>,
+    /// `--encode-in-background-after-stop`: the lossless file ffmpeg is
+    /// writing, which `stop` compresses into `output` with `encode_args`
+    #[serde(default)]
+    pub capture: Option<PathBuf>,
+    #[serde(default)]
+    pub encode_args: Vec<String>,
+    /// `--split-audio-track` WAV written beside `output`
+    #[serde(default)]
+    pub audio_track: Option<PathBuf>,
+    /// `--also-output` files tee writes alongside `output`
+    #[serde(default)]
+    pub also_outputs: Vec<PathBuf>,
+    /// `--resume`: the recovered part of the interrupted recording, joined
+    /// in front of `output` at `stop`
+    #[serde(default)]
+    pub resume_from: Option<PathBuf>,
+    /// `-movflags` the recording is muxed with, kept by every remux `stop`
+    /// does to it; `+faststart` for a session that did not record them
+    #[serde(default = "default_movflags")]
+    pub movflags: Option<String>,
+    /// `--replace-last`: the previous take, in another format, that `stop`
+    /// deletes once `output` is saved
+    #[serde(default)]
+    pub replaces: Option<PathBuf>,
+    /// What the recording was set up as, for `status --json`
+    #[serde(default)]
+    pub settings: Option<Settings>,
+}
+
+/// The options a recording resolved to, so a statusline can show them
+/// without remembering what `start` was passed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Settings {
+    /// Frame size written, after crop and --scale (before rotation and
+    /// padding); `None` when `start` had no reason to probe the screen
+    pub resolution: Option<Size>,
+    /// Output frame rate; `None` for audio only
+    pub framerate: Option<u32>,
+    /// libx264 CRF of the file `stop` leaves; `None` when not H.264
+    pub crf: Option<u32>,
+    /// Crop rectangle on the captured frame; `None` for the full screen
+    pub crop: Option<Crop>,
+    /// Container, as its extension
+    pub format: String,
+    /// `--audio-name` device; `None` records silence (or no audio)
+    pub audio: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Crop {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Session {
+    pub fn new(pid: i32, output: Option<PathBuf>) -> Self {
+        Session {
+            version: STATE_VERSION,
+            pid,
+            output_dir: output.as_ref().and_then(|o| o.parent()).map(PathBuf::from),
+            output,
+            output_root: None,
+            filename_format: default_filename_format(),
+            started_at: Local::now(),
+            status: SessionStatus::Recording,
+            ffmpeg_args: Vec::new(),
+            capture: None,
+            encode_args: Vec::new(),
+            audio_track: None,
+            also_outputs: Vec::new(),
+            resume_from: None,
+            movflags: default_movflags(),
+            replaces: None,
+            settings: None,
+        }
+    }
+
+    /// Write `STATE_FILE` whole: a reader sees the old state or the new one,
+    /// never half of either.
+    pub fn save(&self) -> anyhow::Result<()> {
+        let state = Session {
+            version: STATE_VERSION,
+            ..self.clone()
+        };
+        let partial = format!("{}.partial", *STATE_FILE);
+        fs::write(&partial, serde_json::to_string_pretty(&state)?)?;
+        fs::rename(&partial, &*STATE_FILE)?;
+        Ok(())
+    }
+
+    /// `STATE_FILE`, else the `SESSION_FILE` of an older rec-cli.
+    pub fn load() -> Option<Self> {
+        let (file, text) = match fs::read_to_string(&*STATE_FILE) {
+            Ok(text) => (&*STATE_FILE, text),
+            Err(_) => (&*SESSION_FILE, fs::read_to_string(&*SESSION_FILE).ok()?),
+        };
+        let state: serde_json::Value = match serde_json::from_str(&text) {
+            Ok(state) => state,
+            Err(err) => {
+                ignore(&format!("Ignoring unreadable {} ({})", file, err));
+                return None;
+            }
+        };
+        let version = state["version"].as_u64().unwrap_or(0);
+        if version > STATE_VERSION as u64 {
+            ignore(&format!(
+                "Ignoring {}: version {} is newer than this rec-cli's {}",
+                file, version, STATE_VERSION
+            ));
+            return None;
+        }
+        match serde_json::from_value(state) {
+            Ok(session) => Some(session),
+            Err(err) => {
+                ignore(&format!("Ignoring unreadable {} ({})", file, err));
+                None
+            }
+        }
+    }
+
+    /// The running recording's session. One started by a rec-cli that kept
+    /// no session, or whose state is unreadable, is rebuilt from `OUT_FILE`
+    /// and `PID_FILE`.
+    pub fn current() -> Option<Self> {
+        if let Some(session) = Self::load().filter(|s| Some(s.pid) == read_pid()) {
+            return Some(session);
+        }
+        let pid = read_pid()?;
+        let started_at = fs::metadata(&*PID_FILE).and_then(|m| m.modified()).ok()?;
+        Some(Session {
+            started_at: started_at.into(),
+            ..Session::new(pid, read_path_file(&*OUT_FILE))
+        })
+    }
+
+    /// Whether `output_dir` has been removed since `start`.
+    pub fn output_dir_gone(&self) -> bool {
+        self.output_dir
+            .as_ref()
+            .is_some_and(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+    }
+}
+
+/// Log why a state was ignored, once per process: `status --watch` and the
+/// supervisor load it every second.
+fn ignore(msg: &str) {
+    static LOGGED: Once = Once::new();
+    LOGGED.call_once(|| write_log(msg));
+}
+
+fn default_filename_format() -> String {
+    OUTPUT_TS_FORMAT.to_string()
+}
+
+fn default_movflags() -> Option<String> {
+    Some(FASTSTART.to_string())
+}
+
+pub fn clear() {
+    let _ = fs::remove_file(&*STATE_FILE);
+    let _ = fs::remove_file(&*SESSION_FILE);
+}
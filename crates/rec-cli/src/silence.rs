@@ -0,0 +1,209 @@
+//! `stop --trim-silence`: a copy of the recording with its long silent
+//! gaps cut out, for voiceovers.
+//!
+//! `silencedetect` finds the gaps first; the copy then keeps everything
+//! else with `aselect` (and `select` on the video, so a narrated screen
+//! recording stays in sync). A short margin is left at each cut so speech
+//! is not clipped. Both streams are re-encoded.
+
+use crate::ffmpeg::{
+    faststart_args, probe_audio, probe_duration, probe_output_size, CRF, FASTSTART,
+};
+use crate::output::file_size;
+use crate::{log_path, write_log};
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Level below which audio counts as silence, in dBFS
+pub const DEFAULT_THRESHOLD: f64 = -50.0;
+/// Shortest gap that is cut, in seconds
+pub const DEFAULT_DURATION: f64 = 2.0;
+/// Silence kept at each side of a cut, in seconds
+const MARGIN: f64 = 0.25;
+
+/// What `--trim-silence` did.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SilenceTrimmed {
+    /// `gaps` silent stretches were cut into `output`; durations in seconds
+    Done {
+        output: PathBuf,
+        before: f64,
+        after: f64,
+        gaps: usize,
+    },
+    /// Nothing cut, and why
+    Skipped(String),
+}
+
+/// `(start, end)` of each silence silencedetect reported on stderr. A
+/// silence still running at the end has no `silence_end`; it ends at
+/// `duration`.
+fn parse_silences(stderr: &str, duration: f64) -> Vec<(f64, f64)> {
+    let value = |line: &str, key: &str| -> Option<f64> {
+        let rest = &line[line.find(key)? + key.len()..];
+        rest.split_whitespace().next()?.parse().ok()
+    };
+    let mut gaps = Vec::new();
+    let mut start = None;
+    for line in stderr.lines() {
+        if let Some(at) = value(line, "silence_start: ") {
+            start = Some(at.max(0.0));
+        } else if let (Some(from), Some(to)) = (start, value(line, "silence_end: ")) {
+            gaps.push((from, to));
+            start = None;
+        }
+    }
+    if let Some(from) = start {
+        gaps.push((from, duration));
+    }
+    gaps
+}
+
+fn detect(
+    out: &Path,
+    threshold: f64,
+    min_secs: f64,
+    duration: f64,
+) -> Result<Vec<(f64, f64)>, String> {
+    let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-nostats", "-i"])
+        .arg(out)
+        .args(["-vn", "-af"])
+        .arg(format!(
+            "silencedetect=noise={}dB:d={}",
+            threshold, min_secs
+        ))
+        .args(["-f", "null", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .output()
+        .map_err(|e| format!("cannot run ffmpeg: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("ffmpeg exited with {}", output.status));
+    }
+    Ok(parse_silences(
+        &String::from_utf8_lossy(&output.stderr),
+        duration,
+    ))
+}
+
+/// Cut the silences of `out` longer than `min_secs` below `threshold` dB
+/// into `<stem>_nosilence.<ext>`.
+pub fn trim(out: &Path, threshold: f64, min_secs: f64) -> SilenceTrimmed {
+    if probe_audio(out).is_none() {
+        write_log("--trim-silence: no audio track; skipped");
+        return SilenceTrimmed::Skipped("the recording has no audio track".into());
+    }
+    let Some(before) = probe_duration(out) else {
+        return SilenceTrimmed::Skipped("ffprobe cannot read the duration".into());
+    };
+    let silences = match detect(out, threshold, min_secs, before) {
+        Ok(silences) => silences,
+        Err(err) => {
+            write_log(&format!("--trim-silence: detection failed: {}", err));
+            return SilenceTrimmed::Skipped(format!("could not detect silence ({})", err));
+        }
+    };
+    // anullsrc, the track of a recording without --audio-name, is one gap
+    let silent: f64 = silences.iter().map(|(from, to)| to - from).sum();
+    if silent >= before - MARGIN * 2.0 {
+        write_log("--trim-silence: the audio is silent throughout; skipped");
+        return SilenceTrimmed::Skipped("the audio track is silent (no --audio-name)".into());
+    }
+    let cuts: Vec<(f64, f64)> = silences
+        .iter()
+        .map(|(from, to)| {
+            let from = if *from <= 0.0 { 0.0 } else { from + MARGIN };
+            let to = if *to >= before { before } else { to - MARGIN };
+            (from, to)
+        })
+        .filter(|(from, to)| to > from)
+        .collect();
+    if cuts.is_empty() {
+        write_log(&format!(
+            "--trim-silence: no silence of {}s or more below {} dB",
+            min_secs, threshold
+        ));
+        return SilenceTrimmed::Skipped(format!("no silent gap of {}s or more", min_secs));
+    }
+    write_log(&format!(
+        "--trim-silence: cutting {} gaps ({:.1}s)",
+        cuts.len(),
+        silent
+    ));
+
+    let between: Vec<String> = cuts
+        .iter()
+        .map(|(from, to)| format!("between(t,{:.3},{:.3})", from, to))
+        .collect();
+    let keep = format!("'not({})'", between.join("+"));
+    let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = out
+        .extension()
+        .map(|e| e.to_string_lossy())
+        .unwrap_or("mp4".into());
+    let trimmed = out.with_file_name(format!("{}_nosilence.{}", stem, ext));
+    let Ok(log) = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path())
+    else {
+        return SilenceTrimmed::Skipped(format!("cannot open {}", log_path().display()));
+    };
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(["-hide_banner", "-loglevel", "error", "-y", "-i"])
+        .arg(out)
+        .args(["-af".into(), format!("aselect={},asetpts=N/SR/TB", keep)]);
+    if probe_output_size(out).is_some() {
+        cmd.args([
+            "-vf".into(),
+            format!("select={},setpts=N/FRAME_RATE/TB", keep),
+        ])
+        .args([
+            "-c:v",
+            "libx264",
+            "-preset",
+            "ultrafast",
+            "-pix_fmt",
+            "yuv420p",
+        ])
+        .args(["-crf".into(), CRF.to_string()]);
+    }
+    let codec = match ext.to_lowercase().as_str() {
+        "wav" => "pcm_s16le",
+        _ => "aac",
+    };
+    let status = cmd
+        .args(["-c:a", codec])
+        .args(faststart_args(out, Some(FASTSTART)))
+        .arg(&trimmed)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(log)
+        .status();
+    match status {
+        Ok(status) if status.success() && file_size(&trimmed) > 0 => {
+            let after = probe_duration(&trimmed).unwrap_or(before - silent);
+            write_log(&format!(
+                "Silence-trimmed copy written: {} ({:.1}s -> {:.1}s)",
+                trimmed.display(),
+                before,
+                after
+            ));
+            SilenceTrimmed::Done {
+                output: trimmed,
+                before,
+                after,
+                gaps: cuts.len(),
+            }
+        }
+        _ => {
+            write_log(&format!(
+                "--trim-silence: ffmpeg failed (see {})",
+                log_path().display()
+            ));
+            SilenceTrimmed::Skipped(format!("ffmpeg failed (see {})", log_path().display()))
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! `--stats` sidecar: a JSON record of how a recording was made.
+//!
+//! `start` saves the settings it used; `stop` fills in what only becomes known
+//! once ffmpeg has finished and writes `rec_<ts>.json` next to the output.
+
+use crate::summary::FfmpegSummary;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordingStats {
+    pub output: PathBuf,
+    pub started_at: DateTime<Local>,
+    pub ended_at: Option<DateTime<Local>>,
+    pub duration_secs: Option<f64>,
+    /// Final video size as reported by ffprobe
+    pub resolution: Option<(i32, i32)>,
+    /// Frame rate asked of the output (`--framerate`, `--output-fps` or
+    /// `--timelapse-fps`)
+    pub framerate: u32,
+    /// Average frame rate of the finished file, which falls short of
+    /// `framerate` when the machine could not keep up
+    #[serde(default)]
+    pub achieved_framerate: Option<f64>,
+    /// `--vfr`: unchanged frames were dropped, so `achieved_framerate` says
+    /// how much changed rather than whether the capture kept up
+    #[serde(default)]
+    pub vfr: bool,
+    pub crf: u32,
+    pub preset: String,
+    /// Video encoder (`--codec`); `crf` and `preset` are libx264's, and
+    /// h264_videotoolbox is rated by `bitrate_kbps` instead
+    #[serde(default = "default_codec")]
+    pub codec: String,
+    #[serde(default)]
+    pub bitrate_kbps: Option<u32>,
+    /// Crop rectangle (x, y, w, h) applied at capture time
+    pub crop: Option<(i32, i32, i32, i32)>,
+    /// Frame (w, h) the crop rectangle is measured in
+    #[serde(default)]
+    pub screen: Option<(i32, i32)>,
+    pub size_bytes: Option<u64>,
+    pub ffmpeg_version: Option<String>,
+    /// ffmpeg's own closing stats; `None` when it was killed before printing
+    /// them
+    #[serde(default)]
+    pub ffmpeg_summary: Option<FfmpegSummary>,
+}
+
+/// Sidecars from before `--codec` were all libx264.
+fn default_codec() -> String {
+    "libx264".into()
+}
+
+impl RecordingStats {
+    /// Where the finished sidecar lives: the output path with a `.json` extension.
+    pub fn sidecar_path(&self) -> PathBuf {
+        self.output.with_extension("json")
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Option<Self> {
+        serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+    }
+}
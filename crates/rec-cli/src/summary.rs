@@ -0,0 +1,104 @@
+//! ffmpeg's closing stats line, read back out of the log at `stop`.
+//!
+//! A clean exit ends with a line like
+//! `frame= 1800 fps= 30 q=-1.0 Lsize=   12345kB time=00:01:00.00
+//! bitrate=1685.5kbits/s speed=1.00x`. The `Lsize=` marks it as the final
+//! one; the progress lines before it say `size=`. An ffmpeg that was killed
+//! never prints it, and there is no summary.
+
+use crate::{log_path, write_log};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+/// How much of the end of the log to search
+const TAIL_BYTES: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FfmpegSummary {
+    /// Video frames encoded
+    pub frames: Option<u64>,
+    /// Output size as ffmpeg counted it
+    pub size_bytes: Option<u64>,
+    pub duration_secs: Option<f64>,
+    /// Average bitrate over the recording
+    pub bitrate_kbps: Option<f64>,
+    /// Encoding speed as a multiple of real time
+    pub speed: Option<f64>,
+}
+
+/// The summary of the last recording's ffmpeg, if it printed one.
+pub fn read() -> Option<FfmpegSummary> {
+    let mut log = File::open(log_path()).ok()?;
+    let len = log.metadata().ok()?.len();
+    log.seek(SeekFrom::Start(len.saturating_sub(TAIL_BYTES)))
+        .ok()?;
+    let mut tail = Vec::new();
+    log.read_to_end(&mut tail).ok()?;
+    let tail = String::from_utf8_lossy(&tail);
+    // Only this recording's ffmpeg, not one before it
+    let since_start = tail.rsplit("===== START =====").next().unwrap_or(&tail);
+    let summary = since_start
+        .rsplit(['\r', '\n'])
+        .find(|line| line.contains("Lsize="))
+        .map(parse);
+    match &summary {
+        Some(s) => write_log(&format!(
+            "ffmpeg summary: {} frames, {} bytes, {} kbit/s, {}x",
+            show(s.frames),
+            show(s.size_bytes),
+            show(s.bitrate_kbps),
+            show(s.speed)
+        )),
+        None => write_log("ffmpeg printed no summary line (killed before it finished?)"),
+    }
+    summary
+}
+
+/// Parse a `frame= .. Lsize= ..` line. Fields it lacks (no `frame=` for
+/// audio only, `N/A` while nothing was written) are `None`.
+pub fn parse(line: &str) -> FfmpegSummary {
+    FfmpegSummary {
+        frames: field(line, "frame=").and_then(|v| v.parse().ok()),
+        size_bytes: field(line, "Lsize=").and_then(parse_size),
+        duration_secs: field(line, "time=").and_then(parse_time),
+        bitrate_kbps: field(line, "bitrate=").and_then(|v| v.strip_suffix("kbits/s")?.parse().ok()),
+        speed: field(line, "speed=").and_then(|v| v.strip_suffix('x')?.parse().ok()),
+    }
+}
+
+/// The value after `key`, which ffmpeg pads with spaces to line up.
+fn field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let (_, rest) = line.split_once(key)?;
+    rest.split_whitespace().next()
+}
+
+/// `12345kB` (`KiB` in newer ffmpeg), `12MiB` and the like, in bytes.
+fn parse_size(v: &str) -> Option<u64> {
+    let digits = v
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(v.len());
+    let (n, unit) = v.split_at(digits);
+    let n: f64 = n.parse().ok()?;
+    let scale = match unit {
+        "B" | "" => 1.0,
+        "kB" | "KiB" => 1024.0,
+        "MB" | "MiB" => 1024.0 * 1024.0,
+        "GB" | "GiB" => 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    Some((n * scale) as u64)
+}
+
+/// `HH:MM:SS.cc` in seconds.
+fn parse_time(v: &str) -> Option<f64> {
+    let mut parts = v.splitn(3, ':');
+    let h: f64 = parts.next()?.parse().ok()?;
+    let m: f64 = parts.next()?.parse().ok()?;
+    let s: f64 = parts.next()?.parse().ok()?;
+    Some(h * 3600.0 + m * 60.0 + s)
+}
+
+fn show<T: ToString>(value: Option<T>) -> String {
+    value.map_or("?".to_string(), |v| v.to_string())
+}
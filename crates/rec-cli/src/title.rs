@@ -0,0 +1,227 @@
+//! `--title-card`: a generated intro (text on a solid background) joined onto
+//! the front of the recording once it stops.
+//!
+//! The intro is encoded with the recording's own size, frame rate, H.264
+//! profile/level and audio layout, so the concat demuxer can join the two
+//! with `-c copy` instead of re-encoding a long capture.
+
+use crate::ffmpeg::{faststart_args, probe_audio, probe_duration, probe_output_size};
+use crate::process::run_command;
+use crate::{append_log, remove_temp, write_log, TITLE_CARD_FILE};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// anullsrc's layout, which is what a recording without --audio-name has
+const DEFAULT_AUDIO: (u32, u32) = (44100, 2);
+
+/// Bounds of `ffmpeg_timeout`
+const MIN_FFMPEG_TIMEOUT: Duration = Duration::from_secs(600);
+const MAX_FFMPEG_TIMEOUT: Duration = Duration::from_secs(6 * 3600);
+
+/// What `start` asked for, kept until `stop` can apply it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TitleCard {
+    pub output: PathBuf,
+    pub text: String,
+    pub duration: f64,
+    pub color: String,
+    pub h264_profile: String,
+    pub h264_level: String,
+}
+
+impl TitleCard {
+    pub fn save(&self) -> anyhow::Result<()> {
+        fs::write(&*TITLE_CARD_FILE, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// The pending card for `output`, if `start` left one. Consumed.
+    pub fn take(output: &Path) -> Option<Self> {
+        let saved = fs::read_to_string(&*TITLE_CARD_FILE).ok()?;
+        let card: TitleCard = serde_json::from_str(&saved).ok()?;
+        let _ = fs::remove_file(&*TITLE_CARD_FILE);
+        (card.output == output).then_some(card)
+    }
+}
+
+/// Forget any card a previous `start` left behind.
+pub fn clear() {
+    let _ = fs::remove_file(&*TITLE_CARD_FILE);
+}
+
+/// Quote a path for a concat demuxer list.
+pub fn concat_quote(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', "'\\''"))
+}
+
+/// How long an ffmpeg pass over `duration` seconds of video may take before
+/// it is killed: ten times real time, never under `MIN_FFMPEG_TIMEOUT`, and
+/// `MAX_FFMPEG_TIMEOUT` when the duration is unknown.
+pub fn ffmpeg_timeout(duration: Option<f64>) -> Duration {
+    match duration {
+        Some(duration) if duration > 0.0 => {
+            Duration::from_secs_f64(duration * 10.0).clamp(MIN_FFMPEG_TIMEOUT, MAX_FFMPEG_TIMEOUT)
+        }
+        _ => MAX_FFMPEG_TIMEOUT,
+    }
+}
+
+/// Run ffmpeg quietly on `args`, its errors going to the log. `Err` too when
+/// it does not finish within `timeout`.
+pub fn run_ffmpeg(args: &[String], timeout: Duration) -> Result<(), String> {
+    let output = run_command(
+        "ffmpeg",
+        ["-hide_banner", "-loglevel", "error", "-y"]
+            .into_iter()
+            .chain(args.iter().map(String::as_str)),
+        timeout,
+    )
+    .map_err(|e| match e.kind() {
+        io::ErrorKind::TimedOut => e.to_string(),
+        _ => format!("cannot run ffmpeg: {}", e),
+    })?;
+    append_log(&output.stderr);
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("ffmpeg exited with {}", output.status))
+    }
+}
+
+/// Scratch files next to the recording, removed whatever happens.
+struct Intermediates {
+    text: PathBuf,
+    intro: PathBuf,
+    list: PathBuf,
+    joined: PathBuf,
+}
+
+impl Intermediates {
+    fn new(out: &Path) -> Self {
+        let ext = out
+            .extension()
+            .map(|e| e.to_string_lossy())
+            .unwrap_or("mp4".into());
+        let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+        let dir = out.parent().unwrap_or(Path::new("."));
+        Intermediates {
+            text: dir.join(format!(".{}.title.txt", stem)),
+            intro: dir.join(format!(".{}.title.{}", stem, ext)),
+            list: dir.join(format!(".{}.concat.txt", stem)),
+            joined: dir.join(format!(".{}.joined.{}", stem, ext)),
+        }
+    }
+}
+
+impl Drop for Intermediates {
+    fn drop(&mut self) {
+        for tmp in [&self.text, &self.intro, &self.list, &self.joined] {
+            remove_temp(tmp);
+        }
+    }
+}
+
+/// Prepend `card` to `out` in place, muxed with the recording's `movflags`.
+/// On failure `out` is left untouched.
+pub fn apply(card: &TitleCard, out: &Path, movflags: Option<&str>) -> bool {
+    match join(card, out, movflags) {
+        Ok(()) => {
+            write_log(&format!("Title card added to {}", out.display()));
+            true
+        }
+        Err(err) => {
+            write_log(&format!(
+                "Title card failed ({}); {} left as recorded",
+                err,
+                out.display()
+            ));
+            false
+        }
+    }
+}
+
+fn join(card: &TitleCard, out: &Path, movflags: Option<&str>) -> Result<(), String> {
+    let (w, h) = probe_output_size(out).ok_or("could not probe the recording's size")?;
+    let (rate, channels) = probe_audio(out).unwrap_or(DEFAULT_AUDIO);
+    let tmp = Intermediates::new(out);
+    write_log(&format!(
+        "Title card: {}x{} {}s intro {}, concat list {}, joined {}",
+        w,
+        h,
+        card.duration,
+        tmp.intro.display(),
+        tmp.list.display(),
+        tmp.joined.display()
+    ));
+
+    // textfile= sidesteps drawtext's escaping rules for arbitrary text
+    fs::write(&tmp.text, &card.text).map_err(|e| format!("cannot write title text: {}", e))?;
+    let text_file = tmp.text.to_string_lossy().replace('\'', "'\\''");
+    let drawtext = format!(
+        "drawtext=textfile='{}':fontcolor=white:fontsize=h/12:x=(w-text_w)/2:y=(h-text_h)/2",
+        text_file
+    );
+    let layout = if channels == 1 { "mono" } else { "stereo" };
+    let mut intro: Vec<String> = vec![
+        "-f".into(),
+        "lavfi".into(),
+        "-i".into(),
+        format!(
+            "color=c={}:s={}x{}:r=30:d={}",
+            card.color, w, h, card.duration
+        ),
+        "-f".into(),
+        "lavfi".into(),
+        "-i".into(),
+        format!("anullsrc=r={}:cl={}", rate, layout),
+        "-vf".into(),
+        drawtext,
+    ];
+    // Same encode as the recording, or the copy-join below breaks
+    intro.extend(
+        [
+            "-pix_fmt",
+            "yuv420p",
+            "-profile:v",
+            &card.h264_profile,
+            "-level",
+            &card.h264_level,
+            "-c:v",
+            "libx264",
+            "-preset",
+            "ultrafast",
+            "-crf",
+            "23",
+            "-c:a",
+            "aac",
+            "-shortest",
+        ]
+        .map(String::from),
+    );
+    intro.push(tmp.intro.to_string_lossy().into_owned());
+    run_ffmpeg(&intro, ffmpeg_timeout(Some(card.duration)))?;
+
+    let list = format!(
+        "file {}\nfile {}\n",
+        concat_quote(&tmp.intro),
+        concat_quote(out)
+    );
+    fs::write(&tmp.list, list).map_err(|e| format!("cannot write concat list: {}", e))?;
+    let mut joined: Vec<String> = vec![
+        "-f".into(),
+        "concat".into(),
+        "-safe".into(),
+        "0".into(),
+        "-i".into(),
+        tmp.list.to_string_lossy().into_owned(),
+        "-c".into(),
+        "copy".into(),
+    ];
+    joined.extend(faststart_args(out, movflags).into_iter().map(String::from));
+    joined.push(tmp.joined.to_string_lossy().into_owned());
+    run_ffmpeg(&joined, ffmpeg_timeout(probe_duration(out)))?;
+    fs::rename(&tmp.joined, out).map_err(|e| format!("cannot replace the recording: {}", e))
+}
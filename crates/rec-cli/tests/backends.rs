@@ -0,0 +1,118 @@
+//! The ffmpeg command line of each capture backend, against the fixtures in
+//! `tests/fixtures/backends` (one argument per line).
+
+use clap::{Parser, ValueEnum};
+use rec_cli::ffmpeg::{build_ffmpeg_args, pipewire_input, Backend, CapturePlan};
+use rec_cli::StartOptions;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+
+#[derive(Parser)]
+struct Start {
+    #[command(flatten)]
+    options: StartOptions,
+}
+
+/// Log to a directory of this run's own rather than a real recording's
+/// /tmp/rec.nvim.ffmpeg.log. Every test comes through here before anything
+/// reads the environment.
+fn scratch_state() {
+    static SET: Once = Once::new();
+    SET.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("rec-cli-backends-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        std::env::set_var("REC_STATE_DIR", dir);
+    });
+}
+
+/// `rec-cli start --backend <backend> <flags>` recording `input` to
+/// /r/rec.mp4, with nothing probed.
+fn args(
+    backend: Backend,
+    input: &str,
+    crop: Option<(i32, i32, i32, i32)>,
+    flags: &[&str],
+) -> Vec<String> {
+    scratch_state();
+    let name = backend.to_possible_value().unwrap().get_name().to_string();
+    let argv = ["start", "--backend", &name]
+        .into_iter()
+        .chain(flags.iter().copied());
+    let options = Start::try_parse_from(argv).unwrap().options;
+    let plan = CapturePlan {
+        backend,
+        inputs: vec![input.into()],
+        audio: None,
+        preview: false,
+        stack_height: None,
+        crop,
+        screen: crop.map(|_| (1920, 1080)),
+        capture_fps: 30.0,
+        output: PathBuf::from("/r/rec.mp4"),
+    };
+    build_ffmpeg_args(&options, &plan)
+}
+
+fn assert_fixture(name: &str, actual: &[String]) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/backends")
+        .join(name);
+    let expected =
+        fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    let expected: Vec<&str> = expected.lines().collect();
+    assert_eq!(actual, expected, "{}:\n{}", name, actual.join("\n"));
+}
+
+#[test]
+fn avfoundation_grabs_the_numbered_screen() {
+    let actual = args(Backend::AVFoundation, "1:none", None, &[]);
+    assert_fixture("avfoundation.txt", &actual);
+}
+
+#[test]
+fn avfoundation_crops_with_a_filter() {
+    let actual = args(
+        Backend::AVFoundation,
+        "1:none",
+        Some((100, 50, 640, 480)),
+        &[],
+    );
+    assert_fixture("avfoundation_crop.txt", &actual);
+}
+
+#[test]
+fn x11grab_grabs_the_display_screen() {
+    let actual = args(Backend::X11Grab, ":1", None, &[]);
+    assert_fixture("x11grab.txt", &actual);
+}
+
+#[test]
+fn x11grab_crops_from_an_origin_in_the_input() {
+    let actual = args(Backend::X11Grab, ":0.0", Some((100, 50, 640, 480)), &[]);
+    assert_fixture("x11grab_crop.txt", &actual);
+}
+
+#[test]
+fn gdigrab_crops_with_offsets() {
+    let actual = args(Backend::GdiGrab, "desktop", Some((100, 50, 640, 480)), &[]);
+    assert_fixture("gdigrab_crop.txt", &actual);
+}
+
+#[test]
+fn pipewire_asks_the_portal() {
+    let actual = args(Backend::PipeWire, &pipewire_input(None), None, &[]);
+    assert_fixture("pipewire.txt", &actual);
+}
+
+#[test]
+fn pipewire_records_a_node() {
+    let input = pipewire_input(Some(42));
+    let actual = args(
+        Backend::PipeWire,
+        &input,
+        Some((100, 50, 640, 480)),
+        &["--pipewire-node", "42"],
+    );
+    assert_fixture("pipewire_node.txt", &actual);
+}
@@ -0,0 +1,67 @@
+//! Screen detection against `ffmpeg -f avfoundation -list_devices true -i ""`
+//! output captured from several ffmpeg versions, in `tests/fixtures/devices`.
+
+use rec_cli::devices::{detect_screen_index, parse_devices, Device, DeviceKind};
+use std::fs;
+use std::path::Path;
+
+fn listing(name: &str) -> Vec<Device> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/devices")
+        .join(format!("{}.txt", name));
+    parse_devices(&fs::read_to_string(&path).unwrap())
+}
+
+/// The names of the `kind` devices, in listed order.
+fn names(devices: &[Device], kind: DeviceKind) -> Vec<&str> {
+    devices
+        .iter()
+        .filter(|d| d.kind == kind)
+        .map(|d| d.name.as_str())
+        .collect()
+}
+
+#[test]
+fn ffmpeg6_lists_one_screen_after_the_cameras() {
+    let devices = listing("ffmpeg6_macbook");
+    assert_eq!(
+        names(&devices, DeviceKind::Video),
+        [
+            "FaceTime HD Camera",
+            "OBS Virtual Camera",
+            "Capture screen 0"
+        ]
+    );
+    assert_eq!(
+        names(&devices, DeviceKind::Audio),
+        ["MacBook Pro Microphone", "BlackHole 2ch"]
+    );
+    assert_eq!(detect_screen_index(&devices), Some(2));
+}
+
+#[test]
+fn ffmpeg4_context_prefix_is_stripped() {
+    let devices = listing("ffmpeg4_two_screens");
+    assert_eq!(devices.len(), 6);
+    assert_eq!(
+        names(&devices, DeviceKind::Video)[1],
+        "Anna's iPhone Camera"
+    );
+    assert_eq!(detect_screen_index(&devices), Some(2));
+}
+
+#[test]
+fn screen_0_is_found_after_other_screens() {
+    let devices = listing("external_first");
+    assert_eq!(detect_screen_index(&devices), Some(3));
+    // An audio device named after a screen is not one
+    let audio = names(&devices, DeviceKind::Audio);
+    assert_eq!(audio[1], "Capture screen 0 (ZoomAudioDevice)");
+}
+
+#[test]
+fn no_screen_without_the_permission() {
+    let devices = listing("no_screen_permission");
+    assert_eq!(names(&devices, DeviceKind::Video), ["FaceTime HD Camera"]);
+    assert_eq!(detect_screen_index(&devices), None);
+}
@@ -0,0 +1,391 @@
+//! `rec-cli start` and `stop` end to end, against a mock ffmpeg and ffprobe
+//! that are all there is on `PATH`. Each test keeps its state files in its
+//! own directory (`REC_STATE_DIR`), so they run side by side.
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Records until it reads `q` on stdin or gets SIGINT, then prints a summary
+/// the way ffmpeg does. Writes to `pipe:1` until the reader goes away.
+const MOCK_FFMPEG: &str = r#"#!/bin/bash
+[[ "$1" == "-version" ]] && { echo "ffmpeg version 6.1-mock"; exit 0; }
+prev=""
+for a in "$@"; do
+    [[ "$prev" == -progress ]] && progress="$a"
+    prev="$a"
+    out="$a"
+done
+summary() {
+    echo "frame=   90 fps= 30 q=-1.0 Lsize=     123kB time=00:00:03.00 bitrate= 335.4kbits/s speed=1.0x" >&2
+    exit 0
+}
+trap summary INT
+echo "mock ffmpeg recording $out" >&2
+[[ -n "$progress" ]] && printf "frame=15\nout_time_us=500000\nprogress=continue\n" >> "$progress"
+[[ "$out" == pipe:1 ]] || echo data > "$out"
+while true; do
+    if read -t 0.1 line && [[ "$line" == q ]]; then
+        echo "mock ffmpeg got q" >&2
+        summary
+    fi
+    [[ "$out" == pipe:1 ]] && echo streamed-data
+done
+"#;
+
+/// Describes every recording as three seconds of 30 fps video.
+const MOCK_FFPROBE: &str = r#"#!/bin/bash
+case "$*" in
+    -version*) echo "ffprobe version 6.1-mock" ;;
+    *format=duration*) echo "3.000000" ;;
+    *avg_frame_rate*) echo "30/1" ;;
+    *width,height*) echo "1920,1080" ;;
+esac
+"#;
+
+/// Host tools rec-cli itself runs, linked next to the mocks.
+const HOST_TOOLS: &[&str] = &["ps"];
+
+/// A scratch directory with the mock and host tools, an empty config, an output
+/// directory and the state files.
+struct Harness {
+    dir: PathBuf,
+}
+
+impl Harness {
+    fn new(name: &str) -> Harness {
+        let dir =
+            std::env::temp_dir().join(format!("rec-cli-session-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for sub in ["bin", "config", "out", "state"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        for tool in HOST_TOOLS {
+            let host = std::env::split_paths(&path)
+                .map(|dir| dir.join(tool))
+                .find(|bin| bin.is_file())
+                .unwrap_or_else(|| panic!("no {} on PATH", tool));
+            symlink(host, dir.join("bin").join(tool)).unwrap();
+        }
+        let harness = Harness { dir };
+        harness.mock("ffmpeg", MOCK_FFMPEG);
+        harness.mock("ffprobe", MOCK_FFPROBE);
+        harness
+    }
+
+    /// Put `script` on `PATH` as `tool`, in place of any mock before it.
+    fn mock(&self, tool: &str, script: &str) {
+        let bin = self.dir.join("bin").join(tool);
+        fs::write(&bin, script).unwrap();
+        fs::set_permissions(&bin, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    /// `rec-cli <args>` with only the mocks on `PATH`.
+    fn rec(&self, args: &[&str]) -> Command {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_rec-cli"));
+        cmd.args(args)
+            .env("PATH", self.dir.join("bin"))
+            .env("XDG_CONFIG_HOME", self.dir.join("config"))
+            .env("REC_STATE_DIR", self.dir.join("state"));
+        cmd
+    }
+
+    /// The ffmpeg log of this harness's recordings.
+    fn log(&self) -> String {
+        fs::read_to_string(self.dir.join("state/rec.nvim.ffmpeg.log")).unwrap()
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        self.rec(args).output().unwrap()
+    }
+
+    /// `rec-cli start` into the scratch output directory.
+    fn start(&self, flags: &[&str]) -> Output {
+        let out = self.dir.join("out");
+        let mut args = vec!["start", "--output-dir", out.to_str().unwrap()];
+        args.extend_from_slice(flags);
+        self.run(&args)
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        // A failed test must not leave a recording behind for the next one
+        let _ = self.run(&["stop-all"]);
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+/// The `PID: <pid>` line of `rec-cli start` (on stderr with `--stdout`).
+fn started_pid(text: &str) -> i32 {
+    text.lines()
+        .find_map(|line| line.strip_prefix("PID: "))
+        .and_then(|pid| pid.trim().parse().ok())
+        .unwrap_or_else(|| panic!("no PID in:\n{}", text))
+}
+
+/// The state letter of `pid` in /proc (`T` when stopped), when there is one.
+fn proc_state(pid: i32) -> Option<char> {
+    let stat = fs::read_to_string(Path::new("/proc").join(pid.to_string()).join("stat")).ok()?;
+    stat.rsplit(") ").next()?.chars().next()
+}
+
+#[test]
+fn stop_resumes_a_paused_recording() {
+    let harness = Harness::new("paused");
+    let started = harness.start(&[]);
+    assert!(started.status.success(), "{}", stdout(&started));
+    let pid = started_pid(&stdout(&started));
+
+    kill(Pid::from_raw(pid), Signal::SIGSTOP).unwrap();
+    if Path::new("/proc").exists() {
+        while proc_state(pid) != Some('T') {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    let stopped = harness.run(&["stop"]);
+    let text = stdout(&stopped);
+    assert!(stopped.status.success(), "{}", text);
+    assert!(text.contains("Recording saved: "), "{}", text);
+    let log = harness.log();
+    let log = log.rsplit("===== STOP =====").next().unwrap();
+    assert!(
+        log.contains("Recording is paused; sending SIGCONT"),
+        "{}",
+        log
+    );
+    assert!(log.contains("mock ffmpeg got q"), "{}", log);
+}
+
+#[test]
+fn a_consumer_that_exits_early_closes_the_stream() {
+    let harness = Harness::new("stdout");
+    let mut child = harness
+        .rec(&["start", "--stdout", "--format", "mkv"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Like `| head -c 8`: a few bytes, then the pipe closes
+    let mut head = [0; 8];
+    child.stdout.take().unwrap().read_exact(&mut head).unwrap();
+    assert_eq!(&head, b"streamed");
+
+    let started = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&started.stderr);
+    assert!(started.status.success(), "{}", stderr);
+
+    // ffmpeg dies of SIGPIPE on its next write
+    let pid = Pid::from_raw(started_pid(&stderr));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while kill(pid, None).is_ok() {
+        assert!(Instant::now() < deadline, "ffmpeg outlived its reader");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // A closed stream is how a piped recording ends: a token, not a failure
+    let stopped = harness.run(&["stop"]);
+    let text = stdout(&stopped);
+    assert!(stopped.status.success(), "{}", text);
+    assert!(text.starts_with("REC_STREAM_CLOSED\n"), "{}", text);
+    let stopped = harness.run(&["stop"]);
+    assert!(
+        stdout(&stopped).contains("REC_NOT_RUNNING"),
+        "{}",
+        stdout(&stopped)
+    );
+}
+
+#[test]
+fn log_interleaves_rec_cli_and_ffmpeg_in_order() {
+    let harness = Harness::new("log");
+    let started = harness.start(&[]);
+    assert!(started.status.success(), "{}", stdout(&started));
+    let stopped = harness.run(&["stop"]);
+    assert!(stopped.status.success(), "{}", stdout(&stopped));
+
+    let log = harness.log();
+    let run = &log[log.rfind("===== START =====").unwrap()..];
+    // rec-cli's markers around what ffmpeg wrote to the same file
+    let lines = [
+        "===== START =====",
+        "mock ffmpeg recording ",
+        "First frame after ",
+        "===== STOP =====",
+        "mock ffmpeg got q",
+        "Lsize=     123kB",
+        "ffmpeg summary: 90 frames",
+    ];
+    let mut from = 0;
+    for line in lines {
+        match run[from..].find(line) {
+            Some(at) => from += at + line.len(),
+            None => panic!("{:?} missing or out of order in:\n{}", line, run),
+        }
+    }
+}
+
+#[test]
+fn a_hung_ffprobe_times_the_verification_out() {
+    let harness = Harness::new("verify");
+    let started = harness.start(&[]);
+    assert!(started.status.success(), "{}", stdout(&started));
+    // Never answers; nothing but the mocks is on PATH, so no sleep(1)
+    harness.mock("ffprobe", "#!/bin/bash\nwhile true; do :; done\n");
+
+    let begun = Instant::now();
+    let stopped = harness.run(&["stop"]);
+    let text = stdout(&stopped);
+    // A rejection, which exits 0 like every token the plugin matches on
+    assert!(stopped.status.success(), "{}", text);
+    assert!(text.starts_with("REC_VERIFY_TIMEOUT\n"), "{}", text);
+    assert!(text.contains("Kept as recorded: "), "{}", text);
+    assert!(
+        begun.elapsed() < Duration::from_secs(30),
+        "{:?}",
+        begun.elapsed()
+    );
+}
+
+#[test]
+fn a_failed_encode_keeps_the_capture() {
+    let harness = Harness::new("encode");
+    // Records as usual, but the encode at `stop` (the one run reporting
+    // -progress on stdout) fails
+    harness.mock(
+        "ffmpeg",
+        &MOCK_FFMPEG.replacen(
+            '\n',
+            "\n[[ \"$*\" == *\"-progress pipe:1\"* ]] && exit 1\n",
+            1,
+        ),
+    );
+    let started = harness.start(&["--encode-in-background-after-stop"]);
+    assert!(started.status.success(), "{}", stdout(&started));
+
+    let stopped = harness.run(&["stop"]);
+    let text = stdout(&stopped);
+    assert!(text.starts_with("REC_ENCODE_ERR\n"), "{}", text);
+    let kept = text
+        .lines()
+        .find_map(|line| line.strip_prefix("The lossless capture is kept: "))
+        .unwrap_or_else(|| panic!("no capture in:\n{}", text));
+    assert!(kept.ends_with(".lossless.mkv"), "{}", kept);
+    assert!(Path::new(kept).exists(), "{}", kept);
+}
+
+#[test]
+fn the_concurrency_limit_refuses_another_session() {
+    let harness = Harness::new("sessions");
+    let started = harness.start(&[]);
+    assert!(started.status.success(), "{}", stdout(&started));
+
+    let refused = harness.start(&["--session", "b", "--concurrency-limit", "1"]);
+    let text = stdout(&refused);
+    assert_eq!(refused.status.code(), Some(1), "{}", text);
+    assert!(text.starts_with("REC_TOO_MANY_SESSIONS\n"), "{}", text);
+    let started = harness.start(&["--session", "b"]);
+    assert!(started.status.success(), "{}", stdout(&started));
+
+    let listed = stdout(&harness.run(&["list-running"]));
+    assert!(
+        listed.lines().any(|line| line.starts_with("default ")),
+        "{}",
+        listed
+    );
+    assert!(
+        listed.lines().any(|line| line.starts_with("b ")),
+        "{}",
+        listed
+    );
+
+    // Each session finalized by its own `stop`
+    let stopped = harness.run(&["stop-all"]);
+    let text = stdout(&stopped);
+    assert!(stopped.status.success(), "{}", text);
+    assert!(text.contains("Stopped 2 of 2 recording(s)"), "{}", text);
+    let listed = stdout(&harness.run(&["list-running"]));
+    assert!(listed.contains("No recordings running"), "{}", listed);
+}
+
+#[test]
+fn stop_saves_to_a_path_with_spaces_at_either_end() {
+    let harness = Harness::new("spaces");
+    // An --output-file has to end in its extension, so the spaces that
+    // trimming a path would eat go at either end of the names in it
+    let dir = harness.dir.join("out/ my recordings ");
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join(" take one .mp4");
+    let started = harness.run(&["start", "--output-file", file.to_str().unwrap()]);
+    assert!(started.status.success(), "{}", stdout(&started));
+
+    let stopped = harness.run(&["stop"]);
+    let text = stdout(&stopped);
+    assert!(stopped.status.success(), "{}", text);
+    let saved = text
+        .lines()
+        .find_map(|line| line.strip_prefix("Recording saved: "))
+        .unwrap_or_else(|| panic!("nothing saved in:\n{}", text));
+    assert_eq!(Path::new(saved), file);
+    assert!(file.is_file(), "{}", file.display());
+}
+
+#[test]
+fn an_ffmpeg_deaf_to_q_is_finalized_by_sigint() {
+    let harness = Harness::new("sigint");
+    // Never takes `q`, and writes the file only as SIGINT ends it
+    harness.mock(
+        "ffmpeg",
+        &MOCK_FFMPEG
+            .replace("[[ \"$line\" == q ]]", "false")
+            .replace("[[ \"$out\" == pipe:1 ]] || echo data > \"$out\"\n", "")
+            .replace("summary() {\n", "summary() {\n    echo data > \"$out\"\n"),
+    );
+    let started = harness.start(&[]);
+    assert!(started.status.success(), "{}", stdout(&started));
+
+    let stopped = harness.run(&["stop"]);
+    let text = stdout(&stopped);
+    assert!(stopped.status.success(), "{}", text);
+    assert!(text.contains("Recording saved: "), "{}", text);
+    let log = harness.log();
+    let log = log.rsplit("===== STOP =====").next().unwrap();
+    assert!(log.contains("Sent 'q' to ffmpeg stdin"), "{}", log);
+    assert!(
+        log.contains("ffmpeg ignored 'q'; falling back to SIGINT"),
+        "{}",
+        log
+    );
+}
+
+#[test]
+fn an_empty_recording_after_q_fails_the_stop() {
+    let harness = Harness::new("empty");
+    // Takes `q` as usual, but never writes a byte
+    harness.mock(
+        "ffmpeg",
+        &MOCK_FFMPEG.replace("|| echo data > \"$out\"", "|| : > \"$out\""),
+    );
+    let started = harness.start(&[]);
+    assert!(started.status.success(), "{}", stdout(&started));
+
+    let stopped = harness.run(&["stop"]);
+    let text = stdout(&stopped);
+    assert!(text.starts_with("REC_STOP_ERR\n"), "{}", text);
+    let log = harness.log();
+    let log = log.rsplit("===== STOP =====").next().unwrap();
+    assert!(log.contains("mock ffmpeg got q"), "{}", log);
+    assert!(!log.contains("falling back to SIGINT"), "{}", log);
+}
@@ -77,9 +77,11 @@ end
 
 function M.status()
 	run({ "status" }, function(out)
-		if out == "REC_RECORDING" then
+		-- The first line is a stable REC_STATUS_* token
+		local token = out:match("^[^\n]*")
+		if token == "REC_STATUS_RUNNING" then
 			vim.notify("rec.nvim is recording 🔴", vim.log.levels.INFO)
-		elseif out == "REC_IDLE" then
+		elseif token == "REC_STATUS_IDLE" or token == "REC_STATUS_STALE" then
 			vim.notify("rec.nvim is idle ⚪", vim.log.levels.INFO)
 		else
 			vim.notify(out, vim.log.levels.INFO)